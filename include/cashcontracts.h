@@ -0,0 +1,48 @@
+/* C interface of cashcontracts, built with the "cabi" feature. See src/cabi.rs. */
+
+#ifndef CASHCONTRACTS_H
+#define CASHCONTRACTS_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define CASH_OK 0
+#define CASH_ERR_NULL_POINTER 1
+#define CASH_ERR_INVALID_ADDRESS 2
+#define CASH_ERR_INVALID_SCRIPT 3
+#define CASH_ERR_INVALID_INDEX 4
+#define CASH_ERR_BUFFER_TOO_SMALL 5
+#define CASH_ERR_MISSING_SIGNATURE 6
+#define CASH_ERR_INVALID_SIGNATURE 7
+
+typedef struct CashTx CashTx;
+
+CashTx *cash_tx_new(int32_t version, uint32_t lock_time);
+void cash_tx_free(CashTx *tx);
+
+int cash_tx_add_p2pkh_input(CashTx *tx, const uint8_t *tx_hash, uint32_t vout, uint64_t value,
+                            const char *cash_addr, uint32_t sequence);
+int cash_tx_add_output(CashTx *tx, uint64_t value, const uint8_t *script, size_t script_len);
+int cash_tx_add_address_output(CashTx *tx, uint64_t value, const char *cash_addr);
+size_t cash_tx_num_inputs(const CashTx *tx);
+
+int cash_tx_sighashes(const CashTx *tx, uint32_t sighash_type,
+                      uint8_t *out, size_t out_len, size_t *written);
+int cash_tx_set_signature(CashTx *tx, size_t input_idx, const uint8_t *sig, size_t sig_len,
+                          const uint8_t *pub_key, size_t pub_key_len);
+int cash_tx_serialize(const CashTx *tx, uint8_t *out, size_t out_len, size_t *written);
+
+int cash_address_decode(const char *cash_addr, uint8_t *hash_out, bool *is_p2sh);
+int cash_address_encode(const char *prefix, const uint8_t *hash, bool is_p2sh,
+                        char *out, size_t out_len, size_t *written);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
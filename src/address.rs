@@ -3,7 +3,7 @@ use crate::hash::hash160;
 const CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
 const DEFAULT_PREFIX: &str = "bitcoincash";
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum AddressError {
     InvalidChecksum,
     InvalidBase32Letter(usize, u8),
@@ -1,11 +1,20 @@
-use crate::unsigned_tx::{Output, PreImage, PreImageWriteFlags};
-use crate::outputs::{SLPSend, P2PKHOutput};
-use crate::script::{Script, Op};
-use crate::address::{Address};
-use crate::tx::TxOutput;
-use crate::serialize::{write_var_int, var_int_to_vec, encode_int};
-
-use byteorder::{LittleEndian, BigEndian, WriteBytesExt};
+use crate::unsigned_tx::{Output, PreImage, UnsignedTx, UnsignedInput};
+use crate::outputs::{SLPSend, P2PKHOutput, P2SHOutput};
+use crate::script::{Script, Op, op_data, find_pattern, find_patterns};
+use crate::address::{Address, AddressType};
+use crate::wallet::{DUST_AMOUNT, UtxoEntry, add_p2pkh_inputs};
+use crate::op_return::LOKAD_ID_SIZE;
+use crate::tx::{Tx, TxOutput, TxOutpoint};
+use crate::serialize::{write_var_int, var_int_to_vec, encode_int, encode_int64, vec_to_int64,
+                       read_var_int, MAX_SCRIPT_INT};
+use crate::decode::{decode_output, DecodedOutput};
+use crate::slp::SLPMessage;
+use crate::spend::{LockingScript, Unlocker, Spend};
+use crate::covenant::{pre_image_prefix, pre_image_value_sequence, pre_image_suffix,
+                      serialize_outputs, script_with_len};
+
+use byteorder::{LittleEndian, BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io;
 use std::iter::repeat;
 
 
@@ -19,12 +28,50 @@ pub struct AdvancedTradeOffer {
     pub token_id: [u8; 32],
     pub token_type: u8,
     pub sell_amount_token: u64,
-    pub price: u32,
+    pub price: u64,
+    // price is price / price_denominator sats per token (tokens per sat if inverted)
+    pub price_denominator: u64,
     pub dust_amount: u64,
     pub address: Address,
-    pub fee_address: Option<Address>,
-    pub fee_divisor: Option<u64>,
-    pub spend_params: Option<AdvancedTradeOfferSpendParams>,
+    pub fees: Vec<OfferFee>,
+}
+
+// Fee output paying max(payment / divisor, dust_amount) to address.
+#[derive(Clone, Debug)]
+pub struct OfferFee {
+    pub address: Address,
+    pub divisor: u64,
+}
+
+// 256^power must fit into a u64
+pub const MAX_OFFER_POWER: u8 = 7;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OfferError {
+    ValueOutOfRange { field: &'static str, value: u64 },
+    ZeroPrice,
+    ZeroSellAmount,
+    ZeroFeeDivisor,
+    PowerTooLarge(u8),
+    InvertedAmountNotDivisible { sell_amount_token: u64, price: u64, price_denominator: u64 },
+    PriceNotDivisible { sell_amount_token: u64, price: u64, price_denominator: u64 },
+    ZeroPriceDenominator,
+    DustTooLow(u64),
+    ValueBelowDust { value: u64, dust_amount: u64 },
+    InvalidLokadId(usize),
+    InvalidRedeemScript,
+    InvalidSpendParams,
+    InvalidBuyAmount(u64),
+    InsufficientFunds(u64),
+    OfferNotSpent,
+    OfferClosed,
+    InvalidPushDataCount { expected: usize, actual: usize },
+    UnexpectedOutputIdx { expected: usize, actual: usize },
+}
+
+#[derive(Clone, Debug)]
+pub struct AdvancedTradeOfferBuilder {
+    offer: AdvancedTradeOffer,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -37,6 +84,138 @@ pub enum AdvancedTradeOfferSpendParams {
 }
 
 impl AdvancedTradeOffer {
+    pub fn validate(&self) -> Result<(), OfferError> {
+        if self.lokad_id.len() != LOKAD_ID_SIZE {
+            return Err(OfferError::InvalidLokadId(self.lokad_id.len()));
+        }
+        if self.price == 0 {
+            return Err(OfferError::ZeroPrice);
+        }
+        if self.price_denominator == 0 {
+            return Err(OfferError::ZeroPriceDenominator);
+        }
+        if self.sell_amount_token == 0 {
+            return Err(OfferError::ZeroSellAmount);
+        }
+        if self.fees.iter().any(|fee| fee.divisor == 0) {
+            return Err(OfferError::ZeroFeeDivisor);
+        }
+        if self.power > MAX_OFFER_POWER {
+            return Err(OfferError::PowerTooLarge(self.power));
+        }
+        // inverted offers require the paid token amount to be divisible by the price,
+        // otherwise the offer could never be accepted fully
+        if self.is_inverted && !self._is_exact(self.sell_amount_token) {
+            return Err(OfferError::InvertedAmountNotDivisible {
+                sell_amount_token: self.sell_amount_token,
+                price: self.price,
+                price_denominator: self.price_denominator,
+            });
+        }
+        // same for fractional prices, where the paid amount has to be a whole satoshi
+        if !self.is_inverted && self.price_denominator != 1 {
+            let is_divisible = self.sell_amount_token.checked_mul(self.price)
+                .is_some_and(|amount| amount % self.price_denominator == 0);
+            if !is_divisible {
+                return Err(OfferError::PriceNotDivisible {
+                    sell_amount_token: self.sell_amount_token,
+                    price: self.price,
+                    price_denominator: self.price_denominator,
+                });
+            }
+        }
+        if self.dust_amount < DUST_AMOUNT {
+            return Err(OfferError::DustTooLow(self.dust_amount));
+        }
+        if self.value < self.dust_amount {
+            return Err(OfferError::ValueBelowDust {
+                value: self.value,
+                dust_amount: self.dust_amount,
+            });
+        }
+        self.check_ranges()
+    }
+
+    pub fn check_ranges(&self) -> Result<(), OfferError> {
+        let fields = [
+            ("sell_amount_token", self.sell_amount_token),
+            ("price", self.price),
+            ("price_denominator", self.price_denominator),
+            ("dust_amount", self.dust_amount),
+            ("fee_divisor", self.fees.iter().map(|fee| fee.divisor).max().unwrap_or(0)),
+            ("accept_fully_amount", self.accept_fully_amount().unwrap_or(u64::MAX)),
+            // the script multiplies by the denominator before dividing by the price
+            ("scaled_amount", self.accept_fully_amount()
+                .and_then(|amount| amount.checked_mul(self.price_denominator))
+                .unwrap_or(u64::MAX)),
+        ];
+        for &(field, value) in fields.iter() {
+            if value > MAX_SCRIPT_INT {
+                return Err(OfferError::ValueOutOfRange { field, value });
+            }
+        }
+        Ok(())
+    }
+
+    // Spends the offer with `spend_params`, e.g. as the output of an UnsignedInput. Fails if
+    // the offer's values don't fit into script numbers or the accept amounts are invalid.
+    pub fn spend(&self, spend_params: AdvancedTradeOfferSpendParams)
+            -> Result<Spend<AdvancedTradeOffer, AdvancedTradeOfferSpendParams>, OfferError> {
+        match spend_params {
+            AdvancedTradeOfferSpendParams::Cancel => self.check_ranges()?,
+            _ => { self.accept_amounts(&spend_params)?; },
+        }
+        Ok(Spend::new(self.clone(), spend_params))
+    }
+
+    // Amount of BCH (or tokens, if inverted) the buyer pays for the whole offer.
+    pub fn accept_fully_amount(&self) -> Option<u64> {
+        if self.is_inverted {
+            Some(self.sell_amount_token)
+        } else {
+            self.sell_amount_token.checked_mul(self.price).map(|amount| amount / self.price_denominator)
+        }
+    }
+
+    // Converts between paid amount and received amount, i.e. amount * price_denominator / price.
+    fn _convert(&self, amount: u64) -> u64 {
+        (amount as u128 * self.price_denominator as u128 / self.price as u128) as u64
+    }
+
+    fn _is_exact(&self, amount: u64) -> bool {
+        (amount as u128 * self.price_denominator as u128) % self.price as u128 == 0
+    }
+
+    fn _make_price_vec(&self) -> Vec<u8> {
+        let mut vec = Vec::new();
+        vec.write_u64::<BigEndian>(self.price).unwrap();
+        if self.price_denominator != 1 {
+            vec.write_u64::<BigEndian>(self.price_denominator).unwrap();
+        }
+        vec
+    }
+
+    // Ops converting the amount on top of the stack, see _convert.
+    fn _convert_ops(&self) -> Vec<Op> {
+        use crate::script::OpCodeType::*;
+        let mut ops = Vec::new();
+        if self.price_denominator != 1 {
+            ops.push(Op::Push(encode_int64(self.price_denominator as i64)));
+            ops.push(Op::Code(OpMul));
+        }
+        ops.push(Op::Push(encode_int64(self.price as i64)));
+        if self.is_inverted || self.price_denominator != 1 {
+            ops.append(&mut vec![
+                Op::Code(Op2Dup),
+                Op::Code(OpMod),
+                Op::Push(encode_int(0)),
+                Op::Code(OpNumEqualVerify),
+            ]);
+        }
+        ops.push(Op::Code(OpDiv));
+        ops
+    }
+
     fn _make_power_vec(&self) -> Vec<u8> {
         let mut vec = vec![self.power];
         if self.is_inverted {
@@ -47,28 +226,17 @@ impl AdvancedTradeOffer {
 
     fn _ops(&self) -> Vec<Op> {
         use crate::script::OpCodeType::*;
-        let serialize = vec![
-            Op::Push(vec![0x04]),
+        // serialize a number as 8 byte big endian SLP quantity
+        let mut serialize = vec![
+            Op::Push(vec![0x08]),
             Op::Code(OpNum2Bin),
-
-            Op::Push(vec![1]),
-            Op::Code(OpSplit),
-            Op::Push(vec![1]),
-            Op::Code(OpSplit),
-            Op::Push(vec![1]),
-            Op::Code(OpSplit),
-
-            Op::Code(OpSwap),
-            Op::Code(OpCat),
-            Op::Code(OpSwap),
-            Op::Code(OpCat),
-            Op::Code(OpSwap),
-            Op::Code(OpCat),
         ];
+        serialize.extend(repeat(vec![Op::Push(vec![1]), Op::Code(OpSplit)]).take(7).flatten());
+        serialize.extend(repeat(vec![Op::Code(OpSwap), Op::Code(OpCat)]).take(7).flatten());
         let mut ops = vec![
             Op::Push({
                 let mut sell_amount_serialized = Vec::new();
-                sell_amount_serialized.write_u32::<LittleEndian>(self.sell_amount_token as u32).unwrap();
+                sell_amount_serialized.write_u64::<LittleEndian>(self.sell_amount_token).unwrap();
                 sell_amount_serialized
             }),
             Op::Code(OpCodeSeparator),
@@ -85,13 +253,12 @@ impl AdvancedTradeOffer {
                 Op::Push(encode_int(0)),
                 Op::Code(OpGreaterThan),
                 Op::Code(OpVerify),
-                Op::Push(encode_int(self.price as i32)),
-                Op::Code(OpDiv),
+            ].into_iter().chain(self._convert_ops()).chain(vec![
                 Op::Code(OpTuck),
                 Op::Code(Op2Dup),
                 Op::Code(OpGreaterThanOrEqual),
                 Op::Code(OpVerify),
-            ]
+            ]).collect()
         } else {
             vec![
                 Op::Code(Op2Dup),
@@ -116,19 +283,15 @@ impl AdvancedTradeOffer {
         ops.append(&mut vec![
             Op::Push(vec![0x08]),
             Op::Push(vec![0x09]),
-            Op::Code(OpNum2Bin),
+            Op::Code(OpNum2Bin),  // 0x08 + 8 byte zero quantity
             Op::Code(OpCat),
             Op::Code(OpElse),
 
-            Op::Push(vec![0x04]),
+            Op::Push(vec![0x08]),
             Op::Code(OpNum2Bin),
 
             Op::Code(OpEndIf),
-            //Op::Push(b"\x08\0\0\0\0\0\0\0\0".to_vec()),
-            //Op::Push(b"\x08\0\0\0\0".to_vec()),
             Op::Push(vec![0x08]),
-            Op::Push(vec![0x05]),
-            Op::Code(OpNum2Bin),
             Op::Code(OpCat),
             Op::Push(vec![0x02]),
             Op::Code(OpPick),
@@ -141,19 +304,19 @@ impl AdvancedTradeOffer {
 
             Op::Code(OpIf),
             Op::Push(var_int_to_vec(
-                SLPSend {
+                Output::script(&SLPSend {
                     token_id: self.token_id,
                     token_type: self.token_type,
                     output_quantities: vec![0, 0, 0],
-                }.into_output().script().to_vec().len() as u64
+                }.into_output_unchecked()).to_vec().len() as u64
             )),
             Op::Code(OpElse),
             Op::Push(var_int_to_vec(
-                SLPSend {
+                Output::script(&SLPSend {
                     token_id: self.token_id,
                     token_type: self.token_type,
                     output_quantities: vec![0, 0],
-                }.into_output().script().to_vec().len() as u64
+                }.into_output_unchecked()).to_vec().len() as u64
             )),
             Op::Code(OpEndIf),
             Op::Code(OpCat),
@@ -163,10 +326,10 @@ impl AdvancedTradeOffer {
                     token_id: self.token_id,
                     token_type: self.token_type,
                     output_quantities: vec![],
-                }.into_output();
+                }.into_output_unchecked();
                 let mut output_pre1 = Vec::new();
-                output_pre1.append(&mut slp_output.script().to_vec());
-                output_pre1.append(&mut b"\x08\0\0\0\0".to_vec());
+                output_pre1.append(&mut Output::script(&slp_output).to_vec());
+                output_pre1.push(0x08);
                 output_pre1
             }),
             Op::Code(OpCat),
@@ -181,7 +344,7 @@ impl AdvancedTradeOffer {
             Op::Code(Op0NotEqual),
             Op::Code(OpIf),
 
-            Op::Push(encode_int(self.dust_amount as i32)),
+            Op::Push(encode_int64(self.dust_amount as i64)),
             Op::Push(vec![0x08]),
             Op::Code(OpNum2Bin),  // push dust 8 bytes little endian
 
@@ -195,9 +358,9 @@ impl AdvancedTradeOffer {
             Op::Code(OpCat),
             Op::Code(OpCat),
             Op::Code(OpSwap),
-            Op::Push(vec![0x04]),
+            Op::Push(vec![0x08]),
             Op::Code(OpNum2Bin),
-            Op::Push(vec![0x04]),
+            Op::Push(vec![0x08]),
             Op::Code(OpSwap),
             Op::Code(OpCat),
             Op::Push(vec![OpCodeSeparator as u8]),
@@ -215,55 +378,61 @@ impl AdvancedTradeOffer {
             Op::Code(OpSwap),
         ]);
         if self.is_inverted {
-            ops.append(&mut vec![
-                Op::Push(encode_int(self.price as i32)),
-                Op::Code(Op2Dup),
-                Op::Code(OpMod),
-                Op::Push(encode_int(0)),
-                Op::Code(OpNumEqualVerify),
-                Op::Code(OpDiv),
-            ]);
+            ops.append(&mut self._convert_ops());
         }
-        let mut push_fee_ops = match (&self.fee_address, self.fee_divisor) {
-            (Some(fee_address), Some(fee_divisor)) => {
-                ops.push(Op::Code(OpTuck));
-                let mut send_fee_script = Vec::new();
-                let mut send_fee_output = P2PKHOutput {
+        let mut push_fee_ops = if self.fees.is_empty() {
+            vec![
+                Op::Code(OpSwap),
+                Op::Code(OpCat),
+            ]
+        } else {
+            ops.push(Op::Code(OpTuck));
+            let mut push_fee_ops = vec![
+                Op::Code(OpRot),
+                Op::Code(OpCat),
+                Op::Code(OpSwap),
+            ];
+            for (idx, fee) in self.fees.iter().enumerate() {
+                let is_last = idx == self.fees.len() - 1;
+                let send_fee_script = script_with_len(&Output::script(&P2PKHOutput {
                     value: 0,
-                    address: fee_address.clone(),
-                }.script().to_vec();
-                write_var_int(&mut send_fee_script, send_fee_output.len() as u64).unwrap();
-                send_fee_script.append(&mut send_fee_output);
-                vec![
-                    Op::Code(OpRot),
-                    Op::Code(OpCat),
-                    Op::Code(OpSwap),
-                    Op::Push(encode_int(fee_divisor as i32)),
+                    address: fee.address.clone(),
+                }).to_vec());
+                if !is_last {
+                    push_fee_ops.push(Op::Code(OpDup));  // keep payment for the next fee
+                }
+                push_fee_ops.append(&mut vec![
+                    Op::Push(encode_int64(fee.divisor as i64)),
                     Op::Code(OpDiv),
-                    Op::Push(encode_int(self.dust_amount as i32)),
+                    Op::Push(encode_int64(self.dust_amount as i64)),
                     Op::Code(OpMax),
                     Op::Push(vec![0x08]),
                     Op::Code(OpNum2Bin),
                     Op::Push(send_fee_script),
                     Op::Code(OpCat),
-                    Op::Code(OpCat),
-                ]
-            },
-            (None, None) => vec![
-                Op::Code(OpSwap),
-                Op::Code(OpCat),
-            ],
-            _ => panic!("Set fee_address and fee_divisor either both Some or None"),
+                ]);
+                push_fee_ops.append(&mut if is_last {
+                    vec![Op::Code(OpCat)]
+                } else {
+                    vec![
+                        Op::Code(OpRot),
+                        Op::Code(OpSwap),
+                        Op::Code(OpCat),
+                        Op::Code(OpSwap),
+                    ]
+                });
+            }
+            push_fee_ops
         };
         ops.append(&mut vec![
             Op::Push(vec![0x08]),
             Op::Code(OpNum2Bin),
             Op::Code(OpCat),
             Op::Push({
-                let p2pkh_serialized = P2PKHOutput {
+                let p2pkh_serialized = Output::script(&P2PKHOutput {
                     value: 0,
                     address: self.address.clone(),
-                }.script().to_vec();
+                }).to_vec();
                 let mut vec = Vec::new();
                 write_var_int(&mut vec, p2pkh_serialized.len() as u64).unwrap();
                 vec.append(&mut vec![OpDup as u8, OpHash160 as u8, 20]);
@@ -298,11 +467,7 @@ impl AdvancedTradeOffer {
             Op::Code(OpFromAltStack),
             Op::Code(OpEqualVerify), // address
 
-            Op::Push({
-                let mut vec = Vec::new();
-                vec.write_u32::<BigEndian>(self.price).unwrap();
-                vec
-            }),
+            Op::Push(self._make_price_vec()),
             Op::Code(OpEqualVerify), // price
 
             Op::Push(self._make_power_vec()),
@@ -328,7 +493,199 @@ impl AdvancedTradeOffer {
     }
 }
 
-impl Output for AdvancedTradeOffer {
+impl AdvancedTradeOffer {
+    // Recovers an offer from its redeem script. The covenant's value is not part of the
+    // script and is set to the dust amount. Fails unless re-generating the script from
+    // the recovered parameters reproduces `redeem_script` exactly.
+    pub fn from_redeem_script(redeem_script: &Script) -> Result<Self, OfferError> {
+        use crate::script::OpCodeType::*;
+        let invalid = || OfferError::InvalidRedeemScript;
+        let ops = redeem_script.ops();
+        let data = |op: &Op| op_data(op).ok_or(OfferError::InvalidRedeemScript);
+        let sell_amount_token = match ops.first().map(op_data) {
+            Some(Some(vec)) if vec.len() == 8 => {
+                io::Cursor::new(vec).read_u64::<LittleEndian>().unwrap()
+            },
+            _ => return Err(invalid()),
+        };
+        let address = match ops.get(2).map(op_data) {
+            Some(Some(vec)) => Address::from_slice(AddressType::P2PKH, &vec).ok_or_else(invalid)?,
+            _ => return Err(invalid()),
+        };
+        let params = find_pattern(ops, &[
+            Some(Op::Code(OpFromAltStack)), Some(Op::Code(OpEqualVerify)),
+            None, Some(Op::Code(OpEqualVerify)),
+            None, Some(Op::Code(OpEqualVerify)),
+            None, Some(Op::Code(OpEqualVerify)),
+            None, Some(Op::Code(OpEqual)),
+        ]).ok_or_else(invalid)?;
+        let price_vec = data(&params[2])?;
+        let mut price_cursor = io::Cursor::new(&price_vec);
+        let (price, price_denominator) = match price_vec.len() {
+            8 => (price_cursor.read_u64::<BigEndian>().unwrap(), 1),
+            16 => (price_cursor.read_u64::<BigEndian>().unwrap(),
+                   price_cursor.read_u64::<BigEndian>().unwrap()),
+            _ => return Err(invalid()),
+        };
+        let power_vec = data(&params[4])?;
+        let (power, is_inverted) = match power_vec.as_slice() {
+            [power] => (*power, false),
+            [power, 1] => (*power, true),
+            _ => return Err(invalid()),
+        };
+        let version = match data(&params[6])?.as_slice() {
+            [version] => *version,
+            _ => return Err(invalid()),
+        };
+        let lokad_id = data(&params[8])?;
+        let (token_type, token_id) = ops.iter()
+            .filter_map(op_data)
+            .filter(|push| push.starts_with(b"\x6a\x04SLP\0") && push.len() > 1)
+            .filter_map(|push| {
+                let mut send_prefix = push[..push.len() - 1].to_vec();
+                send_prefix.extend_from_slice(b"\x08\0\0\0\0\0\0\0\0");
+                match SLPMessage::parse(&Script::from_serialized(&send_prefix)?) {
+                    Ok(SLPMessage::Send(send)) => Some((send.token_type, send.token_id)),
+                    _ => None,
+                }
+            })
+            .next()
+            .ok_or_else(invalid)?;
+        let dust_amount = find_pattern(ops, &[
+            None, Some(Op::Push(vec![0x08])), Some(Op::Code(OpNum2Bin)),
+            Some(Op::Push(vec![23, OpHash160 as u8, 20])),
+        ]).ok_or_else(invalid)?;
+        let dust_amount = vec_to_int64(&data(&dust_amount[0])?).ok_or_else(invalid)? as u64;
+        let fees = find_patterns(ops, &[
+            None, Some(Op::Code(OpDiv)), None, Some(Op::Code(OpMax)),
+            Some(Op::Push(vec![0x08])), Some(Op::Code(OpNum2Bin)), None,
+        ]).map(|fee| {
+            let divisor = vec_to_int64(&data(&fee[0])?).ok_or_else(invalid)? as u64;
+            let mut send_fee_script = io::Cursor::new(data(&fee[6])?);
+            let script_len = read_var_int(&mut send_fee_script).map_err(|_| invalid())?;
+            let script = &send_fee_script.get_ref()[send_fee_script.position() as usize..];
+            if script.len() as u64 != script_len { return Err(invalid()); }
+            let script = Script::from_serialized(script).ok_or_else(invalid)?;
+            match decode_output(&TxOutput::new(0, script)) {
+                DecodedOutput::P2PKH(p2pkh) => Ok(OfferFee { address: p2pkh.address, divisor }),
+                _ => Err(invalid()),
+            }
+        }).collect::<Result<Vec<_>, _>>()?;
+        let offer = AdvancedTradeOffer {
+            value: dust_amount,
+            lokad_id,
+            version,
+            power,
+            is_inverted,
+            token_id,
+            token_type,
+            sell_amount_token,
+            price,
+            price_denominator,
+            dust_amount,
+            address,
+            fees,
+        };
+        if offer.script().to_vec() != redeem_script.to_vec() {
+            return Err(invalid());
+        }
+        Ok(offer)
+    }
+
+    // Recovers an offer from the sig_script of an input spending it, whose last push is
+    // the redeem script.
+    pub fn from_p2sh_sig_script(sig_script: &Script) -> Result<Self, OfferError> {
+        match sig_script.ops().last() {
+            Some(Op::Push(redeem_script)) => {
+                let redeem_script = Script::from_serialized(redeem_script)
+                    .ok_or(OfferError::InvalidRedeemScript)?;
+                AdvancedTradeOffer::from_redeem_script(&redeem_script)
+            },
+            _ => Err(OfferError::InvalidRedeemScript),
+        }
+    }
+}
+
+impl AdvancedTradeOfferBuilder {
+    pub fn new(lokad_id: Vec<u8>,
+               token_id: [u8; 32],
+               sell_amount_token: u64,
+               price: u64,
+               address: Address) -> Self {
+        AdvancedTradeOfferBuilder {
+            offer: AdvancedTradeOffer {
+                value: DUST_AMOUNT,
+                lokad_id,
+                version: 1,
+                power: 0,
+                is_inverted: false,
+                token_id,
+                token_type: 1,
+                sell_amount_token,
+                price,
+                price_denominator: 1,
+                dust_amount: DUST_AMOUNT,
+                address,
+                fees: Vec::new(),
+            },
+        }
+    }
+
+    pub fn value(&mut self, value: u64) -> &mut Self {
+        self.offer.value = value;
+        self
+    }
+
+    pub fn version(&mut self, version: u8) -> &mut Self {
+        self.offer.version = version;
+        self
+    }
+
+    pub fn power(&mut self, power: u8) -> &mut Self {
+        self.offer.power = power;
+        self
+    }
+
+    pub fn inverted(&mut self, is_inverted: bool) -> &mut Self {
+        self.offer.is_inverted = is_inverted;
+        self
+    }
+
+    pub fn token_type(&mut self, token_type: u8) -> &mut Self {
+        self.offer.token_type = token_type;
+        self
+    }
+
+    // Sets the price to price / price_denominator.
+    pub fn price_fraction(&mut self, price: u64, price_denominator: u64) -> &mut Self {
+        self.offer.price = price;
+        self.offer.price_denominator = price_denominator;
+        self
+    }
+
+    // Also raises the covenant's value if it would be below the new dust amount.
+    pub fn dust_amount(&mut self, dust_amount: u64) -> &mut Self {
+        self.offer.dust_amount = dust_amount;
+        self.offer.value = self.offer.value.max(dust_amount);
+        self
+    }
+
+    // Adds a fee recipient; fee outputs are placed last, in the order they are added.
+    pub fn fee(&mut self, fee_address: Address, fee_divisor: u64) -> &mut Self {
+        self.offer.fees.push(OfferFee {
+            address: fee_address,
+            divisor: fee_divisor,
+        });
+        self
+    }
+
+    pub fn build(&self) -> Result<AdvancedTradeOffer, OfferError> {
+        self.offer.validate()?;
+        Ok(self.offer.clone())
+    }
+}
+
+impl LockingScript for AdvancedTradeOffer {
     fn value(&self) -> u64 {
         self.value
     }
@@ -340,106 +697,47 @@ impl Output for AdvancedTradeOffer {
     fn script_code(&self) -> Script {
         Script::new(self._ops())
     }
+}
 
+// Offers out of range give a failing sig script, see AdvancedTradeOffer::spend.
+impl Unlocker<AdvancedTradeOffer> for AdvancedTradeOfferSpendParams {
     fn sig_script(&self,
+                  offer: &AdvancedTradeOffer,
                   mut serialized_sig: Vec<u8>,
                   serialized_pub_key: Vec<u8>,
                   pre_image: &PreImage,
                   outputs: &[TxOutput]) -> Script {
         use crate::advanced_trade_offer::AdvancedTradeOfferSpendParams::*;
-        let accept_fully_amount = if self.is_inverted {
-            self.sell_amount_token
-        } else {
-            self.sell_amount_token * (self.price as u64)
-        };
-        let (buy_amount, is_accept_fully) = match self.spend_params {
-            Some(Cancel) => {
+        let accept_fully_amount = offer.accept_fully_amount().unwrap_or(u64::MAX);
+        let (buy_amount, is_accept_fully) = match *self {
+            Cancel => {
                 return Script::new(vec![
                     Op::Push(serialized_sig),
                     Op::Push(serialized_pub_key),
                     Op::Push(vec![]),
                 ])
             },
-            Some(AcceptFully) => {(accept_fully_amount, true)},
-            Some(AcceptPartially {buy_amount}) => (buy_amount, buy_amount == accept_fully_amount),
-            None => panic!("Spend params not set"),
+            AcceptFully => {(accept_fully_amount, true)},
+            AcceptPartially {buy_amount} => (buy_amount, buy_amount == accept_fully_amount),
         };
         serialized_sig.remove(serialized_sig.len() - 1);
-        let script_code = self.script_code().to_vec_sig();
+        let script_code = offer.script_code().to_vec_sig();
         Script::new(vec![
-            Op::Push(self.lokad_id.clone()),
-            Op::Push(vec![self.version]),
-            Op::Push(self._make_power_vec()),
-            Op::Push({
-                let mut vec = Vec::new();
-                vec.write_u32::<BigEndian>(self.price).unwrap();
-                vec
-            }),
-            Op::Push(self.address.bytes().to_vec()),
+            Op::Push(offer.lokad_id.clone()),
+            Op::Push(vec![offer.version]),
+            Op::Push(offer._make_power_vec()),
+            Op::Push(offer._make_price_vec()),
+            Op::Push(offer.address.bytes().to_vec()),
             Op::Push(serialized_pub_key),
             Op::Push(serialized_sig),
-            Op::Push({
-                let mut pre_image_part = Vec::new();
-                pre_image.write_to_stream_flags(&mut pre_image_part, PreImageWriteFlags {
-                    version: true,       // /-
-                    hash_prevouts: true, // |
-                    hash_sequence: true, // |
-                    outpoint: true,      // \-
-                    script_code: false,  // + len(script_code)
-                    value: false,
-                    sequence: false,
-                    hash_outputs: false,
-                    lock_time: false,
-                    sighash_type: false,
-                }).unwrap();
-                write_var_int(&mut pre_image_part, script_code.len() as u64).unwrap();
-                pre_image_part
-            }),
+            Op::Push(pre_image_prefix(pre_image, script_code.len())),
             Op::Push(script_code),
-            Op::Push({
-                let mut pre_image_part = Vec::new();
-                pre_image.write_to_stream_flags(&mut pre_image_part, PreImageWriteFlags {
-                    version: false,
-                    hash_prevouts: false,
-                    hash_sequence: false,
-                    outpoint: false,
-                    script_code: false,
-                    value: true,    // /-
-                    sequence: true, // \-
-                    hash_outputs: false,
-                    lock_time: false,
-                    sighash_type: false,
-                }).unwrap();
-                pre_image_part
-            }),
-            Op::Push({
-                let mut pre_image_part = Vec::new();
-                pre_image.write_to_stream_flags(&mut pre_image_part, PreImageWriteFlags {
-                    version: false,
-                    hash_prevouts: false,
-                    hash_sequence: false,
-                    outpoint: false,
-                    script_code: false,
-                    value: false,
-                    sequence: false,
-                    hash_outputs: false,
-                    lock_time: true,    // /-
-                    sighash_type: true, // \-
-                }).unwrap();
-                pre_image_part
-            }),
-            Op::Push({
-                let mut outputs_end = Vec::new();
-                outputs[
-                    if is_accept_fully {2} else {3} ..
-                        outputs.len() - if self.fee_address.is_some() {1} else {0}
-                ].iter()
-                    .for_each(|tx_output| {
-                        tx_output.write_to_stream(&mut outputs_end).unwrap()
-                    });
-                outputs_end
-            }),
-            Op::Push(encode_int(buy_amount as i32)),
+            Op::Push(pre_image_value_sequence(pre_image)),
+            Op::Push(pre_image_suffix(pre_image)),
+            Op::Push(serialize_outputs(
+                &outputs[if is_accept_fully {2} else {3} .. outputs.len() - offer.fees.len()]
+            )),
+            Op::Push(encode_int64(buy_amount as i64)),
             Op::Push(encode_int(1)),
         ])
     }
@@ -450,11 +748,30 @@ pub struct P2PKHDropNOutput {
     pub value: u64,
     pub address: Address,
     pub drop_number: usize,
-    pub push_data: Option<Vec<Vec<u8>>>
 }
 
+// Data pushes dropped by the P2PKHDropNOutput, drop_number items.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct P2PKHDropNSpendParams {
+    pub push_data: Vec<Vec<u8>>,
+}
 
-impl Output for P2PKHDropNOutput {
+impl P2PKHDropNOutput {
+    // Spends the output with `spend_params`, e.g. as the output of an UnsignedInput. Fails if
+    // `spend_params` doesn't have drop_number pushes.
+    pub fn spend(&self, spend_params: P2PKHDropNSpendParams)
+            -> Result<Spend<P2PKHDropNOutput, P2PKHDropNSpendParams>, OfferError> {
+        if spend_params.push_data.len() != self.drop_number {
+            return Err(OfferError::InvalidPushDataCount {
+                expected: self.drop_number,
+                actual: spend_params.push_data.len(),
+            });
+        }
+        Ok(Spend::new(self.clone(), spend_params))
+    }
+}
+
+impl LockingScript for P2PKHDropNOutput {
     fn value(&self) -> u64 {
         self.value
     }
@@ -475,19 +792,16 @@ impl Output for P2PKHDropNOutput {
     fn script_code(&self) -> Script {
         self.script()
     }
+}
 
+impl Unlocker<P2PKHDropNOutput> for P2PKHDropNSpendParams {
     fn sig_script(&self,
+                  _output: &P2PKHDropNOutput,
                   serialized_sig: Vec<u8>,
                   serialized_pub_key: Vec<u8>,
                   _pre_image: &PreImage,
                   _outputs: &[TxOutput]) -> Script {
-        let pushes = self.push_data.as_ref().expect("Spend data not set").clone();
-        if pushes.len() != self.drop_number {
-            panic!(format!("push_data should be {} items but is {}",
-                           self.drop_number,
-                           pushes.len()))
-        }
-        let mut ops: Vec<Op> = pushes.into_iter().map(|push| Op::Push(push)).collect();
+        let mut ops: Vec<Op> = self.push_data.iter().cloned().map(Op::Push).collect();
         ops.append(&mut vec![
             Op::Push(serialized_sig),
             Op::Push(serialized_pub_key),
@@ -495,3 +809,364 @@ impl Output for P2PKHDropNOutput {
         Script::new(ops)
     }
 }
+
+// Amounts exchanged when accepting an offer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OfferAcceptAmounts {
+    pub buy_amount: u64,
+    pub token_amount: u64,
+    pub payment_amount: u64,
+    pub fee_amounts: Vec<u64>,
+    pub remaining_token_amount: u64,
+}
+
+// Dust outputs carrying the tokens of an accept, at the indices the SLP SEND assigns tokens
+// to, and the BCH the buyer has to fund on top of the tx fee.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OfferAcceptDust {
+    pub dust_amount: u64,
+    // covenant keeping the remaining tokens, partial accepts only
+    pub covenant_idx: Option<usize>,
+    pub buyer_token_idx: usize,
+    // payment, fees and dust outputs, minus the value of the spent covenant
+    pub buyer_funding: u64,
+}
+
+impl AdvancedTradeOffer {
+    pub fn accept_amounts(&self, spend_params: &AdvancedTradeOfferSpendParams)
+            -> Result<OfferAcceptAmounts, OfferError> {
+        use self::AdvancedTradeOfferSpendParams::*;
+        self.validate()?;
+        let buy_amount = match *spend_params {
+            AcceptFully => self.accept_fully_amount().unwrap(),
+            AcceptPartially { buy_amount } => buy_amount,
+            Cancel => return Err(OfferError::InvalidSpendParams),
+        };
+        if (self.is_inverted || self.price_denominator != 1) && !self._is_exact(buy_amount) {
+            return Err(OfferError::InvalidBuyAmount(buy_amount));
+        }
+        let (token_amount, payment_amount) = if self.is_inverted {
+            (buy_amount, self._convert(buy_amount))
+        } else {
+            (self._convert(buy_amount), buy_amount)
+        };
+        if buy_amount == 0 || token_amount == 0 || token_amount > self.sell_amount_token {
+            return Err(OfferError::InvalidBuyAmount(buy_amount));
+        }
+        Ok(OfferAcceptAmounts {
+            buy_amount,
+            token_amount,
+            payment_amount,
+            fee_amounts: self.fees.iter()
+                .map(|fee| (payment_amount / fee.divisor).max(self.dust_amount))
+                .collect(),
+            remaining_token_amount: self.sell_amount_token - token_amount,
+        })
+    }
+
+    pub fn accept_dust(&self, spend_params: &AdvancedTradeOfferSpendParams)
+            -> Result<OfferAcceptDust, OfferError> {
+        let amounts = self.accept_amounts(spend_params)?;
+        let is_partial = amounts.remaining_token_amount > 0;
+        let (covenant_idx, buyer_token_idx) = if is_partial { (Some(1), 3) } else { (None, 2) };
+        let num_dust_outputs = if is_partial { 2 } else { 1 };
+        let buyer_funding = amounts.fee_amounts.iter()
+            .try_fold(amounts.payment_amount, |sum, &fee_amount| sum.checked_add(fee_amount))
+            .and_then(|sum| sum.checked_add(self.dust_amount * num_dust_outputs))
+            .ok_or(OfferError::InvalidBuyAmount(amounts.buy_amount))?;
+        Ok(OfferAcceptDust {
+            dust_amount: self.dust_amount,
+            covenant_idx,
+            buyer_token_idx,
+            buyer_funding: buyer_funding.saturating_sub(self.value),
+        })
+    }
+}
+
+// Builds the transaction accepting `offer` with `spend_params` AcceptFully or
+// AcceptPartially, paid for with the buyer's P2PKH `buyer_utxos`. Outputs:
+// 0: SLP SEND
+// 1: covenant with the remaining tokens (partial accepts only)
+// next: payment to the seller
+// next: tokens to the buyer, then the buyer's change
+// last: fees (if the offer has fees)
+// With `is_schnorr`, the fee is sized for Schnorr signatures, see UnsignedTx::set_schnorr.
+// `buyer_utxos` need at least the buyer_funding of accept_dust plus the tx fee.
+pub fn accept_trade_offer(offer: AdvancedTradeOffer,
+                          spend_params: AdvancedTradeOfferSpendParams,
+                          offer_outpoint: TxOutpoint,
+                          buyer_utxos: &[UtxoEntry],
+                          buyer_address: Address,
+                          fee_per_kb: u64,
+                          is_schnorr: bool) -> Result<UnsignedTx, OfferError> {
+    let amounts = offer.accept_amounts(&spend_params)?;
+    let dust = offer.accept_dust(&spend_params)?;
+    let is_partial = dust.covenant_idx.is_some();
+    let mut tx_build = UnsignedTx::new_simple();
+    tx_build.set_schnorr(is_schnorr);
+    add_p2pkh_inputs(&mut tx_build, buyer_utxos, &buyer_address);
+    let output_quantities = if is_partial {
+        vec![amounts.remaining_token_amount, 0, amounts.token_amount]
+    } else {
+        vec![0, amounts.token_amount]
+    };
+    tx_build.add_output(SLPSend {
+        token_type: offer.token_type,
+        token_id: offer.token_id,
+        output_quantities,
+    }.into_output().map_err(|_| OfferError::InvalidSpendParams)?.to_output());
+    if is_partial {
+        tx_build.add_output(AdvancedTradeOffer {
+            value: offer.dust_amount,
+            sell_amount_token: amounts.remaining_token_amount,
+            ..offer.clone()
+        }.to_p2sh_output());
+    }
+    tx_build.add_output(P2PKHOutput {
+        value: amounts.payment_amount,
+        address: offer.address.clone(),
+    }.to_output());
+    let buyer_token_idx = tx_build.add_output(P2PKHOutput {
+        value: dust.dust_amount,
+        address: buyer_address.clone(),
+    }.to_output());
+    if buyer_token_idx != dust.buyer_token_idx {
+        return Err(OfferError::UnexpectedOutputIdx { expected: dust.buyer_token_idx, actual: buyer_token_idx });
+    }
+    let change_idx = tx_build.outputs().len();
+    for (fee, &fee_amount) in offer.fees.iter().zip(amounts.fee_amounts.iter()) {
+        tx_build.add_output(P2PKHOutput {
+            value: fee_amount,
+            address: fee.address.clone(),
+        }.to_output());
+    }
+    let dust_amount = offer.dust_amount;
+    let spend = offer.spend(spend_params)?;
+    tx_build.insert_input(0, UnsignedInput::new(
+        offer_outpoint,
+        Box::new(P2SHOutput {
+            output: Box::new(spend),
+        }),
+        0xffff_ffff,
+    ));
+    tx_build.insert_leftover_output(change_idx, buyer_address, fee_per_kb, dust_amount)
+        .map_err(|err| OfferError::InsufficientFunds(err.missing_amount()))?;
+    Ok(tx_build)
+}
+
+// Builds the transaction withdrawing `offer`, sending its tokens back to the
+// seller. The covenant input only carries dust, so `seller_utxos` fund the fee.
+// Outputs:
+// 0: SLP SEND
+// 1: tokens to the seller
+// 2: the seller's change
+pub fn cancel_trade_offer(offer: AdvancedTradeOffer,
+                          offer_outpoint: TxOutpoint,
+                          seller_utxos: &[UtxoEntry],
+                          fee_per_kb: u64,
+                          is_schnorr: bool) -> Result<UnsignedTx, OfferError> {
+    offer.validate()?;
+    let seller_address = offer.address.clone();
+    let dust_amount = offer.dust_amount;
+    let mut tx_build = UnsignedTx::new_simple();
+    tx_build.set_schnorr(is_schnorr);
+    tx_build.add_input(UnsignedInput::new(
+        offer_outpoint,
+        Box::new(P2SHOutput {
+            output: Box::new(offer.spend(AdvancedTradeOfferSpendParams::Cancel)?),
+        }),
+        0xffff_ffff,
+    ));
+    add_p2pkh_inputs(&mut tx_build, seller_utxos, &seller_address);
+    tx_build.add_output(SLPSend {
+        token_type: offer.token_type,
+        token_id: offer.token_id,
+        output_quantities: vec![offer.sell_amount_token],
+    }.into_output().map_err(|_| OfferError::InvalidSpendParams)?.to_output());
+    tx_build.add_output(P2PKHOutput {
+        value: dust_amount,
+        address: seller_address.clone(),
+    }.to_output());
+    tx_build.add_leftover_output(seller_address, fee_per_kb, dust_amount)
+        .map_err(|err| OfferError::InsufficientFunds(err.missing_amount()))?;
+    Ok(tx_build)
+}
+
+// Follows an offer through a chain of partial accepts.
+#[derive(Clone, Debug)]
+pub struct OfferState {
+    offer: AdvancedTradeOffer,
+    outpoint: Option<TxOutpoint>,
+}
+
+impl OfferState {
+    pub fn new(offer: AdvancedTradeOffer, outpoint: TxOutpoint) -> Self {
+        OfferState {
+            offer,
+            outpoint: Some(outpoint),
+        }
+    }
+
+    // Applies a transaction spending the current covenant output. Full accepts and
+    // cancels close the offer.
+    pub fn apply_tx(&mut self, tx: &Tx) -> Result<(), OfferError> {
+        let outpoint = self.outpoint.as_ref().ok_or(OfferError::OfferClosed)?;
+        if !tx.inputs().iter().any(|input| &input.outpoint == outpoint) {
+            return Err(OfferError::OfferNotSpent);
+        }
+        let remaining = tx.outputs().first()
+            .and_then(|output| SLPMessage::parse(&output.script).ok())
+            .and_then(|message| match message {
+                SLPMessage::Send(send) if send.token_id == self.offer.token_id &&
+                                          send.output_quantities.len() == 3 => {
+                    Some(send.output_quantities[0])
+                },
+                _ => None,
+            });
+        match (remaining, tx.outputs().get(1)) {
+            (Some(remaining), Some(covenant_output)) if remaining > 0 => {
+                let next_offer = AdvancedTradeOffer {
+                    value: covenant_output.value,
+                    sell_amount_token: remaining,
+                    ..self.offer.clone()
+                };
+                let expected_script = next_offer.to_p2sh_output().script;
+                if covenant_output.script.to_vec() != expected_script.to_vec() {
+                    self.outpoint = None;
+                    return Ok(());
+                }
+                self.offer = next_offer;
+                self.outpoint = Some(TxOutpoint { tx_hash: tx.hash(), vout: 1 });
+            },
+            _ => self.outpoint = None,
+        }
+        Ok(())
+    }
+
+    pub fn apply_txs<'a>(&mut self, txs: impl IntoIterator<Item=&'a Tx>) -> Result<(), OfferError> {
+        for tx in txs {
+            self.apply_tx(tx)?;
+        }
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.outpoint.is_none()
+    }
+
+    pub fn remaining_sell_amount(&self) -> u64 {
+        if self.is_closed() { 0 } else { self.offer.sell_amount_token }
+    }
+
+    pub fn outpoint(&self) -> Option<&TxOutpoint> {
+        self.outpoint.as_ref()
+    }
+
+    // The open offer, to be spent by the next taker.
+    pub fn offer(&self) -> Option<&AdvancedTradeOffer> {
+        if self.is_closed() { None } else { Some(&self.offer) }
+    }
+}
+
+#[cfg(all(test, feature = "secp256k1"))]
+mod tests {
+    use super::*;
+    use crate::crypto::Crypto;
+    use crate::crypto::secp256k1::CryptoSecp256k1;
+    use crate::script_debugger::ScriptDebugger;
+    use crate::tx::tx_hash_to_hex;
+
+    fn utxo(amount: u64) -> UtxoEntry {
+        UtxoEntry { tx_id_hex: tx_hash_to_hex(&[0x55; 32]), amount, ..UtxoEntry::default() }
+    }
+
+    #[test]
+    fn test_accept_and_cancel() {
+        use self::AdvancedTradeOfferSpendParams::*;
+        let crypto = CryptoSecp256k1::new();
+        let seller_key = secp256k1::SecretKey::from_slice(&[2; 32]).unwrap();
+        let buyer_key = secp256k1::SecretKey::from_slice(&[3; 32]).unwrap();
+        let address = |key| Address::from_serialized_pub_key(
+            "bitcoincash", AddressType::P2PKH, &crypto.secret_to_pub_key(key).serialize());
+        let (seller, buyer) = (address(&seller_key), address(&buyer_key));
+        let builder = |sell_amount_token, price| AdvancedTradeOfferBuilder::new(
+            b"EXCH".to_vec(), [0x22; 32], sell_amount_token, price, seller.clone());
+        let offers = vec![
+            builder(100, 1000).build().unwrap(),
+            builder(100, 1000)
+                .fee(Address::from_bytes(AddressType::P2PKH, [0x44; 20]), 100)
+                .fee(Address::from_bytes(AddressType::P2PKH, [0x66; 20]), 50)
+                .build().unwrap(),
+            builder(100, 1).price_fraction(3, 2).build().unwrap(),
+            builder(100_000, 10).inverted(true).build().unwrap(),
+        ];
+        let outpoint = TxOutpoint { tx_hash: [0x11; 32], vout: 1 };
+        for offer in offers {
+            assert_eq!(AdvancedTradeOffer::from_redeem_script(&offer.script()).unwrap().script().to_vec(),
+                       offer.script().to_vec());
+            let accept_fully_amount = offer.accept_fully_amount().unwrap();
+            let partial = AcceptPartially { buy_amount: accept_fully_amount * 2 / 5 };
+            for spend_params in [partial, AcceptFully] {
+                let dust = offer.accept_dust(&spend_params).unwrap();
+                let tx = accept_trade_offer(offer.clone(), spend_params.clone(), outpoint.clone(),
+                                            &[utxo(dust.buyer_funding + 10_000)], buyer.clone(), 1000, false)
+                    .unwrap()
+                    .sign_with(&crypto, &buyer_key)
+                    .unwrap();
+                let spent_output = offer.to_p2sh_output();
+                assert_eq!(ScriptDebugger::new(&crypto, &tx, 0, &spent_output).run(), Ok(()));
+                assert_eq!(AdvancedTradeOffer::from_p2sh_sig_script(&tx.inputs()[0].script).unwrap().script().to_vec(),
+                           offer.script().to_vec());
+
+                // the remaining covenant of a partial accept can be accepted again
+                let mut state = OfferState::new(offer.clone(), outpoint.clone());
+                state.apply_tx(&tx).unwrap();
+                if spend_params == AcceptFully {
+                    assert!(state.is_closed());
+                    continue;
+                }
+                assert_eq!(state.remaining_sell_amount(), offer.sell_amount_token * 3 / 5);
+                let next_offer = state.offer().unwrap().clone();
+                let dust = next_offer.accept_dust(&AcceptFully).unwrap();
+                let next_tx = accept_trade_offer(next_offer.clone(), AcceptFully, state.outpoint().unwrap().clone(),
+                                                 &[utxo(dust.buyer_funding + 10_000)], buyer.clone(), 1000, false)
+                    .unwrap()
+                    .sign_with(&crypto, &buyer_key)
+                    .unwrap();
+                assert_eq!(ScriptDebugger::new(&crypto, &next_tx, 0, &tx.outputs()[1]).run(), Ok(()));
+            }
+            let tx = cancel_trade_offer(offer.clone(), outpoint.clone(), &[utxo(10_000)], 1000, false)
+                .unwrap()
+                .sign_with(&crypto, &seller_key)
+                .unwrap();
+            assert_eq!(ScriptDebugger::new(&crypto, &tx, 0, &offer.to_p2sh_output()).run(), Ok(()));
+        }
+
+        // out of range values are an error rather than a panic
+        let offer = AdvancedTradeOffer { price: u64::MAX, ..builder(100, 1000).build().unwrap() };
+        assert!(matches!(offer.spend(Cancel), Err(OfferError::ValueOutOfRange { .. })));
+        assert!(accept_trade_offer(offer, AcceptFully, outpoint, &[utxo(1_000_000)], buyer, 1000, false).is_err());
+    }
+
+    #[test]
+    fn test_p2pkh_drop_n() {
+        let crypto = CryptoSecp256k1::new();
+        let key = secp256k1::SecretKey::from_slice(&[2; 32]).unwrap();
+        let address = Address::from_serialized_pub_key(
+            "bitcoincash", AddressType::P2PKH, &crypto.secret_to_pub_key(&key).serialize());
+        let output = P2PKHDropNOutput { value: 10_000, address: address.clone(), drop_number: 2 };
+        let push_data = |n| P2PKHDropNSpendParams { push_data: vec![b"data".to_vec(); n] };
+        assert_eq!(output.spend(push_data(1)).err(),
+                   Some(OfferError::InvalidPushDataCount { expected: 2, actual: 1 }));
+        let mut tx_build = UnsignedTx::new_simple();
+        tx_build.add_input(UnsignedInput::new(
+            TxOutpoint { tx_hash: [0x11; 32], vout: 0 },
+            Box::new(output.spend(push_data(2)).unwrap()),
+            0xffff_ffff,
+        ));
+        tx_build.add_output(P2PKHOutput { value: 9_000, address }.to_output());
+        let tx = tx_build.sign_with(&crypto, &key).unwrap();
+        let spent_output = TxOutput { value: output.value, script: output.script(), token: None };
+        assert_eq!(ScriptDebugger::new(&crypto, &tx, 0, &spent_output).run(), Ok(()));
+    }
+}
@@ -0,0 +1,127 @@
+use crate::address::{Address, AddressType};
+use crate::hash::single_sha256;
+use crate::outputs::P2PKHOutput;
+use crate::script::{Script, Op, OpCodeType};
+use crate::tx::Tx;
+use crate::unsigned_tx::{Output, UnsignedTx};
+use crate::crypto::Crypto;
+use crate::wallet::{Wallet, WalletError, UtxoEntry, SendOptions};
+
+use async_trait::async_trait;
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BackendError {
+    Io(String),
+    Rpc { code: i64, message: String },
+    InvalidResponse(String),
+    Http(u16),
+    Closed,
+    Unsupported,
+}
+
+// Current state of a script hash as reported by the backend; None if it has no history.
+pub type ScriptHashStatus = Option<[u8; 32]>;
+
+// Source of chain data, e.g. an Electrum server or an indexer.
+#[async_trait]
+pub trait ChainBackend {
+    async fn utxos(&self, script_hash: &[u8; 32]) -> Result<Vec<UtxoEntry>, BackendError>;
+
+    // Backends indexed by address instead of script hash override this.
+    async fn address_utxos(&self, address: &Address) -> Result<Vec<UtxoEntry>, BackendError> {
+        self.utxos(&address_script_hash(address)).await
+    }
+
+    async fn tx(&self, tx_hash: &[u8; 32]) -> Result<Tx, BackendError>;
+
+    // Height of the current chain tip.
+    async fn chain_height(&self) -> Result<u32, BackendError> {
+        Err(BackendError::Unsupported)
+    }
+
+    // Returns the tx hash of the broadcast tx.
+    async fn broadcast(&self, tx: &Tx) -> Result<[u8; 32], BackendError>;
+
+    // Subscribes to changes of the script hash and returns its current status.
+    async fn subscribe(&self, script_hash: &[u8; 32]) -> Result<ScriptHashStatus, BackendError>;
+
+    // Waits until one of the subscribed script hashes changes.
+    async fn next_notification(&self) -> Result<([u8; 32], ScriptHashStatus), BackendError>;
+}
+
+// Electrum-style script hash: the sha256 of the output script.
+pub fn script_hash(script: &Script) -> [u8; 32] {
+    single_sha256(&script.to_vec())
+}
+
+// Locking script paying to `address`.
+pub fn address_script(address: &Address) -> Script {
+    match address.addr_type() {
+        AddressType::P2PKH => P2PKHOutput { value: 0, address: address.clone() }.script(),
+        AddressType::P2SH => Script::new(vec![
+            Op::Code(OpCodeType::OpHash160),
+            Op::Push(address.bytes().to_vec()),
+            Op::Code(OpCodeType::OpEqual),
+        ]),
+    }
+}
+
+pub fn address_script_hash(address: &Address) -> [u8; 32] {
+    script_hash(&address_script(address))
+}
+
+impl From<BackendError> for WalletError {
+    fn from(err: BackendError) -> Self {
+        WalletError::Backend(err)
+    }
+}
+
+impl Wallet {
+    pub async fn fetch_utxos<B: ChainBackend + Sync>(&self, backend: &B)
+            -> Result<Vec<UtxoEntry>, BackendError> {
+        backend.address_utxos(self.address()).await
+    }
+
+    // Sets the chain height used for anti-fee-sniping to the backend's tip.
+    pub async fn update_chain_height<B: ChainBackend + Sync>(&mut self, backend: &B)
+            -> Result<u32, BackendError> {
+        let chain_height = backend.chain_height().await?;
+        self.set_chain_height(Some(chain_height));
+        Ok(chain_height)
+    }
+
+    // Balance of all spendable UTXOs, i.e. neither frozen nor filtered as dust.
+    pub async fn refresh_balance<B: ChainBackend + Sync>(&self, backend: &B)
+            -> Result<u64, BackendError> {
+        let utxos = self.fetch_utxos(backend).await?;
+        Ok(self.spendable_utxos(&utxos).iter().map(|utxo| utxo.amount).sum())
+    }
+
+    // Like send_to_address, but spends the UTXOs currently reported by the backend.
+    pub async fn send_to_address_async<B: ChainBackend + Sync>(&self,
+                                                               backend: &B,
+                                                               address: Address,
+                                                               amount: u64,
+                                                               options: SendOptions)
+            -> Result<UnsignedTx, WalletError> {
+        let utxos = self.fetch_utxos(backend).await?;
+        self.send_to_address(address, amount, &utxos, options)
+    }
+
+    pub async fn broadcast<B: ChainBackend + Sync>(&self, backend: &B, tx: &Tx)
+            -> Result<[u8; 32], BackendError> {
+        backend.broadcast(tx).await
+    }
+
+    // Signs the tx with the wallet's key and broadcasts it, returning the tx hash.
+    pub async fn sign_and_broadcast<B: ChainBackend + Sync, C: Crypto>(&self,
+                                                                      backend: &B,
+                                                                      crypto: &C,
+                                                                      tx_build: &UnsignedTx,
+                                                                      secret_key: &C::SecretKey)
+            -> Result<[u8; 32], WalletError> {
+        let tx = self.sign(crypto, tx_build, secret_key)?;
+        Ok(self.broadcast(backend, &tx).await?)
+    }
+}
@@ -0,0 +1,216 @@
+use crate::hash::single_sha256;
+use crate::op_return::{OpReturnBuilder, OpReturnError};
+use crate::outputs::OpReturnOutput;
+use crate::script::{Script, Op, OpCodeType};
+use crate::tx::tx_hex_to_hash;
+
+use serde_json::Value;
+
+use std::collections::BTreeMap;
+
+
+// Bitcoin Cash Metadata Registries: an identity announces a registry on chain with
+// OP_RETURN <'BCMR'> <sha256 of the registry> [<uri>...], and the registry JSON maps token
+// categories to names, symbols and icons.
+
+pub const BCMR_LOKAD_ID: &[u8] = b"BCMR";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BCMRError {
+    NotBCMR,
+    MalformedAnnouncement,
+    InvalidJson(String),
+    MissingField(&'static str),
+    InvalidCategory(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BCMRAnnouncement {
+    pub content_hash: [u8; 32],
+    // Without a scheme, "https://" is implied.
+    pub uris: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BCMRToken {
+    pub category: [u8; 32],
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+// One snapshot of an identity, valid from `timestamp` (ISO 8601) on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BCMRSnapshot {
+    pub authbase: [u8; 32],
+    pub timestamp: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub token: Option<BCMRToken>,
+    // e.g. "icon", "web"
+    pub uris: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BCMRRegistry {
+    pub snapshots: Vec<BCMRSnapshot>,
+}
+
+impl BCMRAnnouncement {
+    pub fn parse(script: &Script) -> Result<Self, BCMRError> {
+        let ops = script.ops();
+        if ops.first() != Some(&Op::Code(OpCodeType::OpReturn)) {
+            return Err(BCMRError::NotBCMR);
+        }
+        let pushes = ops[1..].iter()
+            .map(|op| match op {
+                Op::Push(push) => Ok(push.as_slice()),
+                Op::Code(_) => Err(BCMRError::MalformedAnnouncement),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if pushes.first() != Some(&BCMR_LOKAD_ID) {
+            return Err(BCMRError::NotBCMR);
+        }
+        if pushes.len() < 2 || pushes[1].len() != 32 {
+            return Err(BCMRError::MalformedAnnouncement);
+        }
+        let mut content_hash = [0; 32];
+        content_hash.copy_from_slice(pushes[1]);
+        let uris = pushes[2..].iter()
+            .map(|uri| String::from_utf8(uri.to_vec()).map_err(|_| BCMRError::MalformedAnnouncement))
+            .collect::<Result<_, _>>()?;
+        Ok(BCMRAnnouncement { content_hash, uris })
+    }
+
+    pub fn into_output(self) -> Result<OpReturnOutput, OpReturnError> {
+        let mut builder = OpReturnBuilder::with_lokad_id(BCMR_LOKAD_ID)?;
+        builder.push(self.content_hash.to_vec());
+        for uri in self.uris {
+            builder.push(uri.into_bytes());
+        }
+        builder.build()
+    }
+
+    // URLs to fetch the registry from, with the implied scheme added.
+    pub fn urls(&self) -> Vec<String> {
+        self.uris.iter()
+            .map(|uri| if uri.contains("://") { uri.clone() } else { format!("https://{}", uri) })
+            .collect()
+    }
+
+    // Whether fetched registry contents are the announced ones.
+    pub fn verify_content(&self, content: &[u8]) -> bool {
+        single_sha256(content) == self.content_hash
+    }
+}
+
+fn category_from_hex(hex: &str) -> Result<[u8; 32], BCMRError> {
+    tx_hex_to_hash(hex).ok_or_else(|| BCMRError::InvalidCategory(hex.to_string()))
+}
+
+fn parse_token(value: &Value) -> Result<BCMRToken, BCMRError> {
+    let category = value["category"].as_str().ok_or(BCMRError::MissingField("category"))?;
+    Ok(BCMRToken {
+        category: category_from_hex(category)?,
+        symbol: value["symbol"].as_str().ok_or(BCMRError::MissingField("symbol"))?.to_string(),
+        decimals: value["decimals"].as_u64().unwrap_or(0).min(u8::MAX as u64) as u8,
+    })
+}
+
+impl BCMRRegistry {
+    // Parses the "identities" of a registry, ignoring fields this crate doesn't use (NFT
+    // types, extensions, the registry's own identity).
+    pub fn parse(json: &str) -> Result<Self, BCMRError> {
+        let registry: Value = serde_json::from_str(json)
+            .map_err(|err| BCMRError::InvalidJson(err.to_string()))?;
+        let identities = registry["identities"].as_object()
+            .ok_or(BCMRError::MissingField("identities"))?;
+        let mut snapshots = Vec::new();
+        for (authbase, history) in identities {
+            let authbase = category_from_hex(authbase)?;
+            let history = history.as_object().ok_or(BCMRError::MissingField("identities"))?;
+            for (timestamp, snapshot) in history {
+                let uris = match snapshot["uris"].as_object() {
+                    Some(uris) => uris.iter()
+                        .filter_map(|(kind, uri)| Some((kind.clone(), uri.as_str()?.to_string())))
+                        .collect(),
+                    None => BTreeMap::new(),
+                };
+                snapshots.push(BCMRSnapshot {
+                    authbase,
+                    timestamp: timestamp.clone(),
+                    name: snapshot["name"].as_str().ok_or(BCMRError::MissingField("name"))?.to_string(),
+                    description: snapshot["description"].as_str().map(str::to_string),
+                    token: match &snapshot["token"] {
+                        Value::Null => None,
+                        token => Some(parse_token(token)?),
+                    },
+                    uris,
+                });
+            }
+        }
+        Ok(BCMRRegistry { snapshots })
+    }
+
+    // Latest snapshot describing the token category.
+    pub fn token_metadata(&self, category: &[u8; 32]) -> Option<&BCMRSnapshot> {
+        self.snapshots.iter()
+            .filter(|snapshot| snapshot.token.as_ref().map(|token| &token.category) == Some(category))
+            .max_by(|a, b| a.timestamp.cmp(&b.timestamp))
+    }
+}
+
+impl BCMRSnapshot {
+    pub fn icon_uri(&self) -> Option<&str> {
+        self.uris.get("icon").map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tx::tx_hash_to_hex;
+    use crate::unsigned_tx::Output;
+
+    #[test]
+    fn test_bcmr() {
+        let registry_json = r#"{
+            "version": {"major": 1, "minor": 0, "patch": 0},
+            "latestRevision": "2023-06-01T00:00:00.000Z",
+            "registryIdentity": {"name": "Example Registry"},
+            "identities": {
+                "8c2e0a2a4b6b2e3cd5c4f19c86c0b3ef05d6d8de1d4e20d26c56f4c0a6dd2a31": {
+                    "2023-01-01T00:00:00.000Z": {
+                        "name": "Old Name",
+                        "token": {"category": "8c2e0a2a4b6b2e3cd5c4f19c86c0b3ef05d6d8de1d4e20d26c56f4c0a6dd2a31",
+                                  "symbol": "OLD", "decimals": 2}
+                    },
+                    "2023-06-01T00:00:00.000Z": {
+                        "name": "Example Token",
+                        "description": "For testing",
+                        "token": {"category": "8c2e0a2a4b6b2e3cd5c4f19c86c0b3ef05d6d8de1d4e20d26c56f4c0a6dd2a31",
+                                  "symbol": "XMPL", "decimals": 8},
+                        "uris": {"icon": "ipfs://bafybeiexample", "web": "https://example.com"}
+                    }
+                }
+            }
+        }"#;
+        let announcement = BCMRAnnouncement {
+            content_hash: single_sha256(registry_json.as_bytes()),
+            uris: vec!["example.com/bcmr.json".to_string(), "ipfs://bafybeiregistry".to_string()],
+        };
+        let script = announcement.clone().into_output().unwrap().script();
+        let parsed = BCMRAnnouncement::parse(&Script::from_serialized(&script.to_vec()).unwrap()).unwrap();
+        assert_eq!(parsed, announcement);
+        assert_eq!(parsed.urls(), vec!["https://example.com/bcmr.json", "ipfs://bafybeiregistry"]);
+        assert!(parsed.verify_content(registry_json.as_bytes()));
+
+        let registry = BCMRRegistry::parse(registry_json).unwrap();
+        let category = category_from_hex("8c2e0a2a4b6b2e3cd5c4f19c86c0b3ef05d6d8de1d4e20d26c56f4c0a6dd2a31").unwrap();
+        let metadata = registry.token_metadata(&category).unwrap();
+        assert_eq!(metadata.name, "Example Token");
+        assert_eq!(metadata.token.as_ref().unwrap().symbol, "XMPL");
+        assert_eq!(metadata.icon_uri(), Some("ipfs://bafybeiexample"));
+        assert_eq!(tx_hash_to_hex(&metadata.authbase), tx_hash_to_hex(&category));
+        assert!(registry.token_metadata(&[0; 32]).is_none());
+    }
+}
@@ -0,0 +1,391 @@
+use crate::hash::{hash160, hmac_sha512};
+use crate::base58;
+use crate::base58::Error as Base58Error;
+
+use std::fmt;
+use std::str::FromStr;
+use secp256k1::{Secp256k1, Signing, Verification, SecretKey, PublicKey};
+
+
+const HARDENED_FLAG: u32 = 1 << 31;
+const MASTER_KEY_HMAC_KEY: &[u8] = b"Bitcoin seed";
+const XPRV_VERSION: [u8; 4] = [0x04, 0x88, 0xad, 0xe4];
+const XPUB_VERSION: [u8; 4] = [0x04, 0x88, 0xb2, 0x1e];
+const EXTENDED_KEY_SIZE: usize = 78;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Bip32Error {
+    InvalidSeedLength(usize),
+    InvalidKey,
+    InvalidChildNumber(u32),
+    HardenedFromPublic(ChildNumber),
+    InvalidPath(String),
+    InvalidExtendedKey,
+    Base58(base58::Error),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ChildNumber {
+    Normal(u32),
+    Hardened(u32),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct DerivationPath(Vec<ChildNumber>);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtendedPrivKey {
+    pub depth: u8,
+    pub parent_fingerprint: [u8; 4],
+    pub child_number: ChildNumber,
+    pub chain_code: [u8; 32],
+    pub secret_key: SecretKey,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtendedPubKey {
+    pub depth: u8,
+    pub parent_fingerprint: [u8; 4],
+    pub child_number: ChildNumber,
+    pub chain_code: [u8; 32],
+    pub public_key: PublicKey,
+}
+
+impl From<base58::Error> for Bip32Error {
+    fn from(err: base58::Error) -> Self {
+        Bip32Error::Base58(err)
+    }
+}
+
+impl From<secp256k1::Error> for Bip32Error {
+    fn from(_: secp256k1::Error) -> Self {
+        Bip32Error::InvalidKey
+    }
+}
+
+impl ChildNumber {
+    pub fn normal(index: u32) -> Result<Self, Bip32Error> {
+        if index & HARDENED_FLAG != 0 {
+            return Err(Bip32Error::InvalidChildNumber(index));
+        }
+        Ok(ChildNumber::Normal(index))
+    }
+
+    pub fn hardened(index: u32) -> Result<Self, Bip32Error> {
+        if index & HARDENED_FLAG != 0 {
+            return Err(Bip32Error::InvalidChildNumber(index));
+        }
+        Ok(ChildNumber::Hardened(index))
+    }
+
+    pub fn from_index(index: u32) -> Self {
+        if index & HARDENED_FLAG != 0 {
+            ChildNumber::Hardened(index & !HARDENED_FLAG)
+        } else {
+            ChildNumber::Normal(index)
+        }
+    }
+
+    // Index as serialized, i.e. with the hardened flag set for hardened children.
+    pub fn to_index(self) -> u32 {
+        match self {
+            ChildNumber::Normal(index) => index,
+            ChildNumber::Hardened(index) => index | HARDENED_FLAG,
+        }
+    }
+
+    pub fn is_hardened(self) -> bool {
+        matches!(self, ChildNumber::Hardened(_))
+    }
+}
+
+impl fmt::Display for ChildNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ChildNumber::Normal(index) => write!(f, "{}", index),
+            ChildNumber::Hardened(index) => write!(f, "{}'", index),
+        }
+    }
+}
+
+impl FromStr for ChildNumber {
+    type Err = Bip32Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Bip32Error::InvalidPath(s.to_string());
+        match s.strip_suffix('\'').or_else(|| s.strip_suffix('h')) {
+            Some(index) => ChildNumber::hardened(index.parse().map_err(|_| invalid())?),
+            None => ChildNumber::normal(s.parse().map_err(|_| invalid())?),
+        }
+    }
+}
+
+impl DerivationPath {
+    pub fn master() -> Self {
+        DerivationPath(Vec::new())
+    }
+
+    pub fn child(&self, child_number: ChildNumber) -> Self {
+        let mut path = self.0.clone();
+        path.push(child_number);
+        DerivationPath(path)
+    }
+
+    pub fn extend(&self, child_numbers: &[ChildNumber]) -> Self {
+        let mut path = self.0.clone();
+        path.extend_from_slice(child_numbers);
+        DerivationPath(path)
+    }
+
+    pub fn child_numbers(&self) -> &[ChildNumber] {
+        &self.0
+    }
+}
+
+impl From<Vec<ChildNumber>> for DerivationPath {
+    fn from(child_numbers: Vec<ChildNumber>) -> Self {
+        DerivationPath(child_numbers)
+    }
+}
+
+impl fmt::Display for DerivationPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "m")?;
+        for child_number in self.0.iter() {
+            write!(f, "/{}", child_number)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for DerivationPath {
+    type Err = Bip32Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('/');
+        if parts.next() != Some("m") {
+            return Err(Bip32Error::InvalidPath(s.to_string()));
+        }
+        Ok(DerivationPath(parts.map(ChildNumber::from_str).collect::<Result<_, _>>()?))
+    }
+}
+
+fn split_hmac(hmac: [u8; 64]) -> ([u8; 32], [u8; 32]) {
+    let mut tweak = [0; 32];
+    let mut chain_code = [0; 32];
+    tweak.copy_from_slice(&hmac[..32]);
+    chain_code.copy_from_slice(&hmac[32..]);
+    (tweak, chain_code)
+}
+
+fn write_extended_key(version: [u8; 4],
+                      depth: u8,
+                      parent_fingerprint: &[u8; 4],
+                      child_number: ChildNumber,
+                      chain_code: &[u8; 32],
+                      key: &[u8; 33]) -> String {
+    let mut vec = Vec::with_capacity(EXTENDED_KEY_SIZE);
+    vec.extend_from_slice(&version);
+    vec.push(depth);
+    vec.extend_from_slice(parent_fingerprint);
+    vec.extend_from_slice(&child_number.to_index().to_be_bytes());
+    vec.extend_from_slice(chain_code);
+    vec.extend_from_slice(key);
+    base58::check_encode_slice(&vec)
+}
+
+struct RawExtendedKey {
+    depth: u8,
+    parent_fingerprint: [u8; 4],
+    child_number: ChildNumber,
+    chain_code: [u8; 32],
+    key: Vec<u8>,
+}
+
+fn read_extended_key(s: &str, version: [u8; 4]) -> Result<RawExtendedKey, Bip32Error> {
+    let data = base58::from_check(s)?;
+    if data.len() != EXTENDED_KEY_SIZE {
+        return Err(Base58Error::InvalidLength(data.len()).into());
+    }
+    if data[..4] != version {
+        return Err(Base58Error::InvalidVersion(data[..4].to_vec()).into());
+    }
+    let mut parent_fingerprint = [0; 4];
+    let mut child_number = [0; 4];
+    let mut chain_code = [0; 32];
+    parent_fingerprint.copy_from_slice(&data[5..9]);
+    child_number.copy_from_slice(&data[9..13]);
+    chain_code.copy_from_slice(&data[13..45]);
+    Ok(RawExtendedKey {
+        depth: data[4],
+        parent_fingerprint,
+        child_number: ChildNumber::from_index(u32::from_be_bytes(child_number)),
+        chain_code,
+        key: data[45..].to_vec(),
+    })
+}
+
+impl ExtendedPrivKey {
+    pub fn new_master(seed: &[u8]) -> Result<Self, Bip32Error> {
+        if seed.len() < 16 || seed.len() > 64 {
+            return Err(Bip32Error::InvalidSeedLength(seed.len()));
+        }
+        let (secret_key, chain_code) = split_hmac(hmac_sha512(MASTER_KEY_HMAC_KEY, seed));
+        Ok(ExtendedPrivKey {
+            depth: 0,
+            parent_fingerprint: [0; 4],
+            child_number: ChildNumber::Normal(0),
+            chain_code,
+            secret_key: SecretKey::from_slice(&secret_key)?,
+        })
+    }
+
+    pub fn public_key<C: Signing>(&self, secp: &Secp256k1<C>) -> PublicKey {
+        PublicKey::from_secret_key(secp, &self.secret_key)
+    }
+
+    pub fn fingerprint<C: Signing>(&self, secp: &Secp256k1<C>) -> [u8; 4] {
+        ExtendedPubKey::from_priv(secp, self).fingerprint()
+    }
+
+    pub fn derive_child<C: Signing>(&self, secp: &Secp256k1<C>, child_number: ChildNumber)
+            -> Result<Self, Bip32Error> {
+        let mut data = Vec::with_capacity(37);
+        if child_number.is_hardened() {
+            data.push(0);
+            data.extend_from_slice(&self.secret_key[..]);
+        } else {
+            data.extend_from_slice(&self.public_key(secp).serialize());
+        }
+        data.extend_from_slice(&child_number.to_index().to_be_bytes());
+        let (tweak, chain_code) = split_hmac(hmac_sha512(&self.chain_code, &data));
+        let mut secret_key = self.secret_key;
+        secret_key.add_assign(&tweak)?;
+        Ok(ExtendedPrivKey {
+            depth: self.depth.checked_add(1).ok_or(Bip32Error::InvalidExtendedKey)?,
+            parent_fingerprint: self.fingerprint(secp),
+            child_number,
+            chain_code,
+            secret_key,
+        })
+    }
+
+    pub fn derive_path<C: Signing>(&self, secp: &Secp256k1<C>, path: &DerivationPath)
+            -> Result<Self, Bip32Error> {
+        let mut key = self.clone();
+        for &child_number in path.child_numbers() {
+            key = key.derive_child(secp, child_number)?;
+        }
+        Ok(key)
+    }
+
+    pub fn to_base58(&self) -> String {
+        let mut key = [0; 33];
+        key[1..].copy_from_slice(&self.secret_key[..]);
+        write_extended_key(XPRV_VERSION, self.depth, &self.parent_fingerprint,
+                           self.child_number, &self.chain_code, &key)
+    }
+
+    pub fn from_base58(s: &str) -> Result<Self, Bip32Error> {
+        let raw = read_extended_key(s, XPRV_VERSION)?;
+        if raw.key[0] != 0 {
+            return Err(Bip32Error::InvalidKey);
+        }
+        Ok(ExtendedPrivKey {
+            depth: raw.depth,
+            parent_fingerprint: raw.parent_fingerprint,
+            child_number: raw.child_number,
+            chain_code: raw.chain_code,
+            secret_key: SecretKey::from_slice(&raw.key[1..])?,
+        })
+    }
+}
+
+impl ExtendedPubKey {
+    pub fn from_priv<C: Signing>(secp: &Secp256k1<C>, xprv: &ExtendedPrivKey) -> Self {
+        ExtendedPubKey {
+            depth: xprv.depth,
+            parent_fingerprint: xprv.parent_fingerprint,
+            child_number: xprv.child_number,
+            chain_code: xprv.chain_code,
+            public_key: xprv.public_key(secp),
+        }
+    }
+
+    pub fn fingerprint(&self) -> [u8; 4] {
+        let mut fingerprint = [0; 4];
+        fingerprint.copy_from_slice(&hash160(&self.public_key.serialize())[..4]);
+        fingerprint
+    }
+
+    pub fn derive_child<C: Verification>(&self, secp: &Secp256k1<C>, child_number: ChildNumber)
+            -> Result<Self, Bip32Error> {
+        if child_number.is_hardened() {
+            return Err(Bip32Error::HardenedFromPublic(child_number));
+        }
+        let mut data = self.public_key.serialize().to_vec();
+        data.extend_from_slice(&child_number.to_index().to_be_bytes());
+        let (tweak, chain_code) = split_hmac(hmac_sha512(&self.chain_code, &data));
+        let mut public_key = self.public_key;
+        public_key.add_exp_assign(secp, &tweak)?;
+        Ok(ExtendedPubKey {
+            depth: self.depth.checked_add(1).ok_or(Bip32Error::InvalidExtendedKey)?,
+            parent_fingerprint: self.fingerprint(),
+            child_number,
+            chain_code,
+            public_key,
+        })
+    }
+
+    pub fn derive_path<C: Verification>(&self, secp: &Secp256k1<C>, path: &DerivationPath)
+            -> Result<Self, Bip32Error> {
+        let mut key = self.clone();
+        for &child_number in path.child_numbers() {
+            key = key.derive_child(secp, child_number)?;
+        }
+        Ok(key)
+    }
+
+    pub fn to_base58(&self) -> String {
+        write_extended_key(XPUB_VERSION, self.depth, &self.parent_fingerprint,
+                           self.child_number, &self.chain_code, &self.public_key.serialize())
+    }
+
+    pub fn from_base58(s: &str) -> Result<Self, Bip32Error> {
+        let raw = read_extended_key(s, XPUB_VERSION)?;
+        Ok(ExtendedPubKey {
+            depth: raw.depth,
+            parent_fingerprint: raw.parent_fingerprint,
+            child_number: raw.child_number,
+            chain_code: raw.chain_code,
+            public_key: PublicKey::from_slice(&raw.key)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vector_1() {
+        let secp = Secp256k1::new();
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let master = ExtendedPrivKey::new_master(&seed).unwrap();
+        assert_eq!(master.to_base58(),
+                   "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi");
+        let path: DerivationPath = "m/0'/1/2'/2".parse().unwrap();
+        assert_eq!(path.to_string(), "m/0'/1/2'/2");
+        let xprv = master.derive_path(&secp, &path).unwrap();
+        assert_eq!(xprv.to_base58(),
+                   "xprvA2JDeKCSNNZky6uBCviVfJSKyQ1mDYahRjijr5idH2WwLsEd4Hsb2Tyh8RfQMuPh7f7RtyzTtdrbdqqsunu5Mm3wDvUAKRHSC34sJ7in334");
+        let xpub = ExtendedPubKey::from_priv(&secp, &xprv);
+        assert_eq!(xpub.to_base58(),
+                   "xpub6FHa3pjLCk84BayeJxFW2SP4XRrFd1JYnxeLeU8EqN3vDfZmbqBqaGJAyiLjTAwm6ZLRQUMv1ZACTj37sR62cfN7fe5JnJ7dh8zL4fiyLHV");
+        assert_eq!(ExtendedPubKey::from_base58(&xpub.to_base58()), Ok(xpub.clone()));
+        assert_eq!(ExtendedPrivKey::from_base58(&xprv.to_base58()), Ok(xprv.clone()));
+        let child = ChildNumber::Normal(1_000_000_000);
+        assert_eq!(xpub.derive_child(&secp, child).unwrap(),
+                   ExtendedPubKey::from_priv(&secp, &xprv.derive_child(&secp, child).unwrap()));
+    }
+}
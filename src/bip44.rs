@@ -0,0 +1,197 @@
+use crate::bip32::{Bip32Error, ChildNumber, DerivationPath, ExtendedPrivKey, ExtendedPubKey};
+use crate::address::{Address, AddressType};
+
+use secp256k1::{Secp256k1, All, PublicKey};
+
+
+pub const BIP44_PURPOSE: u32 = 44;
+pub const SLP_ADDRESS_PREFIX: &str = "simpleledger";
+pub const BCH_ADDRESS_PREFIX: &str = "bitcoincash";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CoinType {
+    Bch,
+    Slp,
+    Other(u32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Chain {
+    Receive,
+    Change,
+}
+
+// m/44'/<coin_type>'/<account>'/<chain>/<index>
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Bip44Path {
+    pub coin_type: CoinType,
+    pub account: u32,
+    pub chain: Chain,
+    pub index: u32,
+}
+
+// Derives BIP44 keys and addresses from a master key for a single coin type.
+pub struct Bip44Keychain {
+    secp: Secp256k1<All>,
+    master: ExtendedPrivKey,
+    coin_type: CoinType,
+    prefix: String,
+}
+
+impl CoinType {
+    pub fn index(self) -> u32 {
+        match self {
+            CoinType::Bch => 145,
+            CoinType::Slp => 245,
+            CoinType::Other(index) => index,
+        }
+    }
+
+    pub fn default_prefix(self) -> &'static str {
+        match self {
+            CoinType::Slp => SLP_ADDRESS_PREFIX,
+            _ => BCH_ADDRESS_PREFIX,
+        }
+    }
+}
+
+impl Chain {
+    pub fn index(self) -> u32 {
+        match self {
+            Chain::Receive => 0,
+            Chain::Change => 1,
+        }
+    }
+
+    pub fn from_change(change: bool) -> Self {
+        if change { Chain::Change } else { Chain::Receive }
+    }
+}
+
+// m/44'/<coin_type>'/<account>'
+pub fn account_path(coin_type: CoinType, account: u32) -> Result<DerivationPath, Bip32Error> {
+    Ok(DerivationPath::from(vec![
+        ChildNumber::hardened(BIP44_PURPOSE)?,
+        ChildNumber::hardened(coin_type.index())?,
+        ChildNumber::hardened(account)?,
+    ]))
+}
+
+impl Bip44Path {
+    pub fn new(coin_type: CoinType, account: u32, chain: Chain, index: u32) -> Self {
+        Bip44Path { coin_type, account, chain, index }
+    }
+
+    pub fn account_path(&self) -> Result<DerivationPath, Bip32Error> {
+        account_path(self.coin_type, self.account)
+    }
+
+    // Path relative to the account key, i.e. <chain>/<index>.
+    pub fn relative_path(&self) -> Result<DerivationPath, Bip32Error> {
+        Ok(DerivationPath::from(vec![
+            ChildNumber::normal(self.chain.index())?,
+            ChildNumber::normal(self.index)?,
+        ]))
+    }
+
+    pub fn to_derivation_path(&self) -> Result<DerivationPath, Bip32Error> {
+        Ok(self.account_path()?.extend(self.relative_path()?.child_numbers()))
+    }
+}
+
+impl Bip44Keychain {
+    pub fn new(master: ExtendedPrivKey, coin_type: CoinType) -> Self {
+        Bip44Keychain {
+            secp: Secp256k1::new(),
+            master,
+            coin_type,
+            prefix: coin_type.default_prefix().to_string(),
+        }
+    }
+
+    pub fn from_seed(seed: &[u8], coin_type: CoinType) -> Result<Self, Bip32Error> {
+        Ok(Bip44Keychain::new(ExtendedPrivKey::new_master(seed)?, coin_type))
+    }
+
+    pub fn with_prefix(mut self, prefix: String) -> Self {
+        self.prefix = prefix;
+        self
+    }
+
+    pub fn coin_type(&self) -> CoinType {
+        self.coin_type
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn secp(&self) -> &Secp256k1<All> {
+        &self.secp
+    }
+
+    pub fn path(&self, account: u32, change: bool, index: u32) -> Bip44Path {
+        Bip44Path::new(self.coin_type, account, Chain::from_change(change), index)
+    }
+
+    pub fn account_xprv(&self, account: u32) -> Result<ExtendedPrivKey, Bip32Error> {
+        self.master.derive_path(&self.secp, &account_path(self.coin_type, account)?)
+    }
+
+    // Account xpub, which can derive all addresses of the account without the secret keys.
+    pub fn account_xpub(&self, account: u32) -> Result<ExtendedPubKey, Bip32Error> {
+        Ok(ExtendedPubKey::from_priv(&self.secp, &self.account_xprv(account)?))
+    }
+
+    pub fn derive_xprv(&self, account: u32, change: bool, index: u32)
+            -> Result<ExtendedPrivKey, Bip32Error> {
+        self.master.derive_path(&self.secp, &self.path(account, change, index).to_derivation_path()?)
+    }
+
+    pub fn derive_pub_key(&self, account: u32, change: bool, index: u32)
+            -> Result<PublicKey, Bip32Error> {
+        Ok(self.derive_xprv(account, change, index)?.public_key(&self.secp))
+    }
+
+    pub fn derive_address(&self, account: u32, change: bool, index: u32)
+            -> Result<Address, Bip32Error> {
+        let pub_key = self.derive_pub_key(account, change, index)?;
+        Ok(Address::from_serialized_pub_key(&self.prefix, AddressType::P2PKH, &pub_key.serialize()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mnemonic::Mnemonic;
+
+    #[test]
+    fn test_bch_addresses() {
+        let mnemonic = Mnemonic::parse("abandon abandon abandon abandon abandon abandon \
+                                        abandon abandon abandon abandon abandon about").unwrap();
+        let keychain = Bip44Keychain::from_seed(&mnemonic.to_seed(""), CoinType::Bch).unwrap();
+        // m/44'/145'/0'/0/i
+        let vectors = [
+            "02bbe7dbcdf8b2261530a867df7180b17a90b482f74f2736b8a30d3f756e42e217",
+            "0262f8bf1ab1d25111c569da0133c30f8b3fa411e2f8af2565d3f0111cb34ae7f6",
+            "03e3ec1540bc6f7731d99cbb02448c1fa323c29aaa258b9fde5f4f339f2f975547",
+        ];
+        let addresses = [
+            "bitcoincash:qqyx49mu0kkn9ftfj6hje6g2wfer34yfnq5tahq3q6",
+            "bitcoincash:qp8sfdhgjlq68hlzka9lcsxtcnvuvnd0xqxugfzzc5",
+            "bitcoincash:qqkuy34ntrye9a2h4xpdstcu4aq5wfrwscjtaphenr",
+        ];
+        let account_xpub = keychain.account_xpub(0).unwrap();
+        for (index, (pub_key, address)) in vectors.iter().zip(addresses.iter()).enumerate() {
+            let index = index as u32;
+            assert_eq!(keychain.path(0, false, index).to_derivation_path().unwrap().to_string(),
+                       format!("m/44'/145'/0'/0/{}", index));
+            assert_eq!(hex::encode(keychain.derive_pub_key(0, false, index).unwrap().serialize()), *pub_key);
+            assert_eq!(keychain.derive_address(0, false, index).unwrap().cash_addr(), *address);
+            // the account xpub derives the same keys without the secret keys
+            let relative_path = keychain.path(0, false, index).relative_path().unwrap();
+            let xpub = account_xpub.derive_path(keychain.secp(), &relative_path).unwrap();
+            assert_eq!(hex::encode(xpub.public_key.serialize()), *pub_key);
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use crate::hash::double_sha256;
+use crate::serialize::read_var_int_minimal;
+use crate::tx::Tx;
+
+use std::io;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use num::BigUint;
+
+
+pub const BLOCK_HEADER_SIZE: usize = 80;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockHeader {
+    pub version: i32,
+    pub prev_block_hash: [u8; 32],
+    pub merkle_root: [u8; 32],
+    pub time: u32,
+    pub bits: u32,
+    pub nonce: u32,
+}
+
+// Raw block whose txs are parsed one at a time from the underlying reader, so scanning a
+// block never holds more than one tx in memory.
+pub struct Block<R> {
+    header: BlockHeader,
+    num_txs: u64,
+    txs_read: u64,
+    read: R,
+}
+
+pub struct BlockTxs<'a, R> {
+    block: &'a mut Block<R>,
+}
+
+// Decodes the compact target encoding of `bits`: a base 256 exponent in the top byte and a
+// signed 23 bit mantissa. Negative and overflowing targets are None.
+pub fn bits_to_target(bits: u32) -> Option<BigUint> {
+    let exponent = bits >> 24;
+    let mantissa = bits & 0x007f_ffff;
+    if mantissa != 0 && bits & 0x0080_0000 != 0 {
+        return None;
+    }
+    if mantissa != 0 && (exponent > 34 ||
+                         (mantissa > 0xff && exponent > 33) ||
+                         (mantissa > 0xffff && exponent > 32)) {
+        return None;
+    }
+    Some(if exponent <= 3 {
+        BigUint::from(mantissa >> (8 * (3 - exponent)))
+    } else {
+        BigUint::from(mantissa) << (8 * (exponent - 3) as usize)
+    })
+}
+
+impl BlockHeader {
+    pub fn hash(&self) -> [u8; 32] {
+        let mut vec = Vec::new();
+        self.write_to_stream(&mut vec).unwrap();
+        double_sha256(&vec)
+    }
+
+    pub fn read_from_stream<R: io::Read>(read: &mut R) -> io::Result<Self> {
+        let version = read.read_i32::<LittleEndian>()?;
+        let mut prev_block_hash = [0; 32];
+        read.read_exact(&mut prev_block_hash)?;
+        let mut merkle_root = [0; 32];
+        read.read_exact(&mut merkle_root)?;
+        let time = read.read_u32::<LittleEndian>()?;
+        let bits = read.read_u32::<LittleEndian>()?;
+        let nonce = read.read_u32::<LittleEndian>()?;
+        Ok(BlockHeader { version, prev_block_hash, merkle_root, time, bits, nonce })
+    }
+
+    pub fn write_to_stream<W: io::Write>(&self, write: &mut W) -> io::Result<()> {
+        write.write_i32::<LittleEndian>(self.version)?;
+        write.write_all(&self.prev_block_hash)?;
+        write.write_all(&self.merkle_root)?;
+        write.write_u32::<LittleEndian>(self.time)?;
+        write.write_u32::<LittleEndian>(self.bits)?;
+        write.write_u32::<LittleEndian>(self.nonce)?;
+        Ok(())
+    }
+
+    pub fn target(&self) -> Option<BigUint> {
+        bits_to_target(self.bits)
+    }
+
+    // Whether the header hash, read as little endian number, meets its own target.
+    // Whether `bits` is the correct difficulty for the chain has to be checked by the caller.
+    pub fn check_pow(&self) -> bool {
+        match self.target() {
+            Some(target) if target != BigUint::from(0u32) => {
+                BigUint::from_bytes_le(&self.hash()) <= target
+            },
+            _ => false,
+        }
+    }
+}
+
+impl<R: io::Read> Block<R> {
+    // Reads the header and tx count; the txs are read by iterating over `txs`.
+    pub fn read_from_stream(mut read: R) -> io::Result<Self> {
+        let header = BlockHeader::read_from_stream(&mut read)?;
+        let num_txs = read_var_int_minimal(&mut read)?;
+        Ok(Block { header, num_txs, txs_read: 0, read })
+    }
+
+    pub fn header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    pub fn num_txs(&self) -> u64 {
+        self.num_txs
+    }
+
+    // Remaining txs of the block. Iteration stops after the first error.
+    pub fn txs(&mut self) -> BlockTxs<'_, R> {
+        BlockTxs { block: self }
+    }
+
+    // Gives back the reader, positioned after the txs read so far.
+    pub fn into_inner(self) -> R {
+        self.read
+    }
+}
+
+impl<'a, R: io::Read> Iterator for BlockTxs<'a, R> {
+    type Item = io::Result<Tx>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let block = &mut *self.block;
+        if block.txs_read >= block.num_txs {
+            return None;
+        }
+        let tx = Tx::read_from_stream(&mut block.read);
+        block.txs_read = if tx.is_ok() { block.txs_read + 1 } else { block.num_txs };
+        Some(tx)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.block.num_txs - self.block.txs_read) as usize;
+        (0, Some(remaining))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tx::tx_hash_to_hex;
+
+    #[test]
+    fn test_genesis_header() {
+        let raw = hex::decode("01000000000000000000000000000000000000000000000000000000000000000000\
+                               00003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a\
+                               29ab5f49ffff001d1dac2b7c").unwrap();
+        let header = BlockHeader::read_from_stream(&mut io::Cursor::new(&raw)).unwrap();
+        assert_eq!(header.time, 1231006505);
+        assert_eq!(header.bits, 0x1d00ffff);
+        assert_eq!(header.nonce, 2083236893);
+        assert_eq!(tx_hash_to_hex(&header.hash()),
+                   "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f");
+        let mut serialized = Vec::new();
+        header.write_to_stream(&mut serialized).unwrap();
+        assert_eq!(serialized, raw);
+        assert_eq!(header.target(), Some(BigUint::from(0xffffu32) << 208));
+        assert!(header.check_pow());
+        assert!(!BlockHeader { nonce: 0, ..header }.check_pow());
+        assert_eq!(bits_to_target(0x01803456), None);
+    }
+
+    #[test]
+    fn test_block_txs() {
+        let txs = (0..3).map(|lock_time| Tx::new(1, vec![], vec![], lock_time)).collect::<Vec<_>>();
+        let tx_hashes = txs.iter().map(Tx::hash).collect::<Vec<_>>();
+        let header = BlockHeader {
+            version: 1,
+            prev_block_hash: [0; 32],
+            merkle_root: crate::merkle::merkle_root(&tx_hashes),
+            time: 0,
+            bits: 0x207fffff,
+            nonce: 0,
+        };
+        let mut raw = Vec::new();
+        header.write_to_stream(&mut raw).unwrap();
+        raw.push(txs.len() as u8);
+        for tx in txs.iter() {
+            tx.write_to_stream(&mut raw).unwrap();
+        }
+        let mut block = Block::read_from_stream(io::Cursor::new(&raw)).unwrap();
+        assert_eq!(block.header(), &header);
+        assert_eq!(block.num_txs(), 3);
+        let parsed = block.txs().map(|tx| tx.unwrap().hash()).collect::<Vec<_>>();
+        assert_eq!(parsed, tx_hashes);
+        assert!(block.txs().next().is_none());
+    }
+}
@@ -0,0 +1,201 @@
+use crate::script::{Op, OpCodeType, Script};
+use crate::serialize::{read_var_str_max, write_var_str};
+use crate::tx::{Tx, TxOutpoint};
+
+use std::io::{self, Read, Write};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+
+pub const MAX_BLOOM_FILTER_SIZE: usize = 36_000;
+pub const MAX_HASH_FUNCS: u32 = 50;
+
+// Which outpoints the node adds to the filter when an output matches.
+pub const BLOOM_UPDATE_NONE: u8 = 0;
+pub const BLOOM_UPDATE_ALL: u8 = 1;
+pub const BLOOM_UPDATE_P2PUBKEY_ONLY: u8 = 2;
+
+const HASH_SEED_FACTOR: u32 = 0xfba4_c795;
+
+// BIP37 bloom filter, as sent to peers in a filterload message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BloomFilter {
+    data: Vec<u8>,
+    num_hash_funcs: u32,
+    tweak: u32,
+    flags: u8,
+}
+
+fn murmur3(seed: u32, data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+    let mix = |k1: u32| k1.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+    let mut h1 = seed;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let k1 = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        h1 ^= mix(k1);
+        h1 = h1.rotate_left(13).wrapping_mul(5).wrapping_add(0xe654_6b64);
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        let k1 = tail.iter().rev().fold(0, |k1, &byte| (k1 << 8) | byte as u32);
+        h1 ^= mix(k1);
+    }
+    h1 ^= data.len() as u32;
+    h1 ^= h1 >> 16;
+    h1 = h1.wrapping_mul(0x85eb_ca6b);
+    h1 ^= h1 >> 13;
+    h1 = h1.wrapping_mul(0xc2b2_ae35);
+    h1 ^ (h1 >> 16)
+}
+
+fn is_p2pk_or_multisig(script: &Script) -> bool {
+    matches!(script.ops(),
+             [Op::Push(_), Op::Code(OpCodeType::OpCheckSig)] |
+             [.., Op::Code(OpCodeType::OpCheckMultiSig)])
+}
+
+fn pushes(script: &Script) -> impl Iterator<Item=&[u8]> {
+    script.ops().iter().filter_map(|op| match op {
+        Op::Push(push) if !push.is_empty() => Some(push.as_slice()),
+        _ => None,
+    })
+}
+
+impl BloomFilter {
+    // Filter sized for `num_elements` elements with the given false positive rate,
+    // capped at the BIP37 limits.
+    pub fn new(num_elements: usize, fp_rate: f64, tweak: u32, flags: u8) -> Self {
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-1.0 / (ln2 * ln2) * num_elements as f64 * fp_rate.ln())
+            .min((MAX_BLOOM_FILTER_SIZE * 8) as f64) as usize;
+        let size = (num_bits / 8).max(1);
+        let num_hash_funcs = ((size * 8) as f64 / num_elements.max(1) as f64 * ln2) as u32;
+        BloomFilter {
+            data: vec![0; size],
+            num_hash_funcs: num_hash_funcs.clamp(1, MAX_HASH_FUNCS),
+            tweak,
+            flags,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn num_hash_funcs(&self) -> u32 {
+        self.num_hash_funcs
+    }
+
+    pub fn tweak(&self) -> u32 {
+        self.tweak
+    }
+
+    pub fn flags(&self) -> u8 {
+        self.flags
+    }
+
+    fn bit_indices<'a>(&'a self, element: &'a [u8]) -> impl Iterator<Item=usize> + 'a {
+        let num_bits = self.data.len() * 8;
+        (0..self.num_hash_funcs).map(move |i| {
+            let seed = i.wrapping_mul(HASH_SEED_FACTOR).wrapping_add(self.tweak);
+            murmur3(seed, element) as usize % num_bits
+        })
+    }
+
+    pub fn insert(&mut self, element: &[u8]) {
+        if self.data.is_empty() {
+            return;
+        }
+        let indices = self.bit_indices(element).collect::<Vec<_>>();
+        for idx in indices {
+            self.data[idx / 8] |= 1 << (idx % 8);
+        }
+    }
+
+    pub fn contains(&self, element: &[u8]) -> bool {
+        !self.data.is_empty() &&
+            self.bit_indices(element).all(|idx| self.data[idx / 8] & (1 << (idx % 8)) != 0)
+    }
+
+    pub fn insert_outpoint(&mut self, outpoint: &TxOutpoint) {
+        self.insert(&outpoint.bytes());
+    }
+
+    pub fn contains_outpoint(&self, outpoint: &TxOutpoint) -> bool {
+        self.contains(&outpoint.bytes())
+    }
+
+    pub fn insert_pub_key_hash(&mut self, pub_key_hash: &[u8; 20]) {
+        self.insert(pub_key_hash);
+    }
+
+    // Whether a node would relay `tx` for this filter, following BIP37: the tx hash, any
+    // push of an output script, a spent outpoint or any push of an input script matches.
+    // Like the node, outpoints of matching outputs are added to the filter according to
+    // its update flags, so later spends of them match too.
+    pub fn matches_tx(&mut self, tx: &Tx) -> bool {
+        let tx_hash = tx.hash();
+        let mut is_match = self.contains(&tx_hash);
+        for (vout, output) in tx.outputs().iter().enumerate() {
+            if !pushes(&output.script).any(|push| self.contains(push)) {
+                continue;
+            }
+            is_match = true;
+            let update = match self.flags & 3 {
+                BLOOM_UPDATE_ALL => true,
+                BLOOM_UPDATE_P2PUBKEY_ONLY => is_p2pk_or_multisig(&output.script),
+                _ => false,
+            };
+            if update {
+                self.insert_outpoint(&TxOutpoint { tx_hash, vout: vout as u32 });
+            }
+        }
+        if is_match {
+            return true;
+        }
+        tx.inputs().iter().any(|input| {
+            self.contains_outpoint(&input.outpoint) ||
+                pushes(input.script()).any(|push| self.contains(push))
+        })
+    }
+
+    pub fn read_from_stream<R: Read>(read: &mut R) -> io::Result<Self> {
+        let data = read_var_str_max(read, MAX_BLOOM_FILTER_SIZE)?;
+        let num_hash_funcs = read.read_u32::<LittleEndian>()?;
+        let tweak = read.read_u32::<LittleEndian>()?;
+        let flags = read.read_u8()?;
+        if data.len() > MAX_BLOOM_FILTER_SIZE || num_hash_funcs > MAX_HASH_FUNCS {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Bloom filter too large"));
+        }
+        Ok(BloomFilter { data, num_hash_funcs, tweak, flags })
+    }
+
+    // Payload of the filterload message.
+    pub fn write_to_stream<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        write_var_str(write, &self.data)?;
+        write.write_u32::<LittleEndian>(self.num_hash_funcs)?;
+        write.write_u32::<LittleEndian>(self.tweak)?;
+        write.write_u8(self.flags)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bloom_filter() {
+        let mut filter = BloomFilter::new(3, 0.01, 0, BLOOM_UPDATE_ALL);
+        let element = hex::decode("99108ad8ed9bb6274d3980bab5a85c048f0950c8").unwrap();
+        filter.insert(&element);
+        assert!(filter.contains(&element));
+        assert!(!filter.contains(&hex::decode("19108ad8ed9bb6274d3980bab5a85c048f0950c8").unwrap()));
+        filter.insert(&hex::decode("b5a2c786d9ef4658287ced5914b37a1b4aa32eee").unwrap());
+        filter.insert(&hex::decode("b9300670b4c5366e95b2699e8b18bc75e5f729c5").unwrap());
+        let mut serialized = Vec::new();
+        filter.write_to_stream(&mut serialized).unwrap();
+        assert_eq!(hex::encode(&serialized), "03614e9b050000000000000001");
+        assert_eq!(BloomFilter::read_from_stream(&mut io::Cursor::new(&serialized)).unwrap(), filter);
+    }
+}
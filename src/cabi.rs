@@ -0,0 +1,278 @@
+#![allow(clippy::missing_safety_doc)]
+
+use crate::address::{Address, AddressType};
+use crate::backend::address_script;
+use crate::outputs::P2PKHOutput;
+use crate::script::Script;
+use crate::tx::{TxOutpoint, TxOutput};
+use crate::unsigned_tx::{UnsignedTx, UnsignedInput};
+
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int};
+use std::slice;
+
+
+// Stable C interface for embedding in mobile apps, declared in include/cashcontracts.h.
+// Functions return CASH_OK or an error code. Pointers must be valid for the given lengths,
+// strings NUL terminated, and hashes 32 bytes in internal byte order. Output goes into caller
+// provided buffers: if a buffer is too small, CASH_ERR_BUFFER_TOO_SMALL is returned and
+// `written` holds the required length.
+
+pub const CASH_OK: c_int = 0;
+pub const CASH_ERR_NULL_POINTER: c_int = 1;
+pub const CASH_ERR_INVALID_ADDRESS: c_int = 2;
+pub const CASH_ERR_INVALID_SCRIPT: c_int = 3;
+pub const CASH_ERR_INVALID_INDEX: c_int = 4;
+pub const CASH_ERR_BUFFER_TOO_SMALL: c_int = 5;
+pub const CASH_ERR_MISSING_SIGNATURE: c_int = 6;
+pub const CASH_ERR_INVALID_SIGNATURE: c_int = 7;
+
+// Unsigned tx together with the signatures injected so far.
+pub struct CashTx {
+    tx: UnsignedTx,
+    signatures: Vec<Option<(Vec<u8>, Vec<u8>)>>,
+}
+
+unsafe fn address_from_c(cash_addr: *const c_char) -> Result<Address, c_int> {
+    if cash_addr.is_null() {
+        return Err(CASH_ERR_NULL_POINTER);
+    }
+    let cash_addr = CStr::from_ptr(cash_addr).to_str().map_err(|_| CASH_ERR_INVALID_ADDRESS)?;
+    Address::from_cash_addr(cash_addr.to_string()).map_err(|_| CASH_ERR_INVALID_ADDRESS)
+}
+
+unsafe fn bytes_from_c<'a>(data: *const u8, len: usize) -> Result<&'a [u8], c_int> {
+    if len == 0 {
+        return Ok(&[]);
+    }
+    if data.is_null() {
+        return Err(CASH_ERR_NULL_POINTER);
+    }
+    Ok(slice::from_raw_parts(data, len))
+}
+
+unsafe fn write_out(data: &[u8], out: *mut u8, out_len: usize, written: *mut usize) -> c_int {
+    if !written.is_null() {
+        *written = data.len();
+    }
+    if data.len() > out_len {
+        return CASH_ERR_BUFFER_TOO_SMALL;
+    }
+    if out.is_null() {
+        return CASH_ERR_NULL_POINTER;
+    }
+    slice::from_raw_parts_mut(out, data.len()).copy_from_slice(data);
+    CASH_OK
+}
+
+macro_rules! try_c {
+    ($expr:expr) => {
+        match $expr {
+            Ok(value) => value,
+            Err(code) => return code,
+        }
+    };
+}
+
+macro_rules! tx_mut {
+    ($tx:expr) => {
+        match $tx.as_mut() {
+            Some(tx) => tx,
+            None => return CASH_ERR_NULL_POINTER,
+        }
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn cash_tx_new(version: i32, lock_time: u32) -> *mut CashTx {
+    let mut tx = UnsignedTx::new_locktime(lock_time);
+    tx.set_version(version);
+    Box::into_raw(Box::new(CashTx { tx, signatures: Vec::new() }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn cash_tx_free(tx: *mut CashTx) {
+    if !tx.is_null() {
+        drop(Box::from_raw(tx));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn cash_tx_add_p2pkh_input(tx: *mut CashTx,
+                                                 tx_hash: *const u8,
+                                                 vout: u32,
+                                                 value: u64,
+                                                 cash_addr: *const c_char,
+                                                 sequence: u32) -> c_int {
+    let tx = tx_mut!(tx);
+    let hash = try_c!(bytes_from_c(tx_hash, 32));
+    let address = try_c!(address_from_c(cash_addr));
+    let mut outpoint = TxOutpoint { tx_hash: [0; 32], vout };
+    outpoint.tx_hash.copy_from_slice(hash);
+    tx.tx.add_input(UnsignedInput::new(
+        outpoint,
+        Box::new(P2PKHOutput { value, address }),
+        sequence,
+    ));
+    tx.signatures.push(None);
+    CASH_OK
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn cash_tx_add_output(tx: *mut CashTx,
+                                            value: u64,
+                                            script: *const u8,
+                                            script_len: usize) -> c_int {
+    let tx = tx_mut!(tx);
+    let script = try_c!(bytes_from_c(script, script_len));
+    let script = try_c!(Script::from_serialized(script).ok_or(CASH_ERR_INVALID_SCRIPT));
+    tx.tx.add_output(TxOutput::new(value, script));
+    CASH_OK
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn cash_tx_add_address_output(tx: *mut CashTx,
+                                                    value: u64,
+                                                    cash_addr: *const c_char) -> c_int {
+    let tx = tx_mut!(tx);
+    let address = try_c!(address_from_c(cash_addr));
+    tx.tx.add_output(TxOutput::new(value, address_script(&address)));
+    CASH_OK
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn cash_tx_num_inputs(tx: *const CashTx) -> usize {
+    tx.as_ref().map(|tx| tx.tx.inputs().len()).unwrap_or(0)
+}
+
+// Writes the 32 byte digest to sign of each input, one after another.
+#[no_mangle]
+pub unsafe extern "C" fn cash_tx_sighashes(tx: *const CashTx,
+                                           sighash_type: u32,
+                                           out: *mut u8,
+                                           out_len: usize,
+                                           written: *mut usize) -> c_int {
+    let tx = try_c!(tx.as_ref().ok_or(CASH_ERR_NULL_POINTER));
+    let sighashes = tx.tx.sighashes(sighash_type).concat();
+    write_out(&sighashes, out, out_len, written)
+}
+
+// Sets the DER signature (without sighash byte) and pub key of an input.
+#[no_mangle]
+pub unsafe extern "C" fn cash_tx_set_signature(tx: *mut CashTx,
+                                               input_idx: usize,
+                                               sig: *const u8,
+                                               sig_len: usize,
+                                               pub_key: *const u8,
+                                               pub_key_len: usize) -> c_int {
+    let tx = tx_mut!(tx);
+    let sig = try_c!(bytes_from_c(sig, sig_len)).to_vec();
+    let pub_key = try_c!(bytes_from_c(pub_key, pub_key_len)).to_vec();
+    match tx.signatures.get_mut(input_idx) {
+        Some(signature) => *signature = Some((sig, pub_key)),
+        None => return CASH_ERR_INVALID_INDEX,
+    }
+    CASH_OK
+}
+
+// Writes the serialized signed tx; every input needs a signature.
+#[no_mangle]
+pub unsafe extern "C" fn cash_tx_serialize(tx: *const CashTx,
+                                           out: *mut u8,
+                                           out_len: usize,
+                                           written: *mut usize) -> c_int {
+    let tx = try_c!(tx.as_ref().ok_or(CASH_ERR_NULL_POINTER));
+    let (sigs, pub_keys) = try_c!(tx.signatures.iter().cloned().collect::<Option<Vec<_>>>()
+        .ok_or(CASH_ERR_MISSING_SIGNATURE))
+        .into_iter()
+        .unzip();
+    let signed = try_c!(tx.tx.sign(sigs, pub_keys).map_err(|_| CASH_ERR_INVALID_SIGNATURE));
+    let mut serialized = Vec::new();
+    signed.write_to_stream(&mut serialized).unwrap();
+    write_out(&serialized, out, out_len, written)
+}
+
+// Writes the 20 byte hash of a cash address and sets `is_p2sh`.
+#[no_mangle]
+pub unsafe extern "C" fn cash_address_decode(cash_addr: *const c_char,
+                                             hash_out: *mut u8,
+                                             is_p2sh: *mut bool) -> c_int {
+    let address = try_c!(address_from_c(cash_addr));
+    if hash_out.is_null() || is_p2sh.is_null() {
+        return CASH_ERR_NULL_POINTER;
+    }
+    slice::from_raw_parts_mut(hash_out, 20).copy_from_slice(address.bytes());
+    *is_p2sh = address.addr_type() == AddressType::P2SH;
+    CASH_OK
+}
+
+// Writes the NUL terminated cash address of a 20 byte hash.
+#[no_mangle]
+pub unsafe extern "C" fn cash_address_encode(prefix: *const c_char,
+                                             hash: *const u8,
+                                             is_p2sh: bool,
+                                             out: *mut c_char,
+                                             out_len: usize,
+                                             written: *mut usize) -> c_int {
+    if prefix.is_null() {
+        return CASH_ERR_NULL_POINTER;
+    }
+    let prefix = try_c!(CStr::from_ptr(prefix).to_str().map_err(|_| CASH_ERR_INVALID_ADDRESS));
+    let hash = try_c!(bytes_from_c(hash, 20));
+    let addr_type = if is_p2sh { AddressType::P2SH } else { AddressType::P2PKH };
+    let address = try_c!(Address::from_slice_prefix(prefix, addr_type, hash).ok_or(CASH_ERR_INVALID_ADDRESS));
+    let mut cash_addr = address.cash_addr().as_bytes().to_vec();
+    cash_addr.push(0);
+    write_out(&cash_addr, out as *mut u8, out_len, written)
+}
+
+#[cfg(all(test, feature = "secp256k1"))]
+mod tests {
+    use super::*;
+    use crate::crypto::Crypto;
+    use crate::crypto::secp256k1::CryptoSecp256k1;
+    use crate::tx::Tx;
+    use crate::unsigned_tx::Output;
+    use std::ffi::CString;
+
+    #[test]
+    fn test_cabi_sign() {
+        let crypto = CryptoSecp256k1::new();
+        let secret_key = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let pub_key = crypto.secret_to_pub_key(&secret_key).serialize();
+        let address = Address::from_serialized_pub_key("bitcoincash", AddressType::P2PKH, &pub_key);
+        let cash_addr = CString::new(address.cash_addr()).unwrap();
+        unsafe {
+            let tx = cash_tx_new(2, 0);
+            assert_eq!(cash_tx_add_p2pkh_input(tx, [0x11; 32].as_ptr(), 0, 10_000,
+                                               cash_addr.as_ptr(), 0xffff_ffff), CASH_OK);
+            assert_eq!(cash_tx_add_address_output(tx, 9_000, cash_addr.as_ptr()), CASH_OK);
+            let mut sighash = [0; 32];
+            let mut written = 0;
+            assert_eq!(cash_tx_sighashes(tx, 0x41, sighash.as_mut_ptr(), 32, &mut written), CASH_OK);
+            let mut serialized = vec![0; 1000];
+            assert_eq!(cash_tx_serialize(tx, serialized.as_mut_ptr(), 1000, &mut written),
+                       CASH_ERR_MISSING_SIGNATURE);
+            let sig = crypto.sign(&sighash, &secret_key).serialize_der();
+            assert_eq!(cash_tx_set_signature(tx, 0, sig.as_ptr(), sig.len(), pub_key.as_ptr(), pub_key.len()),
+                       CASH_OK);
+            assert_eq!(cash_tx_serialize(tx, serialized.as_mut_ptr(), 10, &mut written),
+                       CASH_ERR_BUFFER_TOO_SMALL);
+            assert_eq!(cash_tx_serialize(tx, serialized.as_mut_ptr(), 1000, &mut written), CASH_OK);
+            let signed = Tx::read_from_stream(&mut &serialized[..written]).unwrap();
+            let spent_output = P2PKHOutput { value: 10_000, address: address.clone() }.to_output();
+            assert_eq!(signed.verify_input_signatures(&crypto, &[spent_output]), Ok(()));
+            cash_tx_free(tx);
+
+            let mut hash = [0; 20];
+            let mut is_p2sh = true;
+            assert_eq!(cash_address_decode(cash_addr.as_ptr(), hash.as_mut_ptr(), &mut is_p2sh), CASH_OK);
+            assert_eq!((&hash, is_p2sh), (address.bytes(), false));
+            let prefix = CString::new("bitcoincash").unwrap();
+            let mut encoded = [0 as c_char; 64];
+            assert_eq!(cash_address_encode(prefix.as_ptr(), hash.as_ptr(), false, encoded.as_mut_ptr(), 64,
+                                           &mut written), CASH_OK);
+            assert_eq!(CStr::from_ptr(encoded.as_ptr()).to_str(), Ok(address.cash_addr()));
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use crate::unsigned_tx::{Output, PreImage, UnsignedTx};
+use crate::script::Script;
+use crate::tx::TxOutput;
+use crate::serialize::{write_var_int, read_var_int_minimal};
+
+use std::io;
+use byteorder::{ReadBytesExt, WriteBytesExt};
+
+pub const PREFIX_TOKEN: u8 = 0xef;
+pub const TOKEN_MAX_COMMITMENT_LENGTH: usize = 40;
+pub const TOKEN_MAX_AMOUNT: u64 = 0x7fff_ffff_ffff_ffff;
+
+const HAS_AMOUNT: u8 = 0x10;
+const HAS_NFT: u8 = 0x20;
+const HAS_COMMITMENT_LENGTH: u8 = 0x40;
+const RESERVED_BIT: u8 = 0x80;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum NFTCapability {
+    None = 0,
+    Mutable = 1,
+    Minting = 2,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NFT {
+    pub capability: NFTCapability,
+    pub commitment: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TokenData {
+    pub category: [u8; 32],
+    pub amount: u64,
+    pub nft: Option<NFT>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TokenError {
+    EmptyToken,
+    AmountTooLarge(u64),
+    CommitmentTooLong(usize),
+    NoGenesisInput,
+}
+
+#[derive(Clone)]
+pub struct TokenOutput {
+    pub output: Box<dyn Output>,
+    pub token: TokenData,
+}
+
+impl TokenData {
+    pub fn fungible(category: [u8; 32], amount: u64) -> Self {
+        TokenData { category, amount, nft: None }
+    }
+
+    pub fn nft(category: [u8; 32], capability: NFTCapability, commitment: Vec<u8>) -> Self {
+        TokenData { category, amount: 0, nft: Some(NFT { capability, commitment }) }
+    }
+
+    pub fn validate(&self) -> Result<(), TokenError> {
+        if self.amount == 0 && self.nft.is_none() {
+            return Err(TokenError::EmptyToken);
+        }
+        if self.amount > TOKEN_MAX_AMOUNT {
+            return Err(TokenError::AmountTooLarge(self.amount));
+        }
+        if let Some(nft) = &self.nft {
+            if nft.commitment.len() > TOKEN_MAX_COMMITMENT_LENGTH {
+                return Err(TokenError::CommitmentTooLong(nft.commitment.len()));
+            }
+        }
+        Ok(())
+    }
+
+    /* PREFIX_TOKEN (0xef)
+     * <category_id> (32 bytes)
+     * <token_bitfield> (1 byte)
+     * [commitment_length] [commitment] (if HAS_COMMITMENT_LENGTH)
+     * [ft_amount] (compact size, if HAS_AMOUNT) */
+    pub fn write_prefix<W: io::Write>(&self, write: &mut W) -> io::Result<()> {
+        let mut bitfield = 0;
+        if self.amount > 0 { bitfield |= HAS_AMOUNT; }
+        if let Some(nft) = &self.nft {
+            bitfield |= HAS_NFT | nft.capability as u8;
+            if !nft.commitment.is_empty() { bitfield |= HAS_COMMITMENT_LENGTH; }
+        }
+        write.write_u8(PREFIX_TOKEN)?;
+        write.write_all(&self.category)?;
+        write.write_u8(bitfield)?;
+        if let Some(nft) = &self.nft {
+            if !nft.commitment.is_empty() {
+                write_var_int(write, nft.commitment.len() as u64)?;
+                write.write_all(&nft.commitment)?;
+            }
+        }
+        if self.amount > 0 {
+            write_var_int(write, self.amount)?;
+        }
+        Ok(())
+    }
+
+    pub fn prefix_to_vec(&self) -> Vec<u8> {
+        let mut vec = Vec::new();
+        self.write_prefix(&mut vec).unwrap();
+        vec
+    }
+
+    // Reads a token prefix, with the leading PREFIX_TOKEN byte already consumed.
+    pub fn read_prefix<R: io::Read>(read: &mut R) -> io::Result<Self> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let mut category = [0; 32];
+        read.read_exact(&mut category)?;
+        let bitfield = read.read_u8()?;
+        if bitfield & RESERVED_BIT != 0 {
+            return Err(invalid("Reserved token bit set"));
+        }
+        let nft = if bitfield & HAS_NFT != 0 {
+            let capability = match bitfield & 0x0f {
+                0 => NFTCapability::None,
+                1 => NFTCapability::Mutable,
+                2 => NFTCapability::Minting,
+                _ => return Err(invalid("Invalid NFT capability")),
+            };
+            let commitment = if bitfield & HAS_COMMITMENT_LENGTH != 0 {
+                let len = read_var_int_minimal(read)? as usize;
+                if len == 0 || len > TOKEN_MAX_COMMITMENT_LENGTH {
+                    return Err(invalid("Invalid NFT commitment length"));
+                }
+                let mut commitment = vec![0; len];
+                read.read_exact(&mut commitment)?;
+                commitment
+            } else {
+                vec![]
+            };
+            Some(NFT { capability, commitment })
+        } else {
+            if bitfield & (HAS_COMMITMENT_LENGTH | 0x0f) != 0 {
+                return Err(invalid("Token commitment or capability without NFT"));
+            }
+            None
+        };
+        let amount = if bitfield & HAS_AMOUNT != 0 {
+            let amount = read_var_int_minimal(read)?;
+            if amount == 0 || amount > TOKEN_MAX_AMOUNT {
+                return Err(invalid("Invalid token amount"));
+            }
+            amount
+        } else {
+            0
+        };
+        if nft.is_none() && amount == 0 {
+            return Err(invalid("Token prefix without amount or NFT"));
+        }
+        Ok(TokenData { category, amount, nft })
+    }
+}
+
+// Splits a serialized output script into its token prefix (if any) and locking bytecode.
+pub fn split_token_prefix(data: &[u8]) -> io::Result<(Option<TokenData>, &[u8])> {
+    if data.first() != Some(&PREFIX_TOKEN) {
+        return Ok((None, data));
+    }
+    let mut cur = io::Cursor::new(&data[1..]);
+    let token = TokenData::read_prefix(&mut cur)?;
+    let offset = 1 + cur.position() as usize;
+    Ok((Some(token), &data[offset..]))
+}
+
+impl UnsignedTx {
+    // A new token category can only be created by an input spending an output with vout 0;
+    // the category id is that outpoint's tx hash.
+    pub fn token_genesis_category(&self) -> Result<[u8; 32], TokenError> {
+        match self.inputs().first() {
+            Some(input) if input.outpoint.vout == 0 => Ok(input.outpoint.tx_hash),
+            _ => Err(TokenError::NoGenesisInput),
+        }
+    }
+
+    pub fn token_genesis_fungible(&self, amount: u64) -> Result<TokenData, TokenError> {
+        let token = TokenData::fungible(self.token_genesis_category()?, amount);
+        token.validate()?;
+        Ok(token)
+    }
+
+    pub fn token_genesis_nft(&self, capability: NFTCapability, commitment: Vec<u8>)
+            -> Result<TokenData, TokenError> {
+        let token = TokenData::nft(self.token_genesis_category()?, capability, commitment);
+        token.validate()?;
+        Ok(token)
+    }
+}
+
+impl Output for TokenOutput {
+    fn value(&self) -> u64 {
+        self.output.value()
+    }
+
+    fn script(&self) -> Script {
+        self.output.script()
+    }
+
+    fn script_code(&self) -> Script {
+        self.output.script_code()
+    }
+
+    fn sig_script(&self,
+                  serialized_sig: Vec<u8>,
+                  serialized_pub_key: Vec<u8>,
+                  pre_image: &PreImage,
+                  outputs: &[TxOutput]) -> Script {
+        self.output.sig_script(serialized_sig, serialized_pub_key, pre_image, outputs)
+    }
+
+    fn token(&self) -> Option<TokenData> {
+        Some(self.token.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::{Op, OpCodeType};
+
+    #[test]
+    fn test_token_output_roundtrip() {
+        let token = TokenData {
+            category: [0xbb; 32],
+            amount: 252,
+            nft: Some(NFT { capability: NFTCapability::Minting, commitment: vec![0xcc; 3] }),
+        };
+        let output = TxOutput::new_with_token(
+            1000,
+            Script::new(vec![Op::Code(OpCodeType::Op1)]),
+            token.clone(),
+        );
+        let mut ser = Vec::new();
+        output.write_to_stream(&mut ser).unwrap();
+        assert_eq!(hex::encode(&ser[8..]), format!(
+            "{:02x}ef{}72{}{}fc51", 1 + 32 + 1 + 4 + 1 + 1, "bb".repeat(32), "03", "cc".repeat(3),
+        ));
+        let parsed = TxOutput::read_from_stream(&mut io::Cursor::new(&ser)).unwrap();
+        assert_eq!(parsed.token(), Some(&token));
+        assert_eq!(parsed.script.to_vec(), vec![0x51]);
+    }
+}
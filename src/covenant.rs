@@ -0,0 +1,200 @@
+use crate::unsigned_tx::{PreImage, PreImageWriteFlags};
+use crate::script::Op;
+use crate::tx::TxOutput;
+use crate::serialize::{write_var_int, encode_int};
+
+
+// value + sequence + hash_outputs + lock_time + sighash_type
+pub const PRE_IMAGE_SUFFIX_SIZE: usize = 8 + 4 + 32 + 4 + 4;
+// version + hash_prevouts + hash_sequence + outpoint
+pub const PRE_IMAGE_PREFIX_SIZE: usize = 4 + 32 + 32 + 36;
+
+const NO_PRE_IMAGE_FIELDS: PreImageWriteFlags = PreImageWriteFlags {
+    version: false,
+    hash_prevouts: false,
+    hash_sequence: false,
+    outpoint: false,
+    script_code: false,
+    value: false,
+    sequence: false,
+    hash_outputs: false,
+    lock_time: false,
+    sighash_type: false,
+};
+
+// version .. outpoint, followed by the var int length of the script code
+pub fn pre_image_prefix(pre_image: &PreImage, script_code_len: usize) -> Vec<u8> {
+    let mut pre_image_part = Vec::new();
+    pre_image.write_to_stream_flags(&mut pre_image_part, PreImageWriteFlags {
+        version: true,
+        hash_prevouts: true,
+        hash_sequence: true,
+        outpoint: true,
+        ..NO_PRE_IMAGE_FIELDS
+    }).unwrap();
+    write_var_int(&mut pre_image_part, script_code_len as u64).unwrap();
+    pre_image_part
+}
+
+pub fn pre_image_value_sequence(pre_image: &PreImage) -> Vec<u8> {
+    let mut pre_image_part = Vec::new();
+    pre_image.write_to_stream_flags(&mut pre_image_part, PreImageWriteFlags {
+        value: true,
+        sequence: true,
+        ..NO_PRE_IMAGE_FIELDS
+    }).unwrap();
+    pre_image_part
+}
+
+// lock_time and sighash_type
+pub fn pre_image_suffix(pre_image: &PreImage) -> Vec<u8> {
+    let mut pre_image_part = Vec::new();
+    pre_image.write_to_stream_flags(&mut pre_image_part, PreImageWriteFlags {
+        lock_time: true,
+        sighash_type: true,
+        ..NO_PRE_IMAGE_FIELDS
+    }).unwrap();
+    pre_image_part
+}
+
+pub fn serialize_pre_image(pre_image: &PreImage) -> Vec<u8> {
+    let mut pre_image_serialized = Vec::new();
+    pre_image.write_to_stream(&mut pre_image_serialized).unwrap();
+    pre_image_serialized
+}
+
+pub fn serialize_outputs(outputs: &[TxOutput]) -> Vec<u8> {
+    let mut outputs_serialized = Vec::new();
+    for tx_output in outputs {
+        tx_output.write_to_stream(&mut outputs_serialized).unwrap();
+    }
+    outputs_serialized
+}
+
+// Script as serialized in an output: var int length || script
+pub fn script_with_len(script: &[u8]) -> Vec<u8> {
+    let mut vec = Vec::new();
+    write_var_int(&mut vec, script.len() as u64).unwrap();
+    vec.extend_from_slice(script);
+    vec
+}
+
+// [pub_key, sig (without sighash flag), pre_image] -> [pre_image]
+// Verifies pre_image belongs to the spending tx by checking the same signature with
+// OP_CHECKSIG and OP_CHECKDATASIG.
+pub fn verify_pre_image_ops() -> Vec<Op> {
+    use crate::script::OpCodeType::*;
+    vec![
+        Op::Code(OpToAltStack),
+        Op::Code(Op2Dup),
+        Op::Push(vec![0x41]),  // (=sighash_all)
+        Op::Code(OpCat),
+        Op::Code(OpSwap),
+        Op::Code(OpCheckSigVerify),
+        Op::Code(OpFromAltStack),
+        Op::Code(OpDup),
+        Op::Code(OpToAltStack),
+        Op::Code(OpSha256),
+        Op::Code(OpRot),
+        Op::Code(OpCheckDataSigVerify),
+        Op::Code(OpFromAltStack),
+    ]
+}
+
+// [pre_image] -> [hash_outputs]
+pub fn hash_outputs_ops() -> Vec<Op> {
+    use crate::script::OpCodeType::*;
+    vec![
+        Op::Code(OpSize),
+        Op::Push(encode_int(32 + 4 + 4)),  // (=hashOutputs + lock time + sighash type)
+        Op::Code(OpSub),
+        Op::Code(OpSplit),
+        Op::Code(OpNip),
+        Op::Push(encode_int(32)),
+        Op::Code(OpSplit),
+        Op::Code(OpDrop),
+    ]
+}
+
+#[cfg(all(test, feature = "secp256k1"))]
+mod tests {
+    use super::*;
+    use crate::crypto::secp256k1::CryptoSecp256k1;
+    use crate::hash::double_sha256;
+    use crate::script::{Script, OpCodeType};
+    use crate::script_debugger::ScriptDebugger;
+    use crate::tx::{Tx, TxInput, TxOutpoint};
+    use std::convert::TryInto;
+
+    fn hex(hex: &str) -> Vec<u8> {
+        hex::decode(hex).unwrap()
+    }
+
+    #[test]
+    fn test_bip143_pre_image() {
+        // native P2WPKH example of BIP143, signing input 1
+        let outputs = vec![
+            TxOutput::new(112_340_000, Script::from_serialized(
+                &hex("76a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac")).unwrap()),
+            TxOutput::new(223_450_000, Script::from_serialized(
+                &hex("76a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac")).unwrap()),
+        ];
+        let script_code = hex("76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac");
+        let pre_image = PreImage {
+            version: 1,
+            hash_prevouts: hex("96b827c8483d4e9b96712b6713a7b68d6e8003a781feba36c31143470b4efd37")[..].try_into().unwrap(),
+            hash_sequence: hex("52b0a642eea2fb7ae638c36f6252b6750293dbe574a806984b8e4d8548339a3b")[..].try_into().unwrap(),
+            outpoint: TxOutpoint {
+                tx_hash: hex("ef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a")[..].try_into().unwrap(),
+                vout: 1,
+            },
+            token: None,
+            script_code: Script::from_serialized(&script_code).unwrap(),
+            value: 600_000_000,
+            sequence: 0xffff_ffff,
+            hash_outputs: double_sha256(&serialize_outputs(&outputs)),
+            lock_time: 17,
+            sighash_type: 1,
+        };
+        let serialized_outputs = serialize_outputs(&outputs);
+        assert_eq!(serialized_outputs,
+                   hex("202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac\
+                        9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac"));
+        assert_eq!(pre_image.hash_outputs.to_vec(),
+                   hex("863ef3e1a92afbfdb97f31ad0fc7683ee943e9abcf2501590ff8f6551f47e5e5"));
+        assert_eq!(script_with_len(&script_code), hex("1976a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac"));
+
+        let prefix = pre_image_prefix(&pre_image, script_code.len());
+        assert_eq!(prefix.len(), PRE_IMAGE_PREFIX_SIZE + 1);
+        let suffix = [pre_image_value_sequence(&pre_image), pre_image.hash_outputs.to_vec(),
+                      pre_image_suffix(&pre_image)].concat();
+        assert_eq!(suffix.len(), PRE_IMAGE_SUFFIX_SIZE);
+        assert_eq!(pre_image_suffix(&pre_image), hex("1100000001000000"));
+        let serialized = serialize_pre_image(&pre_image);
+        assert_eq!(serialized, [&prefix[..], &script_code, &suffix].concat());
+        assert_eq!(serialized, hex("01000000\
+            96b827c8483d4e9b96712b6713a7b68d6e8003a781feba36c31143470b4efd37\
+            52b0a642eea2fb7ae638c36f6252b6750293dbe574a806984b8e4d8548339a3b\
+            ef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a01000000\
+            1976a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac\
+            0046c32300000000\
+            ffffffff\
+            863ef3e1a92afbfdb97f31ad0fc7683ee943e9abcf2501590ff8f6551f47e5e5\
+            11000000\
+            01000000"));
+        assert_eq!(pre_image.sighash().to_vec(),
+                   hex("c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670"));
+
+        // hash_outputs_ops extracts hashOutputs from the pre-image
+        let mut ops = hash_outputs_ops();
+        ops.push(Op::Push(pre_image.hash_outputs.to_vec()));
+        ops.push(Op::Code(OpCodeType::OpEqual));
+        let spent_output = TxOutput::new(1_000, Script::new(ops));
+        let input = TxInput::new(TxOutpoint { tx_hash: [0; 32], vout: 0 },
+                                 Script::new(vec![Op::Push(serialized)]),
+                                 0xffff_ffff);
+        let tx = Tx::new(1, vec![input], vec![], 0);
+        let crypto = CryptoSecp256k1::new();
+        assert_eq!(ScriptDebugger::new(&crypto, &tx, 0, &spent_output).run(), Ok(()));
+    }
+}
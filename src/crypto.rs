@@ -6,8 +6,21 @@ pub trait Signature {
     fn serialize_der(&self) -> Vec<u8>;
 }
 
-pub trait SecretKey {
-    fn from_slice(slice: &[u8]) -> Result<Self, Box<std::error::Error>>;
+pub trait SecretKey: Sized {
+    fn from_slice(slice: &[u8]) -> Result<Self, Box<dyn std::error::Error>>;
+    fn to_bytes(&self) -> [u8; 32];
+}
+
+// How the ECDSA nonce is generated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NonceMode {
+    // Deterministic RFC6979 nonce.
+    #[default]
+    Rfc6979,
+    // RFC6979 nonce with additional data mixed in; still deterministic for the same entropy.
+    ExtraEntropy([u8; 32]),
+    // RFC6979 nonce, ground until r is low and the DER signature has at most 70 bytes.
+    LowR,
 }
 
 pub trait Crypto {
@@ -21,14 +34,114 @@ pub trait Crypto {
 
     fn sign(&self,
             message: &[u8],
-            key: &Self::SecretKey) -> Self::Signature;
+            key: &Self::SecretKey) -> Self::Signature {
+        self.sign_with_nonce(message, key, NonceMode::Rfc6979)
+    }
+
+    fn sign_with_nonce(&self,
+                       message: &[u8],
+                       key: &Self::SecretKey,
+                       nonce_mode: NonceMode) -> Self::Signature;
 
     fn secret_to_pub_key(&self, key: &Self::SecretKey) -> Self::PublicKey;
+
+    // BCH Schnorr signature (r || s) of the 32 byte `message`, e.g. for OP_CHECKDATASIG or,
+    // with a sighash byte appended, for OP_CHECKSIG.
+    fn sign_schnorr(&self, message: &[u8], key: &Self::SecretKey) -> [u8; 64];
+
+    // Verifies a DER encoded ECDSA signature or, if `sig` has 64 bytes, a BCH Schnorr signature
+    // of the 32 byte `message`.
+    fn verify(&self, message: &[u8], sig: &[u8], pub_key: &[u8]) -> bool;
+
+    // Whether all (message, sig, pub_key) triples are valid. The signatures are checked one by
+    // one, stopping at the first invalid one; there's no batch verification speedup.
+    fn verify_all(&self, items: &[(&[u8], &[u8], &[u8])]) -> bool {
+        items.iter().all(|&(message, sig, pub_key)| self.verify(message, sig, pub_key))
+    }
+
+    // Compressed shared point secret * pub_key; None if `pub_key` is invalid.
+    // BIP47 payment codes use its x coordinate, i.e. bytes 1..33.
+    fn ecdh_point(&self, secret: &Self::SecretKey, pub_key: &[u8]) -> Option<[u8; 33]>;
+
+    // Shared secret: sha256 of the compressed shared point, as in libsecp256k1's ECDH.
+    fn ecdh(&self, secret: &Self::SecretKey, pub_key: &[u8]) -> Option<[u8; 32]> {
+        Some(Self::single_sha256(&self.ecdh_point(secret, pub_key)?))
+    }
 }
 
+// BCH Schnorr signatures (r, s) verify if R = s*G - e*P has x coordinate r and a y coordinate
+// that is a quadratic residue, where e = sha256(r || compressed P || message).
+#[cfg(any(feature = "secp256k1", feature = "k256"))]
+mod schnorr {
+    use crate::hash::single_sha256;
+    use num::BigUint;
+
+    const FIELD_SIZE: &[u8] = b"fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f";
+    const GROUP_ORDER: &[u8] = b"fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141";
+
+    fn to_bytes_32(num: &BigUint) -> [u8; 32] {
+        let bytes = num.to_bytes_be();
+        let mut arr = [0; 32];
+        arr[32 - bytes.len()..].copy_from_slice(&bytes);
+        arr
+    }
+
+    pub fn is_in_range(sig: &[u8]) -> bool {
+        let field_size = BigUint::parse_bytes(FIELD_SIZE, 16).unwrap();
+        let group_order = BigUint::parse_bytes(GROUP_ORDER, 16).unwrap();
+        let (r, s) = sig.split_at(32);
+        BigUint::from_bytes_be(r) < field_size && BigUint::from_bytes_be(s) < group_order
+    }
+
+    pub fn challenge(r: &[u8], pub_key: &[u8; 33], message: &[u8]) -> [u8; 32] {
+        let group_order = BigUint::parse_bytes(GROUP_ORDER, 16).unwrap();
+        let mut e_data = r.to_vec();
+        e_data.extend_from_slice(pub_key);
+        e_data.extend_from_slice(message);
+        to_bytes_32(&(BigUint::from_bytes_be(&single_sha256(&e_data)) % &group_order))
+    }
+
+    pub fn is_quadratic_residue(y: &[u8]) -> bool {
+        let field_size = BigUint::parse_bytes(FIELD_SIZE, 16).unwrap();
+        let legendre = BigUint::from_bytes_be(y).modpow(&((&field_size - 1u32) >> 1), &field_size);
+        legendre == BigUint::from(1u32)
+    }
+
+    // Signs with the nonce k = sha256(secret || message || "Schnorr+SHA256  " || counter),
+    // negated if R's y coordinate isn't a quadratic residue. `nonce_point` returns the
+    // uncompressed point k*G.
+    pub fn sign(message: &[u8],
+                secret: &[u8; 32],
+                pub_key: &[u8; 33],
+                nonce_point: impl FnOnce(&[u8; 32]) -> [u8; 65]) -> [u8; 64] {
+        let group_order = BigUint::parse_bytes(GROUP_ORDER, 16).unwrap();
+        let mut counter = 0u32;
+        let k = loop {
+            let mut k_data = secret.to_vec();
+            k_data.extend_from_slice(message);
+            k_data.extend_from_slice(b"Schnorr+SHA256  ");
+            k_data.extend_from_slice(&counter.to_le_bytes());
+            let k = BigUint::from_bytes_be(&single_sha256(&k_data)) % &group_order;
+            if k != BigUint::from(0u32) {
+                break k;
+            }
+            counter += 1;
+        };
+        let point = nonce_point(&to_bytes_32(&k));
+        let (r, y) = point[1..].split_at(32);
+        let k = if is_quadratic_residue(y) { k } else { &group_order - k };
+        let e = BigUint::from_bytes_be(&challenge(r, pub_key, message));
+        let s = (k + e * BigUint::from_bytes_be(secret)) % &group_order;
+        let mut sig = [0; 64];
+        sig[..32].copy_from_slice(r);
+        sig[32..].copy_from_slice(&to_bytes_32(&s));
+        sig
+    }
+}
 
+#[cfg(feature = "secp256k1")]
 pub mod secp256k1 {
-    use super::{PublicKey, Signature, SecretKey, Crypto};
+    use super::{PublicKey, Signature, SecretKey, Crypto, NonceMode, schnorr};
 
     use sha2::{Sha256, Digest};
     use ripemd160::Ripemd160;
@@ -62,18 +175,91 @@ pub mod secp256k1 {
 
     impl Signature for secp256k1::Signature {
         fn serialize_der(&self) -> Vec<u8> {
-            secp256k1::Signature::serialize_der(self)
+            secp256k1::Signature::serialize_der(self).to_vec()
         }
     }
 
     impl SecretKey for secp256k1::SecretKey {
-        fn from_slice(slice: &[u8]) -> Result<Self, Box<std::error::Error>> {
+        fn from_slice(slice: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
             Ok(secp256k1::SecretKey::from_slice(slice)?)
         }
+
+        fn to_bytes(&self) -> [u8; 32] {
+            let mut arr = [0; 32];
+            arr.copy_from_slice(&self[..]);
+            arr
+        }
     }
 
-    struct CryptoSecp256k1 {
-        secp256k1: secp256k1::Secp256k1<secp256k1::All>
+    pub struct CryptoSecp256k1 {
+        secp256k1: secp256k1::Secp256k1<secp256k1::All>,
+        nonce_mode: NonceMode,
+    }
+
+    impl CryptoSecp256k1 {
+        pub fn new() -> Self {
+            CryptoSecp256k1 {
+                secp256k1: secp256k1::Secp256k1::new(),
+                nonce_mode: NonceMode::Rfc6979,
+            }
+        }
+
+        // Nonce mode used by `sign`, e.g. NonceMode::LowR so signatures never exceed the size
+        // assumed by fee estimation.
+        pub fn with_nonce_mode(mut self, nonce_mode: NonceMode) -> Self {
+            self.nonce_mode = nonce_mode;
+            self
+        }
+
+        pub fn nonce_mode(&self) -> NonceMode {
+            self.nonce_mode
+        }
+
+        // secp256k1 only exposes extra entropy for its own grinding, so call the C API directly.
+        fn sign_extra_entropy(&self,
+                              message: &secp256k1::Message,
+                              key: &secp256k1::SecretKey,
+                              entropy: &[u8; 32]) -> secp256k1::Signature {
+            use secp256k1::ffi::{self, CPtr};
+            unsafe {
+                let mut sig = ffi::Signature::new();
+                let result = ffi::secp256k1_ecdsa_sign(*self.secp256k1.ctx(),
+                                                       &mut sig,
+                                                       message.as_c_ptr(),
+                                                       key.as_c_ptr(),
+                                                       ffi::secp256k1_nonce_function_rfc6979,
+                                                       entropy.as_ptr() as *const ffi::types::c_void);
+                // signing can't fail for a valid message and secret key
+                assert_eq!(result, 1);
+                secp256k1::Signature::from(sig)
+            }
+        }
+    }
+
+    impl CryptoSecp256k1 {
+        fn verify_schnorr(&self, message: &[u8], sig: &[u8], pub_key: &secp256k1::PublicKey) -> bool {
+            if !schnorr::is_in_range(sig) {
+                return false;
+            }
+            let (r, s) = sig.split_at(32);
+            let e = schnorr::challenge(r, &pub_key.serialize(), message);
+            let mut point = *pub_key;
+            let is_valid_point = point.mul_assign(&self.secp256k1, &e).is_ok() && {
+                point.negate_assign(&self.secp256k1);
+                point.add_exp_assign(&self.secp256k1, s).is_ok()
+            };
+            if !is_valid_point {
+                return false;
+            }
+            let point = point.serialize_uncompressed();
+            &point[1..33] == r && schnorr::is_quadratic_residue(&point[33..])
+        }
+    }
+
+    impl Default for CryptoSecp256k1 {
+        fn default() -> Self {
+            CryptoSecp256k1::new()
+        }
     }
 
     impl Crypto for CryptoSecp256k1 {
@@ -94,11 +280,286 @@ pub mod secp256k1 {
         }
 
         fn sign(&self, message: &[u8], key: &Self::SecretKey) -> Self::Signature {
-            self.secp256k1.sign(&secp256k1::Message::from_slice(message).unwrap(), key)
+            self.sign_with_nonce(message, key, self.nonce_mode)
+        }
+
+        fn sign_with_nonce(&self,
+                           message: &[u8],
+                           key: &Self::SecretKey,
+                           nonce_mode: NonceMode) -> Self::Signature {
+            let message = secp256k1::Message::from_slice(message).unwrap();
+            match nonce_mode {
+                NonceMode::Rfc6979 => self.secp256k1.sign(&message, key),
+                NonceMode::ExtraEntropy(entropy) => self.sign_extra_entropy(&message, key, &entropy),
+                NonceMode::LowR => self.secp256k1.sign_low_r(&message, key),
+            }
         }
 
         fn secret_to_pub_key(&self, key: &secp256k1::SecretKey) -> secp256k1::PublicKey {
             secp256k1::PublicKey::from_secret_key(&self.secp256k1, key)
         }
+
+        fn sign_schnorr(&self, message: &[u8], key: &secp256k1::SecretKey) -> [u8; 64] {
+            let pub_key = self.secret_to_pub_key(key).serialize();
+            schnorr::sign(message, &SecretKey::to_bytes(key), &pub_key, |k| {
+                let k = secp256k1::SecretKey::from_slice(k).expect("nonce in range");
+                self.secret_to_pub_key(&k).serialize_uncompressed()
+            })
+        }
+
+        fn verify(&self, message: &[u8], sig: &[u8], pub_key: &[u8]) -> bool {
+            let (message, pub_key) = match (secp256k1::Message::from_slice(message),
+                                            secp256k1::PublicKey::from_slice(pub_key)) {
+                (Ok(message), Ok(pub_key)) => (message, pub_key),
+                _ => return false,
+            };
+            if sig.len() == 64 {
+                return self.verify_schnorr(&message[..], sig, &pub_key);
+            }
+            match secp256k1::Signature::from_der(sig) {
+                Ok(sig) => self.secp256k1.verify(&message, &sig, &pub_key).is_ok(),
+                Err(_) => false,
+            }
+        }
+
+        fn ecdh_point(&self, secret: &secp256k1::SecretKey, pub_key: &[u8]) -> Option<[u8; 33]> {
+            let mut point = secp256k1::PublicKey::from_slice(pub_key).ok()?;
+            point.mul_assign(&self.secp256k1, &secret[..]).ok()?;
+            Some(point.serialize())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::address::{Address, AddressType};
+        use crate::outputs::P2PKHOutput;
+        use crate::tx::{TxOutpoint, SigVerifyError, verify_jobs};
+        use crate::unsigned_tx::{UnsignedTx, UnsignedInput, Output};
+
+        #[test]
+        fn test_verify() {
+            let crypto = CryptoSecp256k1::new();
+            let pub_key = hex::decode("02dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659").unwrap();
+            let message = hex::decode("243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89").unwrap();
+            let mut sig = hex::decode("2a298dacae57395a15d0795ddbfd1dcb564da82b0f269bc70a74f8220429ba1d\
+                                       1e51a22ccec35599b8f266912281f8365ffc2d035a230434a1a64dc59f7013fd").unwrap();
+            assert!(crypto.verify(&message, &sig, &pub_key));
+            sig[63] ^= 1;
+            assert!(!crypto.verify(&message, &sig, &pub_key));
+
+            let secret_key = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+            let pub_key = crypto.secret_to_pub_key(&secret_key).serialize();
+            let mut schnorr_sigs = Vec::new();
+            for i in 0..8u8 {
+                let schnorr_sig = crypto.sign_schnorr(&[i; 32], &secret_key);
+                assert!(crypto.verify(&[i; 32], &schnorr_sig, &pub_key));
+                schnorr_sigs.push(schnorr_sig);
+            }
+            let messages = (0..8u8).map(|i| [i; 32]).collect::<Vec<_>>();
+            let mut items = messages.iter().zip(&schnorr_sigs)
+                .map(|(message, sig)| (&message[..], &sig[..], &pub_key[..]))
+                .collect::<Vec<_>>();
+            assert!(crypto.verify_all(&items));
+            items[3].0 = &messages[4];
+            assert!(!crypto.verify_all(&items));
+            let address = Address::from_serialized_pub_key("bitcoincash", AddressType::P2PKH, &pub_key);
+            let spent_output = P2PKHOutput { value: 10_000, address: address.clone() };
+            let mut tx_build = UnsignedTx::new_simple();
+            tx_build.add_input(UnsignedInput::new(
+                TxOutpoint { tx_hash: [0x11; 32], vout: 0 },
+                Box::new(spent_output.clone()),
+                0xffff_ffff,
+            ));
+            tx_build.add_output(P2PKHOutput { value: 9_000, address }.to_output());
+            let tx = tx_build.sign_with(&crypto, &secret_key).unwrap();
+            assert_eq!(tx.verify_input_signatures(&crypto, &[spent_output.to_output()]), Ok(()));
+            let mut jobs = tx.verification_jobs(&[spent_output.to_output()]).unwrap();
+            assert_eq!(jobs.len(), 1);
+            assert_eq!(jobs[0].pub_keys, vec![pub_key.to_vec()]);
+            jobs.push(jobs[0].clone());
+            assert_eq!(verify_jobs(&crypto, &jobs), Ok(()));
+            jobs[1].sighash[0] ^= 1;
+            assert_eq!(verify_jobs(&crypto, &jobs), Err(1));
+            jobs.swap(0, 1);
+            assert_eq!(verify_jobs(&crypto, &jobs), Err(0));
+            let mut wrong_output = spent_output.to_output();
+            wrong_output.value += 1;
+            assert_eq!(tx.verify_input_signatures(&crypto, &[wrong_output]),
+                       Err(SigVerifyError::InvalidSignature { input_idx: 0 }));
+        }
+    }
+}
+
+// Pure Rust backend for targets where the C secp256k1 library isn't available, e.g. WASM.
+// Produces the same signatures as CryptoSecp256k1 for all nonce modes.
+#[cfg(feature = "k256")]
+pub mod k256 {
+    use super::{PublicKey, Signature, SecretKey, Crypto, NonceMode, schnorr};
+    use crate::hash;
+
+    use k256::{FieldBytes, ProjectivePoint, Scalar};
+    use k256::ecdsa::{SigningKey, VerifyingKey};
+    use k256::ecdsa::hazmat::SignPrimitive;
+    use k256::ecdsa::signature::hazmat::PrehashVerifier;
+    use k256::elliptic_curve::PrimeField;
+    use k256::elliptic_curve::ops::Reduce;
+    use k256::elliptic_curve::sec1::ToEncodedPoint;
+    use k256::sha2::Sha256;
+
+    impl PublicKey for VerifyingKey {
+        fn serialize(&self) -> [u8; 33] {
+            let mut arr = [0; 33];
+            arr.copy_from_slice(self.to_encoded_point(true).as_bytes());
+            arr
+        }
+    }
+
+    impl Signature for k256::ecdsa::Signature {
+        fn serialize_der(&self) -> Vec<u8> {
+            self.to_der().as_bytes().to_vec()
+        }
+    }
+
+    impl SecretKey for SigningKey {
+        fn from_slice(slice: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+            Ok(SigningKey::from_slice(slice)?)
+        }
+
+        fn to_bytes(&self) -> [u8; 32] {
+            SigningKey::to_bytes(self).into()
+        }
+    }
+
+    fn field_bytes(slice: &[u8]) -> FieldBytes {
+        let mut bytes = FieldBytes::default();
+        bytes.copy_from_slice(slice);
+        bytes
+    }
+
+    #[derive(Default)]
+    pub struct CryptoK256 {
+        nonce_mode: NonceMode,
+    }
+
+    impl CryptoK256 {
+        pub fn new() -> Self {
+            CryptoK256::default()
+        }
+
+        pub fn with_nonce_mode(mut self, nonce_mode: NonceMode) -> Self {
+            self.nonce_mode = nonce_mode;
+            self
+        }
+
+        pub fn nonce_mode(&self) -> NonceMode {
+            self.nonce_mode
+        }
+
+        fn sign_rfc6979(message: &[u8], key: &SigningKey, entropy: &[u8]) -> k256::ecdsa::Signature {
+            let scalar: &Scalar = key.as_nonzero_scalar().as_ref();
+            let (sig, _) = scalar.try_sign_prehashed_rfc6979::<Sha256>(&field_bytes(message), entropy)
+                .expect("signing can't fail for a valid message and secret key");
+            sig
+        }
+
+        fn verify_schnorr(message: &[u8], sig: &[u8], pub_key: &VerifyingKey) -> bool {
+            if !schnorr::is_in_range(sig) {
+                return false;
+            }
+            let (r, s) = sig.split_at(32);
+            let e = schnorr::challenge(r, &PublicKey::serialize(pub_key), message);
+            let s = Scalar::from_repr(field_bytes(s)).unwrap();
+            let e = <Scalar as Reduce<k256::U256>>::reduce_bytes(&field_bytes(&e));
+            let point = ProjectivePoint::GENERATOR * s - ProjectivePoint::from(*pub_key.as_affine()) * e;
+            let point = point.to_affine().to_encoded_point(false);
+            match (point.x(), point.y()) {
+                (Some(x), Some(y)) => &x[..] == r && schnorr::is_quadratic_residue(y),
+                _ => false,
+            }
+        }
+    }
+
+    impl Crypto for CryptoK256 {
+        type SecretKey=SigningKey;
+        type PublicKey=VerifyingKey;
+        type Signature=k256::ecdsa::Signature;
+
+        fn hash160(data: &[u8]) -> [u8; 20] {
+            hash::hash160(data)
+        }
+
+        fn single_sha256(data: &[u8]) -> [u8; 32] {
+            hash::single_sha256(data)
+        }
+
+        fn double_sha256(data: &[u8]) -> [u8; 32] {
+            hash::double_sha256(data)
+        }
+
+        fn sign(&self, message: &[u8], key: &Self::SecretKey) -> Self::Signature {
+            self.sign_with_nonce(message, key, self.nonce_mode)
+        }
+
+        // Extra entropy is passed as RFC6979 additional data, like libsecp256k1 does.
+        fn sign_with_nonce(&self,
+                           message: &[u8],
+                           key: &Self::SecretKey,
+                           nonce_mode: NonceMode) -> Self::Signature {
+            match nonce_mode {
+                NonceMode::Rfc6979 => CryptoK256::sign_rfc6979(message, key, &[]),
+                NonceMode::ExtraEntropy(entropy) => CryptoK256::sign_rfc6979(message, key, &entropy),
+                NonceMode::LowR => {
+                    let mut sig = CryptoK256::sign_rfc6979(message, key, &[]);
+                    let mut counter = 0u32;
+                    while sig.r().to_bytes()[0] >= 0x80 {
+                        counter += 1;
+                        let mut entropy = [0; 32];
+                        entropy[..4].copy_from_slice(&counter.to_le_bytes());
+                        sig = CryptoK256::sign_rfc6979(message, key, &entropy);
+                    }
+                    sig
+                },
+            }
+        }
+
+        fn secret_to_pub_key(&self, key: &SigningKey) -> VerifyingKey {
+            *key.verifying_key()
+        }
+
+        fn sign_schnorr(&self, message: &[u8], key: &SigningKey) -> [u8; 64] {
+            let pub_key = PublicKey::serialize(key.verifying_key());
+            schnorr::sign(message, &SecretKey::to_bytes(key), &pub_key, |k| {
+                let point = ProjectivePoint::GENERATOR * Scalar::from_repr(field_bytes(k)).unwrap();
+                let mut arr = [0; 65];
+                arr.copy_from_slice(point.to_affine().to_encoded_point(false).as_bytes());
+                arr
+            })
+        }
+
+        fn verify(&self, message: &[u8], sig: &[u8], pub_key: &[u8]) -> bool {
+            let pub_key = match VerifyingKey::from_sec1_bytes(pub_key) {
+                Ok(pub_key) => pub_key,
+                Err(_) => return false,
+            };
+            if message.len() != 32 {
+                return false;
+            }
+            if sig.len() == 64 {
+                return CryptoK256::verify_schnorr(message, sig, &pub_key);
+            }
+            match k256::ecdsa::Signature::from_der(sig) {
+                Ok(sig) => sig.normalize_s().is_none() && pub_key.verify_prehash(message, &sig).is_ok(),
+                Err(_) => false,
+            }
+        }
+
+        fn ecdh_point(&self, secret: &SigningKey, pub_key: &[u8]) -> Option<[u8; 33]> {
+            let pub_key = VerifyingKey::from_sec1_bytes(pub_key).ok()?;
+            let point = ProjectivePoint::from(*pub_key.as_affine()) * *secret.as_nonzero_scalar().as_ref();
+            let mut arr = [0; 33];
+            arr.copy_from_slice(point.to_affine().to_encoded_point(true).as_bytes());
+            Some(arr)
+        }
     }
 }
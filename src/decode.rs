@@ -0,0 +1,284 @@
+use crate::tx::{Tx, TxOutput};
+use crate::address::{Address, AddressType};
+use crate::outputs::{P2PKHOutput, P2PKOutput, P2SHRawOutput, OpReturnOutput};
+use crate::time_lock::{TimeLockOutput, RelativeTimeLockOutput};
+use crate::slp::SLPMessage;
+use crate::cash_tokens::TokenOutput;
+use crate::unsigned_tx::{Output, UnsignedTx, UnsignedInput};
+use crate::script::{Script, Op, OpCodeType};
+use crate::serialize::vec_to_int64;
+use crate::hash::hash160;
+
+#[derive(Clone, Debug)]
+pub enum DecodedOutput {
+    P2PKH(P2PKHOutput),
+    P2SH {
+        value: u64,
+        address: Address,
+    },
+    P2PK(P2PKOutput),
+    Multisig {
+        value: u64,
+        required: usize,
+        pub_keys: Vec<Vec<u8>>,
+    },
+    TimeLock(TimeLockOutput),
+    RelativeTimeLock(RelativeTimeLockOutput),
+    SLP(SLPMessage),
+    OpReturn(OpReturnOutput),
+    Unknown(TxOutput),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FromTxError {
+    SpentOutputsMismatch { expected: usize, actual: usize },
+    UnknownOutput { input_idx: usize },
+}
+
+// Reads a script number, which small ints are minimally encoded as OP_1..OP_16.
+pub(crate) fn op_to_int(op: &Op) -> Option<i64> {
+    match op {
+        Op::Push(vec) => vec_to_int64(vec),
+        Op::Code(OpCodeType::Op1Negate) => Some(-1),
+        Op::Code(code) if *code >= OpCodeType::Op1 && *code <= OpCodeType::Op16 => {
+            Some(*code as i64 - OpCodeType::Op1 as i64 + 1)
+        },
+        _ => None,
+    }
+}
+
+pub(crate) fn is_pub_key(vec: &[u8]) -> bool {
+    match vec.first() {
+        Some(0x02) | Some(0x03) => vec.len() == 33,
+        Some(0x04) => vec.len() == 65,
+        _ => false,
+    }
+}
+
+pub fn decode_output(output: &TxOutput) -> DecodedOutput {
+    use crate::script::OpCodeType::*;
+    let value = output.value;
+    let ops = output.script.ops();
+    match ops {
+        [Op::Code(OpDup), Op::Code(OpHash160), Op::Push(hash),
+         Op::Code(OpEqualVerify), Op::Code(OpCheckSig)] if hash.len() == 20 => {
+            DecodedOutput::P2PKH(P2PKHOutput {
+                value,
+                address: Address::from_slice(AddressType::P2PKH, hash).unwrap(),
+            })
+        },
+        [Op::Code(OpHash160), Op::Push(hash), Op::Code(OpEqual)] if hash.len() == 20 => {
+            DecodedOutput::P2SH {
+                value,
+                address: Address::from_slice(AddressType::P2SH, hash).unwrap(),
+            }
+        },
+        [Op::Push(pub_key), Op::Code(OpCheckSig)] if is_pub_key(pub_key) => {
+            DecodedOutput::P2PK(P2PKOutput { value, pub_key: pub_key.clone() })
+        },
+        [lock_time, Op::Code(OpCheckLockTimeVerify), Op::Code(OpDrop),
+         Op::Code(OpDup), Op::Code(OpHash160), Op::Push(hash),
+         Op::Code(OpEqualVerify), Op::Code(OpCheckSig)] if hash.len() == 20 => {
+            match op_to_int(lock_time) {
+                Some(lock_time) if lock_time >= 0 && lock_time <= u32::MAX as i64 => {
+                    DecodedOutput::TimeLock(TimeLockOutput {
+                        value,
+                        address: Address::from_slice(AddressType::P2PKH, hash).unwrap(),
+                        lock_time: lock_time as u32,
+                    })
+                },
+                _ => DecodedOutput::Unknown(output.clone()),
+            }
+        },
+        [Op::Code(OpIf), Op::Push(receiver_pk), Op::Code(OpCheckSigVerify),
+         Op::Push(sender_pk), Op::Code(OpCheckSig), Op::Code(OpElse),
+         sequence, Op::Code(OpCheckSequenceVerify), Op::Code(OpDrop),
+         Op::Push(timeout_pk), Op::Code(OpCheckSig), Op::Code(OpEndIf)]
+                if sender_pk == timeout_pk => {
+            match op_to_int(sequence) {
+                Some(sequence) if sequence >= 0 && sequence <= u32::MAX as i64 => {
+                    DecodedOutput::RelativeTimeLock(RelativeTimeLockOutput {
+                        value,
+                        sender_pk: sender_pk.clone(),
+                        receiver_pk: receiver_pk.clone(),
+                        sequence: sequence as u32,
+                    })
+                },
+                _ => DecodedOutput::Unknown(output.clone()),
+            }
+        },
+        [required, pub_keys @ .., num_keys, Op::Code(OpCheckMultiSig)] => {
+            let pub_keys = pub_keys.iter()
+                .map(|op| match op {
+                    Op::Push(pub_key) if is_pub_key(pub_key) => Some(pub_key.clone()),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>();
+            match (op_to_int(required), op_to_int(num_keys), pub_keys) {
+                (Some(required), Some(num_keys), Some(pub_keys))
+                        if num_keys as usize == pub_keys.len() &&
+                           required >= 1 && required <= num_keys => {
+                    DecodedOutput::Multisig { value, required: required as usize, pub_keys }
+                },
+                _ => DecodedOutput::Unknown(output.clone()),
+            }
+        },
+        [Op::Code(OpReturn), pushes @ ..] => {
+            if let Ok(message) = SLPMessage::parse(&output.script) {
+                return DecodedOutput::SLP(message);
+            }
+            let pushes = pushes.iter()
+                .map(|op| match op {
+                    Op::Push(push) => Some(push.clone()),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>();
+            match pushes {
+                Some(pushes) => {
+                    let mut op_return = OpReturnOutput {
+                        pushes,
+                        is_minimal_push: false,
+                        encodings: vec![],
+                    };
+                    op_return.is_minimal_push =
+                        op_return.script().to_vec() != output.script.to_vec();
+                    DecodedOutput::OpReturn(op_return)
+                },
+                None => DecodedOutput::Unknown(output.clone()),
+            }
+        },
+        _ => DecodedOutput::Unknown(output.clone()),
+    }
+}
+
+impl DecodedOutput {
+    // Reconstructs an Output which can be used as input of an UnsignedTx, if the
+    // output can be spent without further knowledge (e.g. a P2SH redeem script).
+    pub fn into_output(self) -> Option<Box<dyn Output>> {
+        Some(match self {
+            DecodedOutput::P2PKH(p2pkh) => Box::new(p2pkh),
+            DecodedOutput::P2PK(p2pk) => Box::new(p2pk),
+            DecodedOutput::TimeLock(time_lock) => Box::new(time_lock),
+            _ => return None,
+        })
+    }
+}
+
+pub fn decode_spendable_output(output: &TxOutput) -> Option<Box<dyn Output>> {
+    let spendable = decode_output(output).into_output()?;
+    Some(match output.token.clone() {
+        Some(token) => Box::new(TokenOutput { output: spendable, token }),
+        None => spendable,
+    })
+}
+
+// Recovers a P2SH output from the sig script spending it, assuming the layout of
+// P2SHRawOutput: <sig_pushes...> <sig> [<pub_key>] <redeem_script>.
+fn decode_p2sh_spend(value: u64, address: &Address, sig_script: &Script) -> Option<P2SHRawOutput> {
+    let pushes = sig_script.ops().iter()
+        .map(|op| match op {
+            Op::Push(push) => Some(push.clone()),
+            Op::Code(_) => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let (redeem_script, rest) = pushes.split_last()?;
+    if &hash160(redeem_script) != address.bytes() {
+        return None;
+    }
+    let push_pub_key = rest.len() >= 2 && is_pub_key(&rest[rest.len() - 1]);
+    let num_sig_pushes = rest.len().checked_sub(if push_pub_key { 2 } else { 1 })?;
+    Some(P2SHRawOutput {
+        value,
+        redeem_script: Script::from_serialized(redeem_script)?,
+        sig_pushes: rest[..num_sig_pushes].to_vec(),
+        push_pub_key,
+    })
+}
+
+impl UnsignedTx {
+    // Rebuilds the unsigned tx from `tx` and the outputs spent by its inputs, e.g. to bump
+    // the fee or re-sign. P2SH outputs are only recognized if the input is signed already.
+    pub fn from_tx(tx: &Tx, spent_outputs: &[TxOutput]) -> Result<UnsignedTx, FromTxError> {
+        if spent_outputs.len() != tx.inputs().len() {
+            return Err(FromTxError::SpentOutputsMismatch {
+                expected: tx.inputs().len(),
+                actual: spent_outputs.len(),
+            });
+        }
+        let mut tx_build = UnsignedTx::new_with_version(tx.version());
+        tx_build.set_lock_time(tx.lock_time());
+        for (input_idx, (input, spent_output)) in tx.inputs().iter().zip(spent_outputs).enumerate() {
+            let output = match decode_output(spent_output) {
+                DecodedOutput::P2SH { value, address } => {
+                    decode_p2sh_spend(value, &address, input.script())
+                        .map(|p2sh| -> Box<dyn Output> {
+                            match spent_output.token.clone() {
+                                Some(token) => Box::new(TokenOutput { output: Box::new(p2sh), token }),
+                                None => Box::new(p2sh),
+                            }
+                        })
+                },
+                _ => decode_spendable_output(spent_output),
+            };
+            tx_build.add_input(UnsignedInput::new(
+                input.outpoint.clone(),
+                output.ok_or(FromTxError::UnknownOutput { input_idx })?,
+                input.sequence,
+            ));
+        }
+        for output in tx.outputs() {
+            tx_build.add_output(output.clone());
+        }
+        Ok(tx_build)
+    }
+}
+
+#[cfg(all(test, feature = "secp256k1"))]
+mod tests {
+    use super::*;
+    use crate::crypto::Crypto;
+    use crate::crypto::secp256k1::CryptoSecp256k1;
+    use crate::tx::{TxInput, TxOutpoint};
+
+    #[test]
+    fn test_unsigned_tx_from_tx() {
+        let crypto = CryptoSecp256k1::new();
+        let secret_key = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let pub_key = crypto.secret_to_pub_key(&secret_key).serialize();
+        let address = Address::from_serialized_pub_key("bitcoincash", AddressType::P2PKH, &pub_key);
+        let p2sh = P2SHRawOutput {
+            value: 20_000,
+            redeem_script: Script::new(vec![Op::Push(pub_key.to_vec()), Op::Code(OpCodeType::OpCheckSig)]),
+            sig_pushes: vec![],
+            push_pub_key: false,
+        };
+        let mut tx_build = UnsignedTx::new_with_version(2);
+        tx_build.set_lock_time(800_000);
+        tx_build.add_input(UnsignedInput::new(
+            TxOutpoint { tx_hash: [0x11; 32], vout: 0 },
+            Box::new(P2PKHOutput { value: 10_000, address: address.clone() }),
+            0xffff_fffe,
+        ));
+        tx_build.add_input(UnsignedInput::new(
+            TxOutpoint { tx_hash: [0x22; 32], vout: 1 },
+            Box::new(p2sh.clone()),
+            0xffff_fffe,
+        ));
+        tx_build.add_output(P2PKHOutput { value: 29_000, address }.to_output());
+        let tx = tx_build.sign_with(&crypto, &secret_key).unwrap();
+        let spent_outputs = tx_build.inputs().iter()
+            .map(|input| input.output.to_output())
+            .collect::<Vec<_>>();
+
+        let rebuilt = UnsignedTx::from_tx(&tx, &spent_outputs).unwrap();
+        assert_eq!(rebuilt.sign_with(&crypto, &secret_key).unwrap().hash(), tx.hash());
+        assert_eq!(UnsignedTx::from_tx(&tx, &spent_outputs[..1]).err(),
+                   Some(FromTxError::SpentOutputsMismatch { expected: 2, actual: 1 }));
+        let unsigned_inputs = tx.inputs().iter()
+            .map(|input| TxInput::new(input.outpoint.clone(), Script::empty(), input.sequence))
+            .collect();
+        let unsigned = Tx::new(2, unsigned_inputs, tx.outputs().to_vec(), 800_000);
+        assert_eq!(UnsignedTx::from_tx(&unsigned, &spent_outputs).err(),
+                   Some(FromTxError::UnknownOutput { input_idx: 1 }));
+    }
+}
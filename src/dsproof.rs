@@ -0,0 +1,279 @@
+use crate::crypto::Crypto;
+use crate::hash::{hash160, HashWriter};
+use crate::script::{Op, OpCodeType};
+use crate::serialize::{read_var_int_minimal, read_bytes, write_var_int};
+use crate::tx::{Tx, TxOutpoint, TxOutput, split_tx_signature};
+use crate::unsigned_tx::PreImage;
+
+use std::io::{self, Read, Write};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DsProofError {
+    OutpointMismatch,
+    // Only spends of P2PKH outputs can be proven.
+    NotP2PKH,
+    PubKeyMismatch,
+    IdenticalSpenders,
+    // Spenders aren't ordered by hash_outputs, then hash_prevouts.
+    NonCanonicalOrder,
+    InputOutOfRange { spender: usize, input_idx: usize },
+    MissingSignature { spender: usize },
+    InvalidSignature { spender: usize },
+}
+
+impl DsProofError {
+    fn with_spender(self, spender: usize) -> Self {
+        match self {
+            DsProofError::MissingSignature { .. } => DsProofError::MissingSignature { spender },
+            DsProofError::InputOutOfRange { input_idx, .. } => {
+                DsProofError::InputOutOfRange { spender, input_idx }
+            },
+            err => err,
+        }
+    }
+}
+
+// The parts of a spending tx's pre-image which the proof can't take from the spent output,
+// plus the pushes of its input script (for P2PKH just the signature).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DsProofSpender {
+    pub tx_version: i32,
+    pub sequence: u32,
+    pub lock_time: u32,
+    pub hash_prevouts: [u8; 32],
+    pub hash_sequence: [u8; 32],
+    pub hash_outputs: [u8; 32],
+    pub push_data: Vec<Vec<u8>>,
+}
+
+// Double-spend proof (dsproof-beta): two signatures spending the same outpoint in different
+// txs. Spenders are ordered by hash_outputs, then hash_prevouts, so both nodes seeing the
+// conflict end up with the same proof.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DsProof {
+    pub outpoint: TxOutpoint,
+    pub spender1: DsProofSpender,
+    pub spender2: DsProofSpender,
+}
+
+fn p2pkh_hash(script: &[Op]) -> Option<&[u8]> {
+    match script {
+        [Op::Code(OpCodeType::OpDup), Op::Code(OpCodeType::OpHash160), Op::Push(hash),
+         Op::Code(OpCodeType::OpEqualVerify), Op::Code(OpCodeType::OpCheckSig)]
+                if hash.len() == 20 => Some(hash),
+        _ => None,
+    }
+}
+
+impl DsProofSpender {
+    // Spender of input `input_idx` of a signed tx spending the P2PKH `spent_output`.
+    pub fn from_tx(tx: &Tx, input_idx: usize, spent_output: &TxOutput) -> Result<Self, DsProofError> {
+        if p2pkh_hash(spent_output.script.ops()).is_none() {
+            return Err(DsProofError::NotP2PKH);
+        }
+        let input = tx.inputs().get(input_idx)
+            .ok_or(DsProofError::InputOutOfRange { spender: 0, input_idx })?;
+        let signature = match input.script().ops().first() {
+            Some(Op::Push(signature)) => signature.clone(),
+            _ => return Err(DsProofError::MissingSignature { spender: 0 }),
+        };
+        let (_, sighash_type) = split_tx_signature(&signature)
+            .ok_or(DsProofError::MissingSignature { spender: 0 })?;
+        let pre_image = tx.pre_image(input_idx, spent_output, spent_output.script.clone(), sighash_type);
+        Ok(DsProofSpender {
+            tx_version: pre_image.version,
+            sequence: pre_image.sequence,
+            lock_time: pre_image.lock_time,
+            hash_prevouts: pre_image.hash_prevouts,
+            hash_sequence: pre_image.hash_sequence,
+            hash_outputs: pre_image.hash_outputs,
+            push_data: vec![signature],
+        })
+    }
+
+    pub fn read_from_stream<R: Read>(read: &mut R) -> io::Result<Self> {
+        let tx_version = read.read_i32::<LittleEndian>()?;
+        let sequence = read.read_u32::<LittleEndian>()?;
+        let lock_time = read.read_u32::<LittleEndian>()?;
+        let mut hash_prevouts = [0; 32];
+        read.read_exact(&mut hash_prevouts)?;
+        let mut hash_sequence = [0; 32];
+        read.read_exact(&mut hash_sequence)?;
+        let mut hash_outputs = [0; 32];
+        read.read_exact(&mut hash_outputs)?;
+        let num_pushes = read_var_int_minimal(read)?;
+        let mut push_data = Vec::new();
+        for _ in 0..num_pushes {
+            let len = read_var_int_minimal(read)?;
+            push_data.push(read_bytes(read, len)?);
+        }
+        Ok(DsProofSpender {
+            tx_version, sequence, lock_time, hash_prevouts, hash_sequence, hash_outputs, push_data,
+        })
+    }
+
+    pub fn write_to_stream<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        write.write_i32::<LittleEndian>(self.tx_version)?;
+        write.write_u32::<LittleEndian>(self.sequence)?;
+        write.write_u32::<LittleEndian>(self.lock_time)?;
+        write.write_all(&self.hash_prevouts)?;
+        write.write_all(&self.hash_sequence)?;
+        write.write_all(&self.hash_outputs)?;
+        write_var_int(write, self.push_data.len() as u64)?;
+        for push in self.push_data.iter() {
+            write_var_int(write, push.len() as u64)?;
+            write.write_all(push)?;
+        }
+        Ok(())
+    }
+
+    fn pre_image(&self, outpoint: &TxOutpoint, spent_output: &TxOutput, sighash_type: u32) -> PreImage {
+        PreImage {
+            version: self.tx_version,
+            hash_prevouts: self.hash_prevouts,
+            hash_sequence: self.hash_sequence,
+            outpoint: outpoint.clone(),
+            token: spent_output.token.clone(),
+            script_code: spent_output.script.clone(),
+            value: spent_output.value,
+            sequence: self.sequence,
+            hash_outputs: self.hash_outputs,
+            lock_time: self.lock_time,
+            sighash_type,
+        }
+    }
+
+    fn sort_key(&self) -> ([u8; 32], [u8; 32]) {
+        (self.hash_outputs, self.hash_prevouts)
+    }
+}
+
+impl DsProof {
+    // Proof from two txs spending the same P2PKH output in input `input_idx1` and
+    // `input_idx2` respectively.
+    pub fn new(tx1: &Tx,
+               input_idx1: usize,
+               tx2: &Tx,
+               input_idx2: usize,
+               spent_output: &TxOutput) -> Result<Self, DsProofError> {
+        let spender1 = DsProofSpender::from_tx(tx1, input_idx1, spent_output)
+            .map_err(|err| err.with_spender(1))?;
+        let spender2 = DsProofSpender::from_tx(tx2, input_idx2, spent_output)
+            .map_err(|err| err.with_spender(2))?;
+        let outpoint = tx1.inputs()[input_idx1].outpoint.clone();
+        if tx2.inputs()[input_idx2].outpoint != outpoint {
+            return Err(DsProofError::OutpointMismatch);
+        }
+        let (spender1, spender2) = if spender1.sort_key() <= spender2.sort_key() {
+            (spender1, spender2)
+        } else {
+            (spender2, spender1)
+        };
+        if spender1 == spender2 {
+            return Err(DsProofError::IdenticalSpenders);
+        }
+        Ok(DsProof { outpoint, spender1, spender2 })
+    }
+
+    pub fn read_from_stream<R: Read>(read: &mut R) -> io::Result<Self> {
+        let mut tx_hash = [0; 32];
+        read.read_exact(&mut tx_hash)?;
+        let vout = read.read_u32::<LittleEndian>()?;
+        let spender1 = DsProofSpender::read_from_stream(read)?;
+        let spender2 = DsProofSpender::read_from_stream(read)?;
+        Ok(DsProof { outpoint: TxOutpoint { tx_hash, vout }, spender1, spender2 })
+    }
+
+    pub fn write_to_stream<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        write.write_all(&self.outpoint.tx_hash)?;
+        write.write_u32::<LittleEndian>(self.outpoint.vout)?;
+        self.spender1.write_to_stream(write)?;
+        self.spender2.write_to_stream(write)
+    }
+
+    // Proof id, as used in inv messages and the RPC interface.
+    pub fn hash(&self) -> [u8; 32] {
+        let mut hasher = HashWriter::new();
+        self.write_to_stream(&mut hasher).unwrap();
+        hasher.finish_double()
+    }
+
+    // Checks that both spenders validly sign a spend of `spent_output` (the output at
+    // `outpoint`) with `pub_key`, which has to be taken from one of the conflicting txs.
+    pub fn validate<C: Crypto>(&self,
+                               crypto: &C,
+                               spent_output: &TxOutput,
+                               pub_key: &[u8]) -> Result<(), DsProofError> {
+        let pub_key_hash = p2pkh_hash(spent_output.script.ops()).ok_or(DsProofError::NotP2PKH)?;
+        if hash160(pub_key)[..] != *pub_key_hash {
+            return Err(DsProofError::PubKeyMismatch);
+        }
+        if self.spender1 == self.spender2 {
+            return Err(DsProofError::IdenticalSpenders);
+        }
+        if self.spender1.sort_key() > self.spender2.sort_key() {
+            return Err(DsProofError::NonCanonicalOrder);
+        }
+        for (spender_idx, spender) in [&self.spender1, &self.spender2].iter().enumerate() {
+            let spender_num = spender_idx + 1;
+            let (sig, sighash_type) = match spender.push_data.as_slice() {
+                [signature] => split_tx_signature(signature)
+                    .ok_or(DsProofError::MissingSignature { spender: spender_num })?,
+                _ => return Err(DsProofError::MissingSignature { spender: spender_num }),
+            };
+            let sig_hash = spender.pre_image(&self.outpoint, spent_output, sighash_type).sighash();
+            if !crypto.verify(&sig_hash, sig, pub_key) {
+                return Err(DsProofError::InvalidSignature { spender: spender_num });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "secp256k1"))]
+mod tests {
+    use super::*;
+    use crate::address::{Address, AddressType};
+    use crate::crypto::secp256k1::CryptoSecp256k1;
+    use crate::outputs::P2PKHOutput;
+    use crate::unsigned_tx::{UnsignedTx, UnsignedInput, Output};
+
+    #[test]
+    fn test_dsproof() {
+        let crypto = CryptoSecp256k1::new();
+        let secret_key = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let pub_key = crypto.secret_to_pub_key(&secret_key).serialize();
+        let address = Address::from_serialized_pub_key("bitcoincash", AddressType::P2PKH, &pub_key);
+        let spent_output = P2PKHOutput { value: 10_000, address: address.clone() };
+        let spend = |value| {
+            let mut tx_build = UnsignedTx::new_simple();
+            tx_build.add_input(UnsignedInput::new(
+                TxOutpoint { tx_hash: [0x11; 32], vout: 0 },
+                Box::new(spent_output.clone()),
+                0xffff_ffff,
+            ));
+            tx_build.add_output(P2PKHOutput { value, address: address.clone() }.to_output());
+            tx_build.sign_with(&crypto, &secret_key).unwrap()
+        };
+        let (tx1, tx2) = (spend(9_000), spend(8_000));
+        let spent_output = spent_output.to_output();
+        let proof = DsProof::new(&tx1, 0, &tx2, 0, &spent_output).unwrap();
+        assert_eq!(DsProof::new(&tx2, 0, &tx1, 0, &spent_output), Ok(proof.clone()));
+        assert_eq!(proof.validate(&crypto, &spent_output, &pub_key), Ok(()));
+
+        let mut serialized = Vec::new();
+        proof.write_to_stream(&mut serialized).unwrap();
+        let mut parsed = DsProof::read_from_stream(&mut io::Cursor::new(&serialized)).unwrap();
+        assert_eq!(parsed, proof);
+        parsed.spender2.hash_outputs = parsed.spender1.hash_outputs;
+        assert_eq!(parsed.validate(&crypto, &spent_output, &pub_key),
+                   Err(DsProofError::InvalidSignature { spender: 2 }));
+        assert_eq!(DsProof::new(&tx1, 0, &tx1, 0, &spent_output), Err(DsProofError::IdenticalSpenders));
+        assert_eq!(DsProof::new(&tx1, 0, &tx2, 1, &spent_output),
+                   Err(DsProofError::InputOutOfRange { spender: 2, input_idx: 1 }));
+        let swapped = DsProof { spender1: proof.spender2.clone(), spender2: proof.spender1.clone(), ..proof };
+        assert_eq!(swapped.validate(&crypto, &spent_output, &pub_key), Err(DsProofError::NonCanonicalOrder));
+    }
+}
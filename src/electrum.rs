@@ -0,0 +1,245 @@
+use crate::backend::{ChainBackend, BackendError, ScriptHashStatus};
+use crate::tx::{Tx, tx_hash_to_hex, tx_hex_to_hash};
+use crate::wallet::UtxoEntry;
+use crate::cash_tokens::{TokenData, NFT, NFTCapability};
+use crate::hex_traits::{FromHex, ToHex};
+
+use std::collections::VecDeque;
+use std::io;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::Mutex;
+
+
+const CLIENT_NAME: &str = "cashcontracts";
+const PROTOCOL_VERSION: &str = "1.4";
+
+struct Connection {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    next_id: u64,
+    notifications: VecDeque<Value>,
+}
+
+// Electrum protocol client over plain TCP, e.g. for Fulcrum or ElectrsCash.
+// Requests are sent one at a time; notifications received in between are queued
+// until next_notification is called.
+pub struct ElectrumClient {
+    connection: Mutex<Connection>,
+}
+
+impl From<io::Error> for BackendError {
+    fn from(err: io::Error) -> Self {
+        BackendError::Io(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for BackendError {
+    fn from(err: serde_json::Error) -> Self {
+        BackendError::InvalidResponse(err.to_string())
+    }
+}
+
+// Electrum displays script hashes byte-reversed, like tx hashes.
+fn script_hash_hex(script_hash: &[u8; 32]) -> String {
+    tx_hash_to_hex(script_hash)
+}
+
+fn invalid_response(value: &Value) -> BackendError {
+    BackendError::InvalidResponse(value.to_string())
+}
+
+fn parse_hash(value: &Value) -> Result<[u8; 32], BackendError> {
+    value.as_str()
+        .and_then(tx_hex_to_hash)
+        .ok_or_else(|| invalid_response(value))
+}
+
+fn parse_status(value: &Value) -> Result<ScriptHashStatus, BackendError> {
+    match value {
+        Value::Null => Ok(None),
+        _ => Ok(Some(parse_hash(value)?)),
+    }
+}
+
+// Fulcrum's "token_data": category (byte-reversed hex), amount (decimal string) and nft.
+fn parse_token_data(value: &Value) -> Result<Option<TokenData>, BackendError> {
+    if value.is_null() {
+        return Ok(None);
+    }
+    let amount = match &value["amount"] {
+        Value::String(amount) => amount.parse().map_err(|_| invalid_response(value))?,
+        amount => amount.as_u64().unwrap_or(0),
+    };
+    let nft = match &value["nft"] {
+        Value::Null => None,
+        nft => Some(NFT {
+            capability: match nft["capability"].as_str() {
+                Some("none") => NFTCapability::None,
+                Some("mutable") => NFTCapability::Mutable,
+                Some("minting") => NFTCapability::Minting,
+                _ => return Err(invalid_response(value)),
+            },
+            commitment: Vec::<u8>::from_hex(nft["commitment"].as_str().unwrap_or(""))
+                .map_err(|_| invalid_response(value))?,
+        }),
+    };
+    Ok(Some(TokenData { category: parse_hash(&value["category"])?, amount, nft }))
+}
+
+fn parse_utxo(value: &Value) -> Result<UtxoEntry, BackendError> {
+    let tx_id_hex = value["tx_hash"].as_str().ok_or_else(|| invalid_response(value))?;
+    let vout = value["tx_pos"].as_u64().ok_or_else(|| invalid_response(value))?;
+    let amount = value["value"].as_u64().ok_or_else(|| invalid_response(value))?;
+    // Electrum reports unconfirmed UTXOs with height 0 (or -1 for unconfirmed parents).
+    let height = value["height"].as_u64().filter(|&height| height > 0);
+    Ok(UtxoEntry {
+        tx_id_hex: tx_id_hex.to_string(),
+        vout: vout as u32,
+        amount,
+        height: height.map(|height| height as u32),
+        script: None,
+        is_coinbase: false,
+        slp_token: None,
+        token: parse_token_data(&value["token_data"])?,
+    })
+}
+
+impl Connection {
+    async fn read_message(&mut self) -> Result<Value, BackendError> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            return Err(BackendError::Closed);
+        }
+        Ok(serde_json::from_str(&line)?)
+    }
+
+    async fn request(&mut self, method: &str, params: Value) -> Result<Value, BackendError> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut request = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        }).to_string();
+        request.push('\n');
+        self.writer.write_all(request.as_bytes()).await?;
+        loop {
+            let mut message = self.read_message().await?;
+            if message.get("method").is_some() {
+                self.notifications.push_back(message);
+                continue;
+            }
+            if message["id"].as_u64() != Some(id) {
+                continue;
+            }
+            if let Some(error) = message.get("error").filter(|error| !error.is_null()) {
+                return Err(BackendError::Rpc {
+                    code: error["code"].as_i64().unwrap_or(0),
+                    message: error["message"].as_str().unwrap_or_default().to_string(),
+                });
+            }
+            return Ok(message["result"].take());
+        }
+    }
+
+    async fn next_notification(&mut self) -> Result<Value, BackendError> {
+        if let Some(notification) = self.notifications.pop_front() {
+            return Ok(notification);
+        }
+        loop {
+            let message = self.read_message().await?;
+            if message.get("method").is_some() {
+                return Ok(message);
+            }
+        }
+    }
+}
+
+impl ElectrumClient {
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self, BackendError> {
+        let (reader, writer) = TcpStream::connect(addr).await?.into_split();
+        let client = ElectrumClient {
+            connection: Mutex::new(Connection {
+                reader: BufReader::new(reader),
+                writer,
+                next_id: 0,
+                notifications: VecDeque::new(),
+            }),
+        };
+        client.request("server.version", json!([CLIENT_NAME, PROTOCOL_VERSION])).await?;
+        Ok(client)
+    }
+
+    pub async fn request(&self, method: &str, params: Value) -> Result<Value, BackendError> {
+        self.connection.lock().await.request(method, params).await
+    }
+
+    // Mempool fee histogram as (sat/byte, size) pairs, to be fed into a FeeEstimator.
+    pub async fn fee_histogram(&self) -> Result<Vec<(f64, u64)>, BackendError> {
+        let result = self.request("mempool.get_fee_histogram", json!([])).await?;
+        result.as_array()
+            .ok_or_else(|| invalid_response(&result))?
+            .iter()
+            .map(|bucket| match (bucket[0].as_f64(), bucket[1].as_u64()) {
+                (Some(fee_rate), Some(size)) => Ok((fee_rate, size)),
+                _ => Err(invalid_response(bucket)),
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl ChainBackend for ElectrumClient {
+    async fn utxos(&self, script_hash: &[u8; 32]) -> Result<Vec<UtxoEntry>, BackendError> {
+        let result = self.request("blockchain.scripthash.listunspent",
+                                  json!([script_hash_hex(script_hash)])).await?;
+        result.as_array()
+            .ok_or_else(|| invalid_response(&result))?
+            .iter()
+            .map(parse_utxo)
+            .collect()
+    }
+
+    async fn tx(&self, tx_hash: &[u8; 32]) -> Result<Tx, BackendError> {
+        let result = self.request("blockchain.transaction.get",
+                                  json!([tx_hash_to_hex(tx_hash)])).await?;
+        result.as_str()
+            .and_then(|tx_hex| Tx::from_hex(tx_hex).ok())
+            .ok_or_else(|| invalid_response(&result))
+    }
+
+    async fn chain_height(&self) -> Result<u32, BackendError> {
+        let result = self.request("blockchain.headers.subscribe", json!([])).await?;
+        result["height"].as_u64()
+            .map(|height| height as u32)
+            .ok_or_else(|| invalid_response(&result))
+    }
+
+    async fn broadcast(&self, tx: &Tx) -> Result<[u8; 32], BackendError> {
+        let result = self.request("blockchain.transaction.broadcast",
+                                  json!([tx.to_hex()])).await?;
+        parse_hash(&result)
+    }
+
+    async fn subscribe(&self, script_hash: &[u8; 32]) -> Result<ScriptHashStatus, BackendError> {
+        let result = self.request("blockchain.scripthash.subscribe",
+                                  json!([script_hash_hex(script_hash)])).await?;
+        parse_status(&result)
+    }
+
+    async fn next_notification(&self) -> Result<([u8; 32], ScriptHashStatus), BackendError> {
+        loop {
+            let notification = self.connection.lock().await.next_notification().await?;
+            if notification["method"] != "blockchain.scripthash.subscribe" {
+                continue;
+            }
+            let params = &notification["params"];
+            return Ok((parse_hash(&params[0])?, parse_status(&params[1])?));
+        }
+    }
+}
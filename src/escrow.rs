@@ -0,0 +1,231 @@
+use crate::unsigned_tx::{PreImage, SCHNORR_SIGNATURE_SIZE};
+use crate::spend::{LockingScript, Unlocker};
+use crate::script::{Script, Op};
+use crate::tx::TxOutput;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EscrowParty {
+    Buyer,
+    Seller,
+    Arbiter,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EscrowScheme {
+    // OP_2 <buyer> <seller> <arbiter> OP_3 OP_CHECKMULTISIG
+    TwoOfThree,
+    // buyer+seller, or arbiter together with either buyer or seller
+    BuyerSellerOrArbiter,
+}
+
+#[derive(Clone, Debug)]
+pub struct EscrowOutput {
+    pub value: u64,
+    pub buyer_pk: Vec<u8>,
+    pub seller_pk: Vec<u8>,
+    pub arbiter_pk: Vec<u8>,
+    pub scheme: EscrowScheme,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EscrowSpendParams {
+    signer: EscrowParty,
+    cosigner: EscrowParty,
+    cosigner_sig: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EscrowError {
+    SameParty(EscrowParty),
+}
+
+impl EscrowSpendParams {
+    // `signer` signs the tx, `cosigner_sig` is the serialized signature (with sighash byte)
+    // of the other party. With Schnorr signatures, both parties have to sign with Schnorr.
+    pub fn new(signer: EscrowParty,
+               cosigner: EscrowParty,
+               cosigner_sig: Vec<u8>) -> Result<Self, EscrowError> {
+        if signer == cosigner {
+            return Err(EscrowError::SameParty(signer));
+        }
+        Ok(EscrowSpendParams { signer, cosigner, cosigner_sig })
+    }
+
+    pub fn signer(&self) -> EscrowParty {
+        self.signer
+    }
+
+    pub fn cosigner(&self) -> EscrowParty {
+        self.cosigner
+    }
+
+    pub fn cosigner_sig(&self) -> &[u8] {
+        &self.cosigner_sig
+    }
+}
+
+// CHECKMULTISIG dummy for `sigs` checked against the keys of `parties`: empty for ECDSA, the
+// checkbits selecting the signing keys for Schnorr.
+fn multisig_dummy(parties: &[EscrowParty], sigs: &[(EscrowParty, Vec<u8>)]) -> Vec<u8> {
+    if !sigs.iter().all(|(_, sig)| sig.len() == SCHNORR_SIGNATURE_SIZE) {
+        return vec![];
+    }
+    let checkbits = parties.iter()
+        .enumerate()
+        .filter(|(_, party)| sigs.iter().any(|(signer, _)| signer == *party))
+        .fold(0u8, |checkbits, (idx, _)| checkbits | 1 << idx);
+    vec![checkbits]
+}
+
+impl EscrowOutput {
+    pub fn party_pk(&self, party: EscrowParty) -> &[u8] {
+        match party {
+            EscrowParty::Buyer => &self.buyer_pk,
+            EscrowParty::Seller => &self.seller_pk,
+            EscrowParty::Arbiter => &self.arbiter_pk,
+        }
+    }
+
+    fn _ops(&self) -> Vec<Op> {
+        use crate::script::OpCodeType::*;
+        match self.scheme {
+            EscrowScheme::TwoOfThree => vec![
+                Op::Code(Op2),
+                Op::Push(self.buyer_pk.clone()),
+                Op::Push(self.seller_pk.clone()),
+                Op::Push(self.arbiter_pk.clone()),
+                Op::Code(Op3),
+                Op::Code(OpCheckMultiSig),
+            ],
+            EscrowScheme::BuyerSellerOrArbiter => vec![
+                Op::Code(OpIf),
+                Op::Push(self.buyer_pk.clone()),
+                Op::Code(OpCheckSigVerify),
+                Op::Push(self.seller_pk.clone()),
+                Op::Code(OpCheckSig),
+                Op::Code(OpElse),
+                Op::Push(self.arbiter_pk.clone()),
+                Op::Code(OpCheckSigVerify),
+                Op::Code(Op1),
+                Op::Push(self.buyer_pk.clone()),
+                Op::Push(self.seller_pk.clone()),
+                Op::Code(Op2),
+                Op::Code(OpCheckMultiSig),
+                Op::Code(OpEndIf),
+            ],
+        }
+    }
+}
+
+impl LockingScript for EscrowOutput {
+    fn value(&self) -> u64 {
+        self.value
+    }
+
+    fn script(&self) -> Script {
+        Script::new(self._ops())
+    }
+
+    fn script_code(&self) -> Script {
+        self.script()
+    }
+}
+
+impl Unlocker<EscrowOutput> for EscrowSpendParams {
+    fn sig_script(&self,
+                  output: &EscrowOutput,
+                  serialized_sig: Vec<u8>,
+                  _serialized_pub_key: Vec<u8>,
+                  _pre_image: &PreImage,
+                  _outputs: &[TxOutput]) -> Script {
+        let mut sigs = vec![
+            (self.signer, serialized_sig),
+            (self.cosigner, self.cosigner_sig.clone()),
+        ];
+        sigs.sort_by_key(|(party, _)| *party);
+        let uses_arbiter = sigs[1].0 == EscrowParty::Arbiter;
+        let mut ops = Vec::new();
+        match output.scheme {
+            EscrowScheme::TwoOfThree => {
+                let parties = [EscrowParty::Buyer, EscrowParty::Seller, EscrowParty::Arbiter];
+                ops.push(Op::Push(multisig_dummy(&parties, &sigs)));
+                ops.extend(sigs.into_iter().map(|(_, sig)| Op::Push(sig)));
+            },
+            EscrowScheme::BuyerSellerOrArbiter if uses_arbiter => {
+                let dummy = multisig_dummy(&[EscrowParty::Buyer, EscrowParty::Seller], &sigs[..1]);
+                let mut sigs = sigs.into_iter().map(|(_, sig)| Op::Push(sig));
+                let party_sig = sigs.next().unwrap();
+                let arbiter_sig = sigs.next().unwrap();
+                ops.append(&mut vec![
+                    Op::Push(dummy),
+                    party_sig,
+                    arbiter_sig,
+                    Op::Push(vec![]),
+                ]);
+            },
+            EscrowScheme::BuyerSellerOrArbiter => {
+                ops.extend(sigs.into_iter().rev().map(|(_, sig)| Op::Push(sig)));
+                ops.push(Op::Push(vec![1]));
+            },
+        }
+        Script::new(ops)
+    }
+}
+
+#[cfg(all(test, feature = "secp256k1"))]
+mod tests {
+    use super::*;
+    use crate::crypto::Crypto;
+    use crate::crypto::secp256k1::CryptoSecp256k1;
+    use crate::script_debugger::ScriptDebugger;
+    use crate::spend::Spend;
+    use crate::tx::TxOutpoint;
+    use crate::unsigned_tx::{UnsignedTx, UnsignedInput};
+
+    #[test]
+    fn test_escrow_spend() {
+        use self::EscrowParty::*;
+        let crypto = CryptoSecp256k1::new();
+        let key = |party| secp256k1::SecretKey::from_slice(&[party as u8 + 1; 32]).unwrap();
+        let pub_key = |party| crypto.secret_to_pub_key(&key(party)).serialize().to_vec();
+        let pairs = [(Buyer, Seller), (Seller, Buyer), (Buyer, Arbiter), (Arbiter, Seller)];
+        for &scheme in &[EscrowScheme::TwoOfThree, EscrowScheme::BuyerSellerOrArbiter] {
+            let output = EscrowOutput {
+                value: 10_000,
+                buyer_pk: pub_key(Buyer),
+                seller_pk: pub_key(Seller),
+                arbiter_pk: pub_key(Arbiter),
+                scheme,
+            };
+            let spent_output = TxOutput { value: output.value, script: output.script(), token: None };
+            for &(signer, cosigner) in &pairs {
+                let tx_build = |cosigner_sig| {
+                    let params = EscrowSpendParams::new(signer, cosigner, cosigner_sig).unwrap();
+                    let mut tx_build = UnsignedTx::new_simple();
+                    tx_build.add_input(UnsignedInput::new(
+                        TxOutpoint { tx_hash: [0x11; 32], vout: 0 },
+                        Box::new(Spend::new(output.clone(), params)),
+                        0xffff_ffff,
+                    ));
+                    tx_build.add_output(TxOutput { value: 9_000, script: output.script(), token: None });
+                    tx_build
+                };
+                let sighash = tx_build(vec![]).pre_images(0x41)[0].sighash();
+                let cosigner_sig = [crypto.sign(&sighash, &key(cosigner)).serialize_der().to_vec(), vec![0x41]].concat();
+                let tx = tx_build(cosigner_sig).sign_with(&crypto, &key(signer)).unwrap();
+                assert_eq!(ScriptDebugger::new(&crypto, &tx, 0, &spent_output).run(), Ok(()));
+            }
+
+            // Schnorr multisig selects the signing keys with the checkbits dummy
+            let params = EscrowSpendParams::new(Arbiter, Seller, vec![0x22; 65]).unwrap();
+            let sig_script = params.sig_script(&output, vec![0x33; 65], vec![], &PreImage::empty(output.script()), &[]);
+            let expected_dummy = match scheme {
+                EscrowScheme::TwoOfThree => vec![0b110],
+                EscrowScheme::BuyerSellerOrArbiter => vec![0b10],
+            };
+            assert_eq!(sig_script.ops()[0], Op::Push(expected_dummy));
+        }
+        assert_eq!(EscrowSpendParams::new(Buyer, Buyer, vec![]), Err(EscrowError::SameParty(Buyer)));
+    }
+}
@@ -0,0 +1,136 @@
+use crate::wallet::Wallet;
+
+
+pub const DEFAULT_BLOCK_SIZE: u64 = 32_000_000;
+pub const MIN_FEE_PER_KB: u64 = 1000;
+// Blocks within which an economical fee rate should confirm.
+pub const ECONOMICAL_BLOCKS: u64 = 6;
+// Number of recent blocks remembered by the estimator.
+pub const MAX_RECENT_BLOCKS: usize = 12;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeeTarget {
+    NextBlock,
+    Economical,
+}
+
+// Recommends fee rates from the mempool fee histogram and the lowest fee rates recently
+// mined, never going below the minimum relay fee.
+#[derive(Clone, Debug)]
+pub struct FeeEstimator {
+    // (fee rate in sat/byte, size in bytes) sorted by descending fee rate, as returned by
+    // Electrum's mempool.get_fee_histogram.
+    histogram: Vec<(f64, u64)>,
+    // Lowest fee rate in sat/byte of each recent block, oldest first.
+    recent_blocks: Vec<f64>,
+    block_size: u64,
+    min_fee_per_kb: u64,
+}
+
+impl Default for FeeEstimator {
+    fn default() -> Self {
+        FeeEstimator {
+            histogram: Vec::new(),
+            recent_blocks: Vec::new(),
+            block_size: DEFAULT_BLOCK_SIZE,
+            min_fee_per_kb: MIN_FEE_PER_KB,
+        }
+    }
+}
+
+fn sat_per_byte_to_kb(fee_rate: f64) -> u64 {
+    (fee_rate * 1000.0).ceil() as u64
+}
+
+impl FeeEstimator {
+    pub fn new() -> Self {
+        FeeEstimator::default()
+    }
+
+    // Block space available per block, e.g. lower if miners produce smaller blocks.
+    pub fn with_block_size(mut self, block_size: u64) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    pub fn with_min_fee_rate(mut self, min_fee_per_kb: u64) -> Self {
+        self.min_fee_per_kb = min_fee_per_kb;
+        self
+    }
+
+    pub fn set_fee_histogram(&mut self, mut histogram: Vec<(f64, u64)>) {
+        histogram.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+        self.histogram = histogram;
+    }
+
+    // Records the lowest fee rate (sat/byte) of a newly mined block, ignoring the coinbase.
+    pub fn add_block(&mut self, min_fee_rate: f64) {
+        self.recent_blocks.push(min_fee_rate);
+        if self.recent_blocks.len() > MAX_RECENT_BLOCKS {
+            self.recent_blocks.remove(0);
+        }
+    }
+
+    // Fee rate of the last histogram bucket which still fits into `num_blocks` blocks, i.e.
+    // paying more than this gets ahead of everything that doesn't fit.
+    fn mempool_fee_per_kb(&self, num_blocks: u64) -> u64 {
+        let capacity = self.block_size.saturating_mul(num_blocks);
+        let mut size = 0u64;
+        for &(fee_rate, bucket_size) in self.histogram.iter() {
+            size = size.saturating_add(bucket_size);
+            if size >= capacity {
+                return sat_per_byte_to_kb(fee_rate);
+            }
+        }
+        0
+    }
+
+    fn recent_blocks_fee_per_kb(&self, target: FeeTarget) -> u64 {
+        let mut rates = self.recent_blocks.clone();
+        rates.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let rate = match target {
+            FeeTarget::NextBlock => rates.last(),
+            FeeTarget::Economical => rates.get(rates.len() / 2),
+        };
+        rate.map(|&rate| sat_per_byte_to_kb(rate)).unwrap_or(0)
+    }
+
+    pub fn fee_per_kb(&self, target: FeeTarget) -> u64 {
+        let num_blocks = match target {
+            FeeTarget::NextBlock => 1,
+            FeeTarget::Economical => ECONOMICAL_BLOCKS,
+        };
+        self.mempool_fee_per_kb(num_blocks)
+            .max(self.recent_blocks_fee_per_kb(target))
+            .max(self.min_fee_per_kb)
+    }
+}
+
+impl Wallet {
+    pub fn with_estimated_fee_rate(self, estimator: &FeeEstimator, target: FeeTarget) -> Self {
+        self.with_fee_rate(estimator.fee_per_kb(target))
+    }
+
+    pub fn set_estimated_fee_rate(&mut self, estimator: &FeeEstimator, target: FeeTarget) {
+        self.set_fee_rate(estimator.fee_per_kb(target));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fee_estimator() {
+        let mut estimator = FeeEstimator::new().with_block_size(1_000_000);
+        assert_eq!(estimator.fee_per_kb(FeeTarget::NextBlock), MIN_FEE_PER_KB);
+        estimator.set_fee_histogram(vec![(1.0, 5_000_000), (5.0, 600_000), (2.5, 500_000)]);
+        assert_eq!(estimator.fee_per_kb(FeeTarget::NextBlock), 2500);
+        assert_eq!(estimator.fee_per_kb(FeeTarget::Economical), 1000);
+        estimator.add_block(1.2);
+        estimator.add_block(3.0);
+        estimator.add_block(1.5);
+        assert_eq!(estimator.fee_per_kb(FeeTarget::NextBlock), 3000);
+        assert_eq!(estimator.fee_per_kb(FeeTarget::Economical), 1500);
+    }
+}
@@ -1,6 +1,8 @@
-use sha2::{Sha256, Digest};
+use sha2::{Sha256, Sha512, Digest};
 use ripemd160::Ripemd160;
 
+use std::io;
+
 pub fn single_sha256(data: &[u8]) -> [u8; 32] {
     let sha = Sha256::digest(data);
     let mut arr = [0; 32];
@@ -16,8 +18,109 @@ pub fn double_sha256(data: &[u8]) -> [u8; 32] {
     arr
 }
 
+// io::Write adapter which feeds everything written into SHA256, so serializations can be
+// hashed without buffering them.
+#[derive(Clone, Default)]
+pub struct HashWriter {
+    sha: Sha256,
+}
+
+impl HashWriter {
+    pub fn new() -> Self {
+        HashWriter::default()
+    }
+
+    pub fn finish_single(self) -> [u8; 32] {
+        let mut arr = [0; 32];
+        arr.copy_from_slice(&self.sha.result()[..]);
+        arr
+    }
+
+    pub fn finish_double(self) -> [u8; 32] {
+        single_sha256(&self.finish_single())
+    }
+}
+
+impl io::Write for HashWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sha.input(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 pub fn hash160(data: &[u8]) -> [u8; 20] {
     let mut arr = [0; 20];
     arr.copy_from_slice(&Ripemd160::digest(&Sha256::digest(data)));
     arr
 }
+
+const SHA512_BLOCK_SIZE: usize = 128;
+
+// HMAC-SHA512 (RFC 2104), as used by BIP32 key derivation.
+pub fn hmac_sha512(key: &[u8], data: &[u8]) -> [u8; 64] {
+    let mut key_block = [0; SHA512_BLOCK_SIZE];
+    if key.len() > SHA512_BLOCK_SIZE {
+        key_block[..64].copy_from_slice(&Sha512::digest(key));
+    } else {
+        key_block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = Sha512::new();
+    inner.input(key_block.iter().map(|b| b ^ 0x36).collect::<Vec<_>>());
+    inner.input(data);
+    let mut outer = Sha512::new();
+    outer.input(key_block.iter().map(|b| b ^ 0x5c).collect::<Vec<_>>());
+    outer.input(inner.result());
+    let mut arr = [0; 64];
+    arr.copy_from_slice(&outer.result());
+    arr
+}
+
+// PBKDF2 (RFC 8018) with HMAC-SHA512, as used by BIP39 seeds; fills all of `output`.
+pub fn pbkdf2_hmac_sha512(password: &[u8], salt: &[u8], iterations: u32, output: &mut [u8]) {
+    for (block_idx, chunk) in output.chunks_mut(64).enumerate() {
+        let mut salt_block = salt.to_vec();
+        salt_block.extend_from_slice(&(block_idx as u32 + 1).to_be_bytes());
+        let mut u = hmac_sha512(password, &salt_block);
+        let mut block = u;
+        for _ in 1..iterations {
+            u = hmac_sha512(password, &u);
+            block.iter_mut().zip(u.iter()).for_each(|(b, u)| *b ^= u);
+        }
+        chunk.copy_from_slice(&block[..chunk.len()]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hmac_pbkdf2_vectors() {
+        // RFC 4231 test cases 2 and 6
+        assert_eq!(hex::encode(&hmac_sha512(b"Jefe", b"what do ya want for nothing?")[..]),
+                   "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea250554\
+                    9758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737");
+        assert_eq!(hex::encode(&hmac_sha512(&[0xaa; 131], b"Test Using Larger Than Block-Size Key - Hash Key First")[..]),
+                   "80b24263c7c1a3ebb71493c1dd7be8b49b46d1f41b4aeec1121b013783f8f352\
+                    6b56d037e05f2598bd0fd2215d6a1e5295e64f73f63f0aec8b915a985d786598");
+        let mut output = [0; 64];
+        pbkdf2_hmac_sha512(b"password", b"salt", 2, &mut output);
+        assert_eq!(hex::encode(&output[..]),
+                   "e1d9c16aa681708a45f5c7c4e215ceb66e011a2e9f0040713f18aefdb866d53c\
+                    f76cab2868a39b9f7840edce4fef5a82be67335c77a6068e04112754f27ccf4e");
+    }
+
+    #[test]
+    fn test_hash_writer() {
+        use std::io::Write;
+        let mut hasher = HashWriter::new();
+        hasher.write_all(b"hello ").unwrap();
+        hasher.write_all(b"world").unwrap();
+        assert_eq!(hasher.clone().finish_single(), single_sha256(b"hello world"));
+        assert_eq!(hasher.finish_double(), double_sha256(b"hello world"));
+    }
+}
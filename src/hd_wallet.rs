@@ -0,0 +1,256 @@
+use crate::address::{Address, AddressType};
+use crate::bip32::{Bip32Error, ChildNumber, ExtendedPubKey};
+use crate::bip44::{Bip44Keychain, Chain};
+use crate::unsigned_tx::{UnsignedTx, Output};
+use crate::outputs::P2PKHOutput;
+use crate::wallet::{UtxoEntry, DUST_AMOUNT, DEFAULT_FEE_PER_KB, WALLET_TX_VERSION, add_p2pkh_inputs};
+
+use std::collections::HashSet;
+
+
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+
+// Source of UTXOs used for address discovery, e.g. an indexer or a local node.
+pub trait UtxoSource {
+    type Error;
+
+    fn utxos(&self, address: &Address) -> Result<Vec<UtxoEntry>, Self::Error>;
+
+    // Addresses without UTXOs but with past transactions still count as used.
+    fn has_history(&self, _address: &Address) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HdWalletError {
+    Bip32(Bip32Error),
+    InsufficientFunds(u64),
+    DustOutput { idx: usize },
+    NoUtxos,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScanError<E> {
+    Bip32(Bip32Error),
+    Source(E),
+}
+
+#[derive(Clone, Debug)]
+pub struct HdUtxo {
+    pub utxo: UtxoEntry,
+    pub address: Address,
+    pub chain: Chain,
+    pub index: u32,
+}
+
+// BIP44 account wallet with a receive and a change chain.
+pub struct HdWallet {
+    keychain: Bip44Keychain,
+    account: u32,
+    receive_xpub: ExtendedPubKey,
+    change_xpub: ExtendedPubKey,
+    used: HashSet<(Chain, u32)>,
+    gap_limit: u32,
+    fee_per_kb: u64,
+    dust_limit: u64,
+}
+
+impl From<Bip32Error> for HdWalletError {
+    fn from(err: Bip32Error) -> Self {
+        HdWalletError::Bip32(err)
+    }
+}
+
+impl<E> From<Bip32Error> for ScanError<E> {
+    fn from(err: Bip32Error) -> Self {
+        ScanError::Bip32(err)
+    }
+}
+
+impl HdWallet {
+    pub fn new(keychain: Bip44Keychain, account: u32) -> Result<Self, Bip32Error> {
+        let account_xpub = keychain.account_xpub(account)?;
+        let secp = keychain.secp();
+        Ok(HdWallet {
+            receive_xpub: account_xpub.derive_child(secp, ChildNumber::normal(Chain::Receive.index())?)?,
+            change_xpub: account_xpub.derive_child(secp, ChildNumber::normal(Chain::Change.index())?)?,
+            keychain,
+            account,
+            used: HashSet::new(),
+            gap_limit: DEFAULT_GAP_LIMIT,
+            fee_per_kb: DEFAULT_FEE_PER_KB,
+            dust_limit: DUST_AMOUNT,
+        })
+    }
+
+    pub fn with_gap_limit(mut self, gap_limit: u32) -> Self {
+        self.gap_limit = gap_limit;
+        self
+    }
+
+    pub fn with_fee_rate(mut self, fee_per_kb: u64) -> Self {
+        self.fee_per_kb = fee_per_kb;
+        self
+    }
+
+    pub fn with_dust_limit(mut self, dust_limit: u64) -> Self {
+        self.dust_limit = dust_limit;
+        self
+    }
+
+    pub fn keychain(&self) -> &Bip44Keychain {
+        &self.keychain
+    }
+
+    pub fn account(&self) -> u32 {
+        self.account
+    }
+
+    pub fn gap_limit(&self) -> u32 {
+        self.gap_limit
+    }
+
+    pub fn address(&self, chain: Chain, index: u32) -> Result<Address, Bip32Error> {
+        let chain_xpub = match chain {
+            Chain::Receive => &self.receive_xpub,
+            Chain::Change => &self.change_xpub,
+        };
+        let xpub = chain_xpub.derive_child(self.keychain.secp(), ChildNumber::normal(index)?)?;
+        Ok(Address::from_serialized_pub_key(self.keychain.prefix(),
+                                            AddressType::P2PKH,
+                                            &xpub.public_key.serialize()))
+    }
+
+    pub fn mark_used(&mut self, chain: Chain, index: u32) {
+        self.used.insert((chain, index));
+    }
+
+    pub fn is_used(&self, chain: Chain, index: u32) -> bool {
+        self.used.contains(&(chain, index))
+    }
+
+    // Index following the highest used index, so addresses are never reused.
+    pub fn next_index(&self, chain: Chain) -> u32 {
+        self.used.iter()
+            .filter(|(used_chain, _)| *used_chain == chain)
+            .map(|&(_, index)| index + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn receive_address(&self) -> Result<Address, Bip32Error> {
+        self.address(Chain::Receive, self.next_index(Chain::Receive))
+    }
+
+    pub fn change_address(&self) -> Result<Address, Bip32Error> {
+        self.address(Chain::Change, self.next_index(Chain::Change))
+    }
+
+    // Scans both chains until `gap_limit` consecutive unused addresses are found.
+    pub fn discover<S: UtxoSource>(&mut self, source: &S) -> Result<Vec<HdUtxo>, ScanError<S::Error>> {
+        let mut hd_utxos = Vec::new();
+        for &chain in &[Chain::Receive, Chain::Change] {
+            let mut gap = 0;
+            let mut index = 0;
+            while gap < self.gap_limit {
+                let address = self.address(chain, index)?;
+                let utxos = source.utxos(&address).map_err(ScanError::Source)?;
+                if !utxos.is_empty() || source.has_history(&address).map_err(ScanError::Source)? {
+                    self.mark_used(chain, index);
+                    gap = 0;
+                } else {
+                    gap += 1;
+                }
+                hd_utxos.extend(utxos.into_iter().map(|utxo| HdUtxo {
+                    utxo,
+                    address: address.clone(),
+                    chain,
+                    index,
+                }));
+                index += 1;
+            }
+        }
+        Ok(hd_utxos)
+    }
+
+    pub fn init_tx(&self, utxos: &[HdUtxo]) -> UnsignedTx {
+        let mut tx_build = UnsignedTx::new_with_version(WALLET_TX_VERSION);
+        for hd_utxo in utxos {
+            add_p2pkh_inputs(&mut tx_build, std::slice::from_ref(&hd_utxo.utxo), &hd_utxo.address);
+        }
+        tx_build
+    }
+
+    // Appends a leftover output to a fresh change address, which is marked used if needed.
+    pub fn add_change_output(&mut self, tx_build: &mut UnsignedTx)
+            -> Result<Option<usize>, HdWalletError> {
+        let index = self.next_index(Chain::Change);
+        let change_address = self.address(Chain::Change, index)?;
+        let change_idx = tx_build.add_leftover_output(change_address, self.fee_per_kb, self.dust_amount())
+            .map_err(|err| HdWalletError::InsufficientFunds(err.missing_amount()))?;
+        if change_idx.is_some() {
+            self.mark_used(Chain::Change, index);
+        }
+        Ok(change_idx)
+    }
+
+    pub fn send_to_address(&mut self, address: Address, amount: u64, utxos: &[HdUtxo])
+            -> Result<UnsignedTx, HdWalletError> {
+        if amount < self.dust_amount() {
+            return Err(HdWalletError::DustOutput { idx: 0 });
+        }
+        if utxos.is_empty() {
+            return Err(HdWalletError::NoUtxos);
+        }
+        let mut tx_build = self.init_tx(utxos);
+        tx_build.add_output(P2PKHOutput {
+            address,
+            value: amount,
+        }.to_output());
+        self.add_change_output(&mut tx_build)?;
+        Ok(tx_build)
+    }
+
+    pub fn dust_amount(&self) -> u64 {
+        self.dust_limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bip44::CoinType;
+    use crate::tx::tx_hash_to_hex;
+
+    #[test]
+    fn test_send_to_address() {
+        let keychain = Bip44Keychain::from_seed(&[0x11; 32], CoinType::Bch).unwrap();
+        let mut wallet = HdWallet::new(keychain, 0).unwrap();
+        let hd_utxo = HdUtxo {
+            utxo: UtxoEntry { tx_id_hex: tx_hash_to_hex(&[0x22; 32]), amount: 10_000, ..UtxoEntry::default() },
+            address: wallet.address(Chain::Receive, 0).unwrap(),
+            chain: Chain::Receive,
+            index: 0,
+        };
+        let utxos = vec![hd_utxo];
+        let recipient = Address::from_bytes(AddressType::P2PKH, [0x44; 20]);
+        assert_eq!(wallet.send_to_address(recipient.clone(), DUST_AMOUNT - 1, &utxos).err(),
+                   Some(HdWalletError::DustOutput { idx: 0 }));
+        assert_eq!(wallet.send_to_address(recipient.clone(), 5_000, &[]).err(), Some(HdWalletError::NoUtxos));
+        assert!(matches!(wallet.send_to_address(recipient.clone(), 10_000, &utxos),
+                         Err(HdWalletError::InsufficientFunds(_))));
+        assert!(!wallet.is_used(Chain::Change, 0));
+
+        // change goes to a fresh change address each time
+        for index in 0..2 {
+            let tx_build = wallet.send_to_address(recipient.clone(), DUST_AMOUNT, &utxos).unwrap();
+            assert_eq!(tx_build.outputs().len(), 2);
+            assert_eq!(tx_build.outputs()[0].value, DUST_AMOUNT);
+            let change_address = wallet.address(Chain::Change, index).unwrap();
+            assert_eq!(tx_build.outputs()[1].script.to_vec(),
+                       P2PKHOutput { value: 0, address: change_address }.script().to_vec());
+            assert!(wallet.is_used(Chain::Change, index));
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use crate::script::Script;
+use crate::tx::{Tx, TxOutput};
+use crate::unsigned_tx::PreImage;
+
+use std::io;
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FromHexError {
+    InvalidHex,
+    InvalidLength { expected: usize, actual: usize },
+    // Valid hex, but not a valid serialization of the type.
+    InvalidData,
+}
+
+// Lowercase hex of the serialized value. Byte arrays are encoded as they are, i.e. tx hashes
+// are not reversed; use tx_hash_to_hex for tx ids.
+pub trait ToHex {
+    fn to_hex(&self) -> String;
+}
+
+pub trait FromHex: Sized {
+    fn from_hex(hex: &str) -> Result<Self, FromHexError>;
+}
+
+fn decode(hex: &str) -> Result<Vec<u8>, FromHexError> {
+    hex::decode(hex).map_err(|_| FromHexError::InvalidHex)
+}
+
+// Parses the whole of `hex` with `read`, rejecting trailing bytes.
+fn read_all<T, F>(hex: &str, read: F) -> Result<T, FromHexError>
+        where F: FnOnce(&mut io::Cursor<Vec<u8>>) -> io::Result<T> {
+    let mut cur = io::Cursor::new(decode(hex)?);
+    let value = read(&mut cur).map_err(|_| FromHexError::InvalidData)?;
+    if cur.position() as usize != cur.get_ref().len() {
+        return Err(FromHexError::InvalidData);
+    }
+    Ok(value)
+}
+
+impl ToHex for [u8] {
+    fn to_hex(&self) -> String {
+        hex::encode(self)
+    }
+}
+
+impl FromHex for Vec<u8> {
+    fn from_hex(hex: &str) -> Result<Self, FromHexError> {
+        decode(hex)
+    }
+}
+
+impl FromHex for [u8; 32] {
+    fn from_hex(hex: &str) -> Result<Self, FromHexError> {
+        let bytes = decode(hex)?;
+        if bytes.len() != 32 {
+            return Err(FromHexError::InvalidLength { expected: 32, actual: bytes.len() });
+        }
+        let mut arr = [0; 32];
+        arr.copy_from_slice(&bytes);
+        Ok(arr)
+    }
+}
+
+impl ToHex for Script {
+    fn to_hex(&self) -> String {
+        self.to_vec().to_hex()
+    }
+}
+
+impl FromHex for Script {
+    fn from_hex(hex: &str) -> Result<Self, FromHexError> {
+        Script::from_serialized(&decode(hex)?).ok_or(FromHexError::InvalidData)
+    }
+}
+
+impl ToHex for Tx {
+    fn to_hex(&self) -> String {
+        let mut bytes = Vec::new();
+        self.write_to_stream(&mut bytes).unwrap();
+        bytes.to_hex()
+    }
+}
+
+impl FromHex for Tx {
+    fn from_hex(hex: &str) -> Result<Self, FromHexError> {
+        read_all(hex, Tx::read_from_stream)
+    }
+}
+
+impl ToHex for TxOutput {
+    fn to_hex(&self) -> String {
+        let mut bytes = Vec::new();
+        self.write_to_stream(&mut bytes).unwrap();
+        bytes.to_hex()
+    }
+}
+
+impl FromHex for TxOutput {
+    fn from_hex(hex: &str) -> Result<Self, FromHexError> {
+        read_all(hex, TxOutput::read_from_stream)
+    }
+}
+
+// Pre-images are only encoded: the optional token prefix before the script code makes them
+// ambiguous to parse.
+impl ToHex for PreImage {
+    fn to_hex(&self) -> String {
+        let mut bytes = Vec::new();
+        self.write_to_stream(&mut bytes).unwrap();
+        bytes.to_hex()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::{Op, OpCodeType};
+
+    #[test]
+    fn test_hex_round_trip() {
+        let script = Script::new(vec![Op::Code(OpCodeType::OpDup), Op::Push(vec![0xab; 3])]);
+        assert_eq!(script.to_hex(), "7603ababab");
+        assert_eq!(Script::from_hex("7603ababab").unwrap().ops(), script.ops());
+        let output = TxOutput::new(1000, script);
+        assert_eq!(TxOutput::from_hex(&output.to_hex()).unwrap().to_hex(), output.to_hex());
+        let tx = Tx::new(1, vec![], vec![output], 0);
+        assert_eq!(Tx::from_hex(&tx.to_hex()).unwrap().hash(), tx.hash());
+        assert_eq!(Tx::from_hex(&(tx.to_hex() + "00")).unwrap_err(), FromHexError::InvalidData);
+        assert_eq!(<[u8; 32]>::from_hex(&[7; 32].to_hex()), Ok([7; 32]));
+        assert_eq!(<[u8; 32]>::from_hex("0707"), Err(FromHexError::InvalidLength { expected: 32, actual: 2 }));
+        assert_eq!(Vec::<u8>::from_hex("0g"), Err(FromHexError::InvalidHex));
+    }
+}
@@ -0,0 +1,142 @@
+use crate::address::Address;
+use crate::decode::{decode_output, DecodedOutput};
+use crate::outputs::P2PKHOutput;
+use crate::tx::{Tx, TxOutput};
+use crate::unsigned_tx::Output;
+use crate::wallet::{Wallet, UtxoEntry, TokenUtxoEntry};
+
+use std::collections::{HashMap, HashSet};
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Balance {
+    pub confirmed: u64,
+    pub unconfirmed: u64,
+    // BCH locked in SLP token carrying outputs.
+    pub token_dust: u64,
+    pub frozen: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxDirection {
+    Incoming,
+    Outgoing,
+    SelfTransfer,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TxHistoryEntry {
+    pub tx_hash: [u8; 32],
+    pub direction: TxDirection,
+    // Change of the wallet's balance caused by the tx.
+    pub amount_delta: i64,
+    // None if not all spent outputs are known.
+    pub fee: Option<u64>,
+    // Recipients of outgoing and senders of incoming txs, as far as they are known.
+    pub counterparties: Vec<Address>,
+}
+
+impl Balance {
+    pub fn total(&self) -> u64 {
+        self.confirmed + self.unconfirmed + self.token_dust + self.frozen
+    }
+
+    pub fn spendable(&self) -> u64 {
+        self.confirmed + self.unconfirmed
+    }
+}
+
+fn output_address(output: &TxOutput, prefix: &str) -> Option<Address> {
+    match decode_output(output) {
+        DecodedOutput::P2PKH(p2pkh) => Some(p2pkh.address.with_prefix(prefix.to_string())),
+        DecodedOutput::P2SH { address, .. } => Some(address.with_prefix(prefix.to_string())),
+        _ => None,
+    }
+}
+
+impl Wallet {
+    // UTXOs listed in `token_utxos` count as token dust instead of BCH balance.
+    pub fn balance(&self, utxos: &[UtxoEntry], token_utxos: &[TokenUtxoEntry]) -> Balance {
+        let token_outpoints = token_utxos.iter()
+            .filter_map(|token_utxo| token_utxo.utxo.outpoint())
+            .collect::<HashSet<_>>();
+        let mut balance = Balance::default();
+        for token_utxo in token_utxos {
+            balance.token_dust += token_utxo.utxo.amount;
+        }
+        for utxo in utxos {
+            if utxo.outpoint().map(|outpoint| token_outpoints.contains(&outpoint)).unwrap_or(false) {
+                continue;
+            }
+            if self.is_frozen(utxo) {
+                balance.frozen += utxo.amount;
+            } else if utxo.height.is_some() {
+                balance.confirmed += utxo.amount;
+            } else {
+                balance.unconfirmed += utxo.amount;
+            }
+        }
+        balance
+    }
+
+    // Computes the ledger view of `txs`, which should contain the wallet's entire history
+    // so that spent outputs (and therefore sent amounts and fees) can be resolved.
+    pub fn history(&self, txs: &[Tx]) -> Vec<TxHistoryEntry> {
+        let own_script = P2PKHOutput { value: 0, address: self.address().clone() }.script();
+        let prefix = self.address().prefix();
+        let txs_by_hash = txs.iter()
+            .map(|tx| (tx.hash(), tx))
+            .collect::<HashMap<_, _>>();
+        txs.iter().map(|tx| {
+            let is_own = |output: &TxOutput| output.script.to_vec() == own_script.to_vec();
+            let spent_outputs = tx.inputs().iter()
+                .map(|input| {
+                    txs_by_hash.get(&input.outpoint.tx_hash)
+                        .and_then(|prev_tx| prev_tx.outputs().get(input.outpoint.vout as usize))
+                })
+                .collect::<Vec<_>>();
+            let received = tx.outputs().iter()
+                .filter(|output| is_own(output))
+                .map(|output| output.value)
+                .sum::<u64>();
+            let sent = spent_outputs.iter()
+                .filter_map(|output| output.filter(|output| is_own(output)))
+                .map(|output| output.value)
+                .sum::<u64>();
+            let output_amount = tx.outputs().iter().map(|output| output.value).sum::<u64>();
+            let fee = spent_outputs.iter()
+                .map(|output| output.map(|output| output.value))
+                .sum::<Option<u64>>()
+                .map(|input_amount| input_amount.saturating_sub(output_amount));
+            let foreign_outputs = tx.outputs().iter()
+                .filter(|output| !is_own(output) && output.value > 0)
+                .collect::<Vec<_>>();
+            let (direction, counterparties) = if sent == 0 {
+                let senders = spent_outputs.iter()
+                    .filter_map(|output| output.and_then(|output| output_address(output, prefix)))
+                    .collect();
+                (TxDirection::Incoming, senders)
+            } else if foreign_outputs.is_empty() {
+                (TxDirection::SelfTransfer, vec![])
+            } else {
+                let recipients = foreign_outputs.iter()
+                    .filter_map(|output| output_address(output, prefix))
+                    .collect();
+                (TxDirection::Outgoing, recipients)
+            };
+            let mut unique_counterparties = Vec::new();
+            for address in counterparties {
+                if !unique_counterparties.contains(&address) {
+                    unique_counterparties.push(address);
+                }
+            }
+            TxHistoryEntry {
+                tx_hash: tx.hash(),
+                direction,
+                amount_delta: received as i64 - sent as i64,
+                fee,
+                counterparties: unique_counterparties,
+            }
+        }).collect()
+    }
+}
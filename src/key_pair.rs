@@ -0,0 +1,129 @@
+use crate::address::{Address, AddressType};
+use crate::base58;
+use crate::crypto::{Crypto, PublicKey, SecretKey};
+
+use rand_core::{CryptoRng, RngCore};
+
+
+const WIF_MAINNET: u8 = 0x80;
+const WIF_TESTNET: u8 = 0xef;
+const WIF_COMPRESSED: u8 = 0x01;
+
+// Network a WIF is for; testnet WIFs are also used on regtest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WifNetwork {
+    Mainnet,
+    Testnet,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyPairError {
+    Base58(base58::Error),
+    InvalidWif,
+    // Uncompressed keys are not supported, as the crypto backends only serialize compressed keys.
+    UncompressedKey,
+    InvalidSecretKey,
+}
+
+// Secret key together with its compressed public key.
+pub struct KeyPair<C: Crypto> {
+    secret_key: C::SecretKey,
+    public_key: [u8; 33],
+}
+
+impl From<base58::Error> for KeyPairError {
+    fn from(err: base58::Error) -> Self {
+        KeyPairError::Base58(err)
+    }
+}
+
+impl<C: Crypto> KeyPair<C> {
+    pub fn from_secret_key(crypto: &C, secret_key: C::SecretKey) -> Self {
+        KeyPair {
+            public_key: crypto.secret_to_pub_key(&secret_key).serialize(),
+            secret_key,
+        }
+    }
+
+    pub fn secret_key(&self) -> &C::SecretKey {
+        &self.secret_key
+    }
+
+    pub fn public_key(&self) -> [u8; 33] {
+        self.public_key
+    }
+
+    pub fn address(&self, prefix: &str) -> Address {
+        Address::from_serialized_pub_key(prefix, AddressType::P2PKH, &self.public_key)
+    }
+
+    // WIF of the compressed key.
+    pub fn to_wif(&self, network: WifNetwork) -> String {
+        let mut data = vec![match network {
+            WifNetwork::Mainnet => WIF_MAINNET,
+            WifNetwork::Testnet => WIF_TESTNET,
+        }];
+        data.extend_from_slice(&self.secret_key.to_bytes());
+        data.push(WIF_COMPRESSED);
+        base58::check_encode_slice(&data)
+    }
+}
+
+impl<C: Crypto + Default> KeyPair<C> {
+    pub fn generate<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
+        let mut bytes = [0; 32];
+        loop {
+            rng.fill_bytes(&mut bytes);
+            // retry in the unlikely case the bytes are 0 or exceed the group order
+            if let Ok(secret_key) = C::SecretKey::from_slice(&bytes) {
+                return KeyPair::from_secret_key(&C::default(), secret_key);
+            }
+        }
+    }
+
+    // Accepts mainnet and testnet WIFs of compressed keys.
+    pub fn from_wif(wif: &str) -> Result<Self, KeyPairError> {
+        let data = base58::from_check(wif)?;
+        match data.as_slice() {
+            [WIF_MAINNET, ..] | [WIF_TESTNET, ..] => {},
+            _ => return Err(KeyPairError::InvalidWif),
+        }
+        let secret_key = match data.len() {
+            34 if data[33] == WIF_COMPRESSED => &data[1..33],
+            33 => return Err(KeyPairError::UncompressedKey),
+            _ => return Err(KeyPairError::InvalidWif),
+        };
+        let secret_key = C::SecretKey::from_slice(secret_key)
+            .map_err(|_| KeyPairError::InvalidSecretKey)?;
+        Ok(KeyPair::from_secret_key(&C::default(), secret_key))
+    }
+}
+
+#[cfg(all(test, feature = "secp256k1"))]
+mod tests {
+    use super::*;
+    use crate::crypto::secp256k1::CryptoSecp256k1;
+
+    #[test]
+    fn test_wif_roundtrip() {
+        let vectors = [
+            ("0000000000000000000000000000000000000000000000000000000000000001",
+             "KwDiBf89QgGbjEhKnhXJuH7LrciVrZi3qYjgd9M7rFU73sVHnoWn",
+             "cMahea7zqjxrtgAbB7LSGbcQUr1uX1ojuat9jZodMN87JcbXMTcA"),
+            ("0c28fca386c7a227600b2fe50b7cae11ec86d3bf1fbe471be89827e19d72aa1d",
+             "KwdMAjGmerYanjeui5SHS7JkmpZvVipYvB2LJGU1ZxJwYvP98617",
+             "cMzLdeGd5vEqxB8B6VFQoRopQ3sLAAvEzDAoQgvX54xwofSWj1fx"),
+        ];
+        for &(secret_hex, mainnet_wif, testnet_wif) in &vectors {
+            let secret_key = secp256k1::SecretKey::from_slice(&hex::decode(secret_hex).unwrap()).unwrap();
+            let key_pair = KeyPair::from_secret_key(&CryptoSecp256k1::new(), secret_key);
+            assert_eq!(key_pair.to_wif(WifNetwork::Mainnet), mainnet_wif);
+            assert_eq!(key_pair.to_wif(WifNetwork::Testnet), testnet_wif);
+            for &wif in &[mainnet_wif, testnet_wif] {
+                let parsed = KeyPair::<CryptoSecp256k1>::from_wif(wif).unwrap();
+                assert_eq!(parsed.secret_key().to_bytes(), key_pair.secret_key().to_bytes());
+                assert_eq!(parsed.public_key(), key_pair.public_key());
+            }
+        }
+    }
+}
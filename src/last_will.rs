@@ -0,0 +1,125 @@
+use crate::unsigned_tx::{PreImage, UnsignedTx, UnsignedInput};
+use crate::script::{Script, Op};
+use crate::address::Address;
+use crate::tx::{TxOutput, TxOutpoint};
+use crate::outputs::P2SHOutput;
+use crate::wallet::{DUST_AMOUNT, WalletError};
+use crate::serialize::encode_int64;
+use crate::spend::{LockingScript, Unlocker, Spend};
+
+
+// Dead man's switch: the owner can spend at any time, the inheritor once the output is
+// older than `timeout` (BIP68 sequence, see relative_lock_blocks). The owner resets the
+// timeout by refreshing, i.e. re-creating the output.
+#[derive(Clone, Debug)]
+pub struct LastWillOutput {
+    pub value: u64,
+    pub owner: Address,
+    pub inheritor: Address,
+    pub timeout: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LastWillSpendParams {
+    Owner,
+    Inherit,
+}
+
+impl LastWillOutput {
+    // Builds the transaction re-creating the output with an identical script, minus fee.
+    // Fails with InsufficientFunds if the remaining value would be below dust.
+    pub fn refresh_tx(&self, outpoint: TxOutpoint, fee_per_kb: u64) -> Result<UnsignedTx, WalletError> {
+        let mut tx_build = UnsignedTx::new_simple();
+        tx_build.add_input(UnsignedInput::new(
+            outpoint,
+            Box::new(P2SHOutput {
+                output: Box::new(Spend::new(self.clone(), LastWillSpendParams::Owner)),
+            }),
+            0xffff_ffff,
+        ));
+        tx_build.add_output(self._refreshed(0).to_p2sh_output());
+        let fee = tx_build.estimate_size() as u64 * fee_per_kb / 1000;
+        match self.value.checked_sub(fee).filter(|&value| value >= DUST_AMOUNT) {
+            Some(value) => {
+                tx_build.replace_output(0, self._refreshed(value).to_p2sh_output());
+                Ok(tx_build)
+            },
+            None => Err(WalletError::InsufficientFunds { missing: fee + DUST_AMOUNT - self.value }),
+        }
+    }
+
+    // Builds the transaction paying everything to the inheritor after the timeout.
+    // Requires tx version 2 for the relative lock.
+    pub fn inherit_tx(&self, outpoint: TxOutpoint, fee_per_kb: u64) -> Result<UnsignedTx, WalletError> {
+        let mut tx_build = UnsignedTx::new_simple();
+        tx_build.set_version(2);
+        tx_build.add_input(UnsignedInput::new(
+            outpoint,
+            Box::new(P2SHOutput {
+                output: Box::new(Spend::new(self.clone(), LastWillSpendParams::Inherit)),
+            }),
+            self.timeout,
+        ));
+        tx_build.add_leftover_output(self.inheritor.clone(), fee_per_kb, DUST_AMOUNT)?
+            .ok_or(WalletError::DustOutput { idx: 0 })?;
+        Ok(tx_build)
+    }
+
+    fn _refreshed(&self, value: u64) -> LastWillOutput {
+        LastWillOutput {
+            value,
+            ..self.clone()
+        }
+    }
+}
+
+impl LockingScript for LastWillOutput {
+    fn value(&self) -> u64 {
+        self.value
+    }
+
+    fn script(&self) -> Script {
+        use crate::script::OpCodeType::*;
+        Script::new(vec![
+            Op::Code(OpIf),
+            Op::Code(OpDup),
+            Op::Code(OpHash160),
+            Op::Push(self.owner.bytes().to_vec()),
+            Op::Code(OpEqualVerify),
+            Op::Code(OpCheckSig),
+            Op::Code(OpElse),
+            Op::Push(encode_int64(self.timeout as i64)),
+            Op::Code(OpCheckSequenceVerify),
+            Op::Code(OpDrop),
+            Op::Code(OpDup),
+            Op::Code(OpHash160),
+            Op::Push(self.inheritor.bytes().to_vec()),
+            Op::Code(OpEqualVerify),
+            Op::Code(OpCheckSig),
+            Op::Code(OpEndIf),
+        ])
+    }
+
+    fn script_code(&self) -> Script {
+        self.script()
+    }
+}
+
+impl Unlocker<LastWillOutput> for LastWillSpendParams {
+    fn sig_script(&self,
+                  _output: &LastWillOutput,
+                  serialized_sig: Vec<u8>,
+                  serialized_pub_key: Vec<u8>,
+                  _pre_image: &PreImage,
+                  _outputs: &[TxOutput]) -> Script {
+        let branch = match self {
+            LastWillSpendParams::Owner => vec![1],
+            LastWillSpendParams::Inherit => vec![],
+        };
+        Script::new(vec![
+            Op::Push(serialized_sig),
+            Op::Push(serialized_pub_key),
+            Op::Push(branch),
+        ])
+    }
+}
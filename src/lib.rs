@@ -4,16 +4,131 @@ mod outputs;
 mod script;
 pub mod serialize;
 mod tx;
+mod tx_ref;
+mod hex_traits;
 mod unsigned_tx;
+pub mod spend;
 mod wallet;
 pub mod base58;
+pub mod memo;
 mod p2_ascending_nonce;
+mod nonce_channel;
+mod time_lock;
+mod escrow;
+mod slp;
+mod slp_validator;
+mod cash_tokens;
+mod op_return;
+mod lokad_id;
+mod decode;
+mod covenant;
+mod advanced_trade_offer;
+mod offerbook;
+mod oracle_conditional;
+mod recurring_payment;
+mod last_will;
+mod mnemonic;
+mod crypto;
+mod sig_encoding;
+mod tx_signer;
+mod key_pair;
+mod merkle;
+mod block;
+mod dsproof;
+mod bloom;
+pub mod net;
+mod backend;
+mod history;
+mod multisig;
+mod utxo_store;
+mod fee_estimator;
+mod size_report;
+mod script_debugger;
+#[cfg(feature = "electrum")]
+mod electrum;
+#[cfg(feature = "rest")]
+mod rest;
+#[cfg(feature = "payment-protocol")]
+mod payment_protocol;
+#[cfg(feature = "testkit")]
+mod testkit;
+#[cfg(feature = "wasm")]
+mod wasm;
+#[cfg(feature = "cabi")]
+pub mod cabi;
+#[cfg(feature = "bcmr")]
+mod bcmr;
+#[cfg(feature = "secp256k1")]
+mod bip32;
+#[cfg(feature = "secp256k1")]
+mod bip44;
+#[cfg(feature = "secp256k1")]
+mod hd_wallet;
+#[cfg(feature = "secp256k1")]
+mod signed_message;
 
 pub use address::*;
 pub use outputs::*;
 pub use hash::*;
 pub use script::*;
 pub use tx::*;
+pub use tx_ref::*;
+pub use hex_traits::*;
 pub use unsigned_tx::*;
 pub use wallet::*;
 pub use p2_ascending_nonce::*;
+pub use nonce_channel::*;
+pub use time_lock::*;
+pub use escrow::*;
+pub use slp::*;
+pub use slp_validator::*;
+pub use cash_tokens::*;
+pub use op_return::*;
+pub use lokad_id::*;
+pub use decode::*;
+pub use covenant::*;
+pub use advanced_trade_offer::*;
+pub use offerbook::*;
+pub use oracle_conditional::*;
+pub use recurring_payment::*;
+pub use last_will::*;
+pub use mnemonic::*;
+pub use crypto::*;
+pub use sig_encoding::*;
+pub use tx_signer::*;
+pub use key_pair::*;
+pub use merkle::*;
+pub use block::*;
+pub use dsproof::*;
+pub use bloom::*;
+#[cfg(feature = "secp256k1")]
+pub use crypto::secp256k1::CryptoSecp256k1;
+#[cfg(feature = "k256")]
+pub use crypto::k256::CryptoK256;
+pub use backend::*;
+pub use history::*;
+pub use multisig::*;
+pub use utxo_store::*;
+pub use fee_estimator::*;
+pub use size_report::*;
+pub use script_debugger::*;
+#[cfg(feature = "electrum")]
+pub use electrum::*;
+#[cfg(feature = "rest")]
+pub use rest::*;
+#[cfg(feature = "payment-protocol")]
+pub use payment_protocol::*;
+#[cfg(feature = "testkit")]
+pub use testkit::*;
+#[cfg(feature = "wasm")]
+pub use wasm::*;
+#[cfg(feature = "bcmr")]
+pub use bcmr::*;
+#[cfg(feature = "secp256k1")]
+pub use bip32::*;
+#[cfg(feature = "secp256k1")]
+pub use bip44::*;
+#[cfg(feature = "secp256k1")]
+pub use hd_wallet::*;
+#[cfg(feature = "secp256k1")]
+pub use signed_message::*;
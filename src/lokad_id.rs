@@ -0,0 +1,162 @@
+use crate::memo::{MEMO_SET_NAME, MEMO_POST, MEMO_REPLY, MEMO_LIKE, MEMO_SET_PROFILE_TEXT,
+                  MEMO_FOLLOW, MEMO_UNFOLLOW};
+use crate::op_return::LOKAD_ID_SIZE;
+use crate::script::{Script, Op, OpCodeType};
+use crate::slp::SLPMessage;
+
+use std::fmt;
+
+
+// 4 byte protocol id pushed first in an OP_RETURN.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LokadId(pub [u8; LOKAD_ID_SIZE]);
+
+impl LokadId {
+    pub const SLP: LokadId = LokadId(*b"SLP\0");
+    pub const BCMR: LokadId = LokadId(*b"BCMR");
+
+    pub fn from_slice(slice: &[u8]) -> Option<Self> {
+        if slice.len() != LOKAD_ID_SIZE {
+            return None;
+        }
+        let mut lokad_id = [0; LOKAD_ID_SIZE];
+        lokad_id.copy_from_slice(slice);
+        Some(LokadId(lokad_id))
+    }
+
+    // Lokad id of an OP_RETURN script, if its first push has 4 bytes.
+    pub fn from_script(script: &Script) -> Option<Self> {
+        LokadId::from_slice(protocol_id(script)?)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for LokadId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "LokadId({:?})", String::from_utf8_lossy(&self.0))
+    }
+}
+
+// First push of an OP_RETURN script.
+fn protocol_id(script: &Script) -> Option<&[u8]> {
+    match script.ops() {
+        [Op::Code(OpCodeType::OpReturn), Op::Push(id), ..] => Some(id),
+        _ => None,
+    }
+}
+
+// Messages of the protocols known to OpReturnDispatcher::standard.
+#[derive(Clone, Debug)]
+pub enum OpReturnMessage {
+    SLP(SLPMessage),
+    // memo.cash action prefix, e.g. MEMO_POST, and the pushes after it
+    Memo { action: [u8; 2], pushes: Vec<Vec<u8>> },
+}
+
+type OpReturnParser<T> = Box<dyn Fn(&Script) -> Option<T> + Send + Sync>;
+
+// Routes OP_RETURN scripts to the parser registered for their first push: a lokad id, or
+// another prefix such as memo.cash's 2 byte actions. Covenant protocols with a lokad id
+// (trade offers, ascending nonces) can register parsers for their announcements the same way.
+pub struct OpReturnDispatcher<T> {
+    parsers: Vec<(Vec<u8>, OpReturnParser<T>)>,
+}
+
+impl<T> OpReturnDispatcher<T> {
+    pub fn new() -> Self {
+        OpReturnDispatcher { parsers: Vec::new() }
+    }
+
+    // Replaces the parser of `protocol_id` if there is one.
+    pub fn register<F>(&mut self, protocol_id: &[u8], parser: F) -> &mut Self
+            where F: Fn(&Script) -> Option<T> + Send + Sync + 'static {
+        self.parsers.retain(|(id, _)| id != protocol_id);
+        self.parsers.push((protocol_id.to_vec(), Box::new(parser)));
+        self
+    }
+
+    pub fn register_lokad_id<F>(&mut self, lokad_id: LokadId, parser: F) -> &mut Self
+            where F: Fn(&Script) -> Option<T> + Send + Sync + 'static {
+        self.register(lokad_id.as_bytes(), parser)
+    }
+
+    pub fn is_registered(&self, protocol_id: &[u8]) -> bool {
+        self.parsers.iter().any(|(id, _)| id == protocol_id)
+    }
+
+    // None if no parser is registered for the script or the parser rejects it.
+    pub fn dispatch(&self, script: &Script) -> Option<T> {
+        let id = protocol_id(script)?;
+        let (_, parser) = self.parsers.iter().find(|(protocol_id, _)| protocol_id.as_slice() == id)?;
+        parser(script)
+    }
+}
+
+impl<T> Default for OpReturnDispatcher<T> {
+    fn default() -> Self {
+        OpReturnDispatcher::new()
+    }
+}
+
+impl OpReturnDispatcher<OpReturnMessage> {
+    // Dispatcher for SLP and memo.cash.
+    pub fn standard() -> Self {
+        let mut dispatcher = OpReturnDispatcher::new();
+        dispatcher.register_lokad_id(LokadId::SLP, |script| {
+            SLPMessage::parse(script).ok().map(OpReturnMessage::SLP)
+        });
+        for &action in &[MEMO_SET_NAME, MEMO_POST, MEMO_REPLY, MEMO_LIKE, MEMO_SET_PROFILE_TEXT,
+                         MEMO_FOLLOW, MEMO_UNFOLLOW] {
+            dispatcher.register(&action, move |script| {
+                let pushes = script.ops()[2..].iter()
+                    .map(|op| match op {
+                        Op::Push(push) => Some(push.clone()),
+                        Op::Code(_) => None,
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                Some(OpReturnMessage::Memo { action, pushes })
+            });
+        }
+        dispatcher
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memo;
+    use crate::outputs::SLPSend;
+    use crate::unsigned_tx::Output;
+
+    #[test]
+    fn test_dispatch_op_return() {
+        let mut dispatcher = OpReturnDispatcher::standard();
+        let slp = SLPSend { token_type: 1, token_id: [1; 32], output_quantities: vec![5] }
+            .into_output().unwrap().script();
+        assert_eq!(LokadId::from_script(&slp), Some(LokadId::SLP));
+        match dispatcher.dispatch(&slp) {
+            Some(OpReturnMessage::SLP(SLPMessage::Send(send))) => assert_eq!(send.output_quantities, vec![5]),
+            message => panic!("unexpected {:?}", message),
+        }
+        match dispatcher.dispatch(&memo::post("hello").unwrap().script()) {
+            Some(OpReturnMessage::Memo { action, pushes }) => {
+                assert_eq!(action, MEMO_POST);
+                assert_eq!(pushes, vec![b"hello".to_vec()]);
+            },
+            message => panic!("unexpected {:?}", message),
+        }
+
+        let exch = LokadId(*b"EXCH");
+        let script = Script::new(vec![Op::Code(OpCodeType::OpReturn), Op::Push(b"EXCH".to_vec())]);
+        assert!(dispatcher.dispatch(&script).is_none());
+        dispatcher.register_lokad_id(exch, |script| {
+            Some(OpReturnMessage::Memo { action: [0; 2], pushes: vec![script.to_vec()] })
+        });
+        assert!(dispatcher.is_registered(b"EXCH"));
+        assert!(dispatcher.dispatch(&script).is_some());
+        assert_eq!(format!("{:?}", exch), "LokadId(\"EXCH\")");
+    }
+}
@@ -0,0 +1,76 @@
+//! Builders for the memo.cash OP_RETURN protocol (https://memo.cash/protocol)
+
+use crate::outputs::OpReturnOutput;
+use crate::address::Address;
+
+pub const MEMO_SET_NAME: [u8; 2] = [0x6d, 0x01];
+pub const MEMO_POST: [u8; 2] = [0x6d, 0x02];
+pub const MEMO_REPLY: [u8; 2] = [0x6d, 0x03];
+pub const MEMO_LIKE: [u8; 2] = [0x6d, 0x04];
+pub const MEMO_SET_PROFILE_TEXT: [u8; 2] = [0x6d, 0x05];
+pub const MEMO_FOLLOW: [u8; 2] = [0x6d, 0x06];
+pub const MEMO_UNFOLLOW: [u8; 2] = [0x6d, 0x07];
+
+pub const MAX_NAME_SIZE: usize = 217;
+pub const MAX_POST_SIZE: usize = 217;
+pub const MAX_REPLY_SIZE: usize = 184;
+pub const MAX_PROFILE_TEXT_SIZE: usize = 217;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MemoError {
+    Empty,
+    TooLong { max: usize, len: usize },
+}
+
+fn check_text(text: &str, max: usize) -> Result<Vec<u8>, MemoError> {
+    if text.is_empty() {
+        return Err(MemoError::Empty);
+    }
+    if text.len() > max {
+        return Err(MemoError::TooLong { max, len: text.len() });
+    }
+    Ok(text.as_bytes().to_vec())
+}
+
+fn memo_output(pushes: Vec<Vec<u8>>) -> OpReturnOutput {
+    OpReturnOutput {
+        is_minimal_push: false,
+        pushes,
+        encodings: vec![],
+    }
+}
+
+pub fn set_name(name: &str) -> Result<OpReturnOutput, MemoError> {
+    Ok(memo_output(vec![MEMO_SET_NAME.to_vec(), check_text(name, MAX_NAME_SIZE)?]))
+}
+
+pub fn post(message: &str) -> Result<OpReturnOutput, MemoError> {
+    Ok(memo_output(vec![MEMO_POST.to_vec(), check_text(message, MAX_POST_SIZE)?]))
+}
+
+pub fn reply(tx_hash: &[u8; 32], message: &str) -> Result<OpReturnOutput, MemoError> {
+    Ok(memo_output(vec![
+        MEMO_REPLY.to_vec(),
+        tx_hash.to_vec(),
+        check_text(message, MAX_REPLY_SIZE)?,
+    ]))
+}
+
+pub fn like(tx_hash: &[u8; 32]) -> OpReturnOutput {
+    memo_output(vec![MEMO_LIKE.to_vec(), tx_hash.to_vec()])
+}
+
+pub fn set_profile_text(text: &str) -> Result<OpReturnOutput, MemoError> {
+    Ok(memo_output(vec![
+        MEMO_SET_PROFILE_TEXT.to_vec(),
+        check_text(text, MAX_PROFILE_TEXT_SIZE)?,
+    ]))
+}
+
+pub fn follow(address: &Address) -> OpReturnOutput {
+    memo_output(vec![MEMO_FOLLOW.to_vec(), address.bytes().to_vec()])
+}
+
+pub fn unfollow(address: &Address) -> OpReturnOutput {
+    memo_output(vec![MEMO_UNFOLLOW.to_vec(), address.bytes().to_vec()])
+}
@@ -0,0 +1,218 @@
+use crate::hash::double_sha256;
+use crate::serialize::{read_var_int_minimal, write_var_int};
+
+use std::io;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MerkleError {
+    NoTransactions,
+    TooManyHashes,
+    NotEnoughHashes,
+    NotEnoughBits,
+    UnusedHashes,
+    UnusedBits,
+    // Both children of a node are equal, which would allow CVE-2012-2459 style forgeries.
+    DuplicateHashes,
+}
+
+// Hash of a matched tx and its index in the block.
+pub type MerkleMatch = ([u8; 32], u32);
+
+// Merkle branch of a tx, e.g. as returned by Electrum's blockchain.transaction.get_merkle.
+// Hashes are in internal byte order, from the leaf level up.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MerkleBranch {
+    pub hashes: Vec<[u8; 32]>,
+    pub index: u32,
+}
+
+// Partial merkle tree of a merkleblock message, proving the inclusion of the matched txs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartialMerkleTree {
+    pub num_txs: u32,
+    pub hashes: Vec<[u8; 32]>,
+    pub flags: Vec<u8>,
+}
+
+fn merkle_parent(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut data = [0; 64];
+    data[..32].copy_from_slice(left);
+    data[32..].copy_from_slice(right);
+    double_sha256(&data)
+}
+
+// Merkle root of the tx hashes (internal byte order); levels with an odd number of nodes
+// duplicate their last node. The root of an empty list is all zeros.
+pub fn merkle_root(tx_hashes: &[[u8; 32]]) -> [u8; 32] {
+    if tx_hashes.is_empty() {
+        return [0; 32];
+    }
+    let mut level = tx_hashes.to_vec();
+    while level.len() > 1 {
+        level = level.chunks(2)
+            .map(|pair| merkle_parent(&pair[0], pair.last().unwrap()))
+            .collect();
+    }
+    level[0]
+}
+
+impl MerkleBranch {
+    pub fn from_tx_hashes(tx_hashes: &[[u8; 32]], index: u32) -> Option<Self> {
+        if index as usize >= tx_hashes.len() {
+            return None;
+        }
+        let mut hashes = Vec::new();
+        let mut level = tx_hashes.to_vec();
+        let mut pos = index as usize;
+        while level.len() > 1 {
+            hashes.push(*level.get(pos ^ 1).unwrap_or(&level[pos]));
+            level = level.chunks(2)
+                .map(|pair| merkle_parent(&pair[0], pair.last().unwrap()))
+                .collect();
+            pos /= 2;
+        }
+        Some(MerkleBranch { hashes, index })
+    }
+
+    pub fn compute_root(&self, tx_hash: &[u8; 32]) -> [u8; 32] {
+        let mut hash = *tx_hash;
+        for (level, sibling) in self.hashes.iter().enumerate() {
+            hash = if (self.index >> level) & 1 == 0 {
+                merkle_parent(&hash, sibling)
+            } else {
+                merkle_parent(sibling, &hash)
+            };
+        }
+        hash
+    }
+
+    pub fn verify(&self, tx_hash: &[u8; 32], root: &[u8; 32]) -> bool {
+        self.hashes.len() < 32 && self.index >> self.hashes.len() == 0 &&
+            self.compute_root(tx_hash) == *root
+    }
+}
+
+impl PartialMerkleTree {
+    pub fn read_from_stream<R: io::Read>(read: &mut R) -> io::Result<Self> {
+        let num_txs = read.read_u32::<LittleEndian>()?;
+        let num_hashes = read_var_int_minimal(read)?;
+        let mut hashes = Vec::new();
+        for _ in 0..num_hashes {
+            let mut hash = [0; 32];
+            read.read_exact(&mut hash)?;
+            hashes.push(hash);
+        }
+        let num_flag_bytes = read_var_int_minimal(read)?;
+        let mut flags = Vec::new();
+        for _ in 0..num_flag_bytes {
+            flags.push(read.read_u8()?);
+        }
+        Ok(PartialMerkleTree { num_txs, hashes, flags })
+    }
+
+    pub fn write_to_stream<W: io::Write>(&self, write: &mut W) -> io::Result<()> {
+        write.write_u32::<LittleEndian>(self.num_txs)?;
+        write_var_int(write, self.hashes.len() as u64)?;
+        for hash in self.hashes.iter() {
+            write.write_all(hash)?;
+        }
+        write_var_int(write, self.flags.len() as u64)?;
+        write.write_all(&self.flags)
+    }
+
+    fn tree_width(&self, height: u32) -> u32 {
+        ((self.num_txs as u64 + (1 << height) - 1) >> height) as u32
+    }
+
+    fn flag(&self, idx: usize) -> Option<bool> {
+        self.flags.get(idx / 8).map(|byte| (byte >> (idx % 8)) & 1 == 1)
+    }
+
+    fn traverse(&self,
+                height: u32,
+                pos: u32,
+                bits_used: &mut usize,
+                hashes_used: &mut usize,
+                matches: &mut Vec<MerkleMatch>) -> Result<[u8; 32], MerkleError> {
+        let is_parent_of_match = self.flag(*bits_used).ok_or(MerkleError::NotEnoughBits)?;
+        *bits_used += 1;
+        if height == 0 || !is_parent_of_match {
+            let hash = *self.hashes.get(*hashes_used).ok_or(MerkleError::NotEnoughHashes)?;
+            *hashes_used += 1;
+            if height == 0 && is_parent_of_match {
+                matches.push((hash, pos));
+            }
+            return Ok(hash);
+        }
+        let left = self.traverse(height - 1, pos * 2, bits_used, hashes_used, matches)?;
+        let right = if pos * 2 + 1 < self.tree_width(height - 1) {
+            let right = self.traverse(height - 1, pos * 2 + 1, bits_used, hashes_used, matches)?;
+            if right == left {
+                return Err(MerkleError::DuplicateHashes);
+            }
+            right
+        } else {
+            left
+        };
+        Ok(merkle_parent(&left, &right))
+    }
+
+    // Returns the merkle root and the matched tx hashes with their index in the block.
+    // The root has to be compared with the block header's merkle root.
+    pub fn extract_matches(&self) -> Result<([u8; 32], Vec<MerkleMatch>), MerkleError> {
+        if self.num_txs == 0 {
+            return Err(MerkleError::NoTransactions);
+        }
+        if self.hashes.len() > self.num_txs as usize {
+            return Err(MerkleError::TooManyHashes);
+        }
+        let mut height = 0;
+        while self.tree_width(height) > 1 {
+            height += 1;
+        }
+        let mut bits_used = 0;
+        let mut hashes_used = 0;
+        let mut matches = Vec::new();
+        let root = self.traverse(height, 0, &mut bits_used, &mut hashes_used, &mut matches)?;
+        if (bits_used + 7) / 8 != self.flags.len() {
+            return Err(MerkleError::UnusedBits);
+        }
+        if hashes_used != self.hashes.len() {
+            return Err(MerkleError::UnusedHashes);
+        }
+        Ok((root, matches))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tx::{tx_hex_to_hash, tx_hash_to_hex};
+
+    #[test]
+    fn test_block_100000() {
+        let tx_hashes = [
+            "8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87",
+            "fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4",
+            "6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4",
+            "e9a66845e05d5abc0ad04ec80f774a7e585c6e8db975962d069a522137b80c1d",
+        ].iter().map(|tx_id| tx_hex_to_hash(tx_id).unwrap()).collect::<Vec<_>>();
+        let root = merkle_root(&tx_hashes);
+        assert_eq!(tx_hash_to_hex(&root), "f3e94742aca4b5ef85488dc37c06c3282295ffec960994b2c0d5ac2a25a95766");
+        for (index, tx_hash) in tx_hashes.iter().enumerate() {
+            let branch = MerkleBranch::from_tx_hashes(&tx_hashes, index as u32).unwrap();
+            assert!(branch.verify(tx_hash, &root));
+            assert!(!branch.verify(&tx_hashes[(index + 1) % 4], &root));
+        }
+
+        // match tx 2: flags 1 (root), 0 (left subtree), 1 (right subtree), 1 (tx 2), 0 (tx 3)
+        let tree = PartialMerkleTree {
+            num_txs: 4,
+            hashes: vec![merkle_parent(&tx_hashes[0], &tx_hashes[1]), tx_hashes[2], tx_hashes[3]],
+            flags: vec![0b01101],
+        };
+        assert_eq!(tree.extract_matches(), Ok((root, vec![(tx_hashes[2], 2)])));
+    }
+}
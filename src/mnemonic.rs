@@ -0,0 +1,132 @@
+use crate::hash::{single_sha256, pbkdf2_hmac_sha512};
+
+
+const WORDLIST_ENGLISH: &str = include_str!("wordlists/english.txt");
+const BITS_PER_WORD: usize = 11;
+const SEED_ITERATIONS: u32 = 2048;
+
+pub const SEED_SIZE: usize = 64;
+
+// BIP39 mnemonic using the English wordlist.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mnemonic {
+    entropy: Vec<u8>,
+    words: Vec<&'static str>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MnemonicError {
+    InvalidEntropyLength(usize),
+    InvalidWordCount(usize),
+    UnknownWord(String),
+    InvalidChecksum,
+}
+
+pub fn wordlist() -> Vec<&'static str> {
+    WORDLIST_ENGLISH.lines().collect()
+}
+
+impl Mnemonic {
+    // Entropy has to be 16, 20, 24, 28 or 32 bytes, giving 12 to 24 words.
+    pub fn from_entropy(entropy: &[u8]) -> Result<Self, MnemonicError> {
+        if entropy.len() < 16 || entropy.len() > 32 || entropy.len() % 4 != 0 {
+            return Err(MnemonicError::InvalidEntropyLength(entropy.len()));
+        }
+        let wordlist = wordlist();
+        let checksum = single_sha256(entropy)[0];
+        let mut bits = entropy.to_vec();
+        bits.push(checksum);
+        let num_words = (entropy.len() * 8 + entropy.len() / 4) / BITS_PER_WORD;
+        let words = (0..num_words)
+            .map(|word_idx| {
+                let idx = (0..BITS_PER_WORD).fold(0, |idx, bit| {
+                    let bit_idx = word_idx * BITS_PER_WORD + bit;
+                    (idx << 1) | ((bits[bit_idx / 8] >> (7 - bit_idx % 8)) & 1) as usize
+                });
+                wordlist[idx]
+            })
+            .collect();
+        Ok(Mnemonic { entropy: entropy.to_vec(), words })
+    }
+
+    pub fn parse(phrase: &str) -> Result<Self, MnemonicError> {
+        let wordlist = wordlist();
+        let indices = phrase.split_whitespace()
+            .map(|word| {
+                let word = word.to_lowercase();
+                wordlist.binary_search(&word.as_str())
+                    .map_err(|_| MnemonicError::UnknownWord(word))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if indices.len() < 12 || indices.len() > 24 || indices.len() % 3 != 0 {
+            return Err(MnemonicError::InvalidWordCount(indices.len()));
+        }
+        let num_bits = indices.len() * BITS_PER_WORD;
+        let mut bits = vec![0u8; (num_bits + 7) / 8];
+        for (word_idx, idx) in indices.iter().enumerate() {
+            for bit in 0..BITS_PER_WORD {
+                if (idx >> (BITS_PER_WORD - 1 - bit)) & 1 == 1 {
+                    let bit_idx = word_idx * BITS_PER_WORD + bit;
+                    bits[bit_idx / 8] |= 0x80 >> (bit_idx % 8);
+                }
+            }
+        }
+        let entropy_len = num_bits * 32 / 33 / 8;
+        let mnemonic = Mnemonic::from_entropy(&bits[..entropy_len])?;
+        if mnemonic.words.len() != indices.len() ||
+                mnemonic.words.iter().zip(indices.iter()).any(|(word, &idx)| *word != wordlist[idx]) {
+            return Err(MnemonicError::InvalidChecksum);
+        }
+        Ok(mnemonic)
+    }
+
+    pub fn entropy(&self) -> &[u8] {
+        &self.entropy
+    }
+
+    pub fn words(&self) -> &[&'static str] {
+        &self.words
+    }
+
+    pub fn phrase(&self) -> String {
+        self.words.join(" ")
+    }
+
+    // PBKDF2-HMAC-SHA512 seed. The passphrase is used as is, without NFKD normalization.
+    pub fn to_seed(&self, passphrase: &str) -> [u8; SEED_SIZE] {
+        let mut seed = [0; SEED_SIZE];
+        pbkdf2_hmac_sha512(
+            self.phrase().as_bytes(),
+            format!("mnemonic{}", passphrase).as_bytes(),
+            SEED_ITERATIONS,
+            &mut seed,
+        );
+        seed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vectors() {
+        let mnemonic = Mnemonic::from_entropy(&[0x7f; 16]).unwrap();
+        assert_eq!(mnemonic.phrase(),
+                   "legal winner thank year wave sausage worth useful legal winner thank yellow");
+        assert_eq!(hex::encode(&mnemonic.to_seed("TREZOR")[..]),
+                   "2e8905819b8723fe2c1d161860e5ee1830318dbf49a83bd451cfb8440c28bd6f\
+                    a457fe1296106559a3c80937a1c1069be3a3a5bd381ee6260e8d9739fce1f607");
+        assert_eq!(Mnemonic::parse(&mnemonic.phrase()), Ok(mnemonic));
+        let mnemonic = Mnemonic::from_entropy(&[0x80; 32]).unwrap();
+        assert_eq!(mnemonic.phrase(),
+                   "letter advice cage absurd amount doctor acoustic avoid letter advice cage \
+                    absurd amount doctor acoustic avoid letter advice cage absurd amount \
+                    doctor acoustic bless");
+        assert_eq!(Mnemonic::parse(&mnemonic.phrase()), Ok(mnemonic));
+        assert_eq!(
+            Mnemonic::parse("legal winner thank year wave sausage worth useful legal winner thank thank"),
+            Err(MnemonicError::InvalidChecksum),
+        );
+    }
+}
@@ -0,0 +1,412 @@
+use crate::address::{Address, AddressType};
+use crate::crypto::{Crypto, PublicKey, Signature};
+use crate::hash::hash160;
+use crate::outputs::{P2PKHOutput, P2SHOutput};
+use crate::script::{Script, Op, OpCodeType};
+use crate::tx::{Tx, TxInput, TxOutput, TxOutpoint, tx_hex_to_hash};
+use crate::unsigned_tx::{UnsignedTx, UnsignedInput, Output, PreImage};
+#[cfg(feature = "secp256k1")]
+use crate::bip32::DerivationPath;
+#[cfg(feature = "secp256k1")]
+use crate::tx_signer::ExternalSigner;
+use crate::sig_encoding::{SigEncodingError, canonical_signature};
+use crate::size_report::MAX_SCRIPT_ELEMENT_SIZE;
+use crate::wallet::{UtxoEntry, WalletError, DUST_AMOUNT, DEFAULT_FEE_PER_KB, WALLET_TX_VERSION};
+
+use std::collections::BTreeMap;
+
+
+const SIGHASH_ALL_FORKID: u32 = 0x41;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MultisigError {
+    InvalidThreshold { required: usize, num_keys: usize },
+    // the redeem script has to fit into a single push of the sig script
+    RedeemScriptTooLarge { size: usize, max: usize },
+    UnknownKey,
+    InvalidKeyIndex(usize),
+    SignatureCountMismatch { expected: usize, actual: usize },
+    RequestMismatch,
+    Incomplete { input_idx: usize },
+    InvalidSignature { input_idx: usize, error: SigEncodingError },
+    // well-formed signature which doesn't verify the input's sighash for the cosigner's key
+    WrongSignature { input_idx: usize },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CosignError<E> {
+    Multisig(MultisigError),
+    Signer(E),
+}
+
+// Bare m-of-n CHECKMULTISIG script, used as redeem script of the wallet's P2SH address.
+#[derive(Clone, Debug)]
+pub struct MultisigOutput {
+    pub value: u64,
+    pub required: usize,
+    pub pub_keys: Vec<Vec<u8>>,
+    // Signatures in pub key order; if empty, the signature passed to sig_script is
+    // repeated `required` times, which gives the right size for fee estimation.
+    pub signatures: Vec<Vec<u8>>,
+}
+
+// Partial signatures of one cosigner, one per input, sighash byte included.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartialSignatures {
+    pub key_idx: usize,
+    pub signatures: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SigningRequestInput {
+    pub pre_image: PreImage,
+    pub sig_hash: [u8; 32],
+    // Collected signatures by index of the signing key.
+    pub signatures: BTreeMap<usize, Vec<u8>>,
+}
+
+// PSBT-style request sent by the coordinator to the cosigners, which sign all inputs
+// and send back their PartialSignatures.
+#[derive(Clone, Debug)]
+pub struct SigningRequest {
+    pub required: usize,
+    pub pub_keys: Vec<Vec<u8>>,
+    pub inputs: Vec<SigningRequestInput>,
+}
+
+// Coordinator of an m-of-n P2SH multisig wallet.
+pub struct MultisigWallet {
+    required: usize,
+    pub_keys: Vec<Vec<u8>>,
+    address: Address,
+    fee_per_kb: u64,
+    dust_limit: u64,
+}
+
+impl MultisigOutput {
+    fn _ops(&self) -> Vec<Op> {
+        let mut ops = vec![int_op(self.required)];
+        ops.extend(self.pub_keys.iter().cloned().map(Op::Push));
+        ops.push(int_op(self.pub_keys.len()));
+        ops.push(Op::Code(OpCodeType::OpCheckMultiSig));
+        ops
+    }
+}
+
+fn int_op(n: usize) -> Op {
+    if (1..=16).contains(&n) {
+        Op::Code(num::FromPrimitive::from_u8(OpCodeType::Op1 as u8 + n as u8 - 1).unwrap())
+    } else {
+        Op::Push(vec![n as u8])
+    }
+}
+
+impl Output for MultisigOutput {
+    fn value(&self) -> u64 {
+        self.value
+    }
+
+    fn script(&self) -> Script {
+        Script::new(self._ops())
+    }
+
+    fn script_code(&self) -> Script {
+        self.script()
+    }
+
+    fn sig_script(&self,
+                  serialized_sig: Vec<u8>,
+                  _serialized_pub_key: Vec<u8>,
+                  _pre_image: &PreImage,
+                  _outputs: &[TxOutput]) -> Script {
+        let mut ops = vec![Op::Push(vec![])];  // CHECKMULTISIG dummy
+        if self.signatures.is_empty() {
+            ops.extend(vec![Op::Push(serialized_sig); self.required]);
+        } else {
+            ops.extend(self.signatures.iter().cloned().map(Op::Push));
+        }
+        Script::new(ops)
+    }
+}
+
+impl SigningRequest {
+    pub fn key_idx(&self, pub_key: &[u8]) -> Option<usize> {
+        self.pub_keys.iter().position(|key| key.as_slice() == pub_key)
+    }
+
+    // Signs all inputs as cosigner; fails if the key isn't one of the wallet's keys.
+    pub fn sign<C: Crypto>(&self, crypto: &C, secret_key: &C::SecretKey)
+            -> Result<PartialSignatures, MultisigError> {
+        let pub_key = crypto.secret_to_pub_key(secret_key).serialize();
+        let key_idx = self.key_idx(&pub_key).ok_or(MultisigError::UnknownKey)?;
+        let signatures = self.inputs.iter()
+            .map(|input| {
+                let mut sig = crypto.sign(&input.sig_hash, secret_key).serialize_der();
+                sig.push(input.pre_image.sighash_type as u8);
+                sig
+            })
+            .collect();
+        Ok(PartialSignatures { key_idx, signatures })
+    }
+
+    // Like sign, for a cosigner whose key is held by an external signer.
+    #[cfg(feature = "secp256k1")]
+    pub async fn sign_external<S: ExternalSigner + Sync>(&self, signer: &S, path: &DerivationPath)
+            -> Result<PartialSignatures, CosignError<S::Error>> {
+        let pub_key = signer.pub_key(path).await.map_err(CosignError::Signer)?;
+        let key_idx = self.key_idx(&pub_key).ok_or(CosignError::Multisig(MultisigError::UnknownKey))?;
+        let mut signatures = Vec::with_capacity(self.inputs.len());
+        for input in &self.inputs {
+            let mut sig = signer.sign_digest(&input.sig_hash, path).await.map_err(CosignError::Signer)?;
+            sig.push(input.pre_image.sighash_type as u8);
+            signatures.push(sig);
+        }
+        Ok(PartialSignatures { key_idx, signatures })
+    }
+
+    // Verifies the signatures against the cosigner's key before adding them.
+    pub fn add_partial<C: Crypto>(&mut self, crypto: &C, partial: PartialSignatures)
+            -> Result<(), MultisigError> {
+        if partial.key_idx >= self.pub_keys.len() {
+            return Err(MultisigError::InvalidKeyIndex(partial.key_idx));
+        }
+        if partial.signatures.len() != self.inputs.len() {
+            return Err(MultisigError::SignatureCountMismatch {
+                expected: self.inputs.len(),
+                actual: partial.signatures.len(),
+            });
+        }
+        let pub_key = &self.pub_keys[partial.key_idx];
+        let mut signatures = Vec::with_capacity(partial.signatures.len());
+        for (input_idx, (sig, input)) in partial.signatures.iter().zip(&self.inputs).enumerate() {
+            let (&sighash_type, sig) = sig.split_last()
+                .ok_or(MultisigError::InvalidSignature { input_idx, error: SigEncodingError::NotStrictDer })?;
+            let mut sig = canonical_signature(sig)
+                .map_err(|error| MultisigError::InvalidSignature { input_idx, error })?;
+            if sighash_type as u32 != input.pre_image.sighash_type ||
+                    !crypto.verify(&input.sig_hash, &sig, pub_key) {
+                return Err(MultisigError::WrongSignature { input_idx });
+            }
+            sig.push(sighash_type);
+            signatures.push(sig);
+        }
+        for (input, sig) in self.inputs.iter_mut().zip(signatures) {
+            input.signatures.insert(partial.key_idx, sig);
+        }
+        Ok(())
+    }
+
+    pub fn merge(&mut self, other: &SigningRequest) -> Result<(), MultisigError> {
+        if other.pub_keys != self.pub_keys || other.inputs.len() != self.inputs.len() ||
+                self.inputs.iter().zip(&other.inputs).any(|(a, b)| a.sig_hash != b.sig_hash) {
+            return Err(MultisigError::RequestMismatch);
+        }
+        for (input, other_input) in self.inputs.iter_mut().zip(&other.inputs) {
+            input.signatures.extend(other_input.signatures.clone());
+        }
+        Ok(())
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.inputs.iter().all(|input| input.signatures.len() >= self.required)
+    }
+}
+
+impl MultisigWallet {
+    pub fn new(required: usize, pub_keys: Vec<Vec<u8>>, prefix: &str) -> Result<Self, MultisigError> {
+        if required == 0 || required > pub_keys.len() || pub_keys.len() > 16 {
+            return Err(MultisigError::InvalidThreshold { required, num_keys: pub_keys.len() });
+        }
+        let redeem_script = MultisigOutput {
+            value: 0,
+            required,
+            pub_keys: pub_keys.clone(),
+            signatures: vec![],
+        }.script();
+        let size = redeem_script.to_vec().len();
+        if size > MAX_SCRIPT_ELEMENT_SIZE {
+            return Err(MultisigError::RedeemScriptTooLarge { size, max: MAX_SCRIPT_ELEMENT_SIZE });
+        }
+        let address = Address::from_bytes_prefix(prefix,
+                                                 AddressType::P2SH,
+                                                 hash160(&redeem_script.to_vec()));
+        Ok(MultisigWallet {
+            required,
+            pub_keys,
+            address,
+            fee_per_kb: DEFAULT_FEE_PER_KB,
+            dust_limit: DUST_AMOUNT,
+        })
+    }
+
+    pub fn with_fee_rate(mut self, fee_per_kb: u64) -> Self {
+        self.fee_per_kb = fee_per_kb;
+        self
+    }
+
+    pub fn with_dust_limit(mut self, dust_limit: u64) -> Self {
+        self.dust_limit = dust_limit;
+        self
+    }
+
+    pub fn address(&self) -> &Address {
+        &self.address
+    }
+
+    pub fn required(&self) -> usize {
+        self.required
+    }
+
+    pub fn pub_keys(&self) -> &[Vec<u8>] {
+        &self.pub_keys
+    }
+
+    fn output(&self, value: u64, signatures: Vec<Vec<u8>>) -> P2SHOutput {
+        P2SHOutput {
+            output: Box::new(MultisigOutput {
+                value,
+                required: self.required,
+                pub_keys: self.pub_keys.clone(),
+                signatures,
+            }),
+        }
+    }
+
+    pub fn redeem_script(&self) -> Script {
+        self.output(0, vec![]).script_code()
+    }
+
+    pub fn init_tx(&self, utxos: &[UtxoEntry]) -> UnsignedTx {
+        let mut tx_build = UnsignedTx::new_with_version(WALLET_TX_VERSION);
+        for utxo in utxos {
+            tx_build.add_input(UnsignedInput::new(
+                TxOutpoint {
+                    tx_hash: tx_hex_to_hash(&utxo.tx_id_hex).unwrap(),
+                    vout: utxo.vout,
+                },
+                Box::new(self.output(utxo.amount, vec![])),
+                0xffff_ffff,
+            ));
+        }
+        tx_build
+    }
+
+    // Appends change to the wallet's P2SH address. The leftover is sized as P2PKH output,
+    // which is 2 bytes larger, so the fee is never too low.
+    pub fn add_change_output(&self, tx_build: &mut UnsignedTx) -> Result<Option<usize>, WalletError> {
+        let placeholder = Address::from_bytes(AddressType::P2PKH, *self.address.bytes());
+        let change_idx = tx_build.add_leftover_output(placeholder, self.fee_per_kb, self.dust_limit)?;
+        if let Some(change_idx) = change_idx {
+            let value = tx_build.outputs()[change_idx].value;
+            tx_build.replace_output(change_idx, self.output(value, vec![]).to_output());
+        }
+        Ok(change_idx)
+    }
+
+    pub fn send_to_address(&self, address: Address, amount: u64, utxos: &[UtxoEntry])
+            -> Result<UnsignedTx, WalletError> {
+        let mut tx_build = self.init_tx(utxos);
+        let script = match address.addr_type() {
+            AddressType::P2PKH => P2PKHOutput { address, value: amount }.script(),
+            AddressType::P2SH => Script::new(vec![
+                Op::Code(OpCodeType::OpHash160),
+                Op::Push(address.bytes().to_vec()),
+                Op::Code(OpCodeType::OpEqual),
+            ]),
+        };
+        tx_build.add_output(TxOutput::new(amount, script));
+        self.add_change_output(&mut tx_build)?;
+        Ok(tx_build)
+    }
+
+    pub fn signing_request(&self, tx_build: &UnsignedTx) -> SigningRequest {
+        let inputs = tx_build.pre_images(SIGHASH_ALL_FORKID).into_iter()
+            .map(|pre_image| SigningRequestInput {
+                sig_hash: pre_image.sighash(),
+                pre_image,
+                signatures: BTreeMap::new(),
+            })
+            .collect();
+        SigningRequest {
+            required: self.required,
+            pub_keys: self.pub_keys.clone(),
+            inputs,
+        }
+    }
+
+    // Builds the final tx from a complete request for `tx_build`. Signatures are placed in pub
+    // key order, as required by OP_CHECKMULTISIG; surplus signatures are dropped.
+    pub fn finalize(&self, tx_build: &UnsignedTx, request: &SigningRequest) -> Result<Tx, MultisigError> {
+        if request.pub_keys != self.pub_keys || request.inputs.len() != tx_build.inputs().len() {
+            return Err(MultisigError::RequestMismatch);
+        }
+        let pre_images = tx_build.pre_images(SIGHASH_ALL_FORKID);
+        let mut tx_inputs = Vec::with_capacity(tx_build.inputs().len());
+        for (input_idx, ((input, request_input), pre_image)) in tx_build.inputs().iter()
+                .zip(&request.inputs)
+                .zip(&pre_images)
+                .enumerate() {
+            if request_input.sig_hash != pre_image.sighash() ||
+                    request_input.pre_image.sighash() != request_input.sig_hash {
+                return Err(MultisigError::RequestMismatch);
+            }
+            if request_input.signatures.len() < self.required {
+                return Err(MultisigError::Incomplete { input_idx });
+            }
+            let signatures = request_input.signatures.values()
+                .take(self.required)
+                .cloned()
+                .collect();
+            let script = self.output(input.output.value(), signatures)
+                .sig_script(vec![], vec![], &request_input.pre_image, tx_build.outputs());
+            tx_inputs.push(TxInput::new(input.outpoint.clone(), script, tx_build.input_sequence(input_idx)));
+        }
+        Ok(Tx::new(tx_build.version(), tx_inputs, tx_build.outputs().to_vec(), tx_build.lock_time()))
+    }
+}
+
+#[cfg(all(test, feature = "secp256k1"))]
+mod tests {
+    use super::*;
+    use crate::crypto::secp256k1::CryptoSecp256k1;
+    use crate::tx::tx_hash_to_hex;
+
+    #[test]
+    fn test_multisig_wallet() {
+        let crypto = CryptoSecp256k1::new();
+        let secret_keys = (1..=16u8)
+            .map(|i| secp256k1::SecretKey::from_slice(&[i; 32]).unwrap())
+            .collect::<Vec<_>>();
+        let pub_keys = secret_keys.iter()
+            .map(|key| crypto.secret_to_pub_key(key).serialize().to_vec())
+            .collect::<Vec<_>>();
+        // 16 compressed keys exceed the 520 byte push limit, funds couldn't be spent
+        assert_eq!(MultisigWallet::new(1, pub_keys.clone(), "bitcoincash").err(),
+                   Some(MultisigError::RedeemScriptTooLarge { size: 547, max: 520 }));
+        assert!(MultisigWallet::new(1, pub_keys[..15].to_vec(), "bitcoincash").is_ok());
+
+        let wallet = MultisigWallet::new(2, pub_keys[..3].to_vec(), "bitcoincash").unwrap();
+        let utxo = |tx_hash: [u8; 32]| UtxoEntry {
+            tx_id_hex: tx_hash_to_hex(&tx_hash),
+            amount: 100_000,
+            ..UtxoEntry::default()
+        };
+        let recipient = Address::from_bytes(AddressType::P2PKH, [0x11; 20]);
+        let tx_build = wallet.send_to_address(recipient.clone(), 50_000, &[utxo([0x22; 32])]).unwrap();
+        let mut request = wallet.signing_request(&tx_build);
+        let partial = |request: &SigningRequest, key_idx: usize| request.sign(&crypto, &secret_keys[key_idx]).unwrap();
+
+        // signature of key 2 claimed to be of key 0
+        let wrong = PartialSignatures { key_idx: 0, ..partial(&request, 2) };
+        assert_eq!(request.add_partial(&crypto, wrong), Err(MultisigError::WrongSignature { input_idx: 0 }));
+        request.add_partial(&crypto, partial(&request, 0)).unwrap();
+        request.add_partial(&crypto, partial(&request, 2)).unwrap();
+        assert!(request.is_complete());
+
+        let other_tx = wallet.send_to_address(recipient, 50_000, &[utxo([0x33; 32])]).unwrap();
+        assert_eq!(wallet.finalize(&other_tx, &request).err(), Some(MultisigError::RequestMismatch));
+        let tx = wallet.finalize(&tx_build, &request).unwrap();
+        let spent = tx_build.inputs().iter().map(|input| input.output.to_output()).collect::<Vec<_>>();
+        assert_eq!(tx.verify_input_signatures(&crypto, &spent), Ok(()));
+    }
+}
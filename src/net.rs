@@ -0,0 +1,350 @@
+use crate::bloom::BloomFilter;
+use crate::hash::double_sha256;
+use crate::serialize::{read_var_int_minimal, read_var_str_max, write_var_int, write_var_str};
+use crate::tx::Tx;
+
+use std::io::{self, Read, Write};
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
+
+
+pub const MAINNET_MAGIC: [u8; 4] = [0xe3, 0xe1, 0xf3, 0xe8];
+pub const TESTNET3_MAGIC: [u8; 4] = [0xf4, 0xe5, 0xf3, 0xf4];
+pub const TESTNET4_MAGIC: [u8; 4] = [0xe2, 0xb7, 0xda, 0xaf];
+pub const REGTEST_MAGIC: [u8; 4] = [0xda, 0xb5, 0xbf, 0xfa];
+
+pub const PROTOCOL_VERSION: i32 = 70016;
+pub const NODE_NETWORK: u64 = 1;
+pub const MESSAGE_HEADER_SIZE: usize = 24;
+// Largest payload accepted by BCHN (the 32 MB block size limit plus some slack).
+pub const MAX_PAYLOAD_SIZE: u32 = 2 * 32 * 1_000_000;
+
+// Limits of BCHN for the user agent and the number of inv vectors.
+pub const MAX_USER_AGENT_SIZE: usize = 256;
+pub const MAX_INV_SIZE: u64 = 50_000;
+
+const COMMAND_SIZE: usize = 12;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InvType {
+    Error,
+    Tx,
+    Block,
+    FilteredBlock,
+    DoubleSpendProof,
+    Unknown(u32),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvVector {
+    pub inv_type: InvType,
+    pub hash: [u8; 32],
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NetAddress {
+    pub services: u64,
+    // IPv6 address, or IPv4 address mapped as ::ffff:a.b.c.d
+    pub ip: [u8; 16],
+    pub port: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VersionMessage {
+    pub version: i32,
+    pub services: u64,
+    pub timestamp: i64,
+    pub receiver: NetAddress,
+    pub sender: NetAddress,
+    pub nonce: u64,
+    pub user_agent: String,
+    pub start_height: i32,
+    pub relay: bool,
+}
+
+#[derive(Clone, Debug)]
+pub enum NetMessage {
+    Version(VersionMessage),
+    Verack,
+    Inv(Vec<InvVector>),
+    GetData(Vec<InvVector>),
+    Tx(Tx),
+    Ping(u64),
+    Pong(u64),
+    FilterLoad(BloomFilter),
+    FilterClear,
+    // Any other message, with its command and raw payload.
+    Unknown { command: String, payload: Vec<u8> },
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl InvType {
+    pub fn from_u32(inv_type: u32) -> Self {
+        match inv_type {
+            0 => InvType::Error,
+            1 => InvType::Tx,
+            2 => InvType::Block,
+            3 => InvType::FilteredBlock,
+            0x94a0 => InvType::DoubleSpendProof,
+            inv_type => InvType::Unknown(inv_type),
+        }
+    }
+
+    pub fn to_u32(self) -> u32 {
+        match self {
+            InvType::Error => 0,
+            InvType::Tx => 1,
+            InvType::Block => 2,
+            InvType::FilteredBlock => 3,
+            InvType::DoubleSpendProof => 0x94a0,
+            InvType::Unknown(inv_type) => inv_type,
+        }
+    }
+}
+
+impl InvVector {
+    pub fn read_from_stream<R: Read>(read: &mut R) -> io::Result<Self> {
+        let inv_type = InvType::from_u32(read.read_u32::<LittleEndian>()?);
+        let mut hash = [0; 32];
+        read.read_exact(&mut hash)?;
+        Ok(InvVector { inv_type, hash })
+    }
+
+    pub fn write_to_stream<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        write.write_u32::<LittleEndian>(self.inv_type.to_u32())?;
+        write.write_all(&self.hash)
+    }
+}
+
+impl NetAddress {
+    pub fn from_ipv4(services: u64, ip: [u8; 4], port: u16) -> Self {
+        let mut mapped = [0; 16];
+        mapped[10] = 0xff;
+        mapped[11] = 0xff;
+        mapped[12..].copy_from_slice(&ip);
+        NetAddress { services, ip: mapped, port }
+    }
+
+    // Address as used in the version message, i.e. without timestamp.
+    pub fn read_from_stream<R: Read>(read: &mut R) -> io::Result<Self> {
+        let services = read.read_u64::<LittleEndian>()?;
+        let mut ip = [0; 16];
+        read.read_exact(&mut ip)?;
+        let port = read.read_u16::<BigEndian>()?;
+        Ok(NetAddress { services, ip, port })
+    }
+
+    pub fn write_to_stream<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        write.write_u64::<LittleEndian>(self.services)?;
+        write.write_all(&self.ip)?;
+        write.write_u16::<BigEndian>(self.port)
+    }
+}
+
+impl VersionMessage {
+    pub fn read_from_stream<R: Read>(read: &mut R) -> io::Result<Self> {
+        let version = read.read_i32::<LittleEndian>()?;
+        let services = read.read_u64::<LittleEndian>()?;
+        let timestamp = read.read_i64::<LittleEndian>()?;
+        let receiver = NetAddress::read_from_stream(read)?;
+        let sender = NetAddress::read_from_stream(read)?;
+        let nonce = read.read_u64::<LittleEndian>()?;
+        let user_agent = String::from_utf8(read_var_str_max(read, MAX_USER_AGENT_SIZE)?)
+            .map_err(|_| invalid_data("Invalid user agent"))?;
+        let start_height = read.read_i32::<LittleEndian>()?;
+        // relay is optional for old peers
+        let relay = match read.read_u8() {
+            Ok(relay) => relay != 0,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => true,
+            Err(err) => return Err(err),
+        };
+        Ok(VersionMessage {
+            version, services, timestamp, receiver, sender, nonce, user_agent, start_height, relay,
+        })
+    }
+
+    pub fn write_to_stream<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        write.write_i32::<LittleEndian>(self.version)?;
+        write.write_u64::<LittleEndian>(self.services)?;
+        write.write_i64::<LittleEndian>(self.timestamp)?;
+        self.receiver.write_to_stream(write)?;
+        self.sender.write_to_stream(write)?;
+        write.write_u64::<LittleEndian>(self.nonce)?;
+        write_var_str(write, self.user_agent.as_bytes())?;
+        write.write_i32::<LittleEndian>(self.start_height)?;
+        write.write_u8(self.relay as u8)
+    }
+}
+
+fn read_inv_vectors<R: Read>(read: &mut R) -> io::Result<Vec<InvVector>> {
+    let num_vectors = read_var_int_minimal(read)?;
+    if num_vectors > MAX_INV_SIZE {
+        return Err(invalid_data("Too many inv vectors"));
+    }
+    let mut vectors = Vec::new();
+    for _ in 0..num_vectors {
+        vectors.push(InvVector::read_from_stream(read)?);
+    }
+    Ok(vectors)
+}
+
+fn write_inv_vectors<W: Write>(write: &mut W, vectors: &[InvVector]) -> io::Result<()> {
+    write_var_int(write, vectors.len() as u64)?;
+    for vector in vectors.iter() {
+        vector.write_to_stream(write)?;
+    }
+    Ok(())
+}
+
+impl NetMessage {
+    pub fn command(&self) -> &str {
+        match self {
+            NetMessage::Version(_) => "version",
+            NetMessage::Verack => "verack",
+            NetMessage::Inv(_) => "inv",
+            NetMessage::GetData(_) => "getdata",
+            NetMessage::Tx(_) => "tx",
+            NetMessage::Ping(_) => "ping",
+            NetMessage::Pong(_) => "pong",
+            NetMessage::FilterLoad(_) => "filterload",
+            NetMessage::FilterClear => "filterclear",
+            NetMessage::Unknown { command, .. } => command,
+        }
+    }
+
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        match self {
+            NetMessage::Version(version) => version.write_to_stream(&mut payload).unwrap(),
+            NetMessage::Verack => {},
+            NetMessage::Inv(vectors) | NetMessage::GetData(vectors) => {
+                write_inv_vectors(&mut payload, vectors).unwrap()
+            },
+            NetMessage::Tx(tx) => tx.write_to_stream(&mut payload).unwrap(),
+            NetMessage::Ping(nonce) | NetMessage::Pong(nonce) => {
+                payload.write_u64::<LittleEndian>(*nonce).unwrap()
+            },
+            NetMessage::FilterLoad(filter) => filter.write_to_stream(&mut payload).unwrap(),
+            NetMessage::FilterClear => {},
+            NetMessage::Unknown { payload: raw, .. } => payload.extend_from_slice(raw),
+        }
+        payload
+    }
+
+    pub fn from_payload(command: &str, payload: &[u8]) -> io::Result<Self> {
+        let mut cur = io::Cursor::new(payload);
+        let message = match command {
+            "version" => NetMessage::Version(VersionMessage::read_from_stream(&mut cur)?),
+            "verack" => NetMessage::Verack,
+            "inv" => NetMessage::Inv(read_inv_vectors(&mut cur)?),
+            "getdata" => NetMessage::GetData(read_inv_vectors(&mut cur)?),
+            "tx" => NetMessage::Tx(Tx::read_from_stream(&mut cur)?),
+            "ping" => NetMessage::Ping(cur.read_u64::<LittleEndian>()?),
+            "pong" => NetMessage::Pong(cur.read_u64::<LittleEndian>()?),
+            "filterload" => NetMessage::FilterLoad(BloomFilter::read_from_stream(&mut cur)?),
+            "filterclear" => NetMessage::FilterClear,
+            _ => return Ok(NetMessage::Unknown {
+                command: command.to_string(),
+                payload: payload.to_vec(),
+            }),
+        };
+        if cur.position() as usize != payload.len() {
+            return Err(invalid_data("Trailing bytes in payload"));
+        }
+        Ok(message)
+    }
+
+    // Writes the message with its envelope: magic, NUL padded command, payload length and
+    // the first 4 bytes of the payload's double sha256.
+    pub fn write_to_stream<W: Write>(&self, write: &mut W, magic: [u8; 4]) -> io::Result<()> {
+        let command = self.command().as_bytes();
+        if command.len() > COMMAND_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Command too long"));
+        }
+        let payload = self.payload();
+        let mut command_padded = [0; COMMAND_SIZE];
+        command_padded[..command.len()].copy_from_slice(command);
+        write.write_all(&magic)?;
+        write.write_all(&command_padded)?;
+        write.write_u32::<LittleEndian>(payload.len() as u32)?;
+        write.write_all(&double_sha256(&payload)[..4])?;
+        write.write_all(&payload)
+    }
+
+    pub fn read_from_stream<R: Read>(read: &mut R, magic: [u8; 4]) -> io::Result<Self> {
+        let mut header = [0; MESSAGE_HEADER_SIZE];
+        read.read_exact(&mut header)?;
+        if header[..4] != magic {
+            return Err(invalid_data("Invalid network magic"));
+        }
+        let command = &header[4..4 + COMMAND_SIZE];
+        let command_len = command.iter().position(|&b| b == 0).unwrap_or(COMMAND_SIZE);
+        if command[command_len..].iter().any(|&b| b != 0) {
+            return Err(invalid_data("Invalid command padding"));
+        }
+        let command = std::str::from_utf8(&command[..command_len])
+            .map_err(|_| invalid_data("Invalid command"))?;
+        let mut cur = io::Cursor::new(&header[16..]);
+        let payload_len = cur.read_u32::<LittleEndian>()?;
+        if payload_len > MAX_PAYLOAD_SIZE {
+            return Err(invalid_data("Payload too large"));
+        }
+        let mut payload = vec![0; payload_len as usize];
+        read.read_exact(&mut payload)?;
+        if double_sha256(&payload)[..4] != header[20..] {
+            return Err(invalid_data("Invalid checksum"));
+        }
+        NetMessage::from_payload(command, &payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_framing() {
+        let mut verack = Vec::new();
+        NetMessage::Verack.write_to_stream(&mut verack, MAINNET_MAGIC).unwrap();
+        assert_eq!(hex::encode(&verack), "e3e1f3e876657261636b000000000000000000005df6e0e2");
+
+        let version = NetMessage::Version(VersionMessage {
+            version: PROTOCOL_VERSION,
+            services: 0,
+            timestamp: 1_600_000_000,
+            receiver: NetAddress::from_ipv4(NODE_NETWORK, [127, 0, 0, 1], 8333),
+            sender: NetAddress::from_ipv4(0, [0; 4], 0),
+            nonce: 42,
+            user_agent: "/cashcontracts:0.1.3/".to_string(),
+            start_height: 0,
+            relay: false,
+        });
+        let inv = NetMessage::Inv(vec![InvVector { inv_type: InvType::Tx, hash: [1; 32] }]);
+        for message in &[version, inv, NetMessage::Ping(7)] {
+            let mut serialized = Vec::new();
+            message.write_to_stream(&mut serialized, REGTEST_MAGIC).unwrap();
+            let parsed = NetMessage::read_from_stream(&mut io::Cursor::new(&serialized), REGTEST_MAGIC)
+                .unwrap();
+            assert_eq!(parsed.command(), message.command());
+            assert_eq!(parsed.payload(), message.payload());
+            let parsed = NetMessage::read_from_stream(&mut io::Cursor::new(&serialized), MAINNET_MAGIC);
+            assert!(parsed.is_err());
+            *serialized.last_mut().unwrap() ^= 1;
+            let parsed = NetMessage::read_from_stream(&mut io::Cursor::new(&serialized), REGTEST_MAGIC);
+            assert!(parsed.is_err());
+        }
+
+        // claimed lengths of gigabytes fail without allocating them
+        let huge_len = [0xfe, 0xff, 0xff, 0xff, 0x7f];
+        let mut version = vec![0; 80];
+        version.extend_from_slice(&huge_len);
+        assert!(NetMessage::from_payload("version", &version).is_err());
+        let mut tx = vec![1, 0, 0, 0, 1];
+        tx.extend_from_slice(&[0; 36]);
+        tx.extend_from_slice(&huge_len);
+        assert!(NetMessage::from_payload("tx", &tx).is_err());
+        assert!(NetMessage::from_payload("inv", &huge_len).is_err());
+    }
+}
@@ -0,0 +1,285 @@
+use crate::p2_ascending_nonce::{P2AscendingNonce, NonceError};
+use crate::address::Address;
+use crate::tx::TxOutpoint;
+use crate::unsigned_tx::UnsignedTx;
+use crate::serialize::{encode_int64_n, MAX_SCRIPT_INT};
+
+
+// Owner's authorization for the holder of `redeemer` to redeem `payment_amount` from the
+// covenant with `nonce`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NonceAuthorization {
+    pub redeemer: Address,
+    pub payment_amount: u64,
+    pub nonce: i64,
+    pub owner_sig: Vec<u8>,
+}
+
+// Wallet-side state of a P2AscendingNonce covenant and the authorizations issued against it.
+#[derive(Clone, Debug)]
+pub struct NonceChannel {
+    covenant: P2AscendingNonce,
+    outpoint: Option<TxOutpoint>,
+    pending: Vec<NonceAuthorization>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NonceChannelError {
+    Nonce(NonceError),
+    Closed,
+    StaleNonce { nonce: i64, next_nonce: i64 },
+    InsufficientBalance { balance: u64, payment_amount: u64 },
+    InvalidSignature,
+    UnknownAuthorization,
+}
+
+impl From<NonceError> for NonceChannelError {
+    fn from(err: NonceError) -> Self {
+        NonceChannelError::Nonce(err)
+    }
+}
+
+// Message the owner signs (with OP_CHECKDATASIG semantics) to authorize a payment:
+// hash160(redeemer pk) || payment amount (8 bytes) || nonce (8 bytes)
+pub fn nonce_authorization_message(redeemer: &Address, payment_amount: u64, nonce: i64)
+        -> Result<Vec<u8>, NonceError> {
+    if payment_amount > MAX_SCRIPT_INT {
+        return Err(NonceError::InvalidPaymentAmount(payment_amount));
+    }
+    let mut message = redeemer.bytes().to_vec();
+    message.extend_from_slice(&encode_int64_n(payment_amount as i64, 8)
+        .map_err(|_| NonceError::InvalidPaymentAmount(payment_amount))?);
+    message.extend_from_slice(&encode_int64_n(nonce, 8)
+        .map_err(|_| NonceError::NonceOutOfRange(nonce))?);
+    Ok(message)
+}
+
+impl NonceAuthorization {
+    pub fn message(&self) -> Result<Vec<u8>, NonceError> {
+        nonce_authorization_message(&self.redeemer, self.payment_amount, self.nonce)
+    }
+}
+
+impl NonceChannel {
+    pub fn new(covenant: P2AscendingNonce, outpoint: TxOutpoint) -> Self {
+        NonceChannel {
+            covenant,
+            outpoint: Some(outpoint),
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn covenant(&self) -> &P2AscendingNonce {
+        &self.covenant
+    }
+
+    pub fn outpoint(&self) -> Option<&TxOutpoint> {
+        self.outpoint.as_ref()
+    }
+
+    pub fn pending(&self) -> &[NonceAuthorization] {
+        &self.pending
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.outpoint.is_none()
+    }
+
+    // On-chain value of the covenant.
+    pub fn balance(&self) -> u64 {
+        if self.is_closed() { 0 } else { self.covenant.old_value }
+    }
+
+    // Balance not yet promised by pending authorizations.
+    pub fn available_balance(&self) -> u64 {
+        self.balance().saturating_sub(self.pending.iter().map(|auth| auth.payment_amount).sum())
+    }
+
+    // On-chain nonce of the covenant.
+    pub fn nonce(&self) -> i64 {
+        self.covenant.old_nonce
+    }
+
+    pub fn next_nonce(&self) -> i64 {
+        self.pending.iter()
+            .map(|auth| auth.nonce)
+            .max()
+            .unwrap_or(self.covenant.old_nonce)
+            .saturating_add(1)
+    }
+
+    // Owner side: issues the next authorization, `sign` signs the authorization message.
+    pub fn authorize<F>(&mut self, redeemer: Address, payment_amount: u64, sign: F)
+            -> Result<NonceAuthorization, NonceChannelError>
+            where F: FnOnce(&[u8]) -> Vec<u8> {
+        self._check_payment(payment_amount)?;
+        let nonce = self.next_nonce();
+        let owner_sig = sign(&nonce_authorization_message(&redeemer, payment_amount, nonce)?);
+        let auth = NonceAuthorization { redeemer, payment_amount, nonce, owner_sig };
+        self.pending.push(auth.clone());
+        Ok(auth)
+    }
+
+    // Owner side: authorizes paying out the remaining balance, which closes the covenant.
+    pub fn close<F>(&mut self, redeemer: Address, sign: F)
+            -> Result<NonceAuthorization, NonceChannelError>
+            where F: FnOnce(&[u8]) -> Vec<u8> {
+        let payment_amount = self.available_balance();
+        self.authorize(redeemer, payment_amount, sign)
+    }
+
+    // Redeemer side: accepts an authorization after checking it against the channel state.
+    // `verify` checks a data signature (sig, message, pub key).
+    pub fn receive<F>(&mut self, auth: NonceAuthorization, verify: F) -> Result<(), NonceChannelError>
+            where F: FnOnce(&[u8], &[u8], &[u8]) -> bool {
+        self._check_payment(auth.payment_amount)?;
+        let next_nonce = self.next_nonce();
+        if auth.nonce < next_nonce {
+            return Err(NonceChannelError::StaleNonce { nonce: auth.nonce, next_nonce });
+        }
+        if !verify(&auth.owner_sig, &auth.message()?, &self.covenant.owner_pk) {
+            return Err(NonceChannelError::InvalidSignature);
+        }
+        self.pending.push(auth);
+        Ok(())
+    }
+
+    pub fn redeem_tx(&self, auth: &NonceAuthorization, fee_per_kb: u64, is_schnorr: bool)
+            -> Result<UnsignedTx, NonceChannelError> {
+        let outpoint = self.outpoint.clone().ok_or(NonceChannelError::Closed)?;
+        if !self.pending.contains(auth) {
+            return Err(NonceChannelError::UnknownAuthorization);
+        }
+        Ok(self.covenant.redeem_tx(
+            outpoint,
+            auth.payment_amount,
+            auth.nonce,
+            auth.owner_sig.clone(),
+            auth.redeemer.clone(),
+            fee_per_kb,
+            is_schnorr,
+        )?)
+    }
+
+    // Applies a confirmed redeem transaction. Authorizations with a lower nonce can't be
+    // redeemed anymore and are dropped.
+    pub fn apply_redeem(&mut self, auth: &NonceAuthorization, tx_hash: [u8; 32])
+            -> Result<(), NonceChannelError> {
+        if self.is_closed() {
+            return Err(NonceChannelError::Closed);
+        }
+        match self.covenant.next_state_with_nonce(auth.payment_amount, auth.nonce)? {
+            Some(next_covenant) => {
+                self.covenant = next_covenant;
+                self.outpoint = Some(TxOutpoint { tx_hash, vout: 0 });
+            },
+            None => self.outpoint = None,
+        }
+        self.pending.retain(|pending| pending.nonce > auth.nonce);
+        Ok(())
+    }
+
+    // Applies a confirmed refill transaction.
+    pub fn apply_refill(&mut self, refill_amount: u64, tx_hash: [u8; 32])
+            -> Result<(), NonceChannelError> {
+        if self.is_closed() {
+            return Err(NonceChannelError::Closed);
+        }
+        let refilled = P2AscendingNonce {
+            old_value: self.covenant.old_value.checked_add(refill_amount)
+                .ok_or(NonceError::InvalidPaymentAmount(refill_amount))?,
+            ..self.covenant.clone()
+        };
+        refilled.validate()?;
+        self.covenant = refilled;
+        self.outpoint = Some(TxOutpoint { tx_hash, vout: 0 });
+        Ok(())
+    }
+
+    fn _check_payment(&self, payment_amount: u64) -> Result<(), NonceChannelError> {
+        if self.is_closed() {
+            return Err(NonceChannelError::Closed);
+        }
+        let balance = self.available_balance();
+        if payment_amount == 0 || payment_amount > balance {
+            return Err(NonceChannelError::InsufficientBalance { balance, payment_amount });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "secp256k1"))]
+mod tests {
+    use super::*;
+    use crate::address::AddressType;
+    use crate::crypto::{Crypto, Signature};
+    use crate::crypto::secp256k1::CryptoSecp256k1;
+    use crate::hash::single_sha256;
+    use crate::script_debugger::ScriptDebugger;
+    use crate::spend::LockingScript;
+
+    #[test]
+    fn test_channel_redeem() {
+        let crypto = CryptoSecp256k1::new();
+        let owner_key = secp256k1::SecretKey::from_slice(&[2; 32]).unwrap();
+        let redeemer_key = secp256k1::SecretKey::from_slice(&[3; 32]).unwrap();
+        let redeemer = Address::from_serialized_pub_key(
+            "bitcoincash", AddressType::P2PKH, &crypto.secret_to_pub_key(&redeemer_key).serialize());
+        let covenant = P2AscendingNonce {
+            lokad_id: b"TEST".to_vec(),
+            old_value: 100_000,
+            owner_pk: crypto.secret_to_pub_key(&owner_key).serialize().to_vec(),
+            old_nonce: 1,
+            dust_limit: 546,
+        };
+        let outpoint = TxOutpoint { tx_hash: [1; 32], vout: 0 };
+        let sign = |message: &[u8]| Signature::serialize_der(&crypto.sign(&single_sha256(message), &owner_key));
+        let verify = |sig: &[u8], message: &[u8], pub_key: &[u8]| {
+            crypto.verify(&single_sha256(message), sig, pub_key)
+        };
+        let mut owner = NonceChannel::new(covenant.clone(), outpoint.clone());
+        let mut holder = NonceChannel::new(covenant, outpoint);
+
+        let auth1 = owner.authorize(redeemer.clone(), 20_000, sign).unwrap();
+        let auth2 = owner.authorize(redeemer.clone(), 30_000, sign).unwrap();
+        assert_eq!((auth1.nonce, auth2.nonce), (2, 3));
+        assert_eq!(owner.available_balance(), 50_000);
+        assert_eq!(owner.authorize(redeemer.clone(), 50_001, sign),
+                   Err(NonceChannelError::InsufficientBalance { balance: 50_000, payment_amount: 50_001 }));
+        let forged = NonceAuthorization { payment_amount: 25_000, ..auth1.clone() };
+        assert_eq!(holder.receive(forged, verify), Err(NonceChannelError::InvalidSignature));
+        holder.receive(auth1.clone(), verify).unwrap();
+        holder.receive(auth2.clone(), verify).unwrap();
+        assert_eq!(holder.receive(auth1.clone(), verify),
+                   Err(NonceChannelError::StaleNonce { nonce: 2, next_nonce: 4 }));
+
+        // redeeming auth2 invalidates auth1, which has a lower nonce
+        let spent_output = holder.covenant().to_p2sh_output();
+        let tx = holder.redeem_tx(&auth2, 1000, false).unwrap()
+            .sign_with(&crypto, &redeemer_key)
+            .unwrap();
+        assert_eq!(ScriptDebugger::new(&crypto, &tx, 0, &spent_output).run(), Ok(()));
+        for channel in [&mut owner, &mut holder] {
+            channel.apply_redeem(&auth2, tx.hash()).unwrap();
+            assert!(channel.pending().is_empty());
+            assert_eq!((channel.balance(), channel.nonce()), (70_000, 3));
+            assert_eq!(channel.covenant().to_p2sh_output().script.to_vec(), tx.outputs()[0].script.to_vec());
+        }
+        assert_eq!(holder.redeem_tx(&auth1, 1000, false).err(), Some(NonceChannelError::UnknownAuthorization));
+
+        // closing pays out the remaining balance without a continuation
+        let auth = owner.close(redeemer.clone(), sign).unwrap();
+        assert_eq!((auth.payment_amount, auth.nonce), (70_000, 4));
+        holder.receive(auth.clone(), verify).unwrap();
+        let spent_output = holder.covenant().to_p2sh_output();
+        let tx = holder.redeem_tx(&auth, 1000, false).unwrap()
+            .sign_with(&crypto, &redeemer_key)
+            .unwrap();
+        assert_eq!(tx.outputs().len(), 1);
+        assert_eq!(ScriptDebugger::new(&crypto, &tx, 0, &spent_output).run(), Ok(()));
+        holder.apply_redeem(&auth, tx.hash()).unwrap();
+        assert!(holder.is_closed());
+        assert_eq!(holder.balance(), 0);
+        assert_eq!(holder.apply_redeem(&auth, tx.hash()), Err(NonceChannelError::Closed));
+    }
+}
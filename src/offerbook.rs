@@ -0,0 +1,213 @@
+use crate::advanced_trade_offer::{AdvancedTradeOffer, OfferState};
+use crate::hash::hash160;
+use crate::script::{Op, OpCodeType};
+use crate::slp::SLPMessage;
+use crate::tx::{Tx, TxOutpoint};
+use crate::spend::LockingScript;
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+
+// In-memory order book of AdvancedTradeOffers, kept up to date by applying block and mempool
+// txs in order. Covenant outputs don't reveal their redeem script, so new offers are either
+// announced beforehand (e.g. relayed by the seller) or picked up once a spend reveals them;
+// a partial accept of an unknown offer adds the remaining offer.
+#[derive(Clone, Debug, Default)]
+pub struct OfferBook {
+    // announced offers by P2SH script hash, until their covenant output is seen
+    announced: HashMap<[u8; 20], AdvancedTradeOffer>,
+    offers: HashMap<[u8; 32], Vec<OfferState>>,
+}
+
+// Sats per token as a fraction, for comparing prices of normal and inverted offers.
+fn price_per_token(offer: &AdvancedTradeOffer) -> (u128, u128) {
+    if offer.is_inverted {
+        (offer.price_denominator as u128, offer.price as u128)
+    } else {
+        (offer.price as u128, offer.price_denominator as u128)
+    }
+}
+
+fn cmp_price(a: &AdvancedTradeOffer, b: &AdvancedTradeOffer) -> Ordering {
+    let (a_num, a_den) = price_per_token(a);
+    let (b_num, b_den) = price_per_token(b);
+    (a_num * b_den).cmp(&(b_num * a_den))
+}
+
+impl OfferBook {
+    pub fn new() -> Self {
+        OfferBook::default()
+    }
+
+    // Registers an offer whose covenant output hasn't been seen yet.
+    pub fn announce(&mut self, offer: AdvancedTradeOffer) {
+        let redeem_script = offer.script().to_vec();
+        self.announced.insert(hash160(&redeem_script), offer);
+    }
+
+    pub fn apply_tx(&mut self, tx: &Tx) {
+        for input in tx.inputs() {
+            if self.spend_offer(&input.outpoint, tx) {
+                continue;
+            }
+            // spends of unknown offers reveal the offer, follow it if it stays open
+            if let Ok(offer) = AdvancedTradeOffer::from_p2sh_sig_script(&input.script) {
+                let mut state = OfferState::new(offer, input.outpoint.clone());
+                if state.apply_tx(tx).is_ok() && !state.is_closed() {
+                    self.insert(state);
+                }
+            }
+        }
+        self.add_announced_outputs(tx);
+    }
+
+    pub fn apply_txs<'a>(&mut self, txs: impl IntoIterator<Item=&'a Tx>) {
+        for tx in txs {
+            self.apply_tx(tx);
+        }
+    }
+
+    // Open offers selling `token_id`, cheapest first.
+    pub fn offers(&self, token_id: &[u8; 32]) -> Vec<(&TxOutpoint, &AdvancedTradeOffer)> {
+        let mut offers = self.offers.get(token_id)
+            .map(|states| states.iter()
+                .filter_map(|state| Some((state.outpoint()?, state.offer()?)))
+                .collect::<Vec<_>>())
+            .unwrap_or_default();
+        offers.sort_by(|(_, a), (_, b)| cmp_price(a, b));
+        offers
+    }
+
+    // Cheapest open offer selling `token_id`.
+    pub fn best_offer(&self, token_id: &[u8; 32]) -> Option<(&TxOutpoint, &AdvancedTradeOffer)> {
+        self.offers.get(token_id)?.iter()
+            .filter_map(|state| Some((state.outpoint()?, state.offer()?)))
+            .min_by(|(_, a), (_, b)| cmp_price(a, b))
+    }
+
+    pub fn token_ids(&self) -> impl Iterator<Item=&[u8; 32]> {
+        self.offers.keys()
+    }
+
+    fn insert(&mut self, state: OfferState) {
+        let token_id = match state.offer() {
+            Some(offer) => offer.token_id,
+            None => return,
+        };
+        let states = self.offers.entry(token_id).or_default();
+        if !states.iter().any(|other| other.outpoint() == state.outpoint()) {
+            states.push(state);
+        }
+    }
+
+    // Applies `tx` to the offer with the covenant at `outpoint`, if any; returns whether
+    // there was one.
+    fn spend_offer(&mut self, outpoint: &TxOutpoint, tx: &Tx) -> bool {
+        for (token_id, states) in self.offers.iter_mut() {
+            if let Some(idx) = states.iter().position(|state| state.outpoint() == Some(outpoint)) {
+                if states[idx].apply_tx(tx).is_err() || states[idx].is_closed() {
+                    states.remove(idx);
+                }
+                if states.is_empty() {
+                    let token_id = *token_id;
+                    self.offers.remove(&token_id);
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    // Adds covenant outputs of announced offers which received the offer's tokens.
+    fn add_announced_outputs(&mut self, tx: &Tx) {
+        if self.announced.is_empty() {
+            return;
+        }
+        let tx_hash = tx.hash();
+        let message = tx.outputs().first().and_then(|output| SLPMessage::parse(&output.script).ok());
+        for (vout, output) in tx.outputs().iter().enumerate() {
+            let script_hash = match output.script.ops() {
+                [Op::Code(OpCodeType::OpHash160), Op::Push(hash), Op::Code(OpCodeType::OpEqual)]
+                        if hash.len() == 20 => {
+                    let mut script_hash = [0; 20];
+                    script_hash.copy_from_slice(hash);
+                    script_hash
+                },
+                _ => continue,
+            };
+            let offer = match self.announced.get(&script_hash) {
+                Some(offer) => offer,
+                None => continue,
+            };
+            let token = message.as_ref()
+                .and_then(|message| message.output_token(&tx_hash, vout as u32));
+            if token != Some((offer.token_id, offer.sell_amount_token)) {
+                continue;
+            }
+            let offer = self.announced.remove(&script_hash).unwrap();
+            let outpoint = TxOutpoint { tx_hash, vout: vout as u32 };
+            self.insert(OfferState::new(AdvancedTradeOffer { value: output.value, ..offer }, outpoint));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::{Address, AddressType};
+    use crate::advanced_trade_offer::AdvancedTradeOfferBuilder;
+    use crate::outputs::SLPSend;
+    use crate::unsigned_tx::Output;
+    use crate::script::Script;
+    use crate::tx::{TxInput, TxOutput};
+
+    fn slp_tx(spent: TxOutpoint, sig_script: Script, output_quantities: Vec<u64>, outputs: Vec<TxOutput>) -> Tx {
+        let send = SLPSend { token_type: 1, token_id: [0x22; 32], output_quantities };
+        let mut tx_outputs = vec![send.into_output().unwrap().to_output()];
+        tx_outputs.extend(outputs);
+        Tx::new(1, vec![TxInput::new(spent, sig_script, 0xffff_ffff)], tx_outputs, 0)
+    }
+
+    #[test]
+    fn test_offer_book() {
+        let address = Address::from_bytes(AddressType::P2PKH, [0x11; 20]);
+        let offer = |sell_amount_token, price| {
+            AdvancedTradeOfferBuilder::new(b"EXCH".to_vec(), [0x22; 32], sell_amount_token, price, address.clone())
+                .build()
+                .unwrap()
+        };
+        let covenant = |offer: &AdvancedTradeOffer| offer.to_p2sh_output();
+        let funding = TxOutpoint { tx_hash: [0x33; 32], vout: 0 };
+        let mut book = OfferBook::new();
+
+        // announced offer created with the wrong token amount isn't added
+        let cheap = offer(100, 1000);
+        book.announce(cheap.clone());
+        book.apply_tx(&slp_tx(funding.clone(), Script::empty(), vec![99], vec![covenant(&cheap)]));
+        assert!(book.best_offer(&[0x22; 32]).is_none());
+        let create = slp_tx(funding.clone(), Script::empty(), vec![100], vec![covenant(&cheap)]);
+        book.apply_tx(&create);
+        assert_eq!(book.best_offer(&[0x22; 32]).unwrap().0, &TxOutpoint { tx_hash: create.hash(), vout: 1 });
+
+        // an unknown offer revealed by a partial accept
+        let expensive = offer(100, 2000);
+        let reveal = Script::new(vec![Op::Push(expensive.script().to_vec())]);
+        let remaining = AdvancedTradeOffer { sell_amount_token: 60, ..expensive.clone() };
+        let partial = slp_tx(TxOutpoint { tx_hash: [0x44; 32], vout: 1 }, reveal,
+                             vec![60, 0, 40], vec![covenant(&remaining)]);
+        book.apply_tx(&partial);
+        let offers = book.offers(&[0x22; 32]);
+        assert_eq!(offers.len(), 2);
+        assert_eq!((offers[0].1.price, offers[1].1.price), (1000, 2000));
+        assert_eq!(offers[1].1.sell_amount_token, 60);
+
+        // accepting the cheap offer fully closes it
+        let cheap_outpoint = offers[0].0.clone();
+        book.apply_tx(&slp_tx(cheap_outpoint, Script::empty(), vec![0, 100], vec![]));
+        let (outpoint, best) = book.best_offer(&[0x22; 32]).unwrap();
+        assert_eq!(best.price, 2000);
+        assert_eq!(outpoint, &TxOutpoint { tx_hash: partial.hash(), vout: 1 });
+        assert_eq!(book.token_ids().count(), 1);
+    }
+}
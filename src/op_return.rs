@@ -0,0 +1,156 @@
+use crate::outputs::OpReturnOutput;
+use crate::script::PushEncoding;
+
+// Max standard size of an OP_RETURN output script (OP_RETURN + push opcodes + 220 data bytes)
+pub const MAX_OP_RETURN_RELAY: usize = 223;
+pub const LOKAD_ID_SIZE: usize = 4;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OpReturnError {
+    InvalidLokadId(usize),
+    TooLarge { max: usize, size: usize },
+    InvalidPushEncoding { idx: usize, len: usize, encoding: PushEncoding },
+}
+
+#[derive(Clone, Debug)]
+pub struct OpReturnBuilder {
+    pushes: Vec<Vec<u8>>,
+    encodings: Vec<PushEncoding>,
+    is_minimal_push: bool,
+    max_size: usize,
+}
+
+impl OpReturnBuilder {
+    pub fn new() -> Self {
+        OpReturnBuilder {
+            pushes: Vec::new(),
+            encodings: Vec::new(),
+            is_minimal_push: false,
+            max_size: MAX_OP_RETURN_RELAY,
+        }
+    }
+
+    pub fn with_lokad_id(lokad_id: &[u8]) -> Result<Self, OpReturnError> {
+        if lokad_id.len() != LOKAD_ID_SIZE {
+            return Err(OpReturnError::InvalidLokadId(lokad_id.len()));
+        }
+        let mut builder = OpReturnBuilder::new();
+        builder.push(lokad_id.to_vec());
+        Ok(builder)
+    }
+
+    pub fn push(&mut self, data: Vec<u8>) -> &mut Self {
+        let encoding = self.default_encoding();
+        self.push_with_encoding(data, encoding)
+    }
+
+    pub fn push_with_encoding(&mut self, data: Vec<u8>, encoding: PushEncoding) -> &mut Self {
+        self.pushes.push(data);
+        self.encodings.push(encoding);
+        self
+    }
+
+    fn default_encoding(&self) -> PushEncoding {
+        if self.is_minimal_push { PushEncoding::Minimal } else { PushEncoding::Data }
+    }
+
+    // Applies to subsequent calls of `push`.
+    pub fn set_minimal_push(&mut self, is_minimal_push: bool) -> &mut Self {
+        self.is_minimal_push = is_minimal_push;
+        self
+    }
+
+    // Raise or lower the limit, e.g. for miners accepting larger data carriers.
+    pub fn set_max_size(&mut self, max_size: usize) -> &mut Self {
+        self.max_size = max_size;
+        self
+    }
+
+    pub fn lokad_id(&self) -> Option<&[u8]> {
+        self.pushes.first()
+            .filter(|push| push.len() == LOKAD_ID_SIZE)
+            .map(|push| push.as_slice())
+    }
+
+    pub fn size(&self) -> Result<usize, OpReturnError> {
+        Ok(self.to_output_unchecked().to_vec()?.len())
+    }
+
+    pub fn remaining(&self) -> Result<usize, OpReturnError> {
+        Ok(self.max_size.saturating_sub(self.size()?))
+    }
+
+    fn to_output_unchecked(&self) -> OpReturnOutput {
+        OpReturnOutput {
+            pushes: self.pushes.clone(),
+            is_minimal_push: self.is_minimal_push,
+            encodings: self.encodings.clone(),
+        }
+    }
+
+    pub fn build(&self) -> Result<OpReturnOutput, OpReturnError> {
+        let size = self.size()?;
+        if size > self.max_size {
+            return Err(OpReturnError::TooLarge { max: self.max_size, size });
+        }
+        Ok(self.to_output_unchecked())
+    }
+}
+
+impl Default for OpReturnBuilder {
+    fn default() -> Self {
+        OpReturnBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::unsigned_tx::Output;
+
+    #[test]
+    fn test_push_encodings() {
+        use crate::script::PushEncoding::*;
+        let long_push = vec![0x11; 100];
+        let long_push_with = |prefix: &[u8]| [prefix, &long_push].concat();
+        let cases = vec![
+            (vec![0x05], Minimal, vec![0x55]),
+            (vec![0x05], MinimalNumber, vec![0x55]),
+            (vec![0x05], Data, vec![0x01, 0x05]),
+            (vec![0x05], Direct, vec![0x01, 0x05]),
+            (vec![0x81], Minimal, vec![0x01, 0x81]),
+            (vec![0x81], MinimalNumber, vec![0x4f]),
+            (vec![], Minimal, vec![0x00]),
+            (vec![], Data, vec![0x4c, 0x00]),
+            (long_push.clone(), Minimal, long_push_with(&[0x4c, 100])),
+            (long_push.clone(), Data, long_push_with(&[0x4c, 100])),
+            (long_push.clone(), PushData1, long_push_with(&[0x4c, 100])),
+            (long_push.clone(), PushData2, long_push_with(&[0x4d, 100, 0])),
+            (long_push.clone(), PushData4, long_push_with(&[0x4e, 100, 0, 0, 0])),
+        ];
+        for (push, encoding, encoded_push) in cases {
+            let mut builder = OpReturnBuilder::new();
+            builder.push(b"TEST".to_vec()).push_with_encoding(push, encoding);
+            let output = builder.build().unwrap();
+            let expected = [&[0x6a, 0x04][..], b"TEST", &encoded_push].concat();
+            assert_eq!(output.to_vec().unwrap(), expected);
+            assert_eq!(output.script().to_vec(), expected);
+        }
+
+        // pushes exceeding 75 bytes can't be direct pushes
+        let mut builder = OpReturnBuilder::new();
+        builder.push_with_encoding(long_push.clone(), Direct);
+        let error = OpReturnError::InvalidPushEncoding { idx: 0, len: 100, encoding: Direct };
+        assert_eq!(builder.build().err(), Some(error.clone()));
+        let output = OpReturnOutput::new(vec![long_push.clone()], true);
+        assert_eq!(output.clone().with_encodings(vec![Direct]).err(), Some(error));
+        let output_75 = OpReturnOutput::new(vec![vec![0x11; 75]], true).with_encodings(vec![Direct]).unwrap();
+        assert_eq!(output_75.to_vec().unwrap()[..2], [0x6a, 75]);
+
+        // pushes changed after validation fall back to the default encoding
+        let mut output = output.with_encodings(vec![PushData1]).unwrap();
+        output.pushes[0] = vec![0x11; 0x100];
+        assert!(output.to_vec().is_err());
+        assert_eq!(output.script().to_vec()[..4], [0x6a, 0x4d, 0x00, 0x01]);
+    }
+}
@@ -0,0 +1,171 @@
+use crate::unsigned_tx::{Output, PreImage, UnsignedTx, UnsignedInput};
+use crate::script::{Script, Op};
+use crate::address::Address;
+use crate::tx::{TxOutput, TxOutpoint};
+use crate::outputs::{P2PKHOutput, P2SHOutput};
+use crate::hash::double_sha256;
+use crate::spend::{LockingScript, Unlocker, Spend, max_unlock_size};
+use crate::serialize::{encode_int, encode_int64, encode_int64_n, vec_to_int64, IntWidthError};
+use crate::covenant::{verify_pre_image_ops, hash_outputs_ops, serialize_pre_image, serialize_outputs};
+
+use byteorder::{LittleEndian, ByteOrder};
+
+
+// Covenant paying `payout_amount` to `above_address` if the oracle signed a price at or
+// above `threshold_price`, or to `below_address` otherwise, for a block height of at least
+// `maturity_height`. The difference between value and payout_amount pays the fee.
+#[derive(Clone, Debug)]
+pub struct OracleConditionalOutput {
+    pub value: u64,
+    pub oracle_pk: Vec<u8>,
+    pub maturity_height: u32,
+    pub threshold_price: i64,
+    pub above_address: Address,
+    pub below_address: Address,
+    pub payout_amount: u64,
+}
+
+// Oracle message: height (4 bytes LE) || price (8 byte script number)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OracleMessage {
+    pub height: u32,
+    pub price: i64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OracleSpendParams {
+    pub message: OracleMessage,
+    pub oracle_sig: Vec<u8>,
+}
+
+pub const ORACLE_MESSAGE_SIZE: usize = 12;
+
+impl OracleMessage {
+    // Fails for a price of i64::MIN, which has no 8 byte script number encoding.
+    pub fn to_vec(&self) -> Result<Vec<u8>, IntWidthError> {
+        let mut vec = self.height.to_le_bytes().to_vec();
+        vec.extend_from_slice(&encode_int64_n(self.price, 8)?);
+        Ok(vec)
+    }
+
+    pub fn from_slice(slice: &[u8]) -> Option<Self> {
+        if slice.len() != ORACLE_MESSAGE_SIZE {
+            return None;
+        }
+        Some(OracleMessage {
+            height: LittleEndian::read_u32(&slice[..4]),
+            price: vec_to_int64(&slice[4..])?,
+        })
+    }
+}
+
+impl OracleConditionalOutput {
+    pub fn winning_address(&self, message: &OracleMessage) -> &Address {
+        if message.price >= self.threshold_price {
+            &self.above_address
+        } else {
+            &self.below_address
+        }
+    }
+
+    fn _hash_outputs(&self, address: &Address) -> Vec<u8> {
+        double_sha256(&serialize_outputs(&[P2PKHOutput {
+            value: self.payout_amount,
+            address: address.clone(),
+        }.to_output()])).to_vec()
+    }
+
+    // Builds the transaction paying out to the branch selected by `message`.
+    pub fn settle_tx(&self,
+                     outpoint: TxOutpoint,
+                     message: OracleMessage,
+                     oracle_sig: Vec<u8>) -> Result<UnsignedTx, IntWidthError> {
+        message.to_vec()?;
+        let address = self.winning_address(&message).clone();
+        let mut tx_build = UnsignedTx::new_simple();
+        tx_build.add_input(UnsignedInput::new(
+            outpoint,
+            Box::new(P2SHOutput {
+                output: Box::new(Spend::new(self.clone(), OracleSpendParams { message, oracle_sig })),
+            }),
+            0xffff_ffff,
+        ));
+        tx_build.add_output(P2PKHOutput {
+            value: self.payout_amount,
+            address,
+        }.to_output());
+        Ok(tx_build)
+    }
+
+    fn _ops(&self) -> Vec<Op> {
+        use crate::script::OpCodeType::*;
+        let mut ops = vec![
+            // verify oracle message
+            Op::Code(OpDup),
+            Op::Code(OpToAltStack),
+            Op::Push(self.oracle_pk.clone()),
+            Op::Code(OpCheckDataSigVerify),
+
+        ];
+        ops.append(&mut verify_pre_image_ops());
+        ops.append(&mut hash_outputs_ops());
+        ops.append(&mut vec![
+            // check height and select branch by price
+            Op::Code(OpFromAltStack),
+            Op::Push(encode_int(4)),
+            Op::Code(OpSplit),
+            Op::Code(OpBin2Num),
+            Op::Code(OpSwap),
+            Op::Code(OpBin2Num),
+            Op::Push(encode_int64(self.maturity_height as i64)),
+            Op::Code(OpGreaterThanOrEqual),
+            Op::Code(OpVerify),
+            Op::Push(encode_int64(self.threshold_price)),
+            Op::Code(OpGreaterThanOrEqual),
+            Op::Code(OpIf),
+            Op::Push(self._hash_outputs(&self.above_address)),
+            Op::Code(OpElse),
+            Op::Push(self._hash_outputs(&self.below_address)),
+            Op::Code(OpEndIf),
+            Op::Code(OpEqual),
+        ]);
+        ops
+    }
+}
+
+impl LockingScript for OracleConditionalOutput {
+    fn value(&self) -> u64 {
+        self.value
+    }
+
+    fn script(&self) -> Script {
+        Script::new(self._ops())
+    }
+
+    fn script_code(&self) -> Script {
+        self.script()
+    }
+}
+
+impl Unlocker<OracleConditionalOutput> for OracleSpendParams {
+    fn sig_script(&self,
+                  _output: &OracleConditionalOutput,
+                  mut serialized_sig: Vec<u8>,
+                  serialized_pub_key: Vec<u8>,
+                  pre_image: &PreImage,
+                  _outputs: &[TxOutput]) -> Script {
+        serialized_sig.remove(serialized_sig.len() - 1);  // remove sig flag
+        Script::new(vec![
+            Op::Push(serialized_pub_key),
+            Op::Push(serialized_sig),
+            Op::Push(serialize_pre_image(pre_image)),
+            Op::Push(self.oracle_sig.clone()),
+            // settle_tx rejects messages without encoding
+            Op::Push(self.message.to_vec().unwrap_or_default()),
+        ])
+    }
+
+    fn estimated_unlock_size(&self, output: &OracleConditionalOutput) -> Option<usize> {
+        Some(max_unlock_size(self, output))
+    }
+}
@@ -1,25 +1,68 @@
 use crate::address::Address;
 use crate::unsigned_tx::{Output, PreImage};
 use crate::tx::TxOutput;
-use crate::script::{Script, Op, OpCodeType};
+use crate::script::{Script, Op, OpCodeType, PushEncoding, write_push};
 use crate::hash::hash160;
+use crate::cash_tokens::TokenData;
+use crate::op_return::OpReturnError;
 
 use byteorder::{BigEndian, WriteBytesExt};
 
+pub const SLP_TOKEN_TYPE_FUNGIBLE: u8 = 0x01;
+pub const SLP_TOKEN_TYPE_NFT1_GROUP: u8 = 0x81;
+pub const SLP_TOKEN_TYPE_NFT1_CHILD: u8 = 0x41;
+pub const SLP_MAX_OUTPUT_QUANTITIES: usize = 19;
+pub const SLP_MAX_DECIMALS: u8 = 9;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SLPError {
+    InvalidTokenType(u8),
+    NoOutputQuantities,
+    TooManyOutputQuantities(usize),
+    InvalidDecimals(u8),
+    InvalidDocumentHashLength(usize),
+    InvalidMintBatonVout(u8),
+    InvalidNFT1ChildGenesis,
+    BurnAmountMismatch { input_amount: u64, output_amount: u128, burn_amount: u64 },
+    NotSLP,
+    UnknownTransactionType(Vec<u8>),
+    MalformedMessage,
+}
+
 #[derive(Clone, Debug)]
 pub struct P2PKHOutput {
     pub value: u64,
     pub address: Address,
 }
 
+#[derive(Clone, Debug)]
+pub struct P2PKOutput {
+    pub value: u64,
+    pub pub_key: Vec<u8>,
+}
+
+#[derive(Clone)]
 pub struct P2SHOutput {
     pub output: Box<dyn Output>,
 }
 
+// P2SH output for which only the serialized redeem script is known.
+// The sig_script is: <sig_pushes...> <sig> [<pub_key>] <redeem_script>
+#[derive(Clone, Debug)]
+pub struct P2SHRawOutput {
+    pub value: u64,
+    pub redeem_script: Script,
+    pub sig_pushes: Vec<Vec<u8>>,
+    pub push_pub_key: bool,
+}
+
 #[derive(Clone, Debug)]
 pub struct OpReturnOutput {
     pub pushes: Vec<Vec<u8>>,
     pub is_minimal_push: bool,
+    // Per-push overrides; pushes without an entry use the encoding given by is_minimal_push.
+    // Set through with_encodings or OpReturnBuilder, which validate them.
+    pub(crate) encodings: Vec<PushEncoding>,
 }
 
 #[derive(Clone, Debug)]
@@ -72,6 +115,33 @@ impl Output for P2PKHOutput {
     }
 }
 
+impl Output for P2PKOutput {
+    fn value(&self) -> u64 {
+        self.value
+    }
+
+    fn script(&self) -> Script {
+        Script::new(vec![
+            Op::Push(self.pub_key.clone()),
+            Op::Code(OpCodeType::OpCheckSig),
+        ])
+    }
+
+    fn script_code(&self) -> Script {
+        self.script()
+    }
+
+    fn sig_script(&self,
+                  serialized_sig: Vec<u8>,
+                  _serialized_pub_key: Vec<u8>,
+                  _pre_image: &PreImage,
+                  _outputs: &[TxOutput]) -> Script {
+        Script::new(vec![
+            Op::Push(serialized_sig),
+        ])
+    }
+}
+
 impl Output for P2SHOutput {
     fn value(&self) -> u64 {
         self.output.value()
@@ -99,6 +169,98 @@ impl Output for P2SHOutput {
         script.add_op(Op::Push(self.output.script().to_vec()));
         script
     }
+
+    fn token(&self) -> Option<TokenData> {
+        self.output.token()
+    }
+
+    fn estimated_unlock_size(&self) -> Option<usize> {
+        let redeem_script_push = Script::new(vec![Op::Push(self.output.script().to_vec())]);
+        Some(self.output.estimated_unlock_size()? + redeem_script_push.to_vec().len())
+    }
+}
+
+impl P2SHRawOutput {
+    pub fn from_redeem_script_bytes(value: u64, redeem_script: &[u8]) -> Option<Self> {
+        Some(P2SHRawOutput {
+            value,
+            redeem_script: Script::from_serialized(redeem_script)?,
+            sig_pushes: vec![],
+            push_pub_key: false,
+        })
+    }
+}
+
+impl Output for P2SHRawOutput {
+    fn value(&self) -> u64 {
+        self.value
+    }
+
+    fn script(&self) -> Script {
+        Script::new(vec![
+            Op::Code(OpCodeType::OpHash160),
+            Op::Push(hash160(&self.redeem_script.to_vec()).to_vec()),
+            Op::Code(OpCodeType::OpEqual),
+        ])
+    }
+
+    fn script_code(&self) -> Script {
+        self.redeem_script.clone()
+    }
+
+    fn sig_script(&self,
+                  serialized_sig: Vec<u8>,
+                  serialized_pub_key: Vec<u8>,
+                  _pre_image: &PreImage,
+                  _outputs: &[TxOutput]) -> Script {
+        let mut ops: Vec<Op> = self.sig_pushes.iter().cloned().map(Op::Push).collect();
+        ops.push(Op::Push(serialized_sig));
+        if self.push_pub_key {
+            ops.push(Op::Push(serialized_pub_key));
+        }
+        ops.push(Op::Push(self.redeem_script.to_vec()));
+        Script::new(ops)
+    }
+}
+
+impl OpReturnOutput {
+    pub fn new(pushes: Vec<Vec<u8>>, is_minimal_push: bool) -> Self {
+        OpReturnOutput { pushes, is_minimal_push, encodings: vec![] }
+    }
+
+    // Fails if a push can't be written with its encoding, e.g. 76 bytes as Direct.
+    pub fn with_encodings(mut self, encodings: Vec<PushEncoding>) -> Result<Self, OpReturnError> {
+        self.encodings = encodings;
+        self.validate()?;
+        Ok(self)
+    }
+
+    pub fn encodings(&self) -> &[PushEncoding] {
+        &self.encodings
+    }
+
+    pub fn push_encoding(&self, idx: usize) -> PushEncoding {
+        self.encodings.get(idx).cloned().unwrap_or(
+            if self.is_minimal_push { PushEncoding::Minimal } else { PushEncoding::Data }
+        )
+    }
+
+    pub fn to_vec(&self) -> Result<Vec<u8>, OpReturnError> {
+        let mut vec = vec![OpCodeType::OpReturn as u8];
+        for (idx, push) in self.pushes.iter().enumerate() {
+            let encoding = self.push_encoding(idx);
+            write_push(&mut vec, push, encoding).map_err(|_| OpReturnError::InvalidPushEncoding {
+                idx,
+                len: push.len(),
+                encoding,
+            })?;
+        }
+        Ok(vec)
+    }
+
+    pub fn validate(&self) -> Result<(), OpReturnError> {
+        self.to_vec().map(|_| ())
+    }
 }
 
 impl Output for OpReturnOutput {
@@ -107,6 +269,13 @@ impl Output for OpReturnOutput {
     }
 
     fn script(&self) -> Script {
+        if !self.encodings.is_empty() {
+            // only fails for pushes changed after validating the encodings, which then fall
+            // back to the default encoding
+            if let Ok(vec) = self.to_vec() {
+                return Script::from_serialized(&vec).unwrap();
+            }
+        }
         let mut script_ops = vec![
             Op::Code(OpCodeType::OpReturn),
         ];
@@ -140,7 +309,41 @@ impl SLPSend {
      * ...
      * <token_output_quantity19> (optional, 8 byte integer) */
 
-    pub fn into_output(self) -> OpReturnOutput {
+    pub fn nft1_group(token_id: [u8; 32], output_quantities: Vec<u64>) -> Self {
+        SLPSend {
+            token_type: SLP_TOKEN_TYPE_NFT1_GROUP,
+            token_id,
+            output_quantities,
+        }
+    }
+
+    // An NFT1 child always has a supply of 1, which is sent to output 1.
+    pub fn nft1_child(token_id: [u8; 32]) -> Self {
+        SLPSend {
+            token_type: SLP_TOKEN_TYPE_NFT1_CHILD,
+            token_id,
+            output_quantities: vec![1],
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), SLPError> {
+        validate_token_type(self.token_type)?;
+        if self.output_quantities.is_empty() {
+            return Err(SLPError::NoOutputQuantities);
+        }
+        if self.output_quantities.len() > SLP_MAX_OUTPUT_QUANTITIES {
+            return Err(SLPError::TooManyOutputQuantities(self.output_quantities.len()));
+        }
+        Ok(())
+    }
+
+    pub fn into_output(self) -> Result<OpReturnOutput, SLPError> {
+        self.validate()?;
+        Ok(self.into_output_unchecked())
+    }
+
+    // Skips validation, e.g. for building the SEND prefix without any quantities.
+    pub fn into_output_unchecked(self) -> OpReturnOutput {
         let mut script_ops = vec![
             b"SLP\0".to_vec(),
             vec![self.token_type],
@@ -155,6 +358,7 @@ impl SLPSend {
         OpReturnOutput {
             is_minimal_push: false,
             pushes: script_ops,
+            encodings: vec![],
         }
     }
 }
@@ -171,7 +375,71 @@ impl SLPGenesis {
      * <mint_baton_vout> (0 bytes, or 1 byte in range 0x02-0xff)
      * <initial_token_mint_quantity> (8 byte integer) */
 
-    pub fn into_output(self) -> OpReturnOutput {
+    pub fn nft1_group(token_ticker: Vec<u8>,
+                      token_name: Vec<u8>,
+                      token_document_url: Vec<u8>,
+                      token_document_hash: Vec<u8>,
+                      decimals: u8,
+                      mint_baton_vout: Option<u8>,
+                      initial_token_mint_quantity: u64) -> Self {
+        SLPGenesis {
+            token_type: SLP_TOKEN_TYPE_NFT1_GROUP,
+            token_ticker,
+            token_name,
+            token_document_url,
+            token_document_hash,
+            decimals,
+            mint_baton_vout,
+            initial_token_mint_quantity,
+        }
+    }
+
+    /* NFT1 child genesis requires decimals = 0, no mint baton and a quantity of 1.
+     * The group token must be spent (burning 1 group unit) at input 0. */
+    pub fn nft1_child(token_ticker: Vec<u8>,
+                      token_name: Vec<u8>,
+                      token_document_url: Vec<u8>,
+                      token_document_hash: Vec<u8>) -> Self {
+        SLPGenesis {
+            token_type: SLP_TOKEN_TYPE_NFT1_CHILD,
+            token_ticker,
+            token_name,
+            token_document_url,
+            token_document_hash,
+            decimals: 0,
+            mint_baton_vout: None,
+            initial_token_mint_quantity: 1,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), SLPError> {
+        validate_token_type(self.token_type)?;
+        if self.decimals > SLP_MAX_DECIMALS {
+            return Err(SLPError::InvalidDecimals(self.decimals));
+        }
+        if !self.token_document_hash.is_empty() && self.token_document_hash.len() != 32 {
+            return Err(SLPError::InvalidDocumentHashLength(self.token_document_hash.len()));
+        }
+        if let Some(mint_baton_vout) = self.mint_baton_vout {
+            if mint_baton_vout < 2 {
+                return Err(SLPError::InvalidMintBatonVout(mint_baton_vout));
+            }
+        }
+        if self.token_type == SLP_TOKEN_TYPE_NFT1_CHILD &&
+                (self.decimals != 0 ||
+                 self.mint_baton_vout.is_some() ||
+                 self.initial_token_mint_quantity != 1) {
+            return Err(SLPError::InvalidNFT1ChildGenesis);
+        }
+        Ok(())
+    }
+
+    pub fn into_output(self) -> Result<OpReturnOutput, SLPError> {
+        self.validate()?;
+        Ok(self.into_output_unchecked())
+    }
+
+    pub fn into_output_unchecked(self) -> OpReturnOutput {
         let script_ops = vec![
             b"SLP\0".to_vec(),
             vec![self.token_type],
@@ -191,6 +459,14 @@ impl SLPGenesis {
         OpReturnOutput {
             is_minimal_push: false,
             pushes: script_ops,
+            encodings: vec![],
         }
     }
 }
+
+pub(crate) fn validate_token_type(token_type: u8) -> Result<(), SLPError> {
+    match token_type {
+        SLP_TOKEN_TYPE_FUNGIBLE | SLP_TOKEN_TYPE_NFT1_GROUP | SLP_TOKEN_TYPE_NFT1_CHILD => Ok(()),
+        _ => Err(SLPError::InvalidTokenType(token_type)),
+    }
+}
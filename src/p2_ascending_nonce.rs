@@ -1,9 +1,13 @@
-use crate::unsigned_tx::{Output, PreImage, PreImageWriteFlags};
-use crate::script::{Script, Op};
-use crate::tx::TxOutput;
-use crate::serialize::{write_var_int, encode_int};
-
-use std::convert::TryInto;
+use crate::unsigned_tx::{PreImage, UnsignedTx, UnsignedInput};
+use crate::script::{Script, Op, op_data, find_pattern};
+use crate::tx::{TxOutput, TxOutpoint};
+use crate::address::Address;
+use crate::outputs::P2SHOutput;
+use crate::wallet::{UtxoEntry, DUST_AMOUNT, add_p2pkh_inputs};
+use crate::covenant::{pre_image_prefix, pre_image_suffix, serialize_outputs};
+use crate::serialize::{encode_int, encode_int64, encode_int64_n, vec_to_int64,
+                       MAX_SCRIPT_INT};
+use crate::spend::{LockingScript, Unlocker, Spend};
 
 
 #[derive(Clone, Debug)]
@@ -11,35 +15,220 @@ pub struct P2AscendingNonce {
     pub lokad_id: Vec<u8>,
     pub old_value: u64,
     pub owner_pk: Vec<u8>,
-    pub old_nonce: i32,
-    pub dust_limit: i32,
-    pub spend_params: Option<P2AscendingNonceSpendParams>,
+    pub old_nonce: i64,
+    pub dust_limit: u64,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum P2AscendingNonceSpendParams {
     NonceRedeem {
-        payment_amount: i32,
-        new_nonce: i32,
+        payment_amount: i64,
+        new_nonce: i64,
         owner_sig: Vec<u8>,
         is_terminal: bool,
     },
     NonceRefill {
-        payment_amount: i32,
+        payment_amount: i64,
     },
     P2pk,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NonceError {
+    NonceNotAscending { old_nonce: i64, new_nonce: i64 },
+    InvalidPaymentAmount(u64),
+    InsufficientFunds(u64),
+    ValueOutOfRange { field: &'static str, value: u64 },
+    NonceOutOfRange(i64),
+    InvalidRedeemScript,
+}
+
 impl P2AscendingNonce {
+    pub fn validate(&self) -> Result<(), NonceError> {
+        let fields = [
+            ("old_value", self.old_value),
+            ("dust_limit", self.dust_limit),
+        ];
+        for &(field, value) in fields.iter() {
+            if value > MAX_SCRIPT_INT {
+                return Err(NonceError::ValueOutOfRange { field, value });
+            }
+        }
+        // i64::MIN has no 8 byte script number encoding
+        if self.old_nonce == i64::MIN {
+            return Err(NonceError::NonceOutOfRange(self.old_nonce));
+        }
+        Ok(())
+    }
+
+    // Validates the covenant together with the parameters spending it.
+    pub fn validate_spend(&self, spend_params: &P2AscendingNonceSpendParams) -> Result<(), NonceError> {
+        self.validate()?;
+        let (payment_amount, new_nonce) = match *spend_params {
+            P2AscendingNonceSpendParams::NonceRedeem { payment_amount, new_nonce, .. } => {
+                (payment_amount, new_nonce)
+            },
+            P2AscendingNonceSpendParams::NonceRefill { payment_amount } => {
+                (payment_amount, self.old_nonce)
+            },
+            P2AscendingNonceSpendParams::P2pk => (0, self.old_nonce),
+        };
+        if payment_amount == i64::MIN {
+            return Err(NonceError::InvalidPaymentAmount(payment_amount.unsigned_abs()));
+        }
+        if new_nonce == i64::MIN {
+            return Err(NonceError::NonceOutOfRange(new_nonce));
+        }
+        Ok(())
+    }
+
+    // Builds the transaction paying `payment_amount` to `redeemer_address`, authorized by
+    // `owner_sig`. The covenant continues at output 0 with the remaining value and
+    // `new_nonce`, unless that value is below the dust limit. The payment (minus fee)
+    // follows. With `is_schnorr`, the fee is sized for a Schnorr covenant signature.
+    #[allow(clippy::too_many_arguments)]
+    pub fn redeem_tx(&self,
+                     outpoint: TxOutpoint,
+                     payment_amount: u64,
+                     new_nonce: i64,
+                     owner_sig: Vec<u8>,
+                     redeemer_address: Address,
+                     fee_per_kb: u64,
+                     is_schnorr: bool) -> Result<UnsignedTx, NonceError> {
+        if new_nonce <= self.old_nonce {
+            return Err(NonceError::NonceNotAscending { old_nonce: self.old_nonce, new_nonce });
+        }
+        if payment_amount > self.old_value {
+            return Err(NonceError::InvalidPaymentAmount(payment_amount));
+        }
+        let new_value = self.old_value - payment_amount;
+        let is_terminal = new_value < self.dust_limit;
+        let spend_params = P2AscendingNonceSpendParams::NonceRedeem {
+            payment_amount: payment_amount as i64,
+            new_nonce,
+            owner_sig,
+            is_terminal,
+        };
+        self.validate_spend(&spend_params)?;
+        let mut tx_build = UnsignedTx::new_simple();
+        tx_build.set_schnorr(is_schnorr);
+        tx_build.add_input(UnsignedInput::new(
+            outpoint,
+            Box::new(P2SHOutput { output: Box::new(Spend::new(self.clone(), spend_params)) }),
+            0xffff_ffff,
+        ));
+        if !is_terminal {
+            tx_build.add_output(self._continuation(new_value, new_nonce).to_p2sh_output());
+        }
+        match tx_build.add_leftover_output(redeemer_address, fee_per_kb, DUST_AMOUNT) {
+            Ok(Some(_)) => Ok(tx_build),
+            Ok(None) => Err(NonceError::InvalidPaymentAmount(payment_amount)),
+            Err(err) => Err(NonceError::InsufficientFunds(err.missing_amount())),
+        }
+    }
+
+    // Builds the transaction adding `refill_amount` to the covenant, funded by the P2PKH
+    // `utxos` of `funding_address`, which also receives the change. The nonce is unchanged.
+    pub fn refill_tx(&self,
+                     outpoint: TxOutpoint,
+                     refill_amount: u64,
+                     utxos: &[UtxoEntry],
+                     funding_address: Address,
+                     fee_per_kb: u64,
+                     is_schnorr: bool) -> Result<UnsignedTx, NonceError> {
+        let new_value = self.old_value.checked_add(refill_amount)
+            .filter(|&new_value| refill_amount > 0 && new_value <= MAX_SCRIPT_INT)
+            .ok_or(NonceError::InvalidPaymentAmount(refill_amount))?;
+        let spend_params = P2AscendingNonceSpendParams::NonceRefill {
+            payment_amount: -(refill_amount as i64),
+        };
+        self.validate_spend(&spend_params)?;
+        let mut tx_build = UnsignedTx::new_simple();
+        tx_build.set_schnorr(is_schnorr);
+        tx_build.add_input(UnsignedInput::new(
+            outpoint,
+            Box::new(P2SHOutput { output: Box::new(Spend::new(self.clone(), spend_params)) }),
+            0xffff_ffff,
+        ));
+        add_p2pkh_inputs(&mut tx_build, utxos, &funding_address);
+        tx_build.add_output(
+            self._continuation(new_value, self.old_nonce).to_p2sh_output()
+        );
+        tx_build.add_leftover_output(funding_address, fee_per_kb, DUST_AMOUNT)
+            .map_err(|err| NonceError::InsufficientFunds(err.missing_amount()))?;
+        Ok(tx_build)
+    }
+
+    // Recovers the covenant from its redeem script and the value of its output. Fails
+    // unless re-generating the script from the recovered parameters reproduces
+    // `redeem_script` exactly.
+    pub fn from_redeem_script(redeem_script: &Script, value: u64) -> Result<Self, NonceError> {
+        use crate::script::OpCodeType::*;
+        let invalid = || NonceError::InvalidRedeemScript;
+        let ops = redeem_script.ops();
+        let old_nonce = match ops.first().and_then(op_data) {
+            Some(nonce) if nonce.len() == 8 => vec_to_int64(&nonce).ok_or_else(invalid)?,
+            _ => return Err(invalid()),
+        };
+        let owner_pk = ops.get(1).and_then(op_data).ok_or_else(invalid)?;
+        let dust_limit = find_pattern(ops, &[
+            Some(Op::Code(OpSub)), Some(Op::Code(OpDup)), None, Some(Op::Code(OpGreaterThanOrEqual)),
+        ]).and_then(|ops| op_data(&ops[2])).ok_or_else(invalid)?;
+        let lokad_id = find_pattern(ops, &[
+            Some(Op::Code(OpCheckDataSigVerify)), None, Some(Op::Code(OpEqual)),
+        ]).and_then(|ops| op_data(&ops[1])).ok_or_else(invalid)?;
+        let covenant = P2AscendingNonce {
+            lokad_id,
+            old_value: value,
+            owner_pk,
+            old_nonce,
+            dust_limit: vec_to_int64(&dust_limit).ok_or_else(invalid)? as u64,
+        };
+        if covenant.script().to_vec() != redeem_script.to_vec() {
+            return Err(invalid());
+        }
+        Ok(covenant)
+    }
+
+    // The covenant after redeeming `payment_amount` with the next nonce, or None if the
+    // remaining value would be below the dust limit.
+    pub fn next_state(&self, payment_amount: u64) -> Result<Option<Self>, NonceError> {
+        let new_nonce = self.old_nonce.checked_add(1)
+            .ok_or(NonceError::NonceOutOfRange(self.old_nonce))?;
+        self.next_state_with_nonce(payment_amount, new_nonce)
+    }
+
+    pub fn next_state_with_nonce(&self, payment_amount: u64, new_nonce: i64)
+            -> Result<Option<Self>, NonceError> {
+        if new_nonce <= self.old_nonce {
+            return Err(NonceError::NonceNotAscending { old_nonce: self.old_nonce, new_nonce });
+        }
+        let new_value = self.old_value.checked_sub(payment_amount)
+            .ok_or(NonceError::InvalidPaymentAmount(payment_amount))?;
+        if new_value < self.dust_limit {
+            return Ok(None);
+        }
+        Ok(Some(P2AscendingNonce {
+            old_value: new_value,
+            old_nonce: new_nonce,
+            ..self.clone()
+        }))
+    }
+
+    fn _continuation(&self, new_value: u64, new_nonce: i64) -> P2AscendingNonce {
+        P2AscendingNonce {
+            old_value: new_value,
+            old_nonce: new_nonce,
+            ..self.clone()
+        }
+    }
+
     fn _ops(&self) -> Vec<Op> {
         use crate::script::OpCodeType::*;
         use crate::script::Op::*;
-        let sign_byte = if self.old_nonce < 0 { 0x80 } else { 0 };
         let mut ops = vec![
-            Push([
-                self.old_nonce.abs().to_le_bytes(),
-                [0, 0, 0, sign_byte],
-            ].concat().to_vec()),
+            // validate rejects i64::MIN, which doesn't fit into 8 bytes
+            Push(encode_int64_n(self.old_nonce, 8).unwrap_or_default()),
             Push(self.owner_pk.clone()),
             Code(OpRot),
         ];
@@ -88,7 +277,7 @@ impl P2AscendingNonce {
                 Code(OpSwap),
                 Code(OpSub),
                 Code(OpDup),
-                Push(encode_int(self.dust_limit)),
+                Push(encode_int64(self.dust_limit as i64)),
                 Code(OpGreaterThanOrEqual),
             ]);
             ops.push(Code(OpIf));
@@ -198,7 +387,7 @@ impl P2AscendingNonce {
     }
 }
 
-impl Output for P2AscendingNonce {
+impl LockingScript for P2AscendingNonce {
     fn value(&self) -> u64 {
         self.old_value
     }
@@ -210,81 +399,51 @@ impl Output for P2AscendingNonce {
     fn script_code(&self) -> Script {
         Script::new(self._ops())
     }
+}
 
+// Values out of range give a failing sig script, see P2AscendingNonce::validate_spend.
+impl Unlocker<P2AscendingNonce> for P2AscendingNonceSpendParams {
     fn sig_script(&self,
+                  covenant: &P2AscendingNonce,
                   mut serialized_sig: Vec<u8>,
                   serialized_pub_key: Vec<u8>,
                   pre_image: &PreImage,
                   outputs: &[TxOutput]) -> Script {
         use self::P2AscendingNonceSpendParams::*;
-        let spend_params = self.spend_params.as_ref().expect("must provide spend params!");
-        match spend_params {
+        match self {
             NonceRedeem { .. } | NonceRefill { .. } => {
-                let (payment_amount, new_nonce, owner_sig, is_terminal) = match spend_params {
+                let (payment_amount, new_nonce, owner_sig, is_terminal) = match self {
                     NonceRedeem { payment_amount, new_nonce, owner_sig, is_terminal } => {
                         (*payment_amount, *new_nonce, owner_sig.clone(), *is_terminal)
                     },
                     NonceRefill { payment_amount } => {
-                        (*payment_amount, self.old_nonce, vec![], false)
+                        (*payment_amount, covenant.old_nonce, vec![], false)
                     },
                     P2pk => unreachable!(),
                 };
                 serialized_sig.remove(serialized_sig.len() - 1);  // remove sig flag
-                let script_code = self.script_code().to_vec_sig();
+                let script_code = covenant.script_code().to_vec_sig();
                 let nonce_size = 9;  // len("PUSH <oldNonce>")
                 let pk_size = 34;  // len("PUSH <pubkey>")
                 Script::new(vec![
-                    Op::Push(self.lokad_id.clone()),
+                    Op::Push(covenant.lokad_id.clone()),
                     Op::Push(owner_sig.clone()),  // ownerDataSig
-                    Op::Push({  // outputsPost
-                        let mut outputs_post = Vec::new();
-                        outputs[if is_terminal { 0 } else { 1 }..].iter()
-                            .for_each(|tx_output| {
-                                tx_output.write_to_stream(&mut outputs_post).unwrap()
-                            });
-                        outputs_post
-                    }),
+                    Op::Push(  // outputsPost
+                        serialize_outputs(&outputs[if is_terminal { 0 } else { 1 }..])
+                    ),
                     Op::Push(serialized_pub_key),  // covenantPk
                     Op::Push(serialized_sig),  // covenantDataSig
                     Op::Push({  // preimagePrefix
-                        let mut pre_image_part = Vec::new();
-                        pre_image.write_to_stream_flags(&mut pre_image_part, PreImageWriteFlags {
-                            version: true,       // /-
-                            hash_prevouts: true, // |
-                            hash_sequence: true, // |
-                            outpoint: true,      // \-
-                            script_code: false,  // + len(script_code)
-                            value: false,
-                            sequence: false,
-                            hash_outputs: false,
-                            lock_time: false,
-                            sighash_type: false,
-                        }).unwrap();
-                        write_var_int(&mut pre_image_part, script_code.len() as u64).unwrap();
+                        let mut pre_image_part = pre_image_prefix(pre_image, script_code.len());
                         pre_image_part.extend_from_slice(&script_code[..nonce_size]);
                         pre_image_part
                     }),
-                    Op::Push({  // preimageSuffix
-                        let mut pre_image_part = Vec::new();
-                        pre_image.write_to_stream_flags(&mut pre_image_part, PreImageWriteFlags {
-                            version: false,
-                            hash_prevouts: false,
-                            hash_sequence: false,
-                            outpoint: false,
-                            script_code: false,
-                            value: false,
-                            sequence: false,
-                            hash_outputs: false,
-                            lock_time: true,    // /-
-                            sighash_type: true, // \-
-                        }).unwrap();
-                        pre_image_part
-                    }),
-                    Op::Push(encode_int(payment_amount)),
-                    Op::Push(encode_int(self.old_value.try_into().unwrap())),
+                    Op::Push(pre_image_suffix(pre_image)),  // preimageSuffix
+                    Op::Push(encode_int64(payment_amount)),
+                    Op::Push(encode_int64(covenant.old_value as i64)),
                     Op::Push(script_code[nonce_size..][..pk_size].to_vec()),
                     Op::Push(script_code[nonce_size..][pk_size..].to_vec()),
-                    Op::Push(encode_int(new_nonce)),
+                    Op::Push(encode_int64(new_nonce)),
                     Op::Push(vec![1]),
                 ])
             },
@@ -297,3 +456,143 @@ impl Output for P2AscendingNonce {
         }
     }
 }
+
+#[cfg(all(test, feature = "secp256k1"))]
+mod tests {
+    use super::*;
+    use crate::address::AddressType;
+    use crate::crypto::{Crypto, Signature};
+    use crate::crypto::secp256k1::CryptoSecp256k1;
+    use crate::hash::single_sha256;
+    use crate::nonce_channel::nonce_authorization_message;
+    use crate::script_debugger::ScriptDebugger;
+
+    #[test]
+    fn test_redeem_schnorr() {
+        let crypto = CryptoSecp256k1::new();
+        let owner_key = secp256k1::SecretKey::from_slice(&[2; 32]).unwrap();
+        let redeemer_key = secp256k1::SecretKey::from_slice(&[3; 32]).unwrap();
+        let redeemer_pk = crypto.secret_to_pub_key(&redeemer_key).serialize();
+        let redeemer = Address::from_serialized_pub_key("bitcoincash", AddressType::P2PKH, &redeemer_pk);
+        let covenant = P2AscendingNonce {
+            lokad_id: b"TEST".to_vec(),
+            old_value: 100_000,
+            owner_pk: crypto.secret_to_pub_key(&owner_key).serialize().to_vec(),
+            old_nonce: 1,
+            dust_limit: 546,
+        };
+        let spent_output = covenant.to_p2sh_output();
+        let message = single_sha256(&nonce_authorization_message(&redeemer, 20_000, 2).unwrap());
+        let mut sizes = Vec::new();
+        for &is_schnorr in [false, true].iter() {
+            let owner_sig = if is_schnorr {
+                crypto.sign_schnorr(&message, &owner_key).to_vec()
+            } else {
+                Signature::serialize_der(&crypto.sign(&message, &owner_key))
+            };
+            let outpoint = TxOutpoint { tx_hash: [1; 32], vout: 0 };
+            let tx_build = covenant.redeem_tx(outpoint, 20_000, 2, owner_sig, redeemer.clone(),
+                                              1000, is_schnorr).unwrap();
+            let tx = tx_build.sign_with(&crypto, &redeemer_key).unwrap();
+            assert_eq!(ScriptDebugger::new(&crypto, &tx, 0, &spent_output).run(), Ok(()));
+            assert!(tx.size() <= tx_build.estimate_size());
+            sizes.push(tx_build.estimate_size());
+        }
+        assert!(sizes[1] < sizes[0]);
+
+        // i64::MIN has no 8 byte encoding, instead of encoding as negative zero
+        assert_eq!(nonce_authorization_message(&redeemer, 1, i64::MIN),
+                   Err(NonceError::NonceOutOfRange(i64::MIN)));
+        assert_eq!(nonce_authorization_message(&redeemer, 1, i64::MIN + 1).unwrap()[28..], [0xff; 8]);
+    }
+
+    #[test]
+    fn test_refill() {
+        use crate::outputs::P2PKHOutput;
+        use crate::tx::tx_hash_to_hex;
+        use crate::unsigned_tx::Output;
+        let crypto = CryptoSecp256k1::new();
+        let funding_key = secp256k1::SecretKey::from_slice(&[3; 32]).unwrap();
+        let funding_address = Address::from_serialized_pub_key(
+            "bitcoincash", AddressType::P2PKH, &crypto.secret_to_pub_key(&funding_key).serialize());
+        let utxo = |amount| UtxoEntry { tx_id_hex: tx_hash_to_hex(&[0x55; 32]), amount, ..UtxoEntry::default() };
+        let covenant = P2AscendingNonce {
+            lokad_id: b"TEST".to_vec(),
+            old_value: 100_000,
+            owner_pk: vec![2; 33],
+            old_nonce: 5,
+            dust_limit: 546,
+        };
+        let outpoint = TxOutpoint { tx_hash: [1; 32], vout: 0 };
+        let tx_build = covenant.refill_tx(outpoint.clone(), 50_000, &[utxo(60_000)], funding_address.clone(),
+                                          1000, false).unwrap();
+        let tx = tx_build.sign_with(&crypto, &funding_key).unwrap();
+        assert!(tx.size() <= tx_build.estimate_size());
+        assert_eq!(ScriptDebugger::new(&crypto, &tx, 0, &covenant.to_p2sh_output()).run(), Ok(()));
+        let funding_output = P2PKHOutput { value: 60_000, address: funding_address.clone() }.to_output();
+        assert_eq!(ScriptDebugger::new(&crypto, &tx, 1, &funding_output).run(), Ok(()));
+
+        // the covenant continues with the refilled value and the same nonce, then the change
+        let refilled = P2AscendingNonce { old_value: 150_000, ..covenant.clone() };
+        assert_eq!(tx.outputs()[0].value, 150_000);
+        assert_eq!(tx.outputs()[0].script.to_vec(), refilled.to_p2sh_output().script.to_vec());
+        assert_eq!(tx.outputs().len(), 2);
+        assert!(tx.outputs()[1].value < 10_000);
+
+        assert_eq!(covenant.refill_tx(outpoint.clone(), 0, &[utxo(60_000)], funding_address.clone(), 1000, false).err(),
+                   Some(NonceError::InvalidPaymentAmount(0)));
+        assert_eq!(covenant.refill_tx(outpoint.clone(), MAX_SCRIPT_INT, &[utxo(60_000)], funding_address.clone(),
+                                      1000, false).err(),
+                   Some(NonceError::InvalidPaymentAmount(MAX_SCRIPT_INT)));
+        assert!(matches!(covenant.refill_tx(outpoint, 50_000, &[utxo(40_000)], funding_address, 1000, false),
+                         Err(NonceError::InsufficientFunds(_))));
+    }
+
+    #[test]
+    fn test_from_redeem_script() {
+        let covenant = P2AscendingNonce {
+            lokad_id: b"TEST".to_vec(),
+            old_value: 100_000,
+            owner_pk: vec![2; 33],
+            old_nonce: 1,
+            dust_limit: 546,
+        };
+        let nonces = [1, -1, 0, 0x7f, 0x80, i64::MIN + 1, i64::MAX];
+        for &old_nonce in nonces.iter() {
+            let covenant = P2AscendingNonce { old_nonce, ..covenant.clone() };
+            let recovered = P2AscendingNonce::from_redeem_script(&covenant.script(), 100_000).unwrap();
+            assert_eq!(recovered.lokad_id, covenant.lokad_id);
+            assert_eq!(recovered.owner_pk, covenant.owner_pk);
+            assert_eq!((recovered.old_value, recovered.old_nonce, recovered.dust_limit), (100_000, old_nonce, 546));
+        }
+
+        // scripts which aren't generated from the recovered parameters are rejected
+        let mut ops = covenant.script().ops().to_vec();
+        ops.push(Op::Code(crate::script::OpCodeType::OpNop));
+        assert_eq!(P2AscendingNonce::from_redeem_script(&Script::new(ops), 100_000).err(),
+                   Some(NonceError::InvalidRedeemScript));
+        let mut ops = covenant.script().ops().to_vec();
+        ops[0] = Op::Push(vec![1]);
+        assert_eq!(P2AscendingNonce::from_redeem_script(&Script::new(ops), 100_000).err(),
+                   Some(NonceError::InvalidRedeemScript));
+
+        // next_state matches the covenant continuing in a redeem tx
+        let next = covenant.next_state(20_000).unwrap().unwrap();
+        assert_eq!((next.old_value, next.old_nonce), (80_000, 2));
+        let recovered = P2AscendingNonce::from_redeem_script(&next.script(), next.old_value).unwrap();
+        assert_eq!(recovered.script().to_vec(), next.script().to_vec());
+        let redeemer = Address::from_bytes(AddressType::P2PKH, [0x11; 20]);
+        let tx_build = covenant.redeem_tx(TxOutpoint { tx_hash: [1; 32], vout: 0 }, 20_000, 2, vec![0x30; 71],
+                                          redeemer, 1000, false).unwrap();
+        assert_eq!(tx_build.outputs()[0].script.to_vec(), next.to_p2sh_output().script.to_vec());
+        assert_eq!(tx_build.outputs()[0].value, next.old_value);
+
+        assert!(covenant.next_state(100_000 - 546).unwrap().is_some());
+        assert!(covenant.next_state(100_000 - 545).unwrap().is_none());
+        assert_eq!(covenant.next_state(100_001).err(), Some(NonceError::InvalidPaymentAmount(100_001)));
+        let exhausted = P2AscendingNonce { old_nonce: i64::MAX, ..covenant.clone() };
+        assert_eq!(exhausted.next_state(1).err(), Some(NonceError::NonceOutOfRange(i64::MAX)));
+        assert_eq!(covenant.next_state_with_nonce(1, 1).err(),
+                   Some(NonceError::NonceNotAscending { old_nonce: 1, new_nonce: 1 }));
+    }
+}
@@ -0,0 +1,331 @@
+use crate::address::Address;
+use crate::backend::address_script;
+use crate::tx::{Tx, TxOutput};
+use crate::unsigned_tx::UnsignedTx;
+use crate::wallet::{Wallet, WalletError, UtxoEntry};
+use crate::hex_traits::{FromHex, ToHex};
+
+use serde_json::{json, Value};
+
+
+pub const PAYMENT_REQUEST_CONTENT_TYPE: &str = "application/payment-request";
+pub const PAYMENT_CONTENT_TYPE: &str = "application/payment";
+pub const PAYMENT_ACK_CONTENT_TYPE: &str = "application/payment-ack";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PaymentProtocolError {
+    InvalidJson(String),
+    MissingField(&'static str),
+    InvalidAddress(String),
+    InvalidAmount { idx: usize },
+    InvalidTime(String),
+    InvalidTx(String),
+    WrongCurrency(String),
+    WrongNetwork(String),
+    NoOutputs,
+    Expired { expires: u64 },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PaymentRequestOutput {
+    pub address: Address,
+    pub amount: u64,
+}
+
+// Invoice of the JSON payment protocol as served by BitPay-style merchants with content type
+// application/payment-request. Times are unix timestamps.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PaymentRequest {
+    pub network: String,
+    pub currency: String,
+    pub required_fee_per_byte: f64,
+    pub outputs: Vec<PaymentRequestOutput>,
+    pub time: u64,
+    pub expires: u64,
+    pub memo: String,
+    pub payment_url: String,
+    pub payment_id: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct Payment {
+    pub currency: String,
+    pub transactions: Vec<Tx>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PaymentAck {
+    pub payment: Payment,
+    pub memo: Option<String>,
+}
+
+fn parse_json(json: &str) -> Result<Value, PaymentProtocolError> {
+    serde_json::from_str(json).map_err(|err| PaymentProtocolError::InvalidJson(err.to_string()))
+}
+
+fn str_field<'a>(value: &'a Value, field: &'static str) -> Result<&'a str, PaymentProtocolError> {
+    value[field].as_str().ok_or(PaymentProtocolError::MissingField(field))
+}
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_from_march = (month as i64 + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// Parses UTC ISO 8601 times as used by the protocol, e.g. "2019-06-19T19:52:37.123Z".
+fn parse_iso_time(time: &str) -> Option<u64> {
+    let time = time.strip_suffix('Z')?;
+    let (date, time) = time.split_at(time.find('T')?);
+    let date = date.split('-').map(|part| part.parse::<u32>().ok()).collect::<Option<Vec<_>>>()?;
+    let time = time[1..].split('.').next()?;
+    let time = time.split(':').map(|part| part.parse::<u32>().ok()).collect::<Option<Vec<_>>>()?;
+    match (date.as_slice(), time.as_slice()) {
+        (&[year, month, day], &[hour, minute, second])
+                if (1..=12).contains(&month) && (1..=31).contains(&day) &&
+                   hour < 24 && minute < 60 && second < 61 => {
+            let days = days_from_civil(year as i64, month, day);
+            let secs = days * 86_400 + (hour * 3600 + minute * 60 + second) as i64;
+            if secs < 0 { None } else { Some(secs as u64) }
+        },
+        _ => None,
+    }
+}
+
+fn network_prefix(network: &str) -> Option<&'static str> {
+    match network {
+        "main" => Some("bitcoincash"),
+        "test" => Some("bchtest"),
+        "regtest" => Some("bchreg"),
+        _ => None,
+    }
+}
+
+fn checked_total(outputs: &[PaymentRequestOutput]) -> Result<u64, PaymentProtocolError> {
+    outputs.iter().enumerate().try_fold(0u64, |total, (idx, output)| {
+        total.checked_add(output.amount).ok_or(PaymentProtocolError::InvalidAmount { idx })
+    })
+}
+
+impl PaymentRequest {
+    pub fn from_json(json: &str) -> Result<Self, PaymentProtocolError> {
+        let value = parse_json(json)?;
+        let network = str_field(&value, "network")?.to_string();
+        let prefix = network_prefix(&network)
+            .ok_or_else(|| PaymentProtocolError::WrongNetwork(network.clone()))?;
+        let outputs = value["outputs"].as_array()
+            .ok_or(PaymentProtocolError::MissingField("outputs"))?
+            .iter()
+            .enumerate()
+            .map(|(idx, output)| {
+                let address = str_field(output, "address")?;
+                let cash_addr = if address.contains(':') {
+                    address.to_string()
+                } else {
+                    format!("{}:{}", prefix, address)
+                };
+                let address = Address::from_cash_addr(cash_addr)
+                    .map_err(|_| PaymentProtocolError::InvalidAddress(address.to_string()))?;
+                let amount = output["amount"].as_u64()
+                    .filter(|&amount| amount > 0)
+                    .ok_or(PaymentProtocolError::InvalidAmount { idx })?;
+                Ok(PaymentRequestOutput { address, amount })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        checked_total(&outputs)?;
+        let parse_time = |field| {
+            let time = str_field(&value, field)?;
+            parse_iso_time(time).ok_or_else(|| PaymentProtocolError::InvalidTime(time.to_string()))
+        };
+        Ok(PaymentRequest {
+            currency: str_field(&value, "currency")?.to_string(),
+            required_fee_per_byte: value["requiredFeePerByte"].as_f64().unwrap_or(0.0),
+            outputs,
+            time: parse_time("time")?,
+            expires: parse_time("expires")?,
+            memo: value["memo"].as_str().unwrap_or_default().to_string(),
+            payment_url: str_field(&value, "paymentUrl")?.to_string(),
+            payment_id: value["paymentId"].as_str().unwrap_or_default().to_string(),
+            network,
+        })
+    }
+
+    // Checks that the request is a BCH request for `network` ("main" or "test") which pays
+    // addresses of that network and hasn't expired at `now`.
+    pub fn validate(&self, network: &str, now: u64) -> Result<(), PaymentProtocolError> {
+        if self.currency != "BCH" {
+            return Err(PaymentProtocolError::WrongCurrency(self.currency.clone()));
+        }
+        if self.network != network {
+            return Err(PaymentProtocolError::WrongNetwork(self.network.clone()));
+        }
+        if self.outputs.is_empty() {
+            return Err(PaymentProtocolError::NoOutputs);
+        }
+        let prefix = network_prefix(network);
+        if let Some(output) = self.outputs.iter().find(|output| Some(output.address.prefix()) != prefix) {
+            return Err(PaymentProtocolError::InvalidAddress(output.address.cash_addr().to_string()));
+        }
+        if now >= self.expires {
+            return Err(PaymentProtocolError::Expired { expires: self.expires });
+        }
+        checked_total(&self.outputs)?;
+        Ok(())
+    }
+
+    // Fails with InvalidAmount at the output overflowing the total.
+    pub fn total_amount(&self) -> Result<u64, PaymentProtocolError> {
+        checked_total(&self.outputs)
+    }
+
+    pub fn fee_per_kb(&self) -> u64 {
+        (self.required_fee_per_byte * 1000.0).ceil() as u64
+    }
+
+    pub fn tx_outputs(&self) -> Vec<TxOutput> {
+        self.outputs.iter()
+            .map(|output| TxOutput::new(output.amount, address_script(&output.address)))
+            .collect()
+    }
+
+    // Whether `tx` contains all requested outputs.
+    pub fn is_paid_by(&self, tx: &Tx) -> bool {
+        let mut tx_outputs = tx.outputs().iter()
+            .map(|output| (output.value, output.script.to_vec()))
+            .collect::<Vec<_>>();
+        self.tx_outputs().iter().all(|requested| {
+            let requested = (requested.value, requested.script.to_vec());
+            match tx_outputs.iter().position(|output| *output == requested) {
+                Some(idx) => { tx_outputs.remove(idx); true },
+                None => false,
+            }
+        })
+    }
+
+    // Payment to POST to `payment_url` with content type application/payment.
+    pub fn payment(&self, transactions: Vec<Tx>) -> Payment {
+        Payment { currency: self.currency.clone(), transactions }
+    }
+}
+
+fn tx_from_hex(tx_hex: &Value) -> Result<Tx, PaymentProtocolError> {
+    tx_hex.as_str()
+        .and_then(|tx_hex| Tx::from_hex(tx_hex).ok())
+        .ok_or_else(|| PaymentProtocolError::InvalidTx(tx_hex.to_string()))
+}
+
+impl Payment {
+    fn to_value(&self) -> Value {
+        json!({
+            "currency": self.currency,
+            "transactions": self.transactions.iter().map(ToHex::to_hex).collect::<Vec<_>>(),
+        })
+    }
+
+    fn from_value(value: &Value) -> Result<Self, PaymentProtocolError> {
+        Ok(Payment {
+            currency: str_field(value, "currency")?.to_string(),
+            transactions: value["transactions"].as_array()
+                .ok_or(PaymentProtocolError::MissingField("transactions"))?
+                .iter()
+                .map(tx_from_hex)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn to_json(&self) -> String {
+        self.to_value().to_string()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, PaymentProtocolError> {
+        Payment::from_value(&parse_json(json)?)
+    }
+}
+
+impl PaymentAck {
+    pub fn to_json(&self) -> String {
+        let mut value = json!({ "payment": self.payment.to_value() });
+        if let Some(memo) = &self.memo {
+            value["memo"] = json!(memo);
+        }
+        value.to_string()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, PaymentProtocolError> {
+        let value = parse_json(json)?;
+        Ok(PaymentAck {
+            payment: Payment::from_value(&value["payment"])?,
+            memo: value["memo"].as_str().map(str::to_string),
+        })
+    }
+}
+
+impl Wallet {
+    // Tx paying all outputs of the request, at least at the fee rate it requires.
+    pub fn pay_request(&self, request: &PaymentRequest, utxos: &[UtxoEntry])
+            -> Result<UnsignedTx, WalletError> {
+        let mut tx_build = self.init_tx(utxos)?;
+        if tx_build.inputs().is_empty() {
+            return Err(WalletError::NoUtxos);
+        }
+        for (idx, output) in request.tx_outputs().into_iter().enumerate() {
+            if output.value < self.dust_amount() {
+                return Err(WalletError::DustOutput { idx });
+            }
+            tx_build.add_output(output);
+        }
+        tx_build.add_leftover_output(self.address().clone(),
+                                     self.fee_per_kb().max(request.fee_per_kb()),
+                                     self.dust_amount())?;
+        Ok(tx_build)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payment_request() {
+        let json = r#"{
+            "network": "main",
+            "currency": "BCH",
+            "requiredFeePerByte": 1.0,
+            "outputs": [{"amount": 39300, "address": "qqg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zye3kwllue"}],
+            "time": "2019-06-19T19:52:37.123Z",
+            "expires": "2019-06-19T20:07:37Z",
+            "memo": "Payment request for BitPay invoice",
+            "paymentUrl": "https://bitpay.com/i/TvmJmkp7yX",
+            "paymentId": "TvmJmkp7yX"
+        }"#;
+        let request = PaymentRequest::from_json(json).unwrap();
+        assert_eq!(request.time, 1_560_973_957);
+        assert_eq!(request.expires, 1_560_974_857);
+        assert_eq!(request.total_amount(), Ok(39300));
+        assert_eq!(request.fee_per_kb(), 1000);
+        assert_eq!(request.validate("main", 1_560_974_000), Ok(()));
+        assert_eq!(request.validate("main", 1_560_974_857), Err(PaymentProtocolError::Expired { expires: 1_560_974_857 }));
+        assert_eq!(request.validate("test", 1_560_974_000), Err(PaymentProtocolError::WrongNetwork("main".to_string())));
+
+        let tx = Tx::new(1, vec![], request.tx_outputs(), 0);
+        assert!(request.is_paid_by(&tx));
+        assert!(!request.is_paid_by(&Tx::new(1, vec![], vec![], 0)));
+        let ack = PaymentAck { payment: request.payment(vec![tx.clone()]), memo: Some("Thanks".to_string()) };
+        let parsed = PaymentAck::from_json(&ack.to_json()).unwrap();
+        assert_eq!(parsed.memo, ack.memo);
+        assert_eq!(parsed.payment.transactions[0].hash(), tx.hash());
+
+        // totals overflowing u64 are rejected rather than wrapping
+        let overflow = json.replace(r#""outputs": ["#, &format!(
+            r#""outputs": [{{"amount": {}, "address": "qqg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zye3kwllue"}}, "#, u64::MAX));
+        assert_eq!(PaymentRequest::from_json(&overflow), Err(PaymentProtocolError::InvalidAmount { idx: 1 }));
+        let mut request = request;
+        request.outputs.push(PaymentRequestOutput { address: request.outputs[0].address.clone(), amount: u64::MAX });
+        assert_eq!(request.total_amount(), Err(PaymentProtocolError::InvalidAmount { idx: 1 }));
+        assert_eq!(request.validate("main", 1_560_974_000), Err(PaymentProtocolError::InvalidAmount { idx: 1 }));
+    }
+}
@@ -0,0 +1,292 @@
+use crate::unsigned_tx::{Output, PreImage, UnsignedTx, UnsignedInput};
+use crate::script::{Script, Op};
+use crate::address::Address;
+use crate::tx::{TxOutput, TxOutpoint};
+use crate::outputs::{P2PKHOutput, P2SHOutput};
+use crate::wallet::WalletError;
+use crate::spend::{LockingScript, Unlocker, Spend, max_unlock_size};
+use crate::serialize::{encode_int, encode_int64, MAX_SCRIPT_INT};
+use crate::covenant::{verify_pre_image_ops, serialize_pre_image, script_with_len,
+                      PRE_IMAGE_PREFIX_SIZE, PRE_IMAGE_SUFFIX_SIZE};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+
+// Mecenas-style covenant: anyone can pull `pledge` to `recipient` once every `period`
+// (BIP68 sequence, see relative_lock_blocks), paying `max_fee` from the covenant, which is
+// re-created with the remaining value. Once that would be below `dust_amount`, the last
+// pull pays everything to `recipient`. The funder can reclaim the funds at any time.
+#[derive(Clone, Debug)]
+pub struct RecurringPaymentOutput {
+    pub value: u64,
+    pub recipient: Address,
+    pub funder: Address,
+    pub pledge: u64,
+    pub period: u32,
+    pub max_fee: u64,
+    pub dust_amount: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RecurringPaymentSpendParams {
+    Pull,
+    Reclaim,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RecurringPaymentError {
+    ValueOutOfRange { field: &'static str, value: u64 },
+    InsufficientFunds { value: u64, max_fee: u64 },
+}
+
+impl RecurringPaymentOutput {
+    pub fn validate(&self) -> Result<(), RecurringPaymentError> {
+        let fields = [
+            ("value", self.value),
+            ("pledge", self.pledge),
+            ("max_fee", self.max_fee),
+            ("dust_amount", self.dust_amount),
+            ("pull_amount", self._pull_amount().unwrap_or(u64::MAX)),
+        ];
+        for &(field, value) in fields.iter() {
+            if value > MAX_SCRIPT_INT {
+                return Err(RecurringPaymentError::ValueOutOfRange { field, value });
+            }
+        }
+        Ok(())
+    }
+
+    // The covenant after the next pull, or None if the pull closes it.
+    pub fn next_state(&self) -> Result<Option<Self>, RecurringPaymentError> {
+        self.validate()?;
+        let new_value = match self._pull_amount().and_then(|amount| self.value.checked_sub(amount)) {
+            Some(new_value) if new_value >= self.dust_amount => new_value,
+            _ => return Ok(None),
+        };
+        Ok(Some(RecurringPaymentOutput {
+            value: new_value,
+            ..self.clone()
+        }))
+    }
+
+    // Builds the transaction pulling the pledge. Requires tx version 2 for the relative lock.
+    // Fails if the covenant's value can't pay `max_fee` on the last pull.
+    pub fn pull_tx(&self, outpoint: TxOutpoint) -> Result<UnsignedTx, RecurringPaymentError> {
+        let next_state = self.next_state()?;
+        let mut tx_build = UnsignedTx::new_simple();
+        tx_build.set_version(2);
+        tx_build.add_input(UnsignedInput::new(
+            outpoint,
+            Box::new(P2SHOutput {
+                output: Box::new(Spend::new(self.clone(), RecurringPaymentSpendParams::Pull)),
+            }),
+            self.period,
+        ));
+        match next_state {
+            Some(next_state) => {
+                tx_build.add_output(self._pledge_output(self.pledge).to_output());
+                tx_build.add_output(next_state.to_p2sh_output());
+            },
+            None => {
+                let value = self.value.checked_sub(self.max_fee)
+                    .ok_or(RecurringPaymentError::InsufficientFunds {
+                        value: self.value,
+                        max_fee: self.max_fee,
+                    })?;
+                tx_build.add_output(self._pledge_output(value).to_output());
+            },
+        }
+        Ok(tx_build)
+    }
+
+    // Builds the transaction returning all funds to the funder.
+    pub fn reclaim_tx(&self, outpoint: TxOutpoint, fee_per_kb: u64) -> Result<UnsignedTx, WalletError> {
+        let mut tx_build = UnsignedTx::new_simple();
+        tx_build.add_input(UnsignedInput::new(
+            outpoint,
+            Box::new(P2SHOutput {
+                output: Box::new(Spend::new(self.clone(), RecurringPaymentSpendParams::Reclaim)),
+            }),
+            0xffff_ffff,
+        ));
+        tx_build.add_leftover_output(self.funder.clone(), fee_per_kb, self.dust_amount)?
+            .ok_or(WalletError::DustOutput { idx: 0 })?;
+        Ok(tx_build)
+    }
+
+    fn _pull_amount(&self) -> Option<u64> {
+        self.pledge.checked_add(self.max_fee)
+    }
+
+    fn _pledge_output(&self, value: u64) -> P2PKHOutput {
+        P2PKHOutput {
+            value,
+            address: self.recipient.clone(),
+        }
+    }
+
+    fn _ops(&self) -> Vec<Op> {
+        use crate::script::OpCodeType::*;
+        let recipient_script = script_with_len(&Output::script(&self._pledge_output(0)).to_vec());
+        let mut ops = vec![
+            Op::Code(OpIf),
+            // case: pull
+            Op::Push(encode_int64(self.period as i64)),
+            Op::Code(OpCheckSequenceVerify),
+            Op::Code(OpDrop),
+        ];
+        ops.append(&mut verify_pre_image_ops());
+        ops.append(&mut vec![
+            Op::Code(OpSize),
+            Op::Push(encode_int(PRE_IMAGE_SUFFIX_SIZE as i32)),
+            Op::Code(OpSub),
+            Op::Code(OpSplit),
+            Op::Code(OpSwap),
+            // script code var int is 1 byte, as the script is below 253 bytes
+            Op::Push(encode_int(PRE_IMAGE_PREFIX_SIZE as i32 + 1)),
+            Op::Code(OpSplit),
+            Op::Code(OpNip),
+            Op::Code(OpHash160),  // (=own P2SH hash)
+            Op::Code(OpSwap),
+            Op::Push(encode_int(8)),
+            Op::Code(OpSplit),
+            Op::Push(encode_int(4)),
+            Op::Code(OpSplit),
+            Op::Code(OpNip),
+            Op::Push(encode_int(32)),
+            Op::Code(OpSplit),
+            Op::Code(OpDrop),  // (=hashOutputs)
+            Op::Code(OpSwap),
+            Op::Code(OpBin2Num),
+            // out of range values give a failing script, see validate
+            Op::Push(encode_int64(self._pull_amount().unwrap_or(u64::MAX) as i64)),
+            Op::Code(OpSub),  // (=new value)
+            Op::Code(OpDup),
+            Op::Push(encode_int64(self.dust_amount as i64)),
+            Op::Code(OpGreaterThanOrEqual),
+            Op::Code(OpIf),
+            // case: pledge + continuation
+            Op::Push(vec![8]),  // (=value size)
+            Op::Code(OpNum2Bin),
+            Op::Push(vec![23, OpHash160 as u8, 20]),  // (=p2shpre)
+            Op::Code(OpCat),
+            Op::Code(OpRot),
+            Op::Code(OpCat),
+            Op::Push(vec![OpEqual as u8]),
+            Op::Code(OpCat),
+            Op::Push({
+                let mut vec = Vec::new();
+                vec.write_u64::<LittleEndian>(self.pledge).unwrap();
+                vec.extend_from_slice(&recipient_script);
+                vec
+            }),
+            Op::Code(OpSwap),
+            Op::Code(OpCat),
+            Op::Code(OpElse),
+            // case: last pull
+            Op::Push(encode_int64(self.pledge as i64)),
+            Op::Code(OpAdd),
+            Op::Push(vec![8]),  // (=value size)
+            Op::Code(OpNum2Bin),
+            Op::Push(recipient_script),
+            Op::Code(OpCat),
+            Op::Code(OpRot),
+            Op::Code(OpDrop),
+            Op::Code(OpEndIf),
+            Op::Code(OpHash256),
+            Op::Code(OpEqual),
+
+            Op::Code(OpElse),
+            // case: reclaim
+            Op::Code(OpDup),
+            Op::Code(OpHash160),
+            Op::Push(self.funder.bytes().to_vec()),
+            Op::Code(OpEqualVerify),
+            Op::Code(OpCheckSig),
+            Op::Code(OpEndIf),
+        ]);
+        ops
+    }
+}
+
+impl LockingScript for RecurringPaymentOutput {
+    fn value(&self) -> u64 {
+        self.value
+    }
+
+    fn script(&self) -> Script {
+        Script::new(self._ops())
+    }
+
+    fn script_code(&self) -> Script {
+        self.script()
+    }
+}
+
+impl Unlocker<RecurringPaymentOutput> for RecurringPaymentSpendParams {
+    fn sig_script(&self,
+                  _output: &RecurringPaymentOutput,
+                  mut serialized_sig: Vec<u8>,
+                  serialized_pub_key: Vec<u8>,
+                  pre_image: &PreImage,
+                  _outputs: &[TxOutput]) -> Script {
+        match self {
+            RecurringPaymentSpendParams::Pull => {
+                serialized_sig.remove(serialized_sig.len() - 1);  // remove sig flag
+                Script::new(vec![
+                    Op::Push(serialized_pub_key),
+                    Op::Push(serialized_sig),
+                    Op::Push(serialize_pre_image(pre_image)),
+                    Op::Push(vec![1]),
+                ])
+            },
+            RecurringPaymentSpendParams::Reclaim => Script::new(vec![
+                Op::Push(serialized_sig),
+                Op::Push(serialized_pub_key),
+                Op::Push(vec![]),
+            ]),
+        }
+    }
+
+    fn estimated_unlock_size(&self, output: &RecurringPaymentOutput) -> Option<usize> {
+        Some(max_unlock_size(self, output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::AddressType;
+
+    #[test]
+    fn test_pull_amounts() {
+        let covenant = RecurringPaymentOutput {
+            value: 13_000,
+            recipient: Address::from_bytes(AddressType::P2PKH, [0x11; 20]),
+            funder: Address::from_bytes(AddressType::P2PKH, [0x22; 20]),
+            pledge: 10_000,
+            period: 144,
+            max_fee: 1_000,
+            dust_amount: 2_000,
+        };
+        let outpoint = TxOutpoint { tx_hash: [0x33; 32], vout: 0 };
+        let tx_build = covenant.pull_tx(outpoint.clone()).unwrap();
+        let pre_image = &tx_build.pre_images(0x41)[0];
+        let pull = Spend::new(covenant.clone(), RecurringPaymentSpendParams::Pull);
+        let sig_script = Output::sig_script(&pull, vec![0; 73], vec![0; 33], pre_image, tx_build.outputs());
+        assert_eq!(Output::estimated_unlock_size(&pull), Some(sig_script.to_vec().len()));
+        // exactly dust remaining continues the covenant
+        assert_eq!(covenant.next_state().unwrap().unwrap().value, 2_000);
+        assert_eq!(covenant.pull_tx(outpoint.clone()).unwrap().outputs().len(), 2);
+        // one below pays everything but max_fee
+        let last = RecurringPaymentOutput { value: 12_999, ..covenant.clone() };
+        assert!(last.next_state().unwrap().is_none());
+        assert_eq!(last.pull_tx(outpoint.clone()).unwrap().outputs()[0].value, 11_999);
+        let empty = RecurringPaymentOutput { value: 999, ..covenant.clone() };
+        assert_eq!(empty.pull_tx(outpoint.clone()).err(),
+                   Some(RecurringPaymentError::InsufficientFunds { value: 999, max_fee: 1_000 }));
+        let overflow = RecurringPaymentOutput { pledge: MAX_SCRIPT_INT, ..covenant };
+        assert!(matches!(overflow.pull_tx(outpoint).err(),
+                         Some(RecurringPaymentError::ValueOutOfRange { field: "pull_amount", .. })));
+    }
+}
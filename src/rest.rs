@@ -0,0 +1,163 @@
+use crate::address::Address;
+use crate::backend::{ChainBackend, BackendError, ScriptHashStatus};
+use crate::slp::SLPMessage;
+use crate::tx::{Tx, tx_hash_to_hex, tx_hex_to_hash};
+use crate::wallet::{UtxoEntry, TokenUtxoEntry};
+use crate::hex_traits::{FromHex, ToHex};
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+
+// Client for rest.bitcoin.com-style REST APIs, e.g. "https://rest.bitcoin.com/v2".
+// The REST API is indexed by address, so UTXOs can't be queried by script hash, and
+// it has no push notifications.
+pub struct RestClient {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl From<reqwest::Error> for BackendError {
+    fn from(err: reqwest::Error) -> Self {
+        match err.status() {
+            Some(status) => BackendError::Http(status.as_u16()),
+            None => BackendError::Io(err.to_string()),
+        }
+    }
+}
+
+fn invalid_response(value: &Value) -> BackendError {
+    BackendError::InvalidResponse(value.to_string())
+}
+
+fn parse_utxo(value: &Value) -> Result<UtxoEntry, BackendError> {
+    let tx_id_hex = value["txid"].as_str().ok_or_else(|| invalid_response(value))?;
+    let vout = value["vout"].as_u64().ok_or_else(|| invalid_response(value))?;
+    let amount = value["satoshis"].as_u64().ok_or_else(|| invalid_response(value))?;
+    let height = value["height"].as_u64().filter(|&height| height > 0);
+    Ok(UtxoEntry {
+        tx_id_hex: tx_id_hex.to_string(),
+        vout: vout as u32,
+        amount,
+        height: height.map(|height| height as u32),
+        script: None,
+        is_coinbase: false,
+        slp_token: None,
+        token: None,
+    })
+}
+
+impl RestClient {
+    pub fn new(base_url: &str) -> Self {
+        RestClient {
+            client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    async fn get(&self, path: &str) -> Result<Value, BackendError> {
+        let response = self.client.get(format!("{}/{}", self.base_url, path))
+            .send().await?
+            .error_for_status()?;
+        Ok(response.json().await?)
+    }
+
+    async fn post(&self, path: &str, body: &Value) -> Result<Value, BackendError> {
+        let response = self.client.post(format!("{}/{}", self.base_url, path))
+            .json(body)
+            .send().await?
+            .error_for_status()?;
+        Ok(response.json().await?)
+    }
+
+    // Asks the SLP validator of the API whether the txs are valid SLP txs.
+    pub async fn validate_slp_txs(&self, tx_hashes: &[[u8; 32]]) -> Result<Vec<bool>, BackendError> {
+        let tx_ids = tx_hashes.iter().map(tx_hash_to_hex).collect::<Vec<_>>();
+        let result = self.post("slp/validateTxid", &json!({ "txids": tx_ids })).await?;
+        let validities = result.as_array().ok_or_else(|| invalid_response(&result))?;
+        if validities.len() != tx_hashes.len() {
+            return Err(invalid_response(&result));
+        }
+        validities.iter()
+            .map(|validity| validity["valid"].as_bool().ok_or_else(|| invalid_response(validity)))
+            .collect()
+    }
+
+    // UTXOs of `address` carrying tokens of SLP-valid txs. Token amounts are taken from
+    // the OP_RETURN of the tx, validity from the API's SLP validator.
+    pub async fn token_utxos(&self, address: &Address) -> Result<Vec<TokenUtxoEntry>, BackendError> {
+        let mut candidates = Vec::new();
+        for utxo in self.address_utxos(address).await? {
+            let tx_hash = tx_hex_to_hash(&utxo.tx_id_hex)
+                .ok_or_else(|| BackendError::InvalidResponse(utxo.tx_id_hex.clone()))?;
+            let tx = self.tx(&tx_hash).await?;
+            let message = match tx.outputs().first().map(|output| SLPMessage::parse(&output.script)) {
+                Some(Ok(message)) => message,
+                _ => continue,
+            };
+            if let Some((token_id, token_amount)) = message.output_token(&tx_hash, utxo.vout) {
+                let utxo = UtxoEntry { slp_token: Some((token_id, token_amount)), ..utxo };
+                candidates.push((tx_hash, TokenUtxoEntry { utxo, token_id, token_amount }));
+            }
+        }
+        if candidates.is_empty() {
+            return Ok(Vec::new());
+        }
+        let tx_hashes = candidates.iter().map(|(tx_hash, _)| *tx_hash).collect::<Vec<_>>();
+        let validities = self.validate_slp_txs(&tx_hashes).await?;
+        Ok(candidates.into_iter()
+            .zip(validities)
+            .filter(|(_, is_valid)| *is_valid)
+            .map(|((_, token_utxo), _)| token_utxo)
+            .collect())
+    }
+}
+
+#[async_trait]
+impl ChainBackend for RestClient {
+    async fn utxos(&self, _script_hash: &[u8; 32]) -> Result<Vec<UtxoEntry>, BackendError> {
+        Err(BackendError::Unsupported)
+    }
+
+    async fn address_utxos(&self, address: &Address) -> Result<Vec<UtxoEntry>, BackendError> {
+        let result = self.get(&format!("address/utxo/{}", address.cash_addr())).await?;
+        result["utxos"].as_array()
+            .ok_or_else(|| invalid_response(&result))?
+            .iter()
+            .map(parse_utxo)
+            .collect()
+    }
+
+    async fn tx(&self, tx_hash: &[u8; 32]) -> Result<Tx, BackendError> {
+        let result = self.get(
+            &format!("rawtransactions/getRawTransaction/{}", tx_hash_to_hex(tx_hash))
+        ).await?;
+        result.as_str()
+            .and_then(|tx_hex| Tx::from_hex(tx_hex).ok())
+            .ok_or_else(|| invalid_response(&result))
+    }
+
+    async fn chain_height(&self) -> Result<u32, BackendError> {
+        let result = self.get("blockchain/getBlockCount").await?;
+        result.as_u64()
+            .map(|height| height as u32)
+            .ok_or_else(|| invalid_response(&result))
+    }
+
+    async fn broadcast(&self, tx: &Tx) -> Result<[u8; 32], BackendError> {
+        let result = self.get(
+            &format!("rawtransactions/sendRawTransaction/{}", tx.to_hex())
+        ).await?;
+        result.as_str()
+            .and_then(tx_hex_to_hash)
+            .ok_or_else(|| invalid_response(&result))
+    }
+
+    async fn subscribe(&self, _script_hash: &[u8; 32]) -> Result<ScriptHashStatus, BackendError> {
+        Err(BackendError::Unsupported)
+    }
+
+    async fn next_notification(&self) -> Result<([u8; 32], ScriptHashStatus), BackendError> {
+        Err(BackendError::Unsupported)
+    }
+}
@@ -1,7 +1,25 @@
+use crate::hex_traits::ToHex;
+
 use std::io;
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PushEncoding {
+    // OP_0 and OP_1..OP_16 where possible, else the smallest push opcode, as Script does
+    Minimal,
+    // Like Minimal, but also OP_1NEGATE for 0x81, as the MINIMALDATA rule requires. Opt-in, as
+    // it changes the serialization, and so the P2SH hash, of scripts pushing 0x81.
+    MinimalNumber,
+    // Smallest push opcode, never OP_N; an empty push is OP_PUSHDATA1 0x00 (as SLP requires)
+    Data,
+    // Opcodes 0x01-0x4b
+    Direct,
+    PushData1,
+    PushData2,
+    PushData4,
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Op {
     Push(Vec<u8>),
@@ -49,10 +67,48 @@ impl Op {
     }
 }
 
+pub fn write_push<W: io::Write>(write: &mut W,
+                                data: &[u8],
+                                encoding: PushEncoding) -> io::Result<()> {
+    let max_len = match encoding {
+        PushEncoding::Minimal => return Op::Push(data.to_vec()).write_to_stream(write, true),
+        PushEncoding::MinimalNumber if data == [0x81] => return write.write_u8(OpCodeType::Op1Negate as u8),
+        PushEncoding::MinimalNumber => return Op::Push(data.to_vec()).write_to_stream(write, true),
+        PushEncoding::Data => return Op::Push(data.to_vec()).write_to_stream(write, false),
+        PushEncoding::Direct => 0x4b,
+        PushEncoding::PushData1 => 0xff,
+        PushEncoding::PushData2 => 0xffff,
+        PushEncoding::PushData4 => 0xffff_ffff,
+    };
+    if data.len() > max_len || (encoding == PushEncoding::Direct && data.is_empty()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Push of {} bytes can't be encoded as {:?}", data.len(), encoding),
+        ));
+    }
+    match encoding {
+        PushEncoding::Direct => write.write_u8(data.len() as u8)?,
+        PushEncoding::PushData1 => {
+            write.write_u8(OpCodeType::OpPushData1 as u8)?;
+            write.write_u8(data.len() as u8)?;
+        },
+        PushEncoding::PushData2 => {
+            write.write_u8(OpCodeType::OpPushData2 as u8)?;
+            write.write_u16::<LittleEndian>(data.len() as u16)?;
+        },
+        PushEncoding::PushData4 => {
+            write.write_u8(OpCodeType::OpPushData4 as u8)?;
+            write.write_u32::<LittleEndian>(data.len() as u32)?;
+        },
+        PushEncoding::Minimal | PushEncoding::MinimalNumber | PushEncoding::Data => unreachable!(),
+    }
+    write.write_all(data)
+}
+
 impl std::fmt::Display for Op {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            Op::Push(vec) => write!(f, "PUSH {}", hex::encode(vec)),
+            Op::Push(vec) => write!(f, "PUSH {}", vec.to_hex()),
             Op::Code(code) => write!(f, "{:?}", code),
         }
     }
@@ -150,6 +206,9 @@ impl Script {
         let code_separator_pos = self.ops.iter().rposition(
             |op| op == &Op::Code(OpCodeType::OpCodeSeparator)
         );
+        if let (None, Some(serialized)) = (code_separator_pos, &self.serialized) {
+            return serialized.clone();
+        }
         for (idx, op) in self.ops.iter().enumerate() {
             if let Some(code_sep_pos) = code_separator_pos {
                 if idx <= code_sep_pos {
@@ -185,7 +244,7 @@ impl std::fmt::Display for Script {
         for (i, op) in self.ops.iter().enumerate() {
             write!(f, "{:3}: ", i)?;
             match op {
-                Op::Push(vec) => writeln!(f, "PUSH {}", hex::encode(&vec))?,
+                Op::Push(vec) => writeln!(f, "PUSH {}", vec.to_hex())?,
                 other => writeln!(f, "{:?}", other)?,
             };
         }
@@ -193,6 +252,33 @@ impl std::fmt::Display for Script {
     }
 }
 
+// Data pushed by an op; small numbers are minimally encoded as OP_1..OP_16.
+pub(crate) fn op_data(op: &Op) -> Option<Vec<u8>> {
+    match op {
+        Op::Push(vec) => Some(vec.clone()),
+        Op::Code(code) if *code >= OpCodeType::Op1 && *code <= OpCodeType::Op16 => {
+            Some(vec![*code as u8 - OpCodeType::Op1 as u8 + 1])
+        },
+        Op::Code(OpCodeType::Op1Negate) => Some(vec![0x81]),
+        _ => None,
+    }
+}
+
+pub(crate) fn find_patterns<'a, 'b>(ops: &'a [Op], pattern: &'b [Option<Op>])
+        -> impl Iterator<Item=&'a [Op]> + 'b where 'a: 'b {
+    ops.windows(pattern.len()).filter(move |window| {
+        window.iter().zip(pattern.iter()).all(|(op, expected)| match expected {
+            Some(Op::Push(expected)) => op_data(op).as_ref() == Some(expected),
+            Some(expected) => op == expected,
+            None => op_data(op).is_some(),
+        })
+    })
+}
+
+pub(crate) fn find_pattern<'a>(ops: &'a [Op], pattern: &[Option<Op>]) -> Option<&'a [Op]> {
+    find_patterns(ops, pattern).next()
+}
+
 use num_derive::*;
 
 #[derive(Clone, Debug, Copy, Eq, PartialEq, Ord, PartialOrd, FromPrimitive)]
@@ -346,3 +432,25 @@ pub enum OpCodeType {
 
     OpInvalidOpcode = 0xff,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::hash160;
+
+    #[test]
+    fn test_push_0x81() {
+        // scripts keep pushing 0x81 as data, so existing P2SH addresses don't change
+        let script = Script::new(vec![Op::Push(vec![0x81]), Op::Code(OpCodeType::OpEqual)]);
+        assert_eq!(script.to_vec(), vec![0x01, 0x81, 0x87]);
+        assert_eq!(hash160(&script.to_vec()).to_hex(), "a62d37fb2af6bfa316d54c1305196fbc41472cc1");
+
+        let encode = |encoding| {
+            let mut vec = Vec::new();
+            write_push(&mut vec, &[0x81], encoding).unwrap();
+            vec
+        };
+        assert_eq!(encode(PushEncoding::Minimal), vec![0x01, 0x81]);
+        assert_eq!(encode(PushEncoding::MinimalNumber), vec![OpCodeType::Op1Negate as u8]);
+    }
+}
@@ -0,0 +1,704 @@
+use crate::crypto::Crypto;
+use crate::hash::{single_sha256, double_sha256, hash160};
+use crate::script::{Op, OpCodeType, Script, op_data};
+use crate::serialize::{encode_minimally, encode_int64, vec_to_int64};
+use crate::tx::{Tx, TxOutput};
+use crate::hex_traits::ToHex;
+
+use ripemd160::Ripemd160;
+use sha2::Digest;
+
+
+// Steps through the scripts validating a tx input op by op, recording the stacks after every
+// op, to find out why a spend fails. Follows BCH consensus rules closely enough to debug the
+// covenants of this crate, but doesn't enforce script size, op count or element size limits,
+// and doesn't implement Schnorr multisig, OP_SHA1 or the native introspection opcodes.
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScriptError {
+    StackUnderflow,
+    InvalidStackIndex,
+    InvalidNumber,
+    InvalidSplitRange,
+    InvalidOperandSize,
+    DivByZero,
+    UnbalancedConditional,
+    MinimalIf,
+    OpReturn,
+    VerifyFailed(OpCodeType),
+    UnsupportedOp(OpCodeType),
+    InvalidPubKeyCount,
+    InvalidSigCount,
+    SigMustUseForkId,
+    SignatureCheckFailed(OpCodeType),
+    NegativeLockTime,
+    UnsatisfiedLockTime,
+    InvalidRedeemScript,
+    EvalFalse,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScriptPhase {
+    SigScript,
+    LockingScript,
+    RedeemScript,
+}
+
+// Stacks after an op; ops in unexecuted branches are recorded with executed = false.
+// Conditionals always count as executed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceStep {
+    pub phase: ScriptPhase,
+    pub op_idx: usize,
+    pub op: Op,
+    pub executed: bool,
+    pub stack: Vec<Vec<u8>>,
+    pub alt_stack: Vec<Vec<u8>>,
+}
+
+pub struct ScriptDebugger<'a, C: Crypto> {
+    crypto: &'a C,
+    tx: &'a Tx,
+    input_idx: usize,
+    spent_output: &'a TxOutput,
+    phase: ScriptPhase,
+    script: Script,
+    op_idx: usize,
+    // Script code for signature checks starts after the last executed OP_CODESEPARATOR.
+    code_start: usize,
+    stack: Vec<Vec<u8>>,
+    alt_stack: Vec<Vec<u8>>,
+    exec_stack: Vec<bool>,
+    p2sh_stack: Option<Vec<Vec<u8>>>,
+    trace: Vec<TraceStep>,
+    error: Option<ScriptError>,
+    is_finished: bool,
+}
+
+const LOCK_TIME_THRESHOLD: i64 = 500_000_000;
+const SEQUENCE_DISABLE_FLAG: i64 = 1 << 31;
+const SEQUENCE_TYPE_FLAG: i64 = 1 << 22;
+const SEQUENCE_MASK: i64 = 0xffff;
+const MAX_PUB_KEYS_PER_MULTISIG: i64 = 20;
+
+fn cast_to_bool(data: &[u8]) -> bool {
+    match data.split_last() {
+        Some((&last, rest)) => rest.iter().any(|&byte| byte != 0) || (last != 0 && last != 0x80),
+        None => false,
+    }
+}
+
+fn to_num(data: &[u8], max_len: usize) -> Result<i64, ScriptError> {
+    let mut minimal = data.to_vec();
+    encode_minimally(&mut minimal);
+    if data.len() > max_len || minimal.len() != data.len() {
+        return Err(ScriptError::InvalidNumber);
+    }
+    vec_to_int64(data).ok_or(ScriptError::InvalidNumber)
+}
+
+// Script numbers are symmetric, so i64::MIN is out of range too.
+fn from_num(num: Option<i64>) -> Result<Vec<u8>, ScriptError> {
+    match num {
+        Some(num) if num != i64::MIN => Ok(encode_int64(num)),
+        _ => Err(ScriptError::InvalidNumber),
+    }
+}
+
+fn is_p2sh(script: &Script) -> bool {
+    match script.ops() {
+        [Op::Code(OpCodeType::OpHash160), Op::Push(hash), Op::Code(OpCodeType::OpEqual)] => hash.len() == 20,
+        _ => false,
+    }
+}
+
+fn fmt_stack(stack: &[Vec<u8>]) -> String {
+    let items = stack.iter()
+        .map(|item| if item.is_empty() { "<empty>".to_string() } else { item.to_hex() })
+        .collect::<Vec<_>>();
+    format!("[{}]", items.join(", "))
+}
+
+fn fmt_op(op: &Op) -> String {
+    match op {
+        Op::Push(vec) => format!("PUSH {}", vec.to_hex()),
+        Op::Code(code) => format!("{:?}", code),
+    }
+}
+
+impl std::fmt::Display for TraceStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?} {:3}: {}", self.phase, self.op_idx, fmt_op(&self.op))?;
+        if !self.executed {
+            return writeln!(f, " (skipped)");
+        }
+        writeln!(f)?;
+        writeln!(f, "    stack: {}", fmt_stack(&self.stack))?;
+        writeln!(f, "    alt:   {}", fmt_stack(&self.alt_stack))
+    }
+}
+
+impl<'a, C: Crypto> ScriptDebugger<'a, C> {
+    pub fn new(crypto: &'a C, tx: &'a Tx, input_idx: usize, spent_output: &'a TxOutput) -> Self {
+        ScriptDebugger {
+            crypto,
+            tx,
+            input_idx,
+            spent_output,
+            phase: ScriptPhase::SigScript,
+            script: tx.inputs()[input_idx].script().clone(),
+            op_idx: 0,
+            code_start: 0,
+            stack: Vec::new(),
+            alt_stack: Vec::new(),
+            exec_stack: Vec::new(),
+            p2sh_stack: None,
+            trace: Vec::new(),
+            error: None,
+            is_finished: false,
+        }
+    }
+
+    pub fn stack(&self) -> &[Vec<u8>] {
+        &self.stack
+    }
+
+    pub fn alt_stack(&self) -> &[Vec<u8>] {
+        &self.alt_stack
+    }
+
+    pub fn trace(&self) -> &[TraceStep] {
+        &self.trace
+    }
+
+    pub fn error(&self) -> Option<&ScriptError> {
+        self.error.as_ref()
+    }
+
+    // Script and index of the next op, or of the op which failed.
+    pub fn position(&self) -> (ScriptPhase, usize) {
+        (self.phase, self.op_idx)
+    }
+
+    pub fn format_trace(&self) -> String {
+        let mut formatted = self.trace.iter().map(ToString::to_string).collect::<String>();
+        if let Some(error) = &self.error {
+            let op = self.script.ops().get(self.op_idx).map(fmt_op).unwrap_or_default();
+            formatted += &format!("{:?} {:3}: {} failed: {:?}\n", self.phase, self.op_idx, op, error);
+        }
+        formatted
+    }
+
+    // Executes the next op; returns false once all scripts ran.
+    pub fn step(&mut self) -> Result<bool, ScriptError> {
+        if let Some(error) = &self.error {
+            return Err(error.clone());
+        }
+        let result = self.next_script()
+            .and_then(|has_op| if has_op { self.execute_next().map(|_| true) } else { Ok(false) });
+        if let Err(error) = &result {
+            self.error = Some(error.clone());
+        }
+        result
+    }
+
+    // Runs all remaining ops and checks that the input validates.
+    pub fn run(&mut self) -> Result<(), ScriptError> {
+        while self.step()? {}
+        if !self.stack.last().map(|top| cast_to_bool(top)).unwrap_or(false) {
+            self.error = Some(ScriptError::EvalFalse);
+            return Err(ScriptError::EvalFalse);
+        }
+        Ok(())
+    }
+
+    // Moves on to the next script once the current one is done; false if there is none.
+    fn next_script(&mut self) -> Result<bool, ScriptError> {
+        while self.op_idx >= self.script.ops().len() {
+            if self.is_finished {
+                return Ok(false);
+            }
+            if !self.exec_stack.is_empty() {
+                return Err(ScriptError::UnbalancedConditional);
+            }
+            match self.phase {
+                ScriptPhase::SigScript => {
+                    if is_p2sh(&self.spent_output.script) {
+                        self.p2sh_stack = Some(self.stack.clone());
+                    }
+                    self.phase = ScriptPhase::LockingScript;
+                    self.script = self.spent_output.script.clone();
+                },
+                ScriptPhase::LockingScript if self.p2sh_stack.is_some() => {
+                    if !self.stack.last().map(|top| cast_to_bool(top)).unwrap_or(false) {
+                        return Err(ScriptError::EvalFalse);
+                    }
+                    self.stack = self.p2sh_stack.take().unwrap_or_default();
+                    let redeem_script = self.pop()?;
+                    self.phase = ScriptPhase::RedeemScript;
+                    self.script = Script::from_serialized(&redeem_script)
+                        .ok_or(ScriptError::InvalidRedeemScript)?;
+                },
+                _ => {
+                    self.is_finished = true;
+                    return Ok(false);
+                },
+            }
+            self.op_idx = 0;
+            self.code_start = 0;
+            self.alt_stack.clear();
+        }
+        Ok(true)
+    }
+
+    fn execute_next(&mut self) -> Result<(), ScriptError> {
+        let op = self.script.ops()[self.op_idx].clone();
+        let mut executed = !self.exec_stack.contains(&false);
+        match &op {
+            Op::Code(code @ OpCodeType::OpIf) | Op::Code(code @ OpCodeType::OpNotIf) => {
+                let mut condition = false;
+                if executed {
+                    let top = self.pop()?;
+                    if top.len() > 1 || (top.len() == 1 && top[0] != 1) {
+                        return Err(ScriptError::MinimalIf);
+                    }
+                    condition = cast_to_bool(&top) == (*code == OpCodeType::OpIf);
+                }
+                self.exec_stack.push(condition);
+                executed = true;
+            },
+            Op::Code(OpCodeType::OpElse) => {
+                let branch = self.exec_stack.last_mut().ok_or(ScriptError::UnbalancedConditional)?;
+                *branch = !*branch;
+                executed = true;
+            },
+            Op::Code(OpCodeType::OpEndIf) => {
+                self.exec_stack.pop().ok_or(ScriptError::UnbalancedConditional)?;
+                executed = true;
+            },
+            op if executed => self.execute(op)?,
+            _ => {},
+        }
+        self.trace.push(TraceStep {
+            phase: self.phase,
+            op_idx: self.op_idx,
+            op,
+            executed,
+            stack: self.stack.clone(),
+            alt_stack: self.alt_stack.clone(),
+        });
+        self.op_idx += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<Vec<u8>, ScriptError> {
+        self.stack.pop().ok_or(ScriptError::StackUnderflow)
+    }
+
+    fn pop_num(&mut self) -> Result<i64, ScriptError> {
+        to_num(&self.pop()?, 8)
+    }
+
+    // Element `depth` items below the top.
+    fn peek(&self, depth: usize) -> Result<&Vec<u8>, ScriptError> {
+        self.stack.iter().rev().nth(depth).ok_or(ScriptError::StackUnderflow)
+    }
+
+    fn verify(&mut self, code: OpCodeType) -> Result<(), ScriptError> {
+        if cast_to_bool(&self.pop()?) {
+            Ok(())
+        } else {
+            Err(ScriptError::VerifyFailed(code))
+        }
+    }
+
+    fn script_code(&self) -> Script {
+        if self.code_start == 0 {
+            self.script.clone()
+        } else {
+            Script::new(self.script.ops()[self.code_start..].to_vec())
+        }
+    }
+
+    fn check_tx_sig(&self, sig: &[u8], pub_key: &[u8]) -> Result<bool, ScriptError> {
+        let (&sighash_type, sig) = match sig.split_last() {
+            Some(split) => split,
+            None => return Ok(false),
+        };
+        if sighash_type & 0x40 == 0 {
+            return Err(ScriptError::SigMustUseForkId);
+        }
+        let sighash = self.tx.pre_image(self.input_idx, self.spent_output, self.script_code(),
+                                        sighash_type as u32).sighash();
+        Ok(self.crypto.verify(&sighash, sig, pub_key))
+    }
+
+    fn check_multisig(&mut self, code: OpCodeType) -> Result<bool, ScriptError> {
+        let num_pub_keys = self.pop_num()?;
+        if !(0..=MAX_PUB_KEYS_PER_MULTISIG).contains(&num_pub_keys) {
+            return Err(ScriptError::InvalidPubKeyCount);
+        }
+        let mut pub_keys = (0..num_pub_keys).map(|_| self.pop()).collect::<Result<Vec<_>, _>>()?;
+        pub_keys.reverse();
+        let num_sigs = self.pop_num()?;
+        if num_sigs < 0 || num_sigs > num_pub_keys {
+            return Err(ScriptError::InvalidSigCount);
+        }
+        let mut sigs = (0..num_sigs).map(|_| self.pop()).collect::<Result<Vec<_>, _>>()?;
+        sigs.reverse();
+        if !self.pop()?.is_empty() {
+            // a non-empty dummy selects Schnorr multisig
+            return Err(ScriptError::UnsupportedOp(code));
+        }
+        let mut pub_keys = pub_keys.iter();
+        let mut is_valid = true;
+        for sig in sigs.iter() {
+            let mut found = false;
+            for pub_key in &mut pub_keys {
+                if self.check_tx_sig(sig, pub_key)? {
+                    found = true;
+                    break;
+                }
+            }
+            if !found {
+                is_valid = false;
+                break;
+            }
+        }
+        if !is_valid && sigs.iter().any(|sig| !sig.is_empty()) {
+            return Err(ScriptError::SignatureCheckFailed(code));
+        }
+        Ok(is_valid)
+    }
+
+    fn execute(&mut self, op: &Op) -> Result<(), ScriptError> {
+        use crate::script::OpCodeType::*;
+        if let Some(data) = op_data(op) {
+            self.stack.push(data);
+            return Ok(());
+        }
+        let code = match op {
+            Op::Code(code) => *code,
+            Op::Push(_) => unreachable!(),
+        };
+        match code {
+            Op0 => self.stack.push(vec![]),
+            OpNop | OpNop1 | OpNop4 | OpNop5 | OpNop6 | OpNop7 | OpNop8 | OpNop9 | OpNop10 => {},
+            OpVerify => self.verify(code)?,
+            OpReturn => return Err(ScriptError::OpReturn),
+
+            OpToAltStack => {
+                let item = self.pop()?;
+                self.alt_stack.push(item);
+            },
+            OpFromAltStack => {
+                let item = self.alt_stack.pop().ok_or(ScriptError::StackUnderflow)?;
+                self.stack.push(item);
+            },
+            Op2Drop => {
+                self.pop()?;
+                self.pop()?;
+            },
+            Op2Dup | Op3Dup | Op2Over => {
+                let (num, depth) = match code {
+                    Op2Dup => (2, 1),
+                    Op3Dup => (3, 2),
+                    _ => (2, 3),
+                };
+                for _ in 0..num {
+                    let item = self.peek(depth)?.clone();
+                    self.stack.push(item);
+                }
+            },
+            Op2Rot => {
+                let len = self.stack.len();
+                self.peek(5)?;
+                let items = self.stack.drain(len - 6..len - 4).collect::<Vec<_>>();
+                self.stack.extend(items);
+            },
+            Op2Swap => {
+                let len = self.stack.len();
+                self.peek(3)?;
+                self.stack[len - 4..].rotate_left(2);
+            },
+            OpIfDup => {
+                let top = self.peek(0)?.clone();
+                if cast_to_bool(&top) {
+                    self.stack.push(top);
+                }
+            },
+            OpDepth => self.stack.push(encode_int64(self.stack.len() as i64)),
+            OpDrop => { self.pop()?; },
+            OpDup => self.stack.push(self.peek(0)?.clone()),
+            OpNip => {
+                self.peek(1)?;
+                let len = self.stack.len();
+                self.stack.remove(len - 2);
+            },
+            OpOver => self.stack.push(self.peek(1)?.clone()),
+            OpPick | OpRoll => {
+                let depth = self.pop_num()?;
+                if depth < 0 || depth as usize >= self.stack.len() {
+                    return Err(ScriptError::InvalidStackIndex);
+                }
+                let idx = self.stack.len() - 1 - depth as usize;
+                let item = if code == OpPick { self.stack[idx].clone() } else { self.stack.remove(idx) };
+                self.stack.push(item);
+            },
+            OpRot => {
+                let len = self.stack.len();
+                self.peek(2)?;
+                self.stack[len - 3..].rotate_left(1);
+            },
+            OpSwap => {
+                let len = self.stack.len();
+                self.peek(1)?;
+                self.stack.swap(len - 2, len - 1);
+            },
+            OpTuck => {
+                let top = self.peek(0)?.clone();
+                self.peek(1)?;
+                let len = self.stack.len();
+                self.stack.insert(len - 2, top);
+            },
+
+            OpCat => {
+                let second = self.pop()?;
+                let mut first = self.pop()?;
+                first.extend(second);
+                self.stack.push(first);
+            },
+            OpSplit => {
+                let pos = self.pop_num()?;
+                let mut data = self.pop()?;
+                if pos < 0 || pos as usize > data.len() {
+                    return Err(ScriptError::InvalidSplitRange);
+                }
+                let right = data.split_off(pos as usize);
+                self.stack.push(data);
+                self.stack.push(right);
+            },
+            OpNum2Bin => {
+                let size = self.pop_num()?;
+                let mut num = self.pop()?;
+                encode_minimally(&mut num);
+                if size < 0 || (size as usize) < num.len() {
+                    return Err(ScriptError::InvalidOperandSize);
+                }
+                let sign_bit = num.last_mut().map(|last| {
+                    let sign_bit = *last & 0x80;
+                    *last &= 0x7f;
+                    sign_bit
+                }).unwrap_or(0);
+                num.resize(size as usize, 0);
+                if let Some(last) = num.last_mut() {
+                    *last |= sign_bit;
+                }
+                self.stack.push(num);
+            },
+            OpBin2Num => {
+                let mut num = self.pop()?;
+                encode_minimally(&mut num);
+                if num.len() > 8 {
+                    return Err(ScriptError::InvalidNumber);
+                }
+                self.stack.push(num);
+            },
+            OpSize => self.stack.push(encode_int64(self.peek(0)?.len() as i64)),
+
+            OpAnd | OpOr | OpXor => {
+                let second = self.pop()?;
+                let first = self.pop()?;
+                if first.len() != second.len() {
+                    return Err(ScriptError::InvalidOperandSize);
+                }
+                let result = first.iter().zip(second.iter()).map(|(&a, &b)| match code {
+                    OpAnd => a & b,
+                    OpOr => a | b,
+                    _ => a ^ b,
+                }).collect();
+                self.stack.push(result);
+            },
+            OpEqual | OpEqualVerify => {
+                let is_equal = self.pop()? == self.pop()?;
+                self.stack.push(if is_equal { vec![1] } else { vec![] });
+                if code == OpEqualVerify {
+                    self.verify(code)?;
+                }
+            },
+
+            Op1Add | Op1Sub | OpNegate | OpAbs | OpNot | Op0NotEqual => {
+                let num = self.pop_num()?;
+                let result = match code {
+                    Op1Add => num.checked_add(1),
+                    Op1Sub => num.checked_sub(1),
+                    OpNegate => Some(-num),
+                    OpAbs => Some(num.abs()),
+                    OpNot => Some((num == 0) as i64),
+                    _ => Some((num != 0) as i64),
+                };
+                self.stack.push(from_num(result)?);
+            },
+            OpAdd | OpSub | OpMul | OpDiv | OpMod | OpBoolAnd | OpBoolOr | OpNumEqual |
+            OpNumEqualVerify | OpNumNotEqual | OpLessThan | OpGreaterThan | OpLessThanOrEqual |
+            OpGreaterThanOrEqual | OpMin | OpMax => {
+                let b = self.pop_num()?;
+                let a = self.pop_num()?;
+                if (code == OpDiv || code == OpMod) && b == 0 {
+                    return Err(ScriptError::DivByZero);
+                }
+                let result = match code {
+                    OpAdd => a.checked_add(b),
+                    OpSub => a.checked_sub(b),
+                    OpMul => a.checked_mul(b),
+                    OpDiv => a.checked_div(b),
+                    OpMod => a.checked_rem(b),
+                    OpBoolAnd => Some((a != 0 && b != 0) as i64),
+                    OpBoolOr => Some((a != 0 || b != 0) as i64),
+                    OpNumEqual | OpNumEqualVerify => Some((a == b) as i64),
+                    OpNumNotEqual => Some((a != b) as i64),
+                    OpLessThan => Some((a < b) as i64),
+                    OpGreaterThan => Some((a > b) as i64),
+                    OpLessThanOrEqual => Some((a <= b) as i64),
+                    OpGreaterThanOrEqual => Some((a >= b) as i64),
+                    OpMin => Some(a.min(b)),
+                    _ => Some(a.max(b)),
+                };
+                self.stack.push(from_num(result)?);
+                if code == OpNumEqualVerify {
+                    self.verify(code)?;
+                }
+            },
+            OpWithin => {
+                let max = self.pop_num()?;
+                let min = self.pop_num()?;
+                let num = self.pop_num()?;
+                self.stack.push(from_num(Some((min <= num && num < max) as i64))?);
+            },
+
+            OpRipemd160 => {
+                let data = self.pop()?;
+                self.stack.push(Ripemd160::digest(&data).to_vec());
+            },
+            OpSha256 => {
+                let data = self.pop()?;
+                self.stack.push(single_sha256(&data).to_vec());
+            },
+            OpHash160 => {
+                let data = self.pop()?;
+                self.stack.push(hash160(&data).to_vec());
+            },
+            OpHash256 => {
+                let data = self.pop()?;
+                self.stack.push(double_sha256(&data).to_vec());
+            },
+            OpCodeSeparator => self.code_start = self.op_idx + 1,
+            OpCheckSig | OpCheckSigVerify => {
+                let pub_key = self.pop()?;
+                let sig = self.pop()?;
+                let is_valid = self.check_tx_sig(&sig, &pub_key)?;
+                if !is_valid && !sig.is_empty() {
+                    return Err(ScriptError::SignatureCheckFailed(code));
+                }
+                self.stack.push(if is_valid { vec![1] } else { vec![] });
+                if code == OpCheckSigVerify {
+                    self.verify(code)?;
+                }
+            },
+            OpCheckMultiSig | OpCheckMultiSigVerify => {
+                let is_valid = self.check_multisig(code)?;
+                self.stack.push(if is_valid { vec![1] } else { vec![] });
+                if code == OpCheckMultiSigVerify {
+                    self.verify(code)?;
+                }
+            },
+            OpCheckDataSig | OpCheckDataSigVerify => {
+                let pub_key = self.pop()?;
+                let message = self.pop()?;
+                let sig = self.pop()?;
+                let is_valid = !sig.is_empty() &&
+                    self.crypto.verify(&single_sha256(&message), &sig, &pub_key);
+                if !is_valid && !sig.is_empty() {
+                    return Err(ScriptError::SignatureCheckFailed(code));
+                }
+                self.stack.push(if is_valid { vec![1] } else { vec![] });
+                if code == OpCheckDataSigVerify {
+                    self.verify(code)?;
+                }
+            },
+
+            OpCheckLockTimeVerify => {
+                let lock_time = to_num(self.peek(0)?, 5)?;
+                let tx_lock_time = self.tx.lock_time() as i64;
+                if lock_time < 0 {
+                    return Err(ScriptError::NegativeLockTime);
+                }
+                if (lock_time < LOCK_TIME_THRESHOLD) != (tx_lock_time < LOCK_TIME_THRESHOLD) ||
+                        lock_time > tx_lock_time ||
+                        self.tx.inputs()[self.input_idx].sequence == 0xffff_ffff {
+                    return Err(ScriptError::UnsatisfiedLockTime);
+                }
+            },
+            OpCheckSequenceVerify => {
+                let sequence = to_num(self.peek(0)?, 5)?;
+                if sequence < 0 {
+                    return Err(ScriptError::NegativeLockTime);
+                }
+                if sequence & SEQUENCE_DISABLE_FLAG == 0 {
+                    let tx_sequence = self.tx.inputs()[self.input_idx].sequence as i64;
+                    let mask = SEQUENCE_TYPE_FLAG | SEQUENCE_MASK;
+                    if self.tx.version() < 2 ||
+                            tx_sequence & SEQUENCE_DISABLE_FLAG != 0 ||
+                            (sequence & SEQUENCE_TYPE_FLAG) != (tx_sequence & SEQUENCE_TYPE_FLAG) ||
+                            sequence & mask > tx_sequence & mask {
+                        return Err(ScriptError::UnsatisfiedLockTime);
+                    }
+                }
+            },
+
+            _ => return Err(ScriptError::UnsupportedOp(code)),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "secp256k1"))]
+mod tests {
+    use super::*;
+    use crate::address::{Address, AddressType};
+    use crate::crypto::secp256k1::CryptoSecp256k1;
+    use crate::outputs::P2PKHOutput;
+    use crate::tx::TxOutpoint;
+    use crate::unsigned_tx::{UnsignedTx, UnsignedInput, Output};
+
+    #[test]
+    fn test_script_debugger() {
+        let crypto = CryptoSecp256k1::new();
+        let secret_key = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let pub_key = crypto.secret_to_pub_key(&secret_key).serialize();
+        let address = Address::from_serialized_pub_key("bitcoincash", AddressType::P2PKH, &pub_key);
+        let spent_output = P2PKHOutput { value: 10_000, address: address.clone() };
+        let mut tx_build = UnsignedTx::new_simple();
+        tx_build.add_input(UnsignedInput::new(
+            TxOutpoint { tx_hash: [0x11; 32], vout: 0 },
+            Box::new(spent_output.clone()),
+            0xffff_ffff,
+        ));
+        tx_build.add_output(P2PKHOutput { value: 9_000, address }.to_output());
+        let tx = tx_build.sign_with(&crypto, &secret_key).unwrap();
+        let spent_output = spent_output.to_output();
+
+        let mut debugger = ScriptDebugger::new(&crypto, &tx, 0, &spent_output);
+        assert_eq!(debugger.run(), Ok(()));
+        assert_eq!(debugger.trace().len(), 7);
+        assert_eq!(debugger.trace()[2].stack.len(), 3);
+        assert_eq!(debugger.stack(), &[vec![1]]);
+        assert!(debugger.format_trace().contains("LockingScript   4: OpCheckSig"));
+
+        let wrong_output = TxOutput::new(spent_output.value + 1, spent_output.script.clone());
+        let mut debugger = ScriptDebugger::new(&crypto, &tx, 0, &wrong_output);
+        assert_eq!(debugger.run(), Err(ScriptError::SignatureCheckFailed(OpCodeType::OpCheckSig)));
+        assert_eq!(debugger.position(), (ScriptPhase::LockingScript, 4));
+        assert!(debugger.format_trace().ends_with("OpCheckSig failed: SignatureCheckFailed(OpCheckSig)\n"));
+    }
+}
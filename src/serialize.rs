@@ -1,4 +1,4 @@
-use std::io;
+use std::io::{self, Read};
 use byteorder::{LittleEndian, WriteBytesExt, ReadBytesExt};
 
 
@@ -15,7 +15,7 @@ pub fn write_var_int<W: io::Write>(write: &mut W, number: u64) -> io::Result<()>
         },
         _ => {
             write.write_all(b"\xff")?;
-            write.write_u64::<LittleEndian>(number as u64)?
+            write.write_u64::<LittleEndian>(number)?
         },
     }
     Ok(())
@@ -28,21 +28,105 @@ pub fn var_int_to_vec(number: u64) -> Vec<u8> {
 }
 
 pub fn read_var_int<R: io::Read>(read: &mut R) -> io::Result<u64> {
-    let first_byte = read.read_u8()?;
-    match first_byte {
-        0 ..= 0xfc => Ok(first_byte as u64),
-        0xfd       => Ok(read.read_u16::<LittleEndian>()? as u64),
-        0xfe       => Ok(read.read_u32::<LittleEndian>()? as u64),
-        0xff       => Ok(read.read_u64::<LittleEndian>()? as u64),
+    Ok(read_var_int_with_min(read)?.0)
+}
+
+// Number and the smallest number its encoding is minimal for.
+fn read_var_int_with_min<R: io::Read>(read: &mut R) -> io::Result<(u64, u64)> {
+    Ok(match read.read_u8()? {
+        first_byte @ 0 ..= 0xfc => (first_byte as u64, 0),
+        0xfd => (read.read_u16::<LittleEndian>()? as u64, 0xfd),
+        0xfe => (read.read_u32::<LittleEndian>()? as u64, 0x10000),
+        0xff => (read.read_u64::<LittleEndian>()?, 0x1_0000_0000),
+    })
+}
+
+// Like read_var_int_strict, for readers without a position; used for all consensus and
+// network data.
+pub fn read_var_int_minimal<R: io::Read>(read: &mut R) -> io::Result<u64> {
+    let (number, min) = read_var_int_with_min(read)?;
+    if number < min {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Non-minimal var int"));
     }
+    Ok(number)
 }
 
-pub fn read_var_str<R: io::Read>(read: &mut R) -> io::Result<Vec<u8>> {
-    let mut vec = vec![0; read_var_int(read)? as usize];
-    read.read_exact(&mut vec)?;
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    UnexpectedEnd,
+    NonMinimalVarInt,
+    TrailingData,
+}
+
+// Deserialization error at byte `offset` of the input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub offset: u64,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.kind {
+            ErrorKind::UnexpectedEnd => write!(f, "Unexpected end of data at offset {}", self.offset),
+            ErrorKind::NonMinimalVarInt => write!(f, "Non-minimal var int at offset {}", self.offset),
+            ErrorKind::TrailingData => write!(f, "Trailing data at offset {}", self.offset),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        let kind = match err.kind {
+            ErrorKind::UnexpectedEnd => io::ErrorKind::UnexpectedEof,
+            ErrorKind::NonMinimalVarInt | ErrorKind::TrailingData => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+// Like read_var_int, but rejects encodings which aren't the shortest possible one, e.g.
+// 0xfd 0x01 0x00 for 1.
+pub fn read_var_int_strict<T: AsRef<[u8]>>(cur: &mut io::Cursor<T>) -> Result<u64, Error> {
+    let offset = cur.position();
+    let (number, min) = read_var_int_with_min(cur)
+        .map_err(|_| Error { kind: ErrorKind::UnexpectedEnd, offset })?;
+    if number < min {
+        cur.set_position(offset);
+        return Err(Error { kind: ErrorKind::NonMinimalVarInt, offset });
+    }
+    Ok(number)
+}
+
+// Reads exactly `len` bytes. The buffer grows with the data actually read, so a length
+// claimed by untrusted input can't force a large allocation.
+pub fn read_bytes<R: io::Read>(read: &mut R, len: u64) -> io::Result<Vec<u8>> {
+    let mut vec = Vec::new();
+    read.take(len).read_to_end(&mut vec)?;
+    if vec.len() as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
     Ok(vec)
 }
 
+pub fn read_var_str<R: io::Read>(read: &mut R) -> io::Result<Vec<u8>> {
+    let len = read_var_int(read)?;
+    read_bytes(read, len)
+}
+
+// Like read_var_str, but fails without reading the data if it's longer than `max_len`, and
+// rejects non-minimal lengths.
+pub fn read_var_str_max<R: io::Read>(read: &mut R, max_len: usize) -> io::Result<Vec<u8>> {
+    let len = read_var_int_minimal(read)?;
+    if len > max_len as u64 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("Length {} exceeds maximum of {}", len, max_len)));
+    }
+    read_bytes(read, len)
+}
+
 pub fn write_var_str<W: io::Write>(write: &mut W, string: &[u8]) -> io::Result<()> {
     write_var_int(write, string.len() as u64)?;
     write.write_all(string)?;
@@ -97,14 +181,48 @@ pub fn encode_int(int: i32) -> Vec<u8> {
     vec
 }
 
-pub fn encode_int_n(int: i32, n_bytes: usize) -> Vec<u8> {
-    let mut vec = Vec::with_capacity(n_bytes);
-    vec.write_i32::<LittleEndian>(int.abs()).unwrap();
-    vec.extend((vec.len()..n_bytes-1).map(|_| 0));
-    vec.push(if int < 0 { 0x80 } else { 0 });
+// Script numbers are signed 64 bit integers
+pub const MAX_SCRIPT_INT: u64 = i64::MAX as u64;
+
+pub fn encode_int64(int: i64) -> Vec<u8> {
+    let mut vec = Vec::new();
+    vec.write_u64::<LittleEndian>(int.unsigned_abs()).unwrap();
+    vec.write_u8(if int < 0 { 0x80 } else { 0 }).unwrap();
+    encode_minimally(&mut vec);
     vec
 }
 
+// Script number doesn't fit into the requested fixed width.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IntWidthError {
+    pub int: i64,
+    pub n_bytes: usize,
+}
+
+// Script number padded to exactly `n_bytes`, as produced by OP_NUM2BIN: the magnitude in
+// little endian with the sign in the top bit of the last byte.
+pub fn encode_int64_n(int: i64, n_bytes: usize) -> Result<Vec<u8>, IntWidthError> {
+    let magnitude = int.unsigned_abs();
+    let fits = match n_bytes {
+        0 => magnitude == 0,
+        1 ..= 8 => magnitude < 1 << (8 * n_bytes - 1),
+        _ => true,
+    };
+    if !fits {
+        return Err(IntWidthError { int, n_bytes });
+    }
+    let mut vec = magnitude.to_le_bytes().to_vec();
+    vec.resize(n_bytes, 0);
+    if int < 0 {
+        vec[n_bytes - 1] |= 0x80;
+    }
+    Ok(vec)
+}
+
+pub fn encode_int_n(int: i32, n_bytes: usize) -> Result<Vec<u8>, IntWidthError> {
+    encode_int64_n(int as i64, n_bytes)
+}
+
 pub fn encode_bool(b: bool) -> Vec<u8> {
     if b {
         vec![0x01]
@@ -131,3 +249,70 @@ pub fn vec_to_int(vec: &[u8]) -> i32 {
     }
     int
 }
+
+// Decodes a script number of at most 8 bytes, None if it's longer.
+pub fn vec_to_int64(vec: &[u8]) -> Option<i64> {
+    if vec.len() > 8 {
+        return None;
+    }
+    if vec.is_empty() {
+        return Some(0);
+    }
+    let mut int = 0i64;
+    for (i, value) in vec.iter().enumerate() {
+        int |= (*value as i64) << (8 * i);
+    }
+    let last = vec[vec.len() - 1];
+    if last & 0x80 != 0 {
+        let sign_mask = 0x80i64 << (8 * (vec.len() - 1));
+        Some(-(int & !sign_mask))
+    } else {
+        Some(int)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_var_int_strict() {
+        for &number in &[0, 0xfc, 0xfd, 0xffff, 0x10000, 0xffff_ffff, 0x1_0000_0000] {
+            let mut cur = io::Cursor::new(var_int_to_vec(number));
+            assert_eq!(read_var_int_strict(&mut cur), Ok(number));
+        }
+        let mut cur = io::Cursor::new(vec![0x00, 0xfd, 0x01, 0x00]);
+        assert_eq!(read_var_int_strict(&mut cur), Ok(0));
+        assert_eq!(read_var_int_strict(&mut cur), Err(Error { kind: ErrorKind::NonMinimalVarInt, offset: 1 }));
+        assert_eq!(read_var_int(&mut cur).unwrap(), 1);
+        let mut cur = io::Cursor::new(vec![0xfe, 0x00]);
+        assert_eq!(read_var_int_strict(&mut cur), Err(Error { kind: ErrorKind::UnexpectedEnd, offset: 0 }));
+
+        // txs with non-minimal counts are rejected
+        let mut tx = Vec::new();
+        crate::tx::Tx::new(1, vec![], vec![], 0).write_to_stream(&mut tx).unwrap();
+        assert!(crate::tx::Tx::read_from_stream(&mut io::Cursor::new(&tx)).is_ok());
+        let non_minimal = [&tx[..4], &[0xfd, 0x00, 0x00], &tx[5..]].concat();
+        assert_eq!(crate::tx::Tx::read_from_stream(&mut io::Cursor::new(&non_minimal)).unwrap_err().kind(),
+                   io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_vec_to_int64() {
+        assert_eq!(vec_to_int64(&[]), Some(0));
+        assert_eq!(vec_to_int64(&[0x05, 0x00, 0x00, 0x80]), Some(-5));
+        assert_eq!(vec_to_int64(&[0xff; 8]), Some(-i64::MAX));
+        assert_eq!(vec_to_int64(&[0x01; 9]), None);
+    }
+
+    #[test]
+    fn test_encode_int_n() {
+        assert_eq!(encode_int_n(-5, 4), Ok(vec![0x05, 0x00, 0x00, 0x80]));
+        assert_eq!(encode_int_n(0x7f, 1), Ok(vec![0x7f]));
+        assert_eq!(encode_int_n(0x80, 1), Err(IntWidthError { int: 0x80, n_bytes: 1 }));
+        assert_eq!(encode_int_n(0, 0), Ok(vec![]));
+        assert_eq!(encode_int64_n(-0x12_3456_789a, 6), Ok(vec![0x9a, 0x78, 0x56, 0x34, 0x12, 0x80]));
+        assert_eq!(encode_int64_n(i64::MAX, 10).unwrap()[7..], [0x7f, 0x00, 0x00]);
+        assert!(encode_int64_n(i64::MIN, 8).is_err());
+    }
+}
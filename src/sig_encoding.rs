@@ -0,0 +1,112 @@
+use num::BigUint;
+
+
+const GROUP_ORDER: &[u8] = b"fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141";
+const SCHNORR_SIGNATURE_SIZE: usize = 64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SigEncodingError {
+    NotStrictDer,
+    SOutOfRange,
+}
+
+// BIP66 strict DER check of a signature without sighash byte:
+// 0x30 <len> 0x02 <len r> <r> 0x02 <len s> <s>, with minimally encoded positive r and s.
+pub fn is_strict_der(sig: &[u8]) -> bool {
+    if sig.len() < 8 || sig.len() > 72 {
+        return false;
+    }
+    if sig[0] != 0x30 || sig[1] as usize != sig.len() - 2 || sig[2] != 0x02 {
+        return false;
+    }
+    let len_r = sig[3] as usize;
+    if len_r == 0 || 5 + len_r >= sig.len() {
+        return false;
+    }
+    let len_s = sig[5 + len_r] as usize;
+    if len_s == 0 || len_r + len_s + 6 != sig.len() || sig[4 + len_r] != 0x02 {
+        return false;
+    }
+    let is_minimal_positive = |int: &[u8]| {
+        int[0] & 0x80 == 0 && !(int.len() > 1 && int[0] == 0 && int[1] & 0x80 == 0)
+    };
+    is_minimal_positive(&sig[4..4 + len_r]) && is_minimal_positive(&sig[6 + len_r..])
+}
+
+fn der_s(sig: &[u8]) -> &[u8] {
+    let len_r = sig[3] as usize;
+    &sig[6 + len_r..]
+}
+
+// Whether `sig` is strict DER with s at most half the group order, as required by the LOW_S
+// standardness rule.
+pub fn is_low_s(sig: &[u8]) -> bool {
+    if !is_strict_der(sig) {
+        return false;
+    }
+    let group_order = BigUint::parse_bytes(GROUP_ORDER, 16).unwrap();
+    BigUint::from_bytes_be(der_s(sig)) <= group_order >> 1
+}
+
+// Replaces a high s by n - s, which is an equally valid signature.
+pub fn normalize_low_s(sig: &[u8]) -> Result<Vec<u8>, SigEncodingError> {
+    if !is_strict_der(sig) {
+        return Err(SigEncodingError::NotStrictDer);
+    }
+    let group_order = BigUint::parse_bytes(GROUP_ORDER, 16).unwrap();
+    let s = BigUint::from_bytes_be(der_s(sig));
+    if s == BigUint::from(0u32) || s >= group_order {
+        return Err(SigEncodingError::SOutOfRange);
+    }
+    if s <= &group_order >> 1 {
+        return Ok(sig.to_vec());
+    }
+    let mut s = (group_order - s).to_bytes_be();
+    if s[0] & 0x80 != 0 {
+        s.insert(0, 0);
+    }
+    let len_r = sig[3] as usize;
+    let mut normalized = vec![0x30, (len_r + s.len() + 4) as u8];
+    normalized.extend_from_slice(&sig[2..4 + len_r]);
+    normalized.push(0x02);
+    normalized.push(s.len() as u8);
+    normalized.extend_from_slice(&s);
+    Ok(normalized)
+}
+
+// Brings a tx signature without sighash byte into standard form: empty and Schnorr signatures
+// are kept, ECDSA signatures must be strict DER and are normalized to low s.
+pub fn canonical_signature(sig: &[u8]) -> Result<Vec<u8>, SigEncodingError> {
+    if sig.is_empty() || sig.len() == SCHNORR_SIGNATURE_SIZE {
+        return Ok(sig.to_vec());
+    }
+    normalize_low_s(sig)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_low_s() {
+        let high_s = hex::decode("fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364140").unwrap();
+        let mut sig = vec![0x30, 0x26, 0x02, 0x01, 0x01, 0x02, 0x21, 0x00];
+        sig.extend_from_slice(&high_s);
+        assert!(is_strict_der(&sig));
+        assert!(!is_low_s(&sig));
+        let normalized = normalize_low_s(&sig).unwrap();
+        assert_eq!(normalized, vec![0x30, 0x06, 0x02, 0x01, 0x01, 0x02, 0x01, 0x01]);
+        assert!(is_low_s(&normalized));
+        assert_eq!(normalize_low_s(&normalized).unwrap(), normalized);
+
+        // non-minimal r
+        assert!(!is_strict_der(&[0x30, 0x07, 0x02, 0x02, 0x00, 0x01, 0x02, 0x01, 0x01]));
+        // negative s
+        assert!(!is_strict_der(&[0x30, 0x06, 0x02, 0x01, 0x01, 0x02, 0x01, 0x81]));
+        assert_eq!(canonical_signature(&[0x30, 0x01]), Err(SigEncodingError::NotStrictDer));
+        assert_eq!(canonical_signature(&[]), Ok(vec![]));
+        // truncated
+        assert!(!is_low_s(&normalized[..5]));
+        assert!(!is_low_s(&[]));
+    }
+}
@@ -0,0 +1,173 @@
+use crate::address::{Address, AddressType};
+use crate::hash::double_sha256;
+use crate::hex_traits::ToHex;
+use crate::serialize::write_var_int;
+
+use secp256k1::{Secp256k1, Signing, Verification, Message, PublicKey, SecretKey};
+use secp256k1::recovery::{RecoverableSignature, RecoveryId};
+
+
+pub const SIGNED_MESSAGE_MAGIC: &[u8] = b"Bitcoin Signed Message:\n";
+pub const SIGNED_MESSAGE_SIZE: usize = 65;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SignedMessageError {
+    InvalidLength(usize),
+    InvalidHeader(u8),
+    InvalidSignature,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OwnershipProofError {
+    NotOwnAddress,
+    ChallengeMismatch,
+    Expired { timestamp: u64, now: u64 },
+    InvalidSignature,
+}
+
+// Proof that the signer controls a P2PKH address at a point in time, e.g. for an exchange
+// verifying a withdrawal address. The signed statement includes a challenge nonce chosen by
+// the verifier, so a proof can't be replayed to other verifiers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OwnershipProof {
+    pub address: Address,
+    // unix time in seconds
+    pub timestamp: u64,
+    pub challenge: Vec<u8>,
+    pub signature: [u8; SIGNED_MESSAGE_SIZE],
+}
+
+// Digest which is signed: double sha256 of the length prefixed magic and message.
+pub fn signed_message_hash(message: &[u8]) -> [u8; 32] {
+    let mut data = Vec::new();
+    write_var_int(&mut data, SIGNED_MESSAGE_MAGIC.len() as u64).unwrap();
+    data.extend_from_slice(SIGNED_MESSAGE_MAGIC);
+    write_var_int(&mut data, message.len() as u64).unwrap();
+    data.extend_from_slice(message);
+    double_sha256(&data)
+}
+
+// Compact recoverable signature: header byte (27 + recovery id + 4 for compressed keys)
+// followed by r and s. Usually transmitted base64 encoded.
+pub fn sign_message<C: Signing>(secp: &Secp256k1<C>,
+                                secret_key: &SecretKey,
+                                message: &[u8]) -> [u8; SIGNED_MESSAGE_SIZE] {
+    let digest = Message::from_slice(&signed_message_hash(message)).unwrap();
+    let (recovery_id, compact) = secp.sign_recoverable(&digest, secret_key).serialize_compact();
+    let mut sig = [0; SIGNED_MESSAGE_SIZE];
+    sig[0] = 27 + 4 + recovery_id.to_i32() as u8;
+    sig[1..].copy_from_slice(&compact);
+    sig
+}
+
+// Recovers the signing pub key and whether the signature commits to its compressed form.
+pub fn recover_message_pub_key<C: Verification>(secp: &Secp256k1<C>,
+                                                message: &[u8],
+                                                sig: &[u8]) -> Result<(PublicKey, bool), SignedMessageError> {
+    if sig.len() != SIGNED_MESSAGE_SIZE {
+        return Err(SignedMessageError::InvalidLength(sig.len()));
+    }
+    let header = sig[0];
+    if !(27..=34).contains(&header) {
+        return Err(SignedMessageError::InvalidHeader(header));
+    }
+    let is_compressed = header >= 31;
+    let recovery_id = RecoveryId::from_i32(((header - 27) & 3) as i32)
+        .map_err(|_| SignedMessageError::InvalidHeader(header))?;
+    let sig = RecoverableSignature::from_compact(&sig[1..], recovery_id)
+        .map_err(|_| SignedMessageError::InvalidSignature)?;
+    let digest = Message::from_slice(&signed_message_hash(message)).unwrap();
+    let pub_key = secp.recover(&digest, &sig).map_err(|_| SignedMessageError::InvalidSignature)?;
+    Ok((pub_key, is_compressed))
+}
+
+pub fn recover_message_address<C: Verification>(secp: &Secp256k1<C>,
+                                                message: &[u8],
+                                                sig: &[u8],
+                                                prefix: &str) -> Result<Address, SignedMessageError> {
+    let (pub_key, is_compressed) = recover_message_pub_key(secp, message, sig)?;
+    Ok(if is_compressed {
+        Address::from_serialized_pub_key(prefix, AddressType::P2PKH, &pub_key.serialize())
+    } else {
+        Address::from_serialized_pub_key(prefix, AddressType::P2PKH, &pub_key.serialize_uncompressed())
+    })
+}
+
+// Checks that `sig` signs `message` with the key of the P2PKH `address`.
+pub fn verify_message<C: Verification>(secp: &Secp256k1<C>,
+                                       address: &Address,
+                                       message: &[u8],
+                                       sig: &[u8]) -> bool {
+    address.addr_type() == AddressType::P2PKH &&
+        recover_message_address(secp, message, sig, address.prefix())
+            .map(|recovered| recovered.bytes() == address.bytes())
+            .unwrap_or(false)
+}
+
+impl OwnershipProof {
+    // Signed message: "I control <cash addr> at <timestamp>, challenge <challenge hex>".
+    pub fn statement(address: &Address, timestamp: u64, challenge: &[u8]) -> String {
+        format!("I control {} at {}, challenge {}", address.cash_addr(), timestamp, challenge.to_hex())
+    }
+
+    pub fn sign<C: Signing>(secp: &Secp256k1<C>,
+                            secret_key: &SecretKey,
+                            address: Address,
+                            timestamp: u64,
+                            challenge: &[u8]) -> Result<Self, OwnershipProofError> {
+        let pub_key = PublicKey::from_secret_key(secp, secret_key).serialize();
+        let own_address = Address::from_serialized_pub_key(address.prefix(), AddressType::P2PKH, &pub_key);
+        if own_address != address {
+            return Err(OwnershipProofError::NotOwnAddress);
+        }
+        let statement = OwnershipProof::statement(&address, timestamp, challenge);
+        Ok(OwnershipProof {
+            signature: sign_message(secp, secret_key, statement.as_bytes()),
+            address,
+            timestamp,
+            challenge: challenge.to_vec(),
+        })
+    }
+
+    // Checks the proof answers `challenge` and was made at most `max_age` seconds before `now`.
+    pub fn verify<C: Verification>(&self,
+                                   secp: &Secp256k1<C>,
+                                   challenge: &[u8],
+                                   now: u64,
+                                   max_age: u64) -> Result<(), OwnershipProofError> {
+        if self.challenge != challenge {
+            return Err(OwnershipProofError::ChallengeMismatch);
+        }
+        if self.timestamp > now || now - self.timestamp > max_age {
+            return Err(OwnershipProofError::Expired { timestamp: self.timestamp, now });
+        }
+        let statement = OwnershipProof::statement(&self.address, self.timestamp, &self.challenge);
+        if !verify_message(secp, &self.address, statement.as_bytes(), &self.signature) {
+            return Err(OwnershipProofError::InvalidSignature);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ownership_proof() {
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let pub_key = PublicKey::from_secret_key(&secp, &secret_key).serialize();
+        let address = Address::from_serialized_pub_key("bitcoincash", AddressType::P2PKH, &pub_key);
+        let proof = OwnershipProof::sign(&secp, &secret_key, address.clone(), 1_700_000_000, b"nonce").unwrap();
+        assert_eq!(proof.verify(&secp, b"nonce", 1_700_000_060, 300), Ok(()));
+        assert_eq!(proof.verify(&secp, b"other", 1_700_000_060, 300), Err(OwnershipProofError::ChallengeMismatch));
+        assert_eq!(proof.verify(&secp, b"nonce", 1_700_001_000, 300),
+                   Err(OwnershipProofError::Expired { timestamp: 1_700_000_000, now: 1_700_001_000 }));
+        let forged = OwnershipProof { timestamp: 1_700_000_001, ..proof };
+        assert_eq!(forged.verify(&secp, b"nonce", 1_700_000_060, 300), Err(OwnershipProofError::InvalidSignature));
+        let other = Address::from_bytes(AddressType::P2PKH, [0x11; 20]);
+        assert_eq!(OwnershipProof::sign(&secp, &secret_key, other, 0, b"nonce"),
+                   Err(OwnershipProofError::NotOwnAddress));
+    }
+}
@@ -0,0 +1,210 @@
+use crate::op_return::MAX_OP_RETURN_RELAY;
+use crate::script::{Op, OpCodeType};
+use crate::serialize::var_int_to_vec;
+use crate::unsigned_tx::UnsignedTx;
+
+
+// Largest tx size nodes relay by default.
+pub const MAX_STANDARD_TX_SIZE: usize = 100_000;
+// Largest sig script nodes relay by default.
+pub const MAX_STANDARD_SIG_SCRIPT_SIZE: usize = 1650;
+// Consensus limits on script size and on pushes, e.g. of P2SH redeem scripts.
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+
+// Limits exceeded by a tx, see UnsignedTx::check_standard.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StandardWarning {
+    TxTooLarge { size: usize, max: usize },
+    // not relayed by default
+    SigScriptNonStandard { input_idx: usize, size: usize, max: usize },
+    // invalid
+    SigScriptTooLarge { input_idx: usize, size: usize, max: usize },
+    PushTooLarge { input_idx: usize, size: usize, max: usize },
+    OpReturnTooLarge { output_idx: usize, size: usize, max: usize },
+}
+
+// Bytes of an input: the sig script and the outpoint, script length and sequence around it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputSize {
+    pub script_size: usize,
+    pub overhead_size: usize,
+    pub fee: u64,
+}
+
+// Bytes of an output: the script, including any token prefix, and the value and script length.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutputSize {
+    pub script_size: usize,
+    pub overhead_size: usize,
+    pub fee: u64,
+}
+
+// Size breakdown of a tx as estimated with maximum size signatures. Fees are the share of
+// each part at the fee rate the report was made for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SizeReport {
+    pub inputs: Vec<InputSize>,
+    pub outputs: Vec<OutputSize>,
+    // Version, input and output counts and lock time.
+    pub tx_overhead_size: usize,
+    pub total_size: usize,
+    pub max_standard_size: usize,
+    pub fee: u64,
+}
+
+impl InputSize {
+    pub fn total_size(&self) -> usize {
+        self.script_size + self.overhead_size
+    }
+}
+
+impl OutputSize {
+    pub fn total_size(&self) -> usize {
+        self.script_size + self.overhead_size
+    }
+}
+
+impl SizeReport {
+    pub fn is_standard(&self) -> bool {
+        self.total_size <= self.max_standard_size
+    }
+}
+
+fn var_int_size(number: usize) -> usize {
+    var_int_to_vec(number as u64).len()
+}
+
+impl UnsignedTx {
+    pub fn size_report(&self, fee_per_kb: u64) -> SizeReport {
+        let fee = |size: usize| size as u64 * fee_per_kb / 1000;
+        let tx = self.estimated_tx();
+        let inputs = tx.inputs().iter().map(|input| {
+            let script_size = input.script.to_vec().len();
+            let overhead_size = 32 + 4 + var_int_size(script_size) + 4;
+            InputSize { script_size, overhead_size, fee: fee(script_size + overhead_size) }
+        }).collect::<Vec<_>>();
+        let outputs = tx.outputs().iter().map(|output| {
+            let mut script = Vec::new();
+            if let Some(token) = output.token() {
+                token.write_prefix(&mut script).unwrap();
+            }
+            let script_size = script.len() + output.script.to_vec().len();
+            let overhead_size = 8 + var_int_size(script_size);
+            OutputSize { script_size, overhead_size, fee: fee(script_size + overhead_size) }
+        }).collect::<Vec<_>>();
+        let tx_overhead_size = 4 + var_int_size(inputs.len()) + var_int_size(outputs.len()) + 4;
+        let total_size = tx_overhead_size +
+            inputs.iter().map(InputSize::total_size).sum::<usize>() +
+            outputs.iter().map(OutputSize::total_size).sum::<usize>();
+        SizeReport {
+            inputs,
+            outputs,
+            tx_overhead_size,
+            total_size,
+            max_standard_size: MAX_STANDARD_TX_SIZE,
+            fee: fee(total_size),
+        }
+    }
+
+    // Checks the tx as estimated with maximum size signatures against the size limits of
+    // standardness and consensus rules. Covenant spends with large sig scripts can exceed them.
+    pub fn check_standard(&self) -> Vec<StandardWarning> {
+        let tx = self.estimated_tx();
+        let mut warnings = Vec::new();
+        let size = tx.size();
+        if size > MAX_STANDARD_TX_SIZE {
+            warnings.push(StandardWarning::TxTooLarge { size, max: MAX_STANDARD_TX_SIZE });
+        }
+        for (input_idx, input) in tx.inputs().iter().enumerate() {
+            let size = input.script.to_vec().len();
+            if size > MAX_SCRIPT_SIZE {
+                warnings.push(StandardWarning::SigScriptTooLarge { input_idx, size, max: MAX_SCRIPT_SIZE });
+            } else if size > MAX_STANDARD_SIG_SCRIPT_SIZE {
+                warnings.push(StandardWarning::SigScriptNonStandard {
+                    input_idx,
+                    size,
+                    max: MAX_STANDARD_SIG_SCRIPT_SIZE,
+                });
+            }
+            for op in input.script.ops() {
+                match op {
+                    Op::Push(push) if push.len() > MAX_SCRIPT_ELEMENT_SIZE => {
+                        warnings.push(StandardWarning::PushTooLarge {
+                            input_idx,
+                            size: push.len(),
+                            max: MAX_SCRIPT_ELEMENT_SIZE,
+                        });
+                    },
+                    _ => {},
+                }
+            }
+        }
+        for (output_idx, output) in tx.outputs().iter().enumerate() {
+            let size = output.script.to_vec().len();
+            if output.script.ops().first() == Some(&Op::Code(OpCodeType::OpReturn)) && size > MAX_OP_RETURN_RELAY {
+                warnings.push(StandardWarning::OpReturnTooLarge { output_idx, size, max: MAX_OP_RETURN_RELAY });
+            }
+        }
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::{Address, AddressType};
+    use crate::outputs::{P2PKHOutput, P2SHRawOutput};
+    use crate::script::Script;
+    use crate::tx::{TxOutpoint, TxOutput};
+    use crate::unsigned_tx::{Output, UnsignedInput};
+
+    #[test]
+    fn test_size_report() {
+        let address = Address::from_bytes(AddressType::P2PKH, [0x11; 20]);
+        let mut tx = UnsignedTx::new_simple();
+        tx.add_input(UnsignedInput::new(
+            TxOutpoint { tx_hash: [0; 32], vout: 0 },
+            Box::new(P2PKHOutput { value: 10_000, address: address.clone() }),
+            0xffff_ffff,
+        ));
+        tx.add_output(P2PKHOutput { value: 9_000, address }.to_output());
+        let report = tx.size_report(2000);
+        assert_eq!(report.inputs, vec![InputSize { script_size: 108, overhead_size: 41, fee: 298 }]);
+        assert_eq!(report.outputs, vec![OutputSize { script_size: 25, overhead_size: 9, fee: 68 }]);
+        assert_eq!(report.tx_overhead_size, 10);
+        assert_eq!(report.total_size, 193);
+        assert_eq!(report.total_size + 2, tx.estimate_size());
+        assert_eq!(report.fee, 386);
+        assert!(report.is_standard());
+
+        let address = Address::from_bytes(AddressType::P2PKH, [0x11; 20]);
+        tx.replace_input(0, UnsignedInput::new(
+            TxOutpoint { tx_hash: [0; 32], vout: 0 },
+            Box::new(P2PKHOutput { value: 10_000, address }),
+            0xffff_ffff,
+        ).with_max_sig_script_size(300));
+        let report = tx.size_report(1000);
+        assert_eq!(report.inputs, vec![InputSize { script_size: 300, overhead_size: 43, fee: 343 }]);
+        assert_eq!(report.total_size + 2, tx.estimate_size());
+        assert!(tx.check_standard().is_empty());
+
+        tx.add_input(UnsignedInput::new(
+            TxOutpoint { tx_hash: [0; 32], vout: 1 },
+            Box::new(P2SHRawOutput {
+                value: 10_000,
+                redeem_script: Script::new(vec![Op::Code(OpCodeType::Op1); 600]),
+                sig_pushes: vec![vec![0; 1000]],
+                push_pub_key: true,
+            }),
+            0xffff_ffff,
+        ));
+        tx.add_output(TxOutput::new(0, Script::new(vec![Op::Code(OpCodeType::OpReturn), Op::Push(vec![0; 300])])));
+        assert_eq!(tx.check_standard(), vec![
+            StandardWarning::SigScriptNonStandard { input_idx: 1, size: 1714, max: 1650 },
+            StandardWarning::PushTooLarge { input_idx: 1, size: 1000, max: 520 },
+            StandardWarning::PushTooLarge { input_idx: 1, size: 600, max: 520 },
+            StandardWarning::OpReturnTooLarge { output_idx: 1, size: 304, max: 223 },
+        ]);
+    }
+}
@@ -0,0 +1,346 @@
+use crate::outputs::{OpReturnOutput, SLPSend, SLPGenesis, SLPError, P2PKHOutput,
+                     validate_token_type};
+use crate::script::{Script, Op, OpCodeType};
+use crate::unsigned_tx::{UnsignedTx, Output};
+use crate::address::Address;
+
+use byteorder::{BigEndian, ReadBytesExt};
+
+
+#[derive(Clone, Debug)]
+pub struct SLPMint {
+    pub token_type: u8,
+    pub token_id: [u8; 32],
+    pub mint_baton_vout: Option<u8>,
+    pub additional_token_quantity: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SLPLeftoverError {
+    InsufficientFunds(u64),
+    NoSLPSend,
+    MissingTokenOutputs { num_quantities: usize, num_outputs: usize },
+    SLP(SLPError),
+}
+
+#[derive(Clone, Debug)]
+pub enum SLPMessage {
+    Genesis(SLPGenesis),
+    Mint(SLPMint),
+    Send(SLPSend),
+}
+
+impl SLPMint {
+    /* OP_RETURN
+     * <lokad id: 'SLP\x00'> (4 bytes, ascii)
+     * <token_type: 1> (1 to 2 byte integer)
+     * <transaction_type: 'MINT'> (4 bytes, ascii)
+     * <token_id> (32 bytes)
+     * <mint_baton_vout> (0 bytes, or 1 byte in range 0x02-0xff)
+     * <additional_token_quantity> (8 byte integer) */
+
+    pub fn validate(&self) -> Result<(), SLPError> {
+        validate_token_type(self.token_type)?;
+        match self.mint_baton_vout {
+            Some(mint_baton_vout) if mint_baton_vout < 2 =>
+                Err(SLPError::InvalidMintBatonVout(mint_baton_vout)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn into_output(self) -> Result<OpReturnOutput, SLPError> {
+        self.validate()?;
+        Ok(self.into_output_unchecked())
+    }
+
+    pub fn into_output_unchecked(self) -> OpReturnOutput {
+        OpReturnOutput {
+            is_minimal_push: false,
+            encodings: vec![],
+            pushes: vec![
+                b"SLP\0".to_vec(),
+                vec![self.token_type],
+                b"MINT".to_vec(),
+                self.token_id.iter().cloned().rev().collect(),
+                self.mint_baton_vout.map(|vout| vec![vout]).unwrap_or_default(),
+                self.additional_token_quantity.to_be_bytes().to_vec(),
+            ],
+        }
+    }
+}
+
+impl SLPSend {
+    // As u128, as parsed messages can have quantities summing to more than u64::MAX.
+    pub fn total_quantity(&self) -> u128 {
+        self.output_quantities.iter().map(|&quantity| quantity as u128).sum()
+    }
+
+    // Amount of tokens destroyed when spending `input_token_amount` with this SEND. A SEND
+    // with outputs exceeding its inputs is invalid, destroying all of them.
+    pub fn burned_amount(&self, input_token_amount: u64) -> u64 {
+        match (input_token_amount as u128).checked_sub(self.total_quantity()) {
+            Some(burned_amount) => burned_amount as u64,
+            None => input_token_amount,
+        }
+    }
+
+    // Builds a SEND that intentionally burns `burn_amount` of `input_token_amount`.
+    pub fn with_burn(token_type: u8,
+                     token_id: [u8; 32],
+                     input_token_amount: u64,
+                     output_quantities: Vec<u64>,
+                     burn_amount: u64) -> Result<Self, SLPError> {
+        let send = SLPSend { token_type, token_id, output_quantities };
+        send.validate()?;
+        if send.total_quantity() + burn_amount as u128 != input_token_amount as u128 {
+            return Err(SLPError::BurnAmountMismatch {
+                input_amount: input_token_amount,
+                output_amount: send.total_quantity(),
+                burn_amount,
+            });
+        }
+        Ok(send)
+    }
+}
+
+impl SLPMessage {
+    pub fn parse(script: &Script) -> Result<Self, SLPError> {
+        let ops = script.ops();
+        if ops.first() != Some(&Op::Code(OpCodeType::OpReturn)) {
+            return Err(SLPError::NotSLP);
+        }
+        let pushes = ops[1..].iter()
+            .map(|op| match op {
+                Op::Push(push) => Ok(push.as_slice()),
+                Op::Code(_) => Err(SLPError::MalformedMessage),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if pushes.first() != Some(&&b"SLP\0"[..]) {
+            return Err(SLPError::NotSLP);
+        }
+        if pushes.len() < 3 {
+            return Err(SLPError::MalformedMessage);
+        }
+        let token_type = match pushes[1] {
+            [token_type] | [0, token_type] => *token_type,
+            _ => return Err(SLPError::MalformedMessage),
+        };
+        validate_token_type(token_type)?;
+        let fields = &pushes[3..];
+        match pushes[2] {
+            b"GENESIS" => {
+                if fields.len() != 7 {
+                    return Err(SLPError::MalformedMessage);
+                }
+                let genesis = SLPGenesis {
+                    token_type,
+                    token_ticker: fields[0].to_vec(),
+                    token_name: fields[1].to_vec(),
+                    token_document_url: fields[2].to_vec(),
+                    token_document_hash: fields[3].to_vec(),
+                    decimals: match fields[4] {
+                        [decimals] => *decimals,
+                        _ => return Err(SLPError::MalformedMessage),
+                    },
+                    mint_baton_vout: match fields[5] {
+                        [] => None,
+                        [vout] => Some(*vout),
+                        _ => return Err(SLPError::MalformedMessage),
+                    },
+                    initial_token_mint_quantity: parse_amount(fields[6])?,
+                };
+                genesis.validate()?;
+                Ok(SLPMessage::Genesis(genesis))
+            },
+            b"MINT" => {
+                if fields.len() != 3 {
+                    return Err(SLPError::MalformedMessage);
+                }
+                let mint = SLPMint {
+                    token_type,
+                    token_id: parse_token_id(fields[0])?,
+                    mint_baton_vout: match fields[1] {
+                        [] => None,
+                        [vout] => Some(*vout),
+                        _ => return Err(SLPError::MalformedMessage),
+                    },
+                    additional_token_quantity: parse_amount(fields[2])?,
+                };
+                mint.validate()?;
+                Ok(SLPMessage::Mint(mint))
+            },
+            b"SEND" => {
+                if fields.is_empty() {
+                    return Err(SLPError::MalformedMessage);
+                }
+                let send = SLPSend {
+                    token_type,
+                    token_id: parse_token_id(fields[0])?,
+                    output_quantities: fields[1..].iter()
+                        .map(|field| parse_amount(field))
+                        .collect::<Result<_, _>>()?,
+                };
+                send.validate()?;
+                Ok(SLPMessage::Send(send))
+            },
+            other => Err(SLPError::UnknownTransactionType(other.to_vec())),
+        }
+    }
+
+    pub fn token_type(&self) -> u8 {
+        match self {
+            SLPMessage::Genesis(genesis) => genesis.token_type,
+            SLPMessage::Mint(mint) => mint.token_type,
+            SLPMessage::Send(send) => send.token_type,
+        }
+    }
+
+    // Token id and amount received by output `vout` of the tx with hash `tx_hash`.
+    pub fn output_token(&self, tx_hash: &[u8; 32], vout: u32) -> Option<([u8; 32], u64)> {
+        match self {
+            SLPMessage::Genesis(genesis) if vout == 1 =>
+                Some((*tx_hash, genesis.initial_token_mint_quantity)),
+            SLPMessage::Mint(mint) if vout == 1 =>
+                Some((mint.token_id, mint.additional_token_quantity)),
+            SLPMessage::Send(send) if vout >= 1 => send.output_quantities
+                .get(vout as usize - 1)
+                .filter(|&&quantity| quantity > 0)
+                .map(|&quantity| (send.token_id, quantity)),
+            _ => None,
+        }
+    }
+
+    // Token id of the mint baton sent to output `vout`, if any.
+    pub fn mint_baton(&self, tx_hash: &[u8; 32], vout: u32) -> Option<[u8; 32]> {
+        match self {
+            SLPMessage::Genesis(genesis) if genesis.mint_baton_vout.map(u32::from) == Some(vout) =>
+                Some(*tx_hash),
+            SLPMessage::Mint(mint) if mint.mint_baton_vout.map(u32::from) == Some(vout) =>
+                Some(mint.token_id),
+            _ => None,
+        }
+    }
+
+    // Tokens explicitly or implicitly destroyed given the token amount of the inputs.
+    pub fn burned_amount(&self, input_token_amount: u64) -> u64 {
+        match self {
+            SLPMessage::Genesis(_) => 0,
+            // a MINT only spends the baton
+            SLPMessage::Mint(_) => input_token_amount,
+            SLPMessage::Send(send) => send.burned_amount(input_token_amount),
+        }
+    }
+}
+
+impl UnsignedTx {
+    fn slp_send(&self) -> Result<SLPSend, SLPLeftoverError> {
+        let op_return = self.outputs().first().ok_or(SLPLeftoverError::NoSLPSend)?;
+        match SLPMessage::parse(&op_return.script) {
+            Ok(SLPMessage::Send(send)) => Ok(send),
+            _ => Err(SLPLeftoverError::NoSLPSend),
+        }
+    }
+
+    fn token_outputs_end(&self, send: &SLPSend) -> Result<usize, SLPLeftoverError> {
+        let end = send.output_quantities.len() + 1;
+        if end > self.outputs().len() {
+            return Err(SLPLeftoverError::MissingTokenOutputs {
+                num_quantities: send.output_quantities.len(),
+                num_outputs: self.outputs().len(),
+            });
+        }
+        Ok(end)
+    }
+
+    // Adds a dust output receiving `token_amount` right after the existing token outputs
+    // and appends its quantity to the SEND message at output 0.
+    pub fn add_slp_token_change(&mut self,
+                                token_change_addr: Address,
+                                token_amount: u64,
+                                dust_amount: u64) -> Result<usize, SLPLeftoverError> {
+        let mut send = self.slp_send()?;
+        let token_change_idx = self.token_outputs_end(&send)?;
+        send.output_quantities.push(token_amount);
+        let op_return = send.into_output().map_err(SLPLeftoverError::SLP)?;
+        self.replace_output(0, op_return.to_output());
+        self.insert_output(token_change_idx, P2PKHOutput {
+            value: dust_amount,
+            address: token_change_addr,
+        }.to_output());
+        Ok(token_change_idx)
+    }
+
+    // Like add_leftover_output, but keeps the SEND message at output 0 intact by
+    // placing the BCH change after all outputs that receive tokens.
+    pub fn add_slp_leftover_output(&mut self,
+                                   leftover_addr: Address,
+                                   fee_per_kb: u64,
+                                   dust_limit: u64) -> Result<Option<usize>, SLPLeftoverError> {
+        let send = self.slp_send()?;
+        let leftover_idx = self.token_outputs_end(&send)?;
+        self.insert_leftover_output(leftover_idx, leftover_addr, fee_per_kb, dust_limit)
+            .map_err(|err| SLPLeftoverError::InsufficientFunds(err.missing_amount()))
+    }
+}
+
+fn parse_token_id(data: &[u8]) -> Result<[u8; 32], SLPError> {
+    if data.len() != 32 {
+        return Err(SLPError::MalformedMessage);
+    }
+    let mut token_id = [0; 32];
+    token_id.copy_from_slice(data);
+    token_id.reverse();
+    Ok(token_id)
+}
+
+fn parse_amount(mut data: &[u8]) -> Result<u64, SLPError> {
+    if data.len() != 8 {
+        return Err(SLPError::MalformedMessage);
+    }
+    Ok(data.read_u64::<BigEndian>().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::unsigned_tx::Output;
+
+    #[test]
+    fn test_slp_send_roundtrip() {
+        let send = SLPSend {
+            token_type: 1,
+            token_id: [7; 32],
+            output_quantities: vec![100, 0, 23],
+        };
+        let script = send.clone().into_output().unwrap().script();
+        let script = Script::from_serialized(&script.to_vec()).unwrap();
+        match SLPMessage::parse(&script).unwrap() {
+            SLPMessage::Send(parsed) => {
+                assert_eq!(parsed.token_id, send.token_id);
+                assert_eq!(parsed.output_quantities, send.output_quantities);
+                assert_eq!(parsed.burned_amount(150), 27);
+            },
+            other => panic!("expected SEND, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_slp_burn() {
+        assert!(SLPSend::with_burn(1, [0; 32], 10, vec![4, 5], 2).is_err());
+        let send = SLPSend::with_burn(1, [0; 32], 10, vec![4, 4], 2).unwrap();
+        assert_eq!(send.burned_amount(10), 2);
+        // outputs exceeding the inputs make the SEND invalid, burning all input tokens
+        assert_eq!(send.burned_amount(7), 7);
+        let overflowing = SLPSend { token_type: 1, token_id: [0; 32], output_quantities: vec![u64::MAX, 2] };
+        assert_eq!(overflowing.total_quantity(), u64::MAX as u128 + 2);
+        assert_eq!(overflowing.burned_amount(u64::MAX), u64::MAX);
+        assert!(SLPSend::with_burn(1, [0; 32], 1, vec![u64::MAX, 2], 0).is_err());
+        let burn = OpReturnOutput {
+            is_minimal_push: false,
+            encodings: vec![],
+            pushes: vec![b"SLP\0".to_vec(), vec![1], b"BURN".to_vec(), vec![0; 32], vec![0; 8]],
+        };
+        assert_eq!(SLPMessage::parse(&burn.script()).err(),
+                   Some(SLPError::UnknownTransactionType(b"BURN".to_vec())));
+    }
+}
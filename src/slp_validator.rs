@@ -0,0 +1,298 @@
+use crate::outputs::{SLP_TOKEN_TYPE_NFT1_CHILD, SLP_TOKEN_TYPE_NFT1_GROUP};
+use crate::script::Script;
+use crate::slp::SLPMessage;
+use crate::tx::{Tx, TxInput, TxOutpoint};
+use crate::unsigned_tx::UnsignedTx;
+
+use std::collections::{BTreeMap, HashMap};
+
+
+// Local SLP validation: a tx is valid if its SLP message parses and its inputs hold the
+// tokens (SEND) or the mint baton (MINT) it claims, recursively back to the GENESIS.
+// Parents are fetched through a callback and only followed if they could carry tokens
+// relevant to the child, so unrelated BCH inputs don't pull in their whole history.
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SLPValidationError {
+    MissingTx([u8; 32]),
+}
+
+pub struct SLPValidator<F: FnMut(&[u8; 32]) -> Option<Tx>> {
+    fetch_tx: F,
+    // Some(message) for valid SLP txs, None for invalid or non-SLP txs
+    validated: HashMap<[u8; 32], Option<SLPMessage>>,
+    pending: HashMap<[u8; 32], Tx>,
+}
+
+enum SpentToken {
+    Amount(u64),
+    MintBaton,
+}
+
+fn parse_message(tx: &Tx) -> Option<SLPMessage> {
+    SLPMessage::parse(&tx.outputs().first()?.script).ok()
+}
+
+// What the output at `outpoint`, spent by input `input_idx`, contributes to `message`,
+// given the (not necessarily valid) SLP message of the spent tx.
+fn spent_token(message: &SLPMessage,
+               input_idx: usize,
+               outpoint: &TxOutpoint,
+               parent: &SLPMessage) -> Option<SpentToken> {
+    let output_token = |token_id: &[u8; 32]| {
+        parent.output_token(&outpoint.tx_hash, outpoint.vout)
+            .filter(|(parent_token_id, _)| parent_token_id == token_id)
+            .map(|(_, amount)| SpentToken::Amount(amount))
+    };
+    match message {
+        // NFT1 children are created by spending a group token at input 0
+        SLPMessage::Genesis(genesis) => {
+            if genesis.token_type != SLP_TOKEN_TYPE_NFT1_CHILD || input_idx != 0 ||
+                    parent.token_type() != SLP_TOKEN_TYPE_NFT1_GROUP {
+                return None;
+            }
+            parent.output_token(&outpoint.tx_hash, outpoint.vout)
+                .map(|(_, amount)| SpentToken::Amount(amount))
+        },
+        _ if parent.token_type() != message.token_type() => None,
+        SLPMessage::Mint(mint) => parent.mint_baton(&outpoint.tx_hash, outpoint.vout)
+            .filter(|token_id| token_id == &mint.token_id)
+            .map(|_| SpentToken::MintBaton),
+        SLPMessage::Send(send) => output_token(&send.token_id),
+    }
+}
+
+impl<F: FnMut(&[u8; 32]) -> Option<Tx>> SLPValidator<F> {
+    pub fn new(fetch_tx: F) -> Self {
+        SLPValidator {
+            fetch_tx,
+            validated: HashMap::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    // Validates `tx` and all relevant ancestors, caching the results. Fails if a parent
+    // can't be fetched.
+    pub fn validate(&mut self, tx: &Tx) -> Result<bool, SLPValidationError> {
+        let tx_hash = tx.hash();
+        if let Some(message) = self.validated.get(&tx_hash) {
+            return Ok(message.is_some());
+        }
+        self.pending.insert(tx_hash, tx.clone());
+        // explicit stack, long token chains would overflow the call stack
+        let mut stack = vec![tx_hash];
+        while let Some(&tx_hash) = stack.last() {
+            let parents = match self.unvalidated_parents(&tx_hash) {
+                Ok(parents) => parents,
+                Err(err) => {
+                    self.pending.clear();
+                    return Err(err);
+                },
+            };
+            if parents.is_empty() {
+                let tx = self.pending.remove(&tx_hash).expect("pending tx");
+                let message = self.check(&tx);
+                self.validated.insert(tx_hash, message);
+                stack.pop();
+            } else {
+                stack.extend(parents);
+            }
+        }
+        Ok(self.is_valid(&tx_hash))
+    }
+
+    // Cached result of a previous validation.
+    pub fn is_valid(&self, tx_hash: &[u8; 32]) -> bool {
+        self.valid_message(tx_hash).is_some()
+    }
+
+    // SLP message of a validated tx, None if invalid or not validated yet.
+    pub fn valid_message(&self, tx_hash: &[u8; 32]) -> Option<&SLPMessage> {
+        self.validated.get(tx_hash)?.as_ref()
+    }
+
+    // Tokens destroyed by `tx`, by token id: all valid tokens spent by its inputs, minus the
+    // ones a valid SEND of the same token assigns to existing outputs. Tokens spent by non-SLP
+    // or invalid txs, MINTs and GENESIS are all destroyed.
+    pub fn burned_tokens(&mut self, tx: &Tx) -> Result<BTreeMap<[u8; 32], u64>, SLPValidationError> {
+        let mut input_tokens = BTreeMap::new();
+        for input in tx.inputs() {
+            let parent_hash = input.outpoint.tx_hash;
+            if !self.validated.contains_key(&parent_hash) {
+                let parent = (self.fetch_tx)(&parent_hash)
+                    .ok_or(SLPValidationError::MissingTx(parent_hash))?;
+                self.validate(&parent)?;
+            }
+            let token = self.valid_message(&parent_hash)
+                .and_then(|parent| parent.output_token(&parent_hash, input.outpoint.vout));
+            if let Some((token_id, amount)) = token {
+                *input_tokens.entry(token_id).or_insert(0u128) += amount as u128;
+            }
+        }
+        let is_valid = match self.validated.get(&tx.hash()) {
+            Some(message) => message.is_some(),
+            None => self.check(tx).is_some(),
+        };
+        if let (true, Some(SLPMessage::Send(send))) = (is_valid, parse_message(tx)) {
+            let num_token_outputs = tx.outputs().len().saturating_sub(1);
+            let sent = send.output_quantities.iter()
+                .take(num_token_outputs)
+                .map(|&quantity| quantity as u128)
+                .sum::<u128>();
+            if let Some(amount) = input_tokens.get_mut(&send.token_id) {
+                *amount = amount.saturating_sub(sent);
+            }
+        }
+        Ok(input_tokens.into_iter()
+            .filter(|&(_, amount)| amount > 0)
+            .map(|(token_id, amount)| (token_id, amount.min(u64::MAX as u128) as u64))
+            .collect())
+    }
+
+    // burned_tokens of a tx before signing it, so wallets can warn about accidental burns.
+    pub fn burned_tokens_unsigned(&mut self, tx: &UnsignedTx)
+            -> Result<BTreeMap<[u8; 32], u64>, SLPValidationError> {
+        let inputs = tx.inputs().iter()
+            .map(|input| TxInput::new(input.outpoint.clone(), Script::empty(), input.sequence))
+            .collect();
+        self.burned_tokens(&Tx::new(tx.version(), inputs, tx.outputs().to_vec(), tx.lock_time()))
+    }
+
+    // Parents of a pending tx that have to be validated before it, moving them to pending.
+    // Parents which are pending already are ancestors of themselves and never valid.
+    fn unvalidated_parents(&mut self, tx_hash: &[u8; 32])
+            -> Result<Vec<[u8; 32]>, SLPValidationError> {
+        let message = match parse_message(&self.pending[tx_hash]) {
+            Some(message) => message,
+            None => return Ok(vec![]),
+        };
+        let outpoints = self.pending[tx_hash].inputs().iter()
+            .map(|input| input.outpoint.clone())
+            .collect::<Vec<_>>();
+        let mut parents = Vec::new();
+        for (input_idx, outpoint) in outpoints.iter().enumerate() {
+            let parent_hash = outpoint.tx_hash;
+            if self.validated.contains_key(&parent_hash) || self.pending.contains_key(&parent_hash) {
+                continue;
+            }
+            let parent = (self.fetch_tx)(&parent_hash)
+                .ok_or(SLPValidationError::MissingTx(parent_hash))?;
+            let is_relevant = parse_message(&parent)
+                .and_then(|parent| spent_token(&message, input_idx, outpoint, &parent))
+                .is_some();
+            if is_relevant {
+                self.pending.insert(parent_hash, parent);
+                parents.push(parent_hash);
+            }
+        }
+        Ok(parents)
+    }
+
+    fn check(&self, tx: &Tx) -> Option<SLPMessage> {
+        let message = parse_message(tx)?;
+        let mut input_amount = 0u128;
+        let mut has_mint_baton = false;
+        for (input_idx, input) in tx.inputs().iter().enumerate() {
+            let spent = self.valid_message(&input.outpoint.tx_hash)
+                .and_then(|parent| spent_token(&message, input_idx, &input.outpoint, parent));
+            match spent {
+                Some(SpentToken::Amount(amount)) => input_amount += amount as u128,
+                Some(SpentToken::MintBaton) => has_mint_baton = true,
+                None => {},
+            }
+        }
+        let is_valid = match &message {
+            SLPMessage::Genesis(genesis) if genesis.token_type == SLP_TOKEN_TYPE_NFT1_CHILD =>
+                input_amount >= 1,
+            SLPMessage::Genesis(_) => true,
+            SLPMessage::Mint(_) => has_mint_baton,
+            SLPMessage::Send(send) => input_amount >= send.output_quantities.iter()
+                .map(|&quantity| quantity as u128)
+                .sum::<u128>(),
+        };
+        if is_valid { Some(message) } else { None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::outputs::{SLPGenesis, SLPSend, SLP_TOKEN_TYPE_FUNGIBLE};
+    use crate::slp::SLPMint;
+    use crate::tx::TxOutput;
+    use crate::unsigned_tx::Output;
+
+    fn slp_tx(spent: &[(&Tx, u32)], message: Script, num_outputs: usize) -> Tx {
+        let inputs = spent.iter()
+            .map(|(tx, vout)| TxInput::new(
+                TxOutpoint { tx_hash: tx.hash(), vout: *vout }, Script::empty(), 0xffff_ffff,
+            ))
+            .collect();
+        let mut outputs = vec![TxOutput::new(0, message)];
+        outputs.extend((0..num_outputs).map(|_| TxOutput::new(546, Script::empty())));
+        Tx::new(2, inputs, outputs, 0)
+    }
+
+    #[test]
+    fn test_validate_token_dag() {
+        let funding = Tx::new(2, vec![], vec![TxOutput::new(10_000, Script::empty())], 0);
+        let genesis = slp_tx(&[(&funding, 0)], SLPGenesis {
+            token_type: SLP_TOKEN_TYPE_FUNGIBLE,
+            token_ticker: b"TST".to_vec(),
+            token_name: vec![],
+            token_document_url: vec![],
+            token_document_hash: vec![],
+            decimals: 0,
+            mint_baton_vout: Some(2),
+            initial_token_mint_quantity: 100,
+        }.into_output().unwrap().script(), 2);
+        let token_id = genesis.hash();
+        let send = |spent: &[(&Tx, u32)], output_quantities: Vec<u64>| {
+            let num_outputs = output_quantities.len();
+            let message = SLPSend { token_type: SLP_TOKEN_TYPE_FUNGIBLE, token_id, output_quantities };
+            slp_tx(spent, message.into_output().unwrap().script(), num_outputs)
+        };
+        let valid_send = send(&[(&genesis, 1)], vec![60, 40]);
+        let overspend = send(&[(&valid_send, 1), (&funding, 0)], vec![61]);
+        let fake_send = send(&[(&funding, 0)], vec![100]);
+        let spend_fake = send(&[(&fake_send, 1), (&valid_send, 2)], vec![100]);
+        let mint = |spent: &[(&Tx, u32)]| slp_tx(spent, SLPMint {
+            token_type: SLP_TOKEN_TYPE_FUNGIBLE,
+            token_id,
+            mint_baton_vout: Some(2),
+            additional_token_quantity: 1000,
+        }.into_output().unwrap().script(), 2);
+        let valid_mint = mint(&[(&genesis, 2)]);
+        let mint_again = mint(&[(&valid_mint, 2)]);
+        let fake_mint = mint(&[(&genesis, 1)]);
+
+        let txs = [funding.clone(), genesis.clone(), valid_send.clone(), fake_send.clone(),
+                   valid_mint.clone()];
+        let mut num_fetched = 0;
+        let mut validator = SLPValidator::new(|tx_hash: &[u8; 32]| {
+            num_fetched += 1;
+            txs.iter().find(|tx| &tx.hash() == tx_hash).cloned()
+        });
+        assert_eq!(validator.validate(&valid_send), Ok(true));
+        assert_eq!(validator.validate(&overspend), Ok(false));
+        assert_eq!(validator.validate(&spend_fake), Ok(false));
+        assert!(!validator.is_valid(&fake_send.hash()));
+        assert_eq!(validator.validate(&mint_again), Ok(true));
+        assert_eq!(validator.validate(&fake_mint), Ok(false));
+        let partial_burn = send(&[(&valid_send, 1)], vec![50]);
+        assert_eq!(validator.burned_tokens(&partial_burn).unwrap().get(&token_id), Some(&10));
+        let non_slp = Tx::new(2, partial_burn.inputs().to_vec(), vec![], 0);
+        assert_eq!(validator.burned_tokens(&non_slp).unwrap().get(&token_id), Some(&60));
+        // the quantity for the missing output 2 is burned
+        let missing_output = send(&[(&valid_send, 1)], vec![0, 60]);
+        let missing_output = Tx::new(2, missing_output.inputs().to_vec(), missing_output.outputs()[..2].to_vec(), 0);
+        assert_eq!(validator.burned_tokens(&missing_output).unwrap().get(&token_id), Some(&60));
+        assert!(validator.burned_tokens(&send(&[(&valid_send, 1)], vec![60])).unwrap().is_empty());
+        let unknown = send(&[(&genesis, 1)], vec![100]);
+        assert_eq!(validator.validate(&send(&[(&unknown, 1)], vec![1])),
+                   Err(SLPValidationError::MissingTx(unknown.hash())));
+        drop(validator);
+        // funding is fetched as a parent of genesis and fake_send, but never validated
+        assert_eq!(num_fetched, 7);
+    }
+}
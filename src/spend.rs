@@ -0,0 +1,152 @@
+// Locking and unlocking sides of an output as separate traits.
+//
+// `Output` builds both the locking script and the sig script spending it, so outputs with
+// several spending paths have to carry optional spend parameters which panic if unset. Here an
+// `Unlocker` holds everything needed to spend a `LockingScript`, and `Spend` combines the two
+// into an `Output` for `UnsignedTx`. Every `Output` is a `LockingScript`, while covenants with
+// several spending paths are only `LockingScript`s; the traits aren't re-exported at the crate
+// root, as their methods share the names of `Output`'s.
+
+use crate::cash_tokens::TokenData;
+use crate::hash::hash160;
+use crate::script::{Script, Op, OpCodeType};
+use crate::tx::TxOutput;
+use crate::unsigned_tx::{Output, PreImage, MAX_SIGNATURE_SIZE, PUBKEY_SIZE};
+
+
+pub trait LockingScript {
+    fn value(&self) -> u64;
+    fn script(&self) -> Script;
+    fn script_code(&self) -> Script;
+    fn token(&self) -> Option<TokenData> {
+        None
+    }
+    // P2SH output with this script as redeem script, e.g. to create a covenant.
+    fn to_p2sh_output(&self) -> TxOutput {
+        TxOutput {
+            value: self.value(),
+            script: Script::new(vec![
+                Op::Code(OpCodeType::OpHash160),
+                Op::Push(hash160(&self.script().to_vec()).to_vec()),
+                Op::Code(OpCodeType::OpEqual),
+            ]),
+            token: self.token(),
+        }
+    }
+}
+
+// Spends a `L`; implementors carry all spend parameters of the spending path they take.
+pub trait Unlocker<L: ?Sized> {
+    fn sig_script(&self,
+                  locking: &L,
+                  serialized_sig: Vec<u8>,
+                  serialized_pub_key: Vec<u8>,
+                  pre_image: &PreImage,
+                  outputs: &[TxOutput]) -> Script;
+    // See Output::estimated_unlock_size.
+    fn estimated_unlock_size(&self, _locking: &L) -> Option<usize> {
+        None
+    }
+}
+
+// Output spending `locking` with `unlocker`, e.g. as the output of an UnsignedInput.
+#[derive(Clone, Debug)]
+pub struct Spend<L, U> {
+    pub locking: L,
+    pub unlocker: U,
+}
+
+impl<L, U> Spend<L, U> {
+    pub fn new(locking: L, unlocker: U) -> Self {
+        Spend { locking, unlocker }
+    }
+}
+
+// Size of `unlocker`'s sig script with the largest signature, for unlockers whose sig script
+// doesn't depend on the tx outputs.
+pub(crate) fn max_unlock_size<L: LockingScript, U: Unlocker<L>>(unlocker: &U, locking: &L) -> usize {
+    let pre_image = PreImage { token: locking.token(), ..PreImage::empty(locking.script_code()) };
+    let sig_script = unlocker.sig_script(locking, vec![0; MAX_SIGNATURE_SIZE], vec![0; PUBKEY_SIZE],
+                                         &pre_image, &[]);
+    sig_script.to_vec().len()
+}
+
+impl<T: Output + ?Sized> LockingScript for T {
+    fn value(&self) -> u64 {
+        Output::value(self)
+    }
+
+    fn script(&self) -> Script {
+        Output::script(self)
+    }
+
+    fn script_code(&self) -> Script {
+        Output::script_code(self)
+    }
+
+    fn token(&self) -> Option<TokenData> {
+        Output::token(self)
+    }
+}
+
+impl<L, U> Output for Spend<L, U>
+        where L: LockingScript + Clone + Send + Sync + 'static,
+              U: Unlocker<L> + Clone + Send + Sync + 'static {
+    fn value(&self) -> u64 {
+        self.locking.value()
+    }
+
+    fn script(&self) -> Script {
+        self.locking.script()
+    }
+
+    fn script_code(&self) -> Script {
+        self.locking.script_code()
+    }
+
+    fn sig_script(&self,
+                  serialized_sig: Vec<u8>,
+                  serialized_pub_key: Vec<u8>,
+                  pre_image: &PreImage,
+                  outputs: &[TxOutput]) -> Script {
+        self.unlocker.sig_script(&self.locking, serialized_sig, serialized_pub_key, pre_image, outputs)
+    }
+
+    fn token(&self) -> Option<TokenData> {
+        self.locking.token()
+    }
+
+    fn estimated_unlock_size(&self) -> Option<usize> {
+        self.unlocker.estimated_unlock_size(&self.locking)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::{Address, AddressType};
+    use crate::advanced_trade_offer::{AdvancedTradeOfferBuilder, AdvancedTradeOfferSpendParams};
+    use crate::outputs::P2SHOutput;
+    use crate::script::Op;
+
+    #[test]
+    fn test_spend_offer() {
+        let address = Address::from_bytes(AddressType::P2PKH, [0x11; 20]);
+        let offer = AdvancedTradeOfferBuilder::new(b"EXCH".to_vec(), [0x22; 32], 100, 1000, address)
+            .build()
+            .unwrap();
+        let spend = Spend::new(offer.clone(), AdvancedTradeOfferSpendParams::Cancel);
+        let pre_image = PreImage::empty(offer.script_code());
+        let sig_script = Output::sig_script(&spend, vec![0x30, 0x41], vec![0x02; 33], &pre_image, &[]);
+        assert_eq!(sig_script.ops(), &[
+            Op::Push(vec![0x30, 0x41]),
+            Op::Push(vec![0x02; 33]),
+            Op::Push(vec![]),
+        ][..]);
+        assert_eq!(Output::script(&spend).to_vec(), offer.script().to_vec());
+        assert_eq!(Output::value(&spend), offer.value);
+        let p2sh = Output::to_output(&P2SHOutput { output: Box::new(spend) });
+        assert_eq!(offer.to_p2sh_output().script.to_vec(), p2sh.script.to_vec());
+        assert_eq!(offer.to_p2sh_output().value, p2sh.value);
+    }
+}
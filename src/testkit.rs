@@ -0,0 +1,183 @@
+use crate::address::{Address, AddressType};
+use crate::script::Script;
+use crate::tx::{Tx, TxOutput, tx_hash_to_hex};
+use crate::hex_traits::{FromHex, ToHex};
+
+use serde_json::Value;
+
+
+// Runs the standard test vectors against this crate, e.g. in the CI of downstream users to
+// check their build features. Vectors this crate can't represent are counted as skipped.
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TestkitError {
+    InvalidJson(String),
+    InvalidVector { idx: usize },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TestVectorFailure {
+    pub idx: usize,
+    pub expected: String,
+    pub actual: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TestVectorReport {
+    pub passed: usize,
+    pub skipped: usize,
+    pub failures: Vec<TestVectorFailure>,
+}
+
+impl TestVectorReport {
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    // Panics listing every failed vector, for use in tests.
+    pub fn assert_ok(&self) {
+        if self.is_ok() {
+            return;
+        }
+        let failures = self.failures.iter()
+            .map(|failure| format!("vector {}: expected {}, got {}",
+                                   failure.idx, failure.expected, failure.actual))
+            .collect::<Vec<_>>();
+        panic!("{} of {} test vectors failed:\n{}",
+               self.failures.len(), self.passed + self.failures.len(), failures.join("\n"));
+    }
+
+    fn check(&mut self, idx: usize, expected: String, actual: String) {
+        if expected == actual {
+            self.passed += 1;
+        } else {
+            self.failures.push(TestVectorFailure { idx, expected, actual });
+        }
+    }
+}
+
+fn parse_vectors(json: &str) -> Result<Vec<Value>, TestkitError> {
+    match serde_json::from_str(json) {
+        Ok(Value::Array(vectors)) => Ok(vectors),
+        Ok(_) => Err(TestkitError::InvalidJson("expected an array of test vectors".to_string())),
+        Err(err) => Err(TestkitError::InvalidJson(err.to_string())),
+    }
+}
+
+// Runs sighash.json of Bitcoin ABC/BCHN, with entries of the form [raw_tx, script, input_idx,
+// sighash_type, sighash, ...]. Only the BCH sighash (FORKID set) is implemented, so vectors
+// with legacy sighash types are skipped, as are vectors this crate can't serialize byte for
+// byte. Like the node's tests, the spent amount is 0.
+pub fn run_sighash_vectors(json: &str) -> Result<TestVectorReport, TestkitError> {
+    let mut report = TestVectorReport::default();
+    for (idx, vector) in parse_vectors(json)?.iter().enumerate() {
+        let fields = match vector.as_array() {
+            Some(fields) if fields.len() >= 5 => fields,
+            _ => continue,  // comment
+        };
+        let invalid = || TestkitError::InvalidVector { idx };
+        let raw_tx = fields[0].as_str().ok_or_else(invalid)?;
+        let raw_script = fields[1].as_str().ok_or_else(invalid)?;
+        let input_idx = fields[2].as_u64().ok_or_else(invalid)? as usize;
+        let sighash_type = fields[3].as_i64().ok_or_else(invalid)? as u32;
+        let expected = fields[4].as_str().ok_or_else(invalid)?;
+        if sighash_type & 0x40 == 0 {
+            report.skipped += 1;
+            continue;
+        }
+        let tx = match Tx::from_hex(raw_tx) {
+            Ok(tx) if tx.to_hex() == raw_tx && input_idx < tx.inputs().len() => tx,
+            _ => {
+                report.skipped += 1;
+                continue;
+            },
+        };
+        let script_code = match Script::from_hex(raw_script) {
+            Ok(script) if script.to_vec_sig().to_hex() == raw_script => script,
+            _ => {
+                report.skipped += 1;
+                continue;
+            },
+        };
+        let spent_output = TxOutput::new(0, Script::empty());
+        let pre_image = tx.pre_image(input_idx, &spent_output, script_code, sighash_type);
+        report.check(idx, expected.to_string(), tx_hash_to_hex(&pre_image.sighash()));
+    }
+    Ok(report)
+}
+
+// Runs the vectors of the cashaddr spec as objects with the columns of its table:
+// {"payload_size": 20, "type": 0, "cashaddr": "bitcoincash:...", "payload": "<hex>"}.
+// Addresses hold 20 byte P2PKH or P2SH hashes, so other sizes and types are skipped.
+pub fn run_cashaddr_vectors(json: &str) -> Result<TestVectorReport, TestkitError> {
+    let mut report = TestVectorReport::default();
+    for (idx, vector) in parse_vectors(json)?.iter().enumerate() {
+        if !vector.is_object() {
+            continue;  // comment
+        }
+        let invalid = || TestkitError::InvalidVector { idx };
+        let addr_type = vector["type"].as_u64().ok_or_else(invalid)?;
+        let cash_addr = vector["cashaddr"].as_str().ok_or_else(invalid)?.to_lowercase();
+        let payload = vector["payload"].as_str().ok_or_else(invalid)?.to_lowercase();
+        let addr_type = match addr_type {
+            0 => AddressType::P2PKH,
+            1 => AddressType::P2SH,
+            _ => {
+                report.skipped += 1;
+                continue;
+            },
+        };
+        let prefix = cash_addr.split(':').next().unwrap_or_default();
+        let bytes = Vec::<u8>::from_hex(&payload).map_err(|_| invalid())?;
+        let encoded = match Address::from_slice_prefix(prefix, addr_type, &bytes) {
+            Some(address) => address,
+            None => {
+                report.skipped += 1;
+                continue;
+            },
+        };
+        report.check(idx, cash_addr.clone(), encoded.cash_addr().to_string());
+        let decoded = match Address::from_cash_addr(cash_addr) {
+            Ok(address) => format!("{:?} {}", address.addr_type(), address.bytes().to_hex()),
+            Err(err) => format!("{:?}", err),
+        };
+        report.check(idx, format!("{:?} {}", addr_type, payload), decoded);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tx::{TxInput, TxOutpoint};
+
+    #[test]
+    fn test_run_vectors() {
+        let report = run_cashaddr_vectors(r#"[
+            "payload_size, type, cashaddr, payload",
+            {"payload_size": 20, "type": 0,
+             "cashaddr": "bitcoincash:qr6m7j9njldwwzlg9v7v53unlr4jkmx6eylep8ekg2",
+             "payload": "F5BF48B397DAE70BE82B3CCA4793F8EB2B6CDAC9"},
+            {"payload_size": 20, "type": 15,
+             "cashaddr": "pref:0r6m7j9njldwwzlg9v7v53unlr4jkmx6ey3qnjwsrf",
+             "payload": "F5BF48B397DAE70BE82B3CCA4793F8EB2B6CDAC9"}
+        ]"#).unwrap();
+        report.assert_ok();
+        assert_eq!((report.passed, report.skipped), (2, 1));
+
+        let tx = Tx::new(2, vec![TxInput::new(
+            TxOutpoint { tx_hash: [1; 32], vout: 0 }, Script::empty(), 0,
+        )], vec![TxOutput::new(1000, Script::from_hex("6a").unwrap())], 0);
+        let spent_output = TxOutput::new(0, Script::empty());
+        let sighash = tx.pre_image(0, &spent_output, Script::from_hex("51").unwrap(), 0x41).sighash();
+        let json = format!(r#"[["raw_transaction, script, input_index, hashType, signature_hash"],
+                               ["{tx}", "51", 0, 65, "{hash}"],
+                               ["{tx}", "51", 0, 1, "{hash}"],
+                               ["{tx}", "51", 0, 65, "{zero}"]]"#,
+                           tx = tx.to_hex(), hash = tx_hash_to_hex(&sighash), zero = [0; 32].to_hex());
+        let report = run_sighash_vectors(&json).unwrap();
+        assert_eq!((report.passed, report.skipped, report.failures.len()), (1, 1, 1));
+        assert_eq!(report.failures[0].idx, 3);
+        assert_eq!(run_sighash_vectors("{}"), Err(TestkitError::InvalidJson("expected an array of test vectors".to_string())));
+    }
+}
@@ -0,0 +1,198 @@
+use crate::unsigned_tx::{Output, PreImage, UnsignedTx, UnsignedInput};
+use crate::address::Address;
+use crate::script::{Script, Op, OpCodeType};
+use crate::tx::{TxOutput, TxOutpoint};
+use crate::serialize::encode_int64;
+use crate::spend::{LockingScript, Unlocker, Spend};
+
+pub const LOCK_TIME_THRESHOLD: u32 = 500_000_000;
+pub const SEQUENCE_LOCK_TIME_ENABLED: u32 = 0xffff_fffe;
+pub const SEQUENCE_LOCK_TIME_TYPE_FLAG: u32 = 1 << 22;
+pub const SEQUENCE_LOCK_TIME_MASK: u32 = 0x0000_ffff;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TimeLockError {
+    MixedLockTimeTypes { tx_lock_time: u32, output_lock_time: u32 },
+    TxVersionTooLow(i32),
+    RelativeLockTooLong { seconds: u32 },
+    // Heights at or above LOCK_TIME_THRESHOLD would be read as timestamps.
+    InvalidChainHeight(u32),
+}
+
+#[derive(Clone, Debug)]
+pub struct TimeLockOutput {
+    pub value: u64,
+    pub address: Address,
+    pub lock_time: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct RelativeTimeLockOutput {
+    pub value: u64,
+    pub sender_pk: Vec<u8>,
+    pub receiver_pk: Vec<u8>,
+    pub sequence: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RelativeTimeLockSpendParams {
+    Cooperative {
+        sender_sig: Vec<u8>,
+    },
+    Timeout,
+}
+
+pub fn relative_lock_blocks(blocks: u16) -> u32 {
+    blocks as u32
+}
+
+// Relative lock in units of 512 seconds, rounded down. Fails above 0xffff units (~388 days).
+pub fn relative_lock_seconds(seconds: u32) -> Result<u32, TimeLockError> {
+    let units = seconds / 512;
+    if units > SEQUENCE_LOCK_TIME_MASK {
+        return Err(TimeLockError::RelativeLockTooLong { seconds });
+    }
+    Ok(SEQUENCE_LOCK_TIME_TYPE_FLAG | units)
+}
+
+impl TimeLockOutput {
+    pub fn is_block_height(&self) -> bool {
+        self.lock_time < LOCK_TIME_THRESHOLD
+    }
+
+    /// Adds this output as an input to `tx`, setting a non-final sequence and raising
+    /// the tx's lock_time so OP_CHECKLOCKTIMEVERIFY passes.
+    pub fn add_as_input(self, tx: &mut UnsignedTx, outpoint: TxOutpoint)
+            -> Result<usize, TimeLockError> {
+        let tx_lock_time = tx.lock_time();
+        if tx_lock_time != 0 &&
+                (tx_lock_time < LOCK_TIME_THRESHOLD) != self.is_block_height() {
+            return Err(TimeLockError::MixedLockTimeTypes {
+                tx_lock_time,
+                output_lock_time: self.lock_time,
+            });
+        }
+        tx.set_lock_time(tx_lock_time.max(self.lock_time));
+        Ok(tx.add_input(UnsignedInput::new(outpoint, Box::new(self), SEQUENCE_LOCK_TIME_ENABLED)))
+    }
+}
+
+impl Output for TimeLockOutput {
+    fn value(&self) -> u64 {
+        self.value
+    }
+
+    fn script(&self) -> Script {
+        Script::new(vec![
+            Op::Push(encode_int64(self.lock_time as i64)),
+            Op::Code(OpCodeType::OpCheckLockTimeVerify),
+            Op::Code(OpCodeType::OpDrop),
+            Op::Code(OpCodeType::OpDup),
+            Op::Code(OpCodeType::OpHash160),
+            Op::Push(self.address.bytes().to_vec()),
+            Op::Code(OpCodeType::OpEqualVerify),
+            Op::Code(OpCodeType::OpCheckSig),
+        ])
+    }
+
+    fn script_code(&self) -> Script {
+        Output::script(self)
+    }
+
+    fn sig_script(&self,
+                  serialized_sig: Vec<u8>,
+                  serialized_pub_key: Vec<u8>,
+                  _pre_image: &PreImage,
+                  _outputs: &[TxOutput]) -> Script {
+        Script::new(vec![
+            Op::Push(serialized_sig),
+            Op::Push(serialized_pub_key),
+        ])
+    }
+}
+
+impl RelativeTimeLockOutput {
+    /// Adds this output as an input to `tx`, spent with `spend_params`. The timeout branch
+    /// requires a version 2 tx and the relative lock as input sequence (BIP68).
+    pub fn add_as_input(self,
+                        spend_params: RelativeTimeLockSpendParams,
+                        tx: &mut UnsignedTx,
+                        outpoint: TxOutpoint) -> Result<usize, TimeLockError> {
+        let sequence = match spend_params {
+            RelativeTimeLockSpendParams::Timeout => {
+                if tx.version() < 2 {
+                    return Err(TimeLockError::TxVersionTooLow(tx.version()));
+                }
+                self.sequence
+            },
+            RelativeTimeLockSpendParams::Cooperative { .. } => 0xffff_ffff,
+        };
+        Ok(tx.add_input(UnsignedInput::new(
+            outpoint,
+            Box::new(Spend::new(self, spend_params)),
+            sequence,
+        )))
+    }
+}
+
+impl LockingScript for RelativeTimeLockOutput {
+    fn value(&self) -> u64 {
+        self.value
+    }
+
+    fn script(&self) -> Script {
+        use crate::script::OpCodeType::*;
+        Script::new(vec![
+            Op::Code(OpIf),
+            Op::Push(self.receiver_pk.clone()),
+            Op::Code(OpCheckSigVerify),
+            Op::Push(self.sender_pk.clone()),
+            Op::Code(OpCheckSig),
+            Op::Code(OpElse),
+            Op::Push(encode_int64(self.sequence as i64)),
+            Op::Code(OpCheckSequenceVerify),
+            Op::Code(OpDrop),
+            Op::Push(self.sender_pk.clone()),
+            Op::Code(OpCheckSig),
+            Op::Code(OpEndIf),
+        ])
+    }
+
+    fn script_code(&self) -> Script {
+        self.script()
+    }
+}
+
+impl Unlocker<RelativeTimeLockOutput> for RelativeTimeLockSpendParams {
+    fn sig_script(&self,
+                  _output: &RelativeTimeLockOutput,
+                  serialized_sig: Vec<u8>,
+                  _serialized_pub_key: Vec<u8>,
+                  _pre_image: &PreImage,
+                  _outputs: &[TxOutput]) -> Script {
+        match self {
+            RelativeTimeLockSpendParams::Cooperative { sender_sig } => Script::new(vec![
+                Op::Push(sender_sig.clone()),
+                Op::Push(serialized_sig),
+                Op::Push(vec![1]),
+            ]),
+            RelativeTimeLockSpendParams::Timeout => Script::new(vec![
+                Op::Push(serialized_sig),
+                Op::Push(vec![]),
+            ]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_lock_seconds() {
+        assert_eq!(relative_lock_seconds(1023).unwrap(), SEQUENCE_LOCK_TIME_TYPE_FLAG | 1);
+        assert_eq!(relative_lock_seconds(0xffff * 512 + 511).unwrap(), SEQUENCE_LOCK_TIME_TYPE_FLAG | 0xffff);
+        assert!(matches!(relative_lock_seconds(0x1_0000 * 512),
+                         Err(TimeLockError::RelativeLockTooLong { seconds: 0x200_0000 })));
+    }
+}
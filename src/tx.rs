@@ -1,12 +1,19 @@
-use crate::serialize::{write_var_int, read_var_int};
-use crate::script::Script;
-use crate::hash::double_sha256;
+use crate::serialize::{write_var_int, read_var_int_minimal, read_bytes};
+use crate::script::{Script, Op, OpCodeType};
+use crate::hash::HashWriter;
+use crate::cash_tokens::{TokenData, split_token_prefix};
+use crate::unsigned_tx::PreImage;
+use crate::crypto::Crypto;
+use crate::decode::is_pub_key;
 
-use std::io;
+use std::io::{self, Write};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
 
-#[derive(Clone, Debug)]
+// Consensus limit on the size of a tx.
+pub const MAX_TX_SIZE: usize = 1_000_000;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct TxOutpoint {
     pub tx_hash: [u8; 32],
     pub vout: u32,
@@ -23,6 +30,7 @@ pub struct TxInput {
 pub struct TxOutput {
     pub value: u64,
     pub script: Script,
+    pub token: Option<TokenData>,
 }
 
 #[derive(Clone, Debug)]
@@ -33,6 +41,34 @@ pub struct Tx {
     lock_time: u32,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SigVerifyError {
+    SpentOutputsMismatch { expected: usize, actual: usize },
+    InvalidSignature { input_idx: usize },
+}
+
+// A signature of input `input_idx`, valid if it verifies `sighash` for one of `pub_keys`.
+// Multisig inputs have several candidate pub keys.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerificationJob {
+    pub input_idx: usize,
+    pub sighash: [u8; 32],
+    pub signature: Vec<u8>,
+    pub pub_keys: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TxFeeError {
+    SpentOutputsMismatch { expected: usize, actual: usize },
+    ValueOverflow,
+    OutputsExceedInputs { input_amount: u64, output_amount: u64 },
+}
+
+const SIGHASH_NONE: u32 = 0x02;
+const SIGHASH_SINGLE: u32 = 0x03;
+const SIGHASH_ANYONECANPAY: u32 = 0x80;
+const SIGHASH_FORKID: u32 = 0x40;
+
 pub fn tx_hex_to_hash(s: &str) -> Option<[u8; 32]> {
     let mut tx_hash = [0; 32];
     let tx_hash_slice = hex::decode(s).ok()?;
@@ -42,7 +78,7 @@ pub fn tx_hex_to_hash(s: &str) -> Option<[u8; 32]> {
 }
 
 pub fn tx_hash_to_hex(tx_hash: &[u8; 32]) -> String {
-    hex::encode(&tx_hash.iter().rev().cloned().collect::<Vec<_>>())
+    hex::encode(tx_hash.iter().rev().cloned().collect::<Vec<_>>())
 }
 
 impl TxOutpoint {
@@ -54,6 +90,15 @@ impl TxOutpoint {
     }
 }
 
+// Serialized scripts, token prefix included, can't be larger than the tx.
+fn read_script<R: io::Read>(read: &mut R) -> io::Result<Vec<u8>> {
+    let script_len = read_var_int_minimal(read)?;
+    if script_len > MAX_TX_SIZE as u64 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Script larger than max tx size"));
+    }
+    read_bytes(read, script_len)
+}
+
 impl TxInput {
     pub fn new(outpoint: TxOutpoint,
                script: Script,
@@ -69,9 +114,7 @@ impl TxInput {
         let mut tx_hash = [0; 32];
         read.read_exact(&mut tx_hash)?;
         let vout = read.read_u32::<LittleEndian>()?;
-        let script_len = read_var_int(read)?;
-        let mut script = vec![0; script_len as usize];
-        read.read_exact(&mut script[..])?;
+        let script = read_script(read)?;
         let sequence = read.read_u32::<LittleEndian>()?;
         Ok(TxInput {
             outpoint: TxOutpoint {tx_hash, vout},
@@ -95,29 +138,43 @@ impl TxInput {
 impl TxOutput {
     pub fn new(value: u64,
                script: Script) -> Self {
-        TxOutput { value, script }
+        TxOutput { value, script, token: None }
+    }
+
+    pub fn new_with_token(value: u64,
+                          script: Script,
+                          token: TokenData) -> Self {
+        TxOutput { value, script, token: Some(token) }
     }
 
     pub fn read_from_stream<R: io::Read>(read: &mut R) -> io::Result<Self> {
         let value = read.read_u64::<LittleEndian>()?;
-        let script_len = read_var_int(read)?;
-        let mut script = vec![0; script_len as usize];
-        read.read_exact(&mut script[..])?;
+        let script = read_script(read)?;
+        let (token, script) = split_token_prefix(&script)?;
         Ok(TxOutput {
             value,
-            script: Script::from_serialized(&script)
+            script: Script::from_serialized(script)
                 .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid script"))?,
+            token,
         })
     }
 
     pub fn write_to_stream<W: io::Write>(&self, write: &mut W) -> io::Result<()> {
         write.write_u64::<LittleEndian>(self.value)?;
-        let script = self.script.to_vec();
+        let mut script = Vec::new();
+        if let Some(token) = &self.token {
+            token.write_prefix(&mut script)?;
+        }
+        script.append(&mut self.script.to_vec());
         write_var_int(write, script.len() as u64)?;
         write.write_all(&script)?;
         Ok(())
     }
 
+    pub fn token(&self) -> Option<&TokenData> {
+        self.token.as_ref()
+    }
+
     pub fn script(&self) -> &Script {
         &self.script
     }
@@ -132,19 +189,19 @@ impl Tx {
     }
 
     pub fn hash(&self) -> [u8; 32] {
-        let mut vec = Vec::new();
-        self.write_to_stream(&mut vec).unwrap();
-        double_sha256(&vec)
+        let mut hasher = HashWriter::new();
+        self.write_to_stream(&mut hasher).unwrap();
+        hasher.finish_double()
     }
 
     pub fn read_from_stream<R: io::Read>(read: &mut R) -> io::Result<Self> {
         let version = read.read_i32::<LittleEndian>()?;
-        let num_inputs = read_var_int(read)?;
+        let num_inputs = read_var_int_minimal(read)?;
         let mut inputs = Vec::new();
         for _ in 0..num_inputs {
             inputs.push(TxInput::read_from_stream(read)?);
         }
-        let num_outputs = read_var_int(read)?;
+        let num_outputs = read_var_int_minimal(read)?;
         let mut outputs = Vec::new();
         for _ in 0..num_outputs {
             outputs.push(TxOutput::read_from_stream(read)?);
@@ -174,4 +231,368 @@ impl Tx {
     pub fn outputs(&self) -> &[TxOutput] {
         &self.outputs
     }
+
+    pub fn version(&self) -> i32 {
+        self.version
+    }
+
+    pub fn lock_time(&self) -> u32 {
+        self.lock_time
+    }
+
+    // Coinbase txs have a single input spending the null outpoint.
+    pub fn is_coinbase(&self) -> bool {
+        match self.inputs.as_slice() {
+            [input] => input.outpoint.tx_hash == [0; 32] && input.outpoint.vout == 0xffff_ffff,
+            _ => false,
+        }
+    }
+
+    // Serialized size in bytes.
+    pub fn size(&self) -> usize {
+        let mut vec = Vec::new();
+        self.write_to_stream(&mut vec).unwrap();
+        vec.len()
+    }
+
+    // Fee paid by the tx; `spent_outputs` are the outputs spent by the inputs.
+    pub fn fee(&self, spent_outputs: &[TxOutput]) -> Result<u64, TxFeeError> {
+        if spent_outputs.len() != self.inputs.len() {
+            return Err(TxFeeError::SpentOutputsMismatch {
+                expected: self.inputs.len(),
+                actual: spent_outputs.len(),
+            });
+        }
+        let sum = |outputs: &[TxOutput]| outputs.iter()
+            .try_fold(0u64, |sum, output| sum.checked_add(output.value))
+            .ok_or(TxFeeError::ValueOverflow);
+        let input_amount = sum(spent_outputs)?;
+        let output_amount = sum(&self.outputs)?;
+        input_amount.checked_sub(output_amount)
+            .ok_or(TxFeeError::OutputsExceedInputs { input_amount, output_amount })
+    }
+
+    // Fee in satoshis per byte.
+    pub fn fee_rate(&self, spent_outputs: &[TxOutput]) -> Result<f64, TxFeeError> {
+        Ok(self.fee(spent_outputs)? as f64 / self.size() as f64)
+    }
+
+    // Pre-image of the input spending `spent_output`, following the BIP143-style algorithm
+    // for all sighash types.
+    pub fn pre_image(&self,
+                     input_idx: usize,
+                     spent_output: &TxOutput,
+                     script_code: Script,
+                     sighash_type: u32) -> PreImage {
+        let input = &self.inputs[input_idx];
+        let hashes = SighashHashes::new(
+            self.inputs.iter().map(|input| (&input.outpoint, input.sequence)),
+            &self.outputs,
+        );
+        let (hash_prevouts, hash_sequence, hash_outputs) =
+            hashes.for_input(input_idx, &self.outputs, sighash_type);
+        PreImage {
+            version: self.version,
+            hash_prevouts,
+            hash_sequence,
+            outpoint: input.outpoint.clone(),
+            token: spent_output.token.clone(),
+            script_code,
+            value: spent_output.value,
+            sequence: input.sequence,
+            hash_outputs,
+            lock_time: self.lock_time,
+            sighash_type,
+        }
+    }
+
+    // Signatures to check for the tx: the tx signatures of inputs spending P2PKH, P2PK and
+    // multisig scripts, bare or wrapped in P2SH, at the positions of those templates' sig
+    // scripts. Inputs spending other scripts, e.g. covenants, aren't checked. `spent_outputs`
+    // are the outputs spent by the inputs.
+    pub fn verification_jobs(&self, spent_outputs: &[TxOutput])
+            -> Result<Vec<VerificationJob>, SigVerifyError> {
+        if spent_outputs.len() != self.inputs.len() {
+            return Err(SigVerifyError::SpentOutputsMismatch {
+                expected: self.inputs.len(),
+                actual: spent_outputs.len(),
+            });
+        }
+        let mut jobs = Vec::new();
+        for (input_idx, (input, spent_output)) in self.inputs.iter().zip(spent_outputs).enumerate() {
+            let pushes = script_pushes(&input.script);
+            let (script_code, sig_pushes) = match spent_output.script.ops() {
+                [Op::Code(OpCodeType::OpHash160), Op::Push(hash), Op::Code(OpCodeType::OpEqual)]
+                        if hash.len() == 20 => {
+                    match pushes.split_last()
+                            .and_then(|(redeem_script, rest)| Some((Script::from_serialized(redeem_script)?, rest))) {
+                        Some(split) => split,
+                        None => continue,
+                    }
+                },
+                _ => (spent_output.script.clone(), &pushes[..]),
+            };
+            for (push, pub_keys) in template_signatures(&script_code, sig_pushes) {
+                if let Some((sig, sighash_type)) = split_tx_signature(push) {
+                    jobs.push(VerificationJob {
+                        input_idx,
+                        sighash: self.pre_image(input_idx, spent_output, script_code.clone(), sighash_type)
+                            .sighash(),
+                        signature: sig.to_vec(),
+                        pub_keys,
+                    });
+                }
+            }
+        }
+        Ok(jobs)
+    }
+
+    // Inputs with ECDSA tx signatures. Those can be re-encoded into another valid signature
+    // (e.g. by negating S) without the key, changing the txid, while Schnorr signatures can't.
+    // Without the spent outputs, the template is inferred from the sig script: a P2SH redeem
+    // script of a known template, <sig> <pub key> for P2PKH or a single <sig> for P2PK.
+    pub fn malleable_inputs(&self) -> Vec<usize> {
+        self.inputs.iter()
+            .enumerate()
+            .filter(|(_, input)| {
+                let pushes = script_pushes(&input.script);
+                let p2sh_sigs = pushes.split_last()
+                    .and_then(|(redeem_script, rest)| {
+                        Some(template_signatures(&Script::from_serialized(redeem_script)?, rest))
+                    })
+                    .filter(|sigs| !sigs.is_empty())
+                    .map(|sigs| sigs.into_iter().map(|(sig, _)| sig).collect());
+                let sigs = p2sh_sigs.unwrap_or_else(|| match pushes.as_slice() {
+                    [sig, pub_key] if is_pub_key(pub_key) => vec![*sig],
+                    [sig] => vec![*sig],
+                    _ => vec![],
+                });
+                sigs.into_iter()
+                    .filter_map(split_tx_signature)
+                    .any(|(sig, _)| sig.len() != 64)
+            })
+            .map(|(input_idx, _)| input_idx)
+            .collect()
+    }
+
+    // Checks every tx signature, see verification_jobs.
+    pub fn verify_input_signatures<C: Crypto>(&self, crypto: &C, spent_outputs: &[TxOutput])
+            -> Result<(), SigVerifyError> {
+        let jobs = self.verification_jobs(spent_outputs)?;
+        verify_jobs(crypto, &jobs)
+            .map_err(|job_idx| SigVerifyError::InvalidSignature { input_idx: jobs[job_idx].input_idx })
+    }
+}
+
+// Verifies jobs of any number of txs, one after another. Returns the index of the first invalid
+// job, i.e. one whose signature doesn't verify against any of its candidate pub keys.
+pub fn verify_jobs<C: Crypto>(crypto: &C, jobs: &[VerificationJob]) -> Result<(), usize> {
+    match jobs.iter().position(|job| {
+        !job.pub_keys.iter().any(|pub_key| crypto.verify(&job.sighash, &job.signature, pub_key))
+    }) {
+        Some(job_idx) => Err(job_idx),
+        None => Ok(()),
+    }
+}
+
+// Hashes of the pre-image shared by all inputs of a tx.
+pub(crate) struct SighashHashes {
+    hash_prevouts: [u8; 32],
+    hash_sequence: [u8; 32],
+    hash_outputs: [u8; 32],
+}
+
+impl SighashHashes {
+    pub(crate) fn new<'a>(inputs: impl Iterator<Item=(&'a TxOutpoint, u32)>,
+                          outputs: &[TxOutput]) -> Self {
+        let mut prevouts = HashWriter::new();
+        let mut sequences = HashWriter::new();
+        for (outpoint, sequence) in inputs {
+            prevouts.write_all(&outpoint.bytes()).unwrap();
+            sequences.write_u32::<LittleEndian>(sequence).unwrap();
+        }
+        let mut hasher = HashWriter::new();
+        for output in outputs.iter() {
+            output.write_to_stream(&mut hasher).unwrap();
+        }
+        SighashHashes {
+            hash_prevouts: prevouts.finish_double(),
+            hash_sequence: sequences.finish_double(),
+            hash_outputs: hasher.finish_double(),
+        }
+    }
+
+    // hash_prevouts, hash_sequence and hash_outputs signed by input `input_idx`: ANYONECANPAY
+    // zeros the first two, NONE and SINGLE zero hash_sequence, NONE zeros hash_outputs and
+    // SINGLE hashes only the output at `input_idx`, or zeros it if there is no such output.
+    pub(crate) fn for_input(&self, input_idx: usize, outputs: &[TxOutput], sighash_type: u32)
+            -> ([u8; 32], [u8; 32], [u8; 32]) {
+        let base_type = sighash_type & 0x1f;
+        let anyone_can_pay = sighash_type & SIGHASH_ANYONECANPAY != 0;
+        let is_single_or_none = base_type == SIGHASH_SINGLE || base_type == SIGHASH_NONE;
+        let hash_prevouts = if anyone_can_pay { [0; 32] } else { self.hash_prevouts };
+        let hash_sequence = if anyone_can_pay || is_single_or_none { [0; 32] } else { self.hash_sequence };
+        let hash_outputs = match outputs.get(input_idx) {
+            _ if !is_single_or_none => self.hash_outputs,
+            Some(output) if base_type == SIGHASH_SINGLE => {
+                let mut hasher = HashWriter::new();
+                output.write_to_stream(&mut hasher).unwrap();
+                hasher.finish_double()
+            },
+            _ => [0; 32],
+        };
+        (hash_prevouts, hash_sequence, hash_outputs)
+    }
+}
+
+fn script_pushes(script: &Script) -> Vec<&[u8]> {
+    script.ops().iter()
+        .filter_map(|op| match op {
+            Op::Push(push) => Some(push.as_slice()),
+            _ => None,
+        })
+        .collect()
+}
+
+// Pushes of a sig script spending `script_code` at the signature positions of the P2PKH, P2PK
+// and multisig templates, with the pub keys they can be checked against.
+fn template_signatures<'a>(script_code: &Script, pushes: &[&'a [u8]]) -> Vec<(&'a [u8], Vec<Vec<u8>>)> {
+    match (script_code.ops(), pushes) {
+        ([Op::Code(OpCodeType::OpDup), Op::Code(OpCodeType::OpHash160), Op::Push(hash),
+          Op::Code(OpCodeType::OpEqualVerify), Op::Code(OpCodeType::OpCheckSig)], [sig, pub_key])
+                if hash.len() == 20 && is_pub_key(pub_key) => {
+            vec![(*sig, vec![pub_key.to_vec()])]
+        },
+        ([Op::Push(pub_key), Op::Code(OpCodeType::OpCheckSig)], [sig]) if is_pub_key(pub_key) => {
+            vec![(*sig, vec![pub_key.clone()])]
+        },
+        ([Op::Code(required), keys @ .., Op::Code(num_keys), Op::Code(OpCodeType::OpCheckMultiSig)],
+         [_dummy, sigs @ ..]) => {
+            let small_int = |code: &OpCodeType| match *code {
+                code if code >= OpCodeType::Op1 && code <= OpCodeType::Op16 =>
+                    Some((code as u8 - OpCodeType::Op1 as u8 + 1) as usize),
+                _ => None,
+            };
+            let pub_keys = keys.iter()
+                .filter_map(|op| match op {
+                    Op::Push(pub_key) if is_pub_key(pub_key) => Some(pub_key.clone()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            if small_int(required) != Some(sigs.len()) || small_int(num_keys) != Some(keys.len()) ||
+                    pub_keys.len() != keys.len() {
+                return vec![];
+            }
+            sigs.iter().map(|sig| (*sig, pub_keys.clone())).collect()
+        },
+        _ => vec![],
+    }
+}
+
+// Splits a pushed tx signature into the signature and its sighash type.
+pub(crate) fn split_tx_signature(push: &[u8]) -> Option<(&[u8], u32)> {
+    let (&sighash_type, sig) = push.split_last()?;
+    if sighash_type as u32 & SIGHASH_FORKID == 0 {
+        return None;
+    }
+    let is_schnorr = sig.len() == 64;
+    let is_der = sig.len() >= 8 && sig[0] == 0x30 && sig[1] as usize == sig.len() - 2;
+    if is_schnorr || is_der {
+        Some((sig, sighash_type as u32))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hex_traits::{FromHex, ToHex};
+
+    #[test]
+    fn test_pre_image_sighash_types() {
+        // BIP143 P2SH-P2WSH example; the digest algorithm is the same as BCH's.
+        let tx = Tx::from_hex("010000000136641869ca081e70f394c6948e8af409e18b619df2ed74aa106c1ca29787b96e\
+                               0100000000ffffffff0200e9a435000000001976a914389ffce9cd9ae88dcc0631e88a821f\
+                               fdbe9bfe2688acc0832f05000000001976a9147480a33f950689af511e6e84c138dbbd3c3e\
+                               e41588ac00000000").unwrap();
+        let script_code = Script::from_hex("56210307b8ae49ac90a048e9b53357a2354b3334e9c8bee813ecb98e99a7e0\
+                                            7e8c3ba32103b28f0c28bfab54554ae8c658ac5c3e0ce6e79ad336331f78c4\
+                                            28dd43eea8449b21034b8113d703413d57761b8b9781957b8c0ac1dfe69f49\
+                                            2580ca4195f50376ba4a21033400f6afecb833092a9a21cfdf1ed1376e58c5\
+                                            d1f47de74683123987e967a8f42103a6d48b1131e94ba04d9737d61acdaa13\
+                                            22008af9602b3b14862c07a1789aac162102d8b661b0b3302ee2f162b09e07\
+                                            a55ad5dfbe673a9f01d9f0c19617681024306b56ae").unwrap();
+        let spent_output = TxOutput::new(987654321, script_code.clone());
+        let sighash = |sighash_type| {
+            tx.pre_image(0, &spent_output, script_code.clone(), sighash_type).sighash().to_hex()
+        };
+        assert_eq!(sighash(0x01), "185c0be5263dce5b4bb50a047973c1b6272bfbd0103a89444597dc40b248ee7c");
+        assert_eq!(sighash(0x02), "e9733bc60ea13c95c6527066bb975a2ff29a925e80aa14c213f686cbae5d2f36");
+        assert_eq!(sighash(0x03), "1e1f1c303dc025bd664acb72e583e933fae4cff9148bf78c157d1e8f78530aea");
+        assert_eq!(sighash(0x81), "2a67f03e63a6a422125878b40b82da593be8d4efaafe88ee528af6e5a9955c6e");
+        assert_eq!(sighash(0x82), "781ba15f3779d5542ce8ecb5c18716733a5ee42a6f51488ec96154934e2c890a");
+        assert_eq!(sighash(0x83), "511e8e52ed574121fc1b654970395502128263f62662e076dc6baf05c2e6a99b");
+
+        // SINGLE without an output at the input's index signs no outputs
+        let tx = Tx::new(1, vec![tx.inputs[0].clone(); 3], tx.outputs.clone(), 0);
+        let pre_image = tx.pre_image(2, &spent_output, script_code, 0x43);
+        assert_eq!(pre_image.hash_outputs, [0; 32]);
+        assert_eq!(pre_image.hash_sequence, [0; 32]);
+    }
+    #[test]
+    fn test_fee() {
+        // BIP143 P2SH-P2WSH example, spending 987654321 sats
+        let tx = Tx::from_hex("010000000136641869ca081e70f394c6948e8af409e18b619df2ed74aa106c1ca29787b96e\
+                               0100000000ffffffff0200e9a435000000001976a914389ffce9cd9ae88dcc0631e88a821f\
+                               fdbe9bfe2688acc0832f05000000001976a9147480a33f950689af511e6e84c138dbbd3c3e\
+                               e41588ac00000000").unwrap();
+        let spent_outputs = vec![TxOutput::new(987654321, Script::empty())];
+        assert_eq!(tx.fee(&spent_outputs), Ok(654_321));
+        assert_eq!(tx.size(), 119);
+        assert_eq!(tx.fee_rate(&spent_outputs).unwrap(), 654_321.0 / 119.0);
+        assert_eq!(tx.fee(&[TxOutput::new(1000, Script::empty())]),
+                   Err(TxFeeError::OutputsExceedInputs { input_amount: 1000, output_amount: 987_000_000 }));
+        assert_eq!(tx.fee(&[]), Err(TxFeeError::SpentOutputsMismatch { expected: 1, actual: 0 }));
+    }
+    #[test]
+    fn test_template_signatures() {
+        // uncompressed pub key whose last byte looks like a FORKID sighash type
+        let mut pub_key = vec![0x04; 65];
+        pub_key[64] = 0x41;
+        let p2pkh = Script::new(vec![
+            Op::Code(OpCodeType::OpDup),
+            Op::Code(OpCodeType::OpHash160),
+            Op::Push(crate::hash::hash160(&pub_key).to_vec()),
+            Op::Code(OpCodeType::OpEqualVerify),
+            Op::Code(OpCodeType::OpCheckSig),
+        ]);
+        let schnorr_sig = [&[0x11; 64][..], &[0x41]].concat();
+        let der_sig = vec![0x30, 6, 2, 1, 1, 2, 1, 1, 0x41];
+        let input = |sig: &[u8]| TxInput::new(
+            TxOutpoint { tx_hash: [1; 32], vout: 0 },
+            Script::new(vec![Op::Push(sig.to_vec()), Op::Push(pub_key.clone())]),
+            0xffff_ffff,
+        );
+        let tx = Tx::new(2, vec![input(&schnorr_sig), input(&der_sig)], vec![], 0);
+        let spent_outputs = vec![TxOutput::new(1000, p2pkh.clone()), TxOutput::new(1000, p2pkh)];
+        let jobs = tx.verification_jobs(&spent_outputs).unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].signature, schnorr_sig[..64].to_vec());
+        assert_eq!(jobs[0].pub_keys, vec![pub_key.clone()]);
+        assert_eq!(jobs[1].signature, der_sig[..8].to_vec());
+        assert_eq!(tx.malleable_inputs(), vec![1]);
+
+        // 65 byte data pushes of unknown scripts aren't signatures
+        let data_input = TxInput::new(
+            TxOutpoint { tx_hash: [1; 32], vout: 1 },
+            Script::new(vec![Op::Push(der_sig.clone()), Op::Push(schnorr_sig.clone())]),
+            0xffff_ffff,
+        );
+        let tx = Tx::new(2, vec![data_input], vec![], 0);
+        assert!(tx.verification_jobs(&[TxOutput::new(1000, Script::new(vec![Op::Code(OpCodeType::Op1)]))])
+            .unwrap()
+            .is_empty());
+        assert!(tx.malleable_inputs().is_empty());
+    }
 }
@@ -0,0 +1,295 @@
+use crate::cash_tokens::{TokenData, split_token_prefix};
+use crate::hash::double_sha256;
+use crate::script::{Op, OpCodeType, Script};
+use crate::serialize::{read_var_int_strict, Error, ErrorKind};
+use crate::tx::{Tx, TxOutpoint};
+
+use std::io;
+use byteorder::{ByteOrder, LittleEndian};
+
+
+// Borrowed counterparts of Script and Tx which point into the parsed bytes instead of copying
+// push data, for scanning large amounts of txs. Convert with to_owned where needed.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpRef<'a> {
+    Push(&'a [u8]),
+    Code(OpCodeType),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScriptRef<'a> {
+    data: &'a [u8],
+}
+
+pub struct ScriptRefOps<'a> {
+    reader: SliceReader<'a>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TxInputRef<'a> {
+    pub tx_hash: &'a [u8],
+    pub vout: u32,
+    pub script: ScriptRef<'a>,
+    pub sequence: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TxOutputRef<'a> {
+    pub value: u64,
+    // Output script including the CashTokens prefix, if any.
+    pub script_with_prefix: &'a [u8],
+}
+
+#[derive(Clone, Debug)]
+pub struct TxRef<'a> {
+    raw: &'a [u8],
+    version: i32,
+    inputs: Vec<TxInputRef<'a>>,
+    outputs: Vec<TxOutputRef<'a>>,
+    lock_time: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct SliceReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> SliceReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        SliceReader { data, offset: 0 }
+    }
+
+    fn error(&self, kind: ErrorKind) -> Error {
+        Error { kind, offset: self.offset as u64 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.offset >= self.data.len()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.data.len() - self.offset < len {
+            return Err(self.error(ErrorKind::UnexpectedEnd));
+        }
+        let slice = &self.data[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(slice)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_u32(&mut self) -> Result<u32, Error> {
+        Ok(LittleEndian::read_u32(self.take(4)?))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, Error> {
+        Ok(LittleEndian::read_u64(self.take(8)?))
+    }
+
+    // Rejects non-minimal var ints, like Tx::read_from_stream.
+    fn read_var_int(&mut self) -> Result<u64, Error> {
+        let mut cur = io::Cursor::new(self.data);
+        cur.set_position(self.offset as u64);
+        let number = read_var_int_strict(&mut cur)?;
+        self.offset = cur.position() as usize;
+        Ok(number)
+    }
+
+    fn read_var_slice(&mut self) -> Result<&'a [u8], Error> {
+        let offset = self.offset;
+        let len = self.read_var_int()?;
+        if len > (self.data.len() - self.offset) as u64 {
+            return Err(Error { kind: ErrorKind::UnexpectedEnd, offset: offset as u64 });
+        }
+        self.take(len as usize)
+    }
+}
+
+impl<'a> OpRef<'a> {
+    pub fn to_owned(self) -> Op {
+        match self {
+            OpRef::Push(data) => Op::Push(data.to_vec()),
+            OpRef::Code(code) => Op::Code(code),
+        }
+    }
+}
+
+impl<'a> ScriptRef<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        ScriptRef { data }
+    }
+
+    pub fn bytes(&self) -> &'a [u8] {
+        self.data
+    }
+
+    // Ops of the script; a push running past the end of the script yields an error.
+    pub fn ops(&self) -> ScriptRefOps<'a> {
+        ScriptRefOps { reader: SliceReader::new(self.data) }
+    }
+
+    pub fn to_owned(&self) -> Option<Script> {
+        Script::from_serialized(self.data)
+    }
+}
+
+impl<'a> Iterator for ScriptRefOps<'a> {
+    type Item = Result<OpRef<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.reader.is_empty() {
+            return None;
+        }
+        let start = self.reader;
+        let op = (|| {
+            let len = match self.reader.read_u8()? {
+                0 => return Ok(OpRef::Push(&[])),
+                len @ 1 ..= 0x4b => len as usize,
+                0x4c => self.reader.read_u8()? as usize,
+                0x4d => LittleEndian::read_u16(self.reader.take(2)?) as usize,
+                0x4e => self.reader.read_u32()? as usize,
+                code => return Ok(OpRef::Code(
+                    num::FromPrimitive::from_u8(code).unwrap_or(OpCodeType::OpInvalidOpcode)
+                )),
+            };
+            Ok(OpRef::Push(self.reader.take(len)?))
+        })();
+        if op.is_err() {
+            // stop after the error, reported at the start of the op
+            self.reader.offset = self.reader.data.len();
+            return Some(Err(start.error(ErrorKind::UnexpectedEnd)));
+        }
+        Some(op)
+    }
+}
+
+impl<'a> TxInputRef<'a> {
+    pub fn outpoint(&self) -> TxOutpoint {
+        let mut tx_hash = [0; 32];
+        tx_hash.copy_from_slice(self.tx_hash);
+        TxOutpoint { tx_hash, vout: self.vout }
+    }
+}
+
+impl<'a> TxOutputRef<'a> {
+    pub fn token(&self) -> io::Result<Option<TokenData>> {
+        Ok(split_token_prefix(self.script_with_prefix)?.0)
+    }
+
+    pub fn script(&self) -> io::Result<ScriptRef<'a>> {
+        Ok(ScriptRef::new(split_token_prefix(self.script_with_prefix)?.1))
+    }
+}
+
+impl<'a> TxRef<'a> {
+    // Parses the tx at the start of `data` and returns it with the number of bytes it spans,
+    // e.g. to parse the txs of a block one after another.
+    pub fn parse_prefix(data: &'a [u8]) -> Result<(Self, usize), Error> {
+        let mut reader = SliceReader::new(data);
+        let version = reader.read_u32()? as i32;
+        let num_inputs = reader.read_var_int()?;
+        let mut inputs = Vec::new();
+        for _ in 0..num_inputs {
+            inputs.push(TxInputRef {
+                tx_hash: reader.take(32)?,
+                vout: reader.read_u32()?,
+                script: ScriptRef::new(reader.read_var_slice()?),
+                sequence: reader.read_u32()?,
+            });
+        }
+        let num_outputs = reader.read_var_int()?;
+        let mut outputs = Vec::new();
+        for _ in 0..num_outputs {
+            outputs.push(TxOutputRef {
+                value: reader.read_u64()?,
+                script_with_prefix: reader.read_var_slice()?,
+            });
+        }
+        let lock_time = reader.read_u32()?;
+        let raw = &data[..reader.offset];
+        Ok((TxRef { raw, version, inputs, outputs, lock_time }, reader.offset))
+    }
+
+    pub fn parse(data: &'a [u8]) -> Result<Self, Error> {
+        let (tx, len) = TxRef::parse_prefix(data)?;
+        if len != data.len() {
+            return Err(Error { kind: ErrorKind::TrailingData, offset: len as u64 });
+        }
+        Ok(tx)
+    }
+
+    pub fn raw(&self) -> &'a [u8] {
+        self.raw
+    }
+
+    // Hashes the parsed bytes directly, without serializing the tx again.
+    pub fn hash(&self) -> [u8; 32] {
+        double_sha256(self.raw)
+    }
+
+    pub fn version(&self) -> i32 {
+        self.version
+    }
+
+    pub fn inputs(&self) -> &[TxInputRef<'a>] {
+        &self.inputs
+    }
+
+    pub fn outputs(&self) -> &[TxOutputRef<'a>] {
+        &self.outputs
+    }
+
+    pub fn lock_time(&self) -> u32 {
+        self.lock_time
+    }
+
+    pub fn to_owned(&self) -> io::Result<Tx> {
+        Tx::read_from_stream(&mut io::Cursor::new(self.raw))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tx::{TxInput, TxOutput};
+
+    #[test]
+    fn test_tx_ref() {
+        let script = Script::new(vec![
+            Op::Push(vec![0x11; 80]),
+            Op::Code(OpCodeType::OpDrop),
+            Op::Push(vec![0x22; 300]),
+        ]);
+        let tx = Tx::new(
+            2,
+            vec![TxInput::new(TxOutpoint { tx_hash: [0x33; 32], vout: 1 }, script.clone(), 0xffff_fffe)],
+            vec![TxOutput::new(1000, script.clone()), TxOutput::new(0, Script::empty())],
+            500,
+        );
+        let mut raw = Vec::new();
+        tx.write_to_stream(&mut raw).unwrap();
+        let tx_ref = TxRef::parse(&raw).unwrap();
+        assert_eq!(tx_ref.hash(), tx.hash());
+        assert_eq!(tx_ref.inputs()[0].outpoint(), tx.inputs()[0].outpoint);
+        let ops = tx_ref.outputs()[0].script().unwrap().ops()
+            .map(|op| op.unwrap().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(ops, script.ops());
+        assert_eq!(tx_ref.to_owned().unwrap().hash(), tx.hash());
+
+        let non_minimal = [&raw[..4], &[0xfd, 0x01, 0x00], &raw[5..]].concat();
+        assert_eq!(TxRef::parse(&non_minimal).unwrap_err(), Error { kind: ErrorKind::NonMinimalVarInt, offset: 4 });
+        raw.push(0);
+        assert_eq!(TxRef::parse(&raw).unwrap_err(), Error { kind: ErrorKind::TrailingData, offset: raw.len() as u64 - 1 });
+        assert_eq!(TxRef::parse(&raw[..50]).unwrap_err().kind, ErrorKind::UnexpectedEnd);
+        let truncated = ScriptRef::new(&[0x01, 0x02, 0x4c, 0x05, 0x00]);
+        assert_eq!(truncated.ops().collect::<Vec<_>>(), vec![
+            Ok(OpRef::Push(&[0x02])),
+            Err(Error { kind: ErrorKind::UnexpectedEnd, offset: 2 }),
+        ]);
+    }
+}
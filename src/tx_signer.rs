@@ -0,0 +1,140 @@
+use crate::crypto::{Crypto, PublicKey, Signature};
+use crate::tx::Tx;
+use crate::unsigned_tx::{UnsignedTx, PreImage};
+use crate::wallet::WalletError;
+#[cfg(feature = "secp256k1")]
+use crate::bip32::DerivationPath;
+
+#[cfg(feature = "secp256k1")]
+use async_trait::async_trait;
+
+
+const SIGHASH_ALL_FORKID: u32 = 0x41;
+
+// Signer holding keys outside of this process, e.g. a hardware wallet. It is only shown the
+// digest to sign and the derivation path of the key to sign it with.
+#[cfg(feature = "secp256k1")]
+#[async_trait]
+pub trait ExternalSigner {
+    type Error;
+
+    // Serialized compressed pub key at `path`.
+    async fn pub_key(&self, path: &DerivationPath) -> Result<[u8; 33], Self::Error>;
+
+    // DER signature of `digest` without sighash byte, made with the key at `path`.
+    async fn sign_digest(&self, digest: &[u8; 32], path: &DerivationPath)
+        -> Result<Vec<u8>, Self::Error>;
+}
+
+// Collects the signatures of an UnsignedTx input by input, from any mix of local keys,
+// external signers and signatures obtained elsewhere.
+pub struct TxSigner<'a> {
+    tx_build: &'a UnsignedTx,
+    pre_images: Vec<PreImage>,
+    signatures: Vec<Option<(Vec<u8>, Vec<u8>)>>,
+}
+
+impl<'a> TxSigner<'a> {
+    pub fn new(tx_build: &'a UnsignedTx) -> Self {
+        let pre_images = tx_build.pre_images(SIGHASH_ALL_FORKID);
+        TxSigner {
+            tx_build,
+            signatures: vec![None; pre_images.len()],
+            pre_images,
+        }
+    }
+
+    pub fn pre_images(&self) -> &[PreImage] {
+        &self.pre_images
+    }
+
+    // Digest the signature of input `idx` has to sign.
+    pub fn sig_hash(&self, idx: usize) -> [u8; 32] {
+        self.pre_images[idx].sighash()
+    }
+
+    pub fn add_signature(&mut self, idx: usize, signature: Vec<u8>, pub_key: Vec<u8>) {
+        self.signatures[idx] = Some((signature, pub_key));
+    }
+
+    pub fn sign_input<C: Crypto>(&mut self, idx: usize, crypto: &C, secret_key: &C::SecretKey) {
+        let sig_hash = self.sig_hash(idx);
+        let signature = if self.tx_build.is_schnorr() {
+            crypto.sign_schnorr(&sig_hash, secret_key).to_vec()
+        } else {
+            crypto.sign(&sig_hash, secret_key).serialize_der()
+        };
+        let pub_key = crypto.secret_to_pub_key(secret_key).serialize();
+        self.add_signature(idx, signature, pub_key.to_vec());
+    }
+
+    #[cfg(feature = "secp256k1")]
+    pub async fn sign_input_external<S: ExternalSigner + Sync>(&mut self,
+                                                               idx: usize,
+                                                               signer: &S,
+                                                               path: &DerivationPath)
+            -> Result<(), S::Error> {
+        let sig_hash = self.sig_hash(idx);
+        let pub_key = signer.pub_key(path).await?;
+        let signature = signer.sign_digest(&sig_hash, path).await?;
+        self.add_signature(idx, signature, pub_key.to_vec());
+        Ok(())
+    }
+
+    // Indices of inputs which have no signature yet.
+    pub fn missing_inputs(&self) -> Vec<usize> {
+        self.signatures.iter()
+            .enumerate()
+            .filter(|(_, signature)| signature.is_none())
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.signatures.iter().all(Option::is_some)
+    }
+
+    pub fn finish(self) -> Result<Tx, WalletError> {
+        if let Some(&idx) = self.missing_inputs().first() {
+            return Err(WalletError::MissingSignature { idx });
+        }
+        let (signatures, pub_keys) = self.signatures.into_iter()
+            .map(Option::unwrap)
+            .unzip();
+        self.tx_build.sign(signatures, pub_keys)
+    }
+}
+
+#[cfg(all(test, feature = "secp256k1"))]
+mod tests {
+    use super::*;
+    use crate::address::{Address, AddressType};
+    use crate::crypto::secp256k1::CryptoSecp256k1;
+    use crate::outputs::P2PKHOutput;
+    use crate::tx::TxOutpoint;
+    use crate::unsigned_tx::{UnsignedInput, Output};
+
+    #[test]
+    fn test_sign_input_schnorr() {
+        let crypto = CryptoSecp256k1::new();
+        let secret_key = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let pub_key = crypto.secret_to_pub_key(&secret_key).serialize();
+        let address = Address::from_serialized_pub_key("bitcoincash", AddressType::P2PKH, &pub_key);
+        let spent_output = P2PKHOutput { value: 10_000, address: address.clone() };
+        let mut tx_build = UnsignedTx::new_simple();
+        tx_build.add_input(UnsignedInput::new(
+            TxOutpoint { tx_hash: [0x11; 32], vout: 0 },
+            Box::new(spent_output.clone()),
+            0xffff_ffff,
+        ));
+        tx_build.add_output(P2PKHOutput { value: 9_000, address }.to_output());
+        for &is_schnorr in &[false, true] {
+            tx_build.set_schnorr(is_schnorr);
+            let mut signer = TxSigner::new(&tx_build);
+            signer.sign_input(0, &crypto, &secret_key);
+            let tx = signer.finish().unwrap();
+            assert_eq!(tx.verify_input_signatures(&crypto, &[spent_output.to_output()]), Ok(()));
+            assert_eq!(tx.malleable_inputs().is_empty(), is_schnorr);
+        }
+    }
+}
@@ -1,18 +1,25 @@
-use crate::tx::{TxInput, TxOutput, TxOutpoint, Tx};
+use crate::tx::{TxInput, TxOutput, TxOutpoint, Tx, SighashHashes};
 use crate::outputs::P2PKHOutput;
 use crate::script::*;
-use crate::hash::{double_sha256};
+use crate::hash::HashWriter;
 use crate::serialize::write_var_int;
 use crate::address::Address;
+use crate::cash_tokens::TokenData;
+use crate::wallet::WalletError;
+use crate::crypto::{Crypto, PublicKey, Signature};
+use crate::sig_encoding::canonical_signature;
+use crate::hex_traits::ToHex;
+use crate::time_lock::{TimeLockError, LOCK_TIME_THRESHOLD, SEQUENCE_LOCK_TIME_ENABLED};
 
 use std::io::Write;
 
 use byteorder::{LittleEndian, WriteBytesExt};
 
-const MAX_SIGNATURE_SIZE: usize = 73;  // explained https://bitcoin.stackexchange.com/a/77192
-const PUBKEY_SIZE: usize = 33;
+pub(crate) const MAX_SIGNATURE_SIZE: usize = 73;  // explained https://bitcoin.stackexchange.com/a/77192
+pub(crate) const SCHNORR_SIGNATURE_SIZE: usize = 65;  // 64 + sighash byte
+pub(crate) const PUBKEY_SIZE: usize = 33;
 
-pub trait Output {
+pub trait Output: OutputClone + Send + Sync {
     fn value(&self) -> u64;
     fn script(&self) -> Script;
     fn script_code(&self) -> Script;
@@ -21,19 +28,58 @@ pub trait Output {
                   serialized_pub_key: Vec<u8>,
                   pre_image: &PreImage,
                   outputs: &[TxOutput]) -> Script;
+    fn token(&self) -> Option<TokenData> {
+        None
+    }
+    // Serialized size of the sig script, for outputs where sig_script with a placeholder
+    // signature and pub key doesn't give the actual size. None uses the placeholders.
+    fn estimated_unlock_size(&self) -> Option<usize> {
+        None
+    }
     fn to_output(&self) -> TxOutput {
         TxOutput {
             value: self.value(),
             script: self.script(),
+            token: self.token(),
         }
     }
 }
 
+// Clones boxed outputs, so UnsignedTx can be cloned, e.g. to compare candidate txs.
+pub trait OutputClone {
+    fn clone_box(&self) -> Box<dyn Output>;
+}
+
+impl<T: 'static + Output + Clone> OutputClone for T {
+    fn clone_box(&self) -> Box<dyn Output> {
+        Box::new(self.clone())
+    }
+}
 
+impl Clone for Box<dyn Output> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+#[derive(Clone)]
 pub struct UnsignedInput {
     pub outpoint: TxOutpoint,
     pub output: Box<dyn Output>,
     pub sequence: u32,
+    // Overrides the estimated sig script size, see Output::estimated_unlock_size.
+    pub max_sig_script_size: Option<usize>,
+}
+
+impl UnsignedInput {
+    pub fn new(outpoint: TxOutpoint, output: Box<dyn Output>, sequence: u32) -> Self {
+        UnsignedInput { outpoint, output, sequence, max_sig_script_size: None }
+    }
+
+    pub fn with_max_sig_script_size(mut self, max_sig_script_size: usize) -> Self {
+        self.max_sig_script_size = Some(max_sig_script_size);
+        self
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -42,6 +88,7 @@ pub struct PreImage {
     pub hash_prevouts: [u8; 32],
     pub hash_sequence: [u8; 32],
     pub outpoint: TxOutpoint,
+    pub token: Option<TokenData>,
     pub script_code: Script,
     pub value: u64,
     pub sequence: u32,
@@ -50,41 +97,130 @@ pub struct PreImage {
     pub sighash_type: u32,
 }
 
+#[derive(Clone)]
 pub struct UnsignedTx {
     version: i32,
     inputs: Vec<UnsignedInput>,
     outputs: Vec<TxOutput>,
     lock_time: u32,
+    is_schnorr: bool,
+    anti_fee_sniping: bool,
 }
 
 impl UnsignedTx {
     pub fn new_simple() -> Self {
+        UnsignedTx::new_with_version(1)
+    }
+
+    // Version 2 enables BIP68 relative lock times (OP_CHECKSEQUENCEVERIFY).
+    pub fn new_with_version(version: i32) -> Self {
         UnsignedTx {
-            version: 1,
+            version,
             inputs: Vec::new(),
             outputs: Vec::new(),
             lock_time: 0,
+            is_schnorr: false,
+            anti_fee_sniping: false,
         }
     }
 
     pub fn new_locktime(lock_time: u32) -> Self {
-        UnsignedTx {
-            version: 1,
-            inputs: Vec::new(),
-            outputs: Vec::new(),
-            lock_time,
+        UnsignedTx { lock_time, ..UnsignedTx::new_simple() }
+    }
+
+    pub fn version(&self) -> i32 {
+        self.version
+    }
+
+    pub fn set_version(&mut self, version: i32) {
+        self.version = version;
+    }
+
+    pub fn lock_time(&self) -> u32 {
+        self.lock_time
+    }
+
+    pub fn set_lock_time(&mut self, lock_time: u32) {
+        self.lock_time = lock_time;
+    }
+
+    pub fn is_schnorr(&self) -> bool {
+        self.is_schnorr
+    }
+
+    // With Schnorr signatures, size estimates use 65 instead of 73 byte signatures and
+    // sign_with signs with Schnorr. Covenants pushing the signature again as data signature
+    // save twice.
+    pub fn set_schnorr(&mut self, is_schnorr: bool) {
+        self.is_schnorr = is_schnorr;
+    }
+
+    // Anti-fee-sniping: locks the tx to at least the current chain height like common wallets
+    // do, so reorging the tip to take its fee doesn't pay off. Higher or time based lock times,
+    // e.g. of CLTV inputs, are kept. Inputs with a final sequence, including ones added later,
+    // are built with SEQUENCE_LOCK_TIME_ENABLED, otherwise the lock time isn't enforced.
+    pub fn set_anti_fee_sniping(&mut self, chain_height: u32) -> Result<(), TimeLockError> {
+        if chain_height >= LOCK_TIME_THRESHOLD {
+            return Err(TimeLockError::InvalidChainHeight(chain_height));
+        }
+        if self.lock_time < LOCK_TIME_THRESHOLD {
+            self.lock_time = self.lock_time.max(chain_height);
+        }
+        self.anti_fee_sniping = true;
+        Ok(())
+    }
+
+    // Sequence input `idx` gets in the built tx, see set_anti_fee_sniping.
+    pub fn input_sequence(&self, idx: usize) -> u32 {
+        match self.inputs[idx].sequence {
+            0xffff_ffff if self.anti_fee_sniping => SEQUENCE_LOCK_TIME_ENABLED,
+            sequence => sequence,
         }
     }
 
+    pub fn inputs(&self) -> &[UnsignedInput] {
+        &self.inputs
+    }
+
+    pub fn outputs(&self) -> &[TxOutput] {
+        &self.outputs
+    }
+
     pub fn add_input(&mut self, input: UnsignedInput) -> usize {
         self.inputs.push(input);
         self.inputs.len() - 1
     }
 
+    pub fn insert_input(&mut self, idx: usize, input: UnsignedInput) {
+        self.inputs.insert(idx, input);
+    }
+
     pub fn replace_input(&mut self, idx: usize, input: UnsignedInput) {
         self.inputs[idx] = input;
     }
 
+    // Points inputs spending outputs of `old_tx_hash` to `new_tx_hash`, e.g. after the parent
+    // was malleated and confirmed with another txid. Pre-images follow the new outpoints, so
+    // the tx has to be signed again. Returns the number of rebound inputs.
+    pub fn rebind_parent(&mut self, old_tx_hash: &[u8; 32], new_tx_hash: [u8; 32]) -> usize {
+        let mut num_rebound = 0;
+        for input in self.inputs.iter_mut().filter(|input| &input.outpoint.tx_hash == old_tx_hash) {
+            input.outpoint.tx_hash = new_tx_hash;
+            num_rebound += 1;
+        }
+        num_rebound
+    }
+
+    // Hashes of the unconfirmed `parents` spent by the tx whose txid can still change, see
+    // Tx::malleable_inputs. Children of those should only be broadcast once they confirm.
+    pub fn malleable_parents<'a>(&self, parents: impl IntoIterator<Item=&'a Tx>) -> Vec<[u8; 32]> {
+        parents.into_iter()
+            .filter(|parent| !parent.malleable_inputs().is_empty())
+            .map(|parent| parent.hash())
+            .filter(|tx_hash| self.inputs.iter().any(|input| &input.outpoint.tx_hash == tx_hash))
+            .collect()
+    }
+
     pub fn add_output(&mut self, output: TxOutput) -> usize {
         self.outputs.push(output);
         self.outputs.len() - 1
@@ -102,44 +238,26 @@ impl UnsignedTx {
         self.outputs.remove(idx);
     }
 
+    // Pre-images of all inputs; see SighashHashes::for_input for how the sighash type
+    // selects what is signed.
     pub fn pre_images(&self, sighash_type: u32) -> Vec<PreImage> {
-        let mut hash_prevouts = [0u8; 32];
-        let mut hash_sequence = [0u8; 32];
-        let mut hash_outputs = [0u8; 32];
-        {
-            let mut outpoints_serialized = Vec::new();
-            for input in self.inputs.iter() {
-                outpoints_serialized.write_all(&input.outpoint.tx_hash).unwrap();
-                outpoints_serialized.write_u32::<LittleEndian>(input.outpoint.vout).unwrap();
-            }
-            hash_prevouts.copy_from_slice(&double_sha256(&outpoints_serialized));
-        }
-        {
-            let mut sequence_serialized = Vec::new();
-            for input in self.inputs.iter() {
-                sequence_serialized.write_u32::<LittleEndian>(input.sequence).unwrap();
-            }
-            hash_sequence.copy_from_slice(&double_sha256(&sequence_serialized));
-        }
-        {
-            let mut outputs_serialized = Vec::new();
-            for output in self.outputs.iter() {
-                println!("tx_output: {} {}", output.value, output.script);
-                output.write_to_stream(&mut outputs_serialized).unwrap();
-            }
-            println!("outputs_serialized: {}", hex::encode(&outputs_serialized));
-            hash_outputs.copy_from_slice(&double_sha256(&outputs_serialized));
-        }
+        let hashes = SighashHashes::new(
+            self.inputs.iter().enumerate().map(|(idx, input)| (&input.outpoint, self.input_sequence(idx))),
+            &self.outputs,
+        );
         let mut pre_images = Vec::new();
-        for input in self.inputs.iter() {
+        for (input_idx, input) in self.inputs.iter().enumerate() {
+            let (hash_prevouts, hash_sequence, hash_outputs) =
+                hashes.for_input(input_idx, &self.outputs, sighash_type);
             pre_images.push(PreImage {
                 version: self.version,
                 hash_prevouts,
                 hash_sequence,
                 outpoint: input.outpoint.clone(),
+                token: input.output.token(),
                 script_code: input.output.script_code(),
                 value: input.output.value(),
-                sequence: input.sequence,
+                sequence: self.input_sequence(input_idx),
                 hash_outputs,
                 lock_time: self.lock_time,
                 sighash_type,
@@ -148,27 +266,48 @@ impl UnsignedTx {
         pre_images
     }
 
-    pub fn estimate_size(&self) -> usize {
+    // Digests to sign, one per input, e.g. for remote signers which shouldn't need the
+    // pre-images.
+    pub fn sighashes(&self, sighash_type: u32) -> Vec<[u8; 32]> {
+        self.pre_images(sighash_type).iter()
+            .map(PreImage::sighash)
+            .collect()
+    }
+
+    // The tx with placeholder signatures of maximum size, for size estimates.
+    pub(crate) fn estimated_tx(&self) -> Tx {
         let mut tx_inputs = Vec::with_capacity(self.inputs.len());
-        for input in self.inputs.iter() {
-            let sig_ser = vec![0; MAX_SIGNATURE_SIZE];
-            let pub_key_ser = vec![0; PUBKEY_SIZE];
-            let pre_image = PreImage::empty(input.output.script_code());
-            let script = input.output.sig_script(sig_ser, pub_key_ser, &pre_image,
-                                                 &self.outputs);
-            tx_inputs.push(TxInput::new(input.outpoint.clone(), script, input.sequence));
+        for (idx, input) in self.inputs.iter().enumerate() {
+            let unlock_size = input.max_sig_script_size
+                .or_else(|| input.output.estimated_unlock_size());
+            let script = match unlock_size {
+                // filler of the given size, made of one byte ops
+                Some(size) => Script::new(vec![Op::Code(OpCodeType::Op0); size]),
+                None => {
+                    let sig_size = if self.is_schnorr { SCHNORR_SIGNATURE_SIZE } else { MAX_SIGNATURE_SIZE };
+                    let sig_ser = vec![0; sig_size];
+                    let pub_key_ser = vec![0; PUBKEY_SIZE];
+                    let pre_image = PreImage {
+                        token: input.output.token(),
+                        ..PreImage::empty(input.output.script_code())
+                    };
+                    input.output.sig_script(sig_ser, pub_key_ser, &pre_image, &self.outputs)
+                },
+            };
+            tx_inputs.push(TxInput::new(input.outpoint.clone(), script, self.input_sequence(idx)));
         }
-        let mut vec = Vec::new();
         Tx::new(self.version, tx_inputs, self.outputs.clone(), self.lock_time)
-            .write_to_stream(&mut vec).unwrap();
-        vec.len() + 2
+    }
+
+    pub fn estimate_size(&self) -> usize {
+        self.estimated_tx().size() + 2
     }
 
     pub fn insert_leftover_output(&mut self,
                                   leftover_idx: usize,
                                   leftover_addr: Address,
                                   fee_per_kb: u64,
-                                  dust_limit: u64) -> Result<Option<usize>, u64> {
+                                  dust_limit: u64) -> Result<Option<usize>, WalletError> {
         let total_output_amount = self.outputs.iter()
             .map(|output| output.value)
             .sum::<u64>();
@@ -179,10 +318,7 @@ impl UnsignedTx {
         let tx_size_without = self.estimate_size();
         self.insert_output(
             leftover_idx,
-            TxOutput {
-                value: leftover.value(),
-                script: leftover.script(),
-            },
+            leftover.to_output(),
         );
         let tx_size = self.estimate_size();
         let fee = tx_size as u64 * fee_per_kb / 1000;
@@ -194,21 +330,18 @@ impl UnsignedTx {
         let total_spent_without = total_output_amount + fee_without;
         if total_spent_without > total_input_amount {
             self.outputs.remove(leftover_idx);
-            return Err(total_spent - total_input_amount);
+            return Err(WalletError::InsufficientFunds { missing: total_spent - total_input_amount });
         } else if total_input_amount - total_spent_without < dust_limit {
             self.outputs.remove(leftover_idx);
             return Ok(None);
         }
         leftover.value = total_input_amount - total_spent;
-        self.replace_output(leftover_idx, TxOutput {
-            value: leftover.value(),
-            script: leftover.script(),
-        });
+        self.replace_output(leftover_idx, leftover.to_output());
         Ok(Some(leftover_idx))
     }
 
     pub fn add_leftover_output(&mut self, leftover_addr: Address, fee_per_kb: u64, dust_limit: u64)
-            -> Result<Option<usize>, u64>{
+            -> Result<Option<usize>, WalletError> {
         self.insert_leftover_output(
             self.outputs.len(),
             leftover_addr,
@@ -217,25 +350,45 @@ impl UnsignedTx {
         )
     }
 
+    // Signatures are checked for strict DER and normalized to low s; empty and Schnorr
+    // signatures are used as they are.
     pub fn sign(&self,
                 serialized_signatures: Vec<Vec<u8>>,
-                serialized_pub_keys: Vec<Vec<u8>>) -> Tx {
+                serialized_pub_keys: Vec<Vec<u8>>) -> Result<Tx, WalletError> {
         let sighash_type: u32 = 0x41;
         let mut tx_inputs = Vec::with_capacity(self.inputs.len());
-        for (((input, mut serialized_signature), serialized_pub_key), pre_image) in
+        for (idx, (((input, serialized_signature), serialized_pub_key), pre_image)) in
                 self.inputs.iter()
                     .zip(serialized_signatures)
                     .zip(serialized_pub_keys)
-                    .zip(self.pre_images(sighash_type)) {
+                    .zip(self.pre_images(sighash_type))
+                    .enumerate() {
+            let mut serialized_signature = canonical_signature(&serialized_signature)
+                .map_err(|error| WalletError::InvalidSignature { idx, error })?;
             serialized_signature.write_u8(sighash_type as u8).unwrap();
             let script = input.output.sig_script(
                 serialized_signature,
                 serialized_pub_key,
                 &pre_image,
                 &self.outputs);
-            tx_inputs.push(TxInput::new(input.outpoint.clone(), script, input.sequence));
+            tx_inputs.push(TxInput::new(input.outpoint.clone(), script, self.input_sequence(idx)));
         }
-        Tx::new(self.version, tx_inputs, self.outputs.clone(), self.lock_time)
+        Ok(Tx::new(self.version, tx_inputs, self.outputs.clone(), self.lock_time))
+    }
+
+    // Signs every input with `secret_key` in one call, e.g. for txs spending only P2PKH inputs
+    // of the same address.
+    pub fn sign_with<C: Crypto>(&self, crypto: &C, secret_key: &C::SecretKey) -> Result<Tx, WalletError> {
+        let pub_key = crypto.secret_to_pub_key(secret_key).serialize();
+        let signatures = self.pre_images(0x41).iter()
+            .map(|pre_image| if self.is_schnorr {
+                crypto.sign_schnorr(&pre_image.sighash(), secret_key).to_vec()
+            } else {
+                crypto.sign(&pre_image.sighash(), secret_key).serialize_der()
+            })
+            .collect::<Vec<_>>();
+        let pub_keys = vec![pub_key.to_vec(); signatures.len()];
+        self.sign(signatures, pub_keys)
     }
 }
 
@@ -263,6 +416,7 @@ impl PreImage {
                 vout: 0,
                 tx_hash: [0; 32],
             },
+            token: None,
             script_code,
             value: 0,
             sequence: 0,
@@ -283,6 +437,10 @@ impl PreImage {
             write.write_u32::<LittleEndian>(self.outpoint.vout)?;
         }
         if flags.script_code {
+            // CashTokens: the token prefix of the spent output precedes the script code
+            if let Some(token) = &self.token {
+                token.write_prefix(write)?;
+            }
             let script = self.script_code.to_vec_sig();
             write_var_int(write, script.len() as u64)?;
             write.write_all(&script)?;
@@ -309,21 +467,141 @@ impl PreImage {
             sighash_type: true,
         })
     }
+
+    // Digest signed by OP_CHECKSIG: double sha256 of the fully serialized pre-image.
+    pub fn sighash(&self) -> [u8; 32] {
+        let mut hasher = HashWriter::new();
+        self.write_to_stream(&mut hasher).unwrap();
+        hasher.finish_double()
+    }
 }
 
 impl std::fmt::Display for PreImage {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         writeln!(f, "version: {}", self.version)?;
-        writeln!(f, "hash_prevouts: {}", hex::encode(self.hash_prevouts))?;
-        writeln!(f, "hash_sequence: {}", hex::encode(self.hash_sequence))?;
-        writeln!(f, "outpoint.tx_hash: {}", hex::encode(self.outpoint.tx_hash))?;
+        writeln!(f, "hash_prevouts: {}", self.hash_prevouts.to_hex())?;
+        writeln!(f, "hash_sequence: {}", self.hash_sequence.to_hex())?;
+        writeln!(f, "outpoint.tx_hash: {}", self.outpoint.tx_hash.to_hex())?;
         writeln!(f, "outpoint.output_idx: {}", self.outpoint.vout)?;
-        writeln!(f, "script_code: {}", hex::encode(self.script_code.to_vec()))?;
+        writeln!(f, "script_code: {}", self.script_code.to_hex())?;
         writeln!(f, "value: {}", self.value)?;
         writeln!(f, "sequence: {}", self.sequence)?;
-        writeln!(f, "hash_outputs: {}", hex::encode(self.hash_outputs))?;
+        writeln!(f, "hash_outputs: {}", self.hash_outputs.to_hex())?;
         writeln!(f, "lock_time: {}", self.lock_time)?;
         writeln!(f, "sighash_type: {:x}", self.sighash_type)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::AddressType;
+    use crate::outputs::P2SHOutput;
+    use crate::time_lock::TimeLockOutput;
+
+    #[test]
+    fn test_clone_unsigned_tx() {
+        let address = Address::from_bytes(AddressType::P2PKH, [0x11; 20]);
+        let mut tx = UnsignedTx::new_with_version(2);
+        tx.add_input(UnsignedInput::new(
+            TxOutpoint { tx_hash: [0; 32], vout: 0 },
+            Box::new(P2SHOutput {
+                output: Box::new(P2PKHOutput { value: 10_000, address: address.clone() }),
+            }),
+            0xffff_ffff,
+        ));
+        let mut candidate = tx.clone();
+        let size = std::thread::spawn(move || {
+            candidate.add_leftover_output(address, 1000, 546).unwrap();
+            candidate.estimate_size()
+        }).join().unwrap();
+        assert!(tx.outputs().is_empty());
+        assert_eq!(tx.clone().version(), 2);
+        assert_eq!(tx.inputs()[0].output.script().to_vec(), tx.clone().inputs()[0].output.script().to_vec());
+        assert_eq!(size, tx.estimate_size() + 34);
+    }
+
+    #[test]
+    fn test_sighashes() {
+        let address = Address::from_bytes(AddressType::P2PKH, [0x11; 20]);
+        let mut tx = UnsignedTx::new_with_version(2);
+        for vout in 0..2 {
+            tx.add_input(UnsignedInput::new(
+                TxOutpoint { tx_hash: [0; 32], vout },
+                Box::new(P2PKHOutput { value: 10_000, address: address.clone() }),
+                0xffff_ffff,
+            ));
+        }
+        tx.add_output(P2PKHOutput { value: 19_000, address }.to_output());
+        for &sighash_type in &[0x41, 0xc1, 0x42, 0x43] {
+            let sighashes = tx.sighashes(sighash_type);
+            let expected = tx.pre_images(sighash_type).iter().map(PreImage::sighash).collect::<Vec<_>>();
+            assert_eq!(sighashes, expected);
+        }
+        assert_ne!(tx.sighashes(0x41)[0], tx.sighashes(0x41)[1]);
+        assert_ne!(tx.sighashes(0x41)[0], tx.sighashes(0xc1)[0]);
+    }
+
+    #[test]
+    fn test_rebind_parent() {
+        let address = Address::from_bytes(AddressType::P2PKH, [0x11; 20]);
+        let mut tx = UnsignedTx::new_with_version(2);
+        for vout in 0..2 {
+            tx.add_input(UnsignedInput::new(
+                TxOutpoint { tx_hash: [0; 32], vout },
+                Box::new(P2PKHOutput { value: 10_000, address: address.clone() }),
+                0xffff_ffff,
+            ));
+        }
+        let parent = Tx::new(2, vec![TxInput::new(
+            TxOutpoint { tx_hash: [1; 32], vout: 0 },
+            Script::new(vec![Op::Push(vec![0x30, 6, 2, 1, 1, 2, 1, 1, 0x41])]),
+            0xffff_ffff,
+        )], vec![], 0);
+        assert_eq!(parent.malleable_inputs(), vec![0]);
+        assert!(tx.malleable_parents(Some(&parent)).is_empty());
+        let sighash = tx.pre_images(0x41)[1].sighash();
+        assert_eq!(tx.rebind_parent(&[0; 32], parent.hash()), 2);
+        assert_eq!(tx.rebind_parent(&[0; 32], parent.hash()), 0);
+        assert_eq!(tx.pre_images(0x41)[1].outpoint, TxOutpoint { tx_hash: parent.hash(), vout: 1 });
+        assert_ne!(tx.pre_images(0x41)[1].sighash(), sighash);
+        assert_eq!(tx.malleable_parents(Some(&parent)), vec![parent.hash()]);
+    }
+
+    #[test]
+    fn test_anti_fee_sniping() {
+        let address = Address::from_bytes(AddressType::P2PKH, [0x11; 20]);
+        let p2pkh_input = |vout| UnsignedInput::new(
+            TxOutpoint { tx_hash: [0; 32], vout },
+            Box::new(P2PKHOutput { value: 10_000, address: address.clone() }),
+            0xffff_ffff,
+        );
+        let mut tx = UnsignedTx::new_with_version(2);
+        tx.add_input(p2pkh_input(0));
+        assert_eq!(tx.set_anti_fee_sniping(LOCK_TIME_THRESHOLD), Err(TimeLockError::InvalidChainHeight(LOCK_TIME_THRESHOLD)));
+        assert_eq!(tx.lock_time(), 0);
+        assert_eq!(tx.set_anti_fee_sniping(800_000), Ok(()));
+        assert_eq!(tx.lock_time(), 800_000);
+        // inputs added afterwards get a non-final sequence too, when the tx is built
+        tx.add_input(p2pkh_input(1));
+        assert_eq!(tx.inputs()[1].sequence, 0xffff_ffff);
+        let sequences = tx.estimated_tx().inputs().iter().map(|input| input.sequence).collect::<Vec<_>>();
+        assert_eq!(sequences, vec![SEQUENCE_LOCK_TIME_ENABLED; 2]);
+        assert_eq!(tx.pre_images(0x41)[1].sequence, SEQUENCE_LOCK_TIME_ENABLED);
+
+        // existing CLTV lock times aren't lowered or mixed with heights
+        let time_lock = |lock_time| TimeLockOutput { value: 10_000, address: address.clone(), lock_time };
+        let mut tx = UnsignedTx::new_with_version(2);
+        time_lock(900_000).add_as_input(&mut tx, TxOutpoint { tx_hash: [1; 32], vout: 0 }).unwrap();
+        tx.set_anti_fee_sniping(800_000).unwrap();
+        assert_eq!(tx.lock_time(), 900_000);
+        let mut tx = UnsignedTx::new_with_version(2);
+        time_lock(1_700_000_000).add_as_input(&mut tx, TxOutpoint { tx_hash: [1; 32], vout: 0 }).unwrap();
+        tx.set_anti_fee_sniping(800_000).unwrap();
+        assert_eq!(tx.lock_time(), 1_700_000_000);
+        tx.add_input(p2pkh_input(0));
+        assert_eq!(tx.input_sequence(0), SEQUENCE_LOCK_TIME_ENABLED);
+        assert_eq!(tx.input_sequence(1), SEQUENCE_LOCK_TIME_ENABLED);
+    }
+}
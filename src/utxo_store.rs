@@ -0,0 +1,455 @@
+use crate::cash_tokens::split_token_prefix;
+use crate::hex_traits::{FromHex, ToHex};
+use crate::outputs::P2PKHOutput;
+use crate::script::Script;
+use crate::slp::SLPMessage;
+use crate::tx::{Tx, TxOutpoint, tx_hash_to_hex, tx_hex_to_hash};
+use crate::unsigned_tx::Output;
+use crate::wallet::{Wallet, UtxoEntry};
+
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+
+// Storage of the wallet's UTXOs, kept up to date by Wallet::apply_tx.
+pub trait UtxoStore {
+    type Error;
+
+    // Adds the UTXO, replacing an existing entry with the same outpoint.
+    fn insert(&mut self, utxo: UtxoEntry) -> Result<(), Self::Error>;
+
+    // Removes the UTXO and returns it, if it was in the store.
+    fn spend(&mut self, outpoint: &TxOutpoint) -> Result<Option<UtxoEntry>, Self::Error>;
+
+    // UTXOs in insertion order.
+    fn list(&self) -> Result<Vec<UtxoEntry>, Self::Error>;
+
+    // Writes pending changes to the underlying storage, if any.
+    fn persist(&mut self) -> Result<(), Self::Error>;
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MemoryUtxoStore {
+    utxos: Vec<UtxoEntry>,
+}
+
+// Store backed by a plain text file, starting with a FILE_FORMAT_V2 line followed by one UTXO
+// per line: "<tx id> <vout> <amount> <height> <script> <coinbase> <slp token> <token>", where
+// absent values are "-", the script and CashToken prefix are hex, the coinbase flag is 0 or 1
+// and SLP tokens are "<token id>:<amount>". Files without the version line are read as the
+// previous format of only the first 4 columns. The file is replaced atomically on persist.
+#[derive(Clone, Debug)]
+pub struct FileUtxoStore {
+    path: PathBuf,
+    memory: MemoryUtxoStore,
+}
+
+fn same_outpoint(utxo: &UtxoEntry, outpoint: &TxOutpoint) -> bool {
+    utxo.vout == outpoint.vout && utxo.outpoint().as_ref() == Some(outpoint)
+}
+
+impl MemoryUtxoStore {
+    pub fn new() -> Self {
+        MemoryUtxoStore::default()
+    }
+}
+
+impl UtxoStore for MemoryUtxoStore {
+    type Error = std::convert::Infallible;
+
+    fn insert(&mut self, utxo: UtxoEntry) -> Result<(), Self::Error> {
+        let existing = self.utxos.iter_mut()
+            .find(|entry| entry.tx_id_hex == utxo.tx_id_hex && entry.vout == utxo.vout);
+        match existing {
+            Some(entry) => *entry = utxo,
+            None => self.utxos.push(utxo),
+        }
+        Ok(())
+    }
+
+    fn spend(&mut self, outpoint: &TxOutpoint) -> Result<Option<UtxoEntry>, Self::Error> {
+        Ok(self.utxos.iter()
+            .position(|utxo| same_outpoint(utxo, outpoint))
+            .map(|idx| self.utxos.remove(idx)))
+    }
+
+    fn list(&self) -> Result<Vec<UtxoEntry>, Self::Error> {
+        Ok(self.utxos.clone())
+    }
+
+    fn persist(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+const FILE_FORMAT_V2: &str = "utxos v2";
+
+fn invalid_line(line: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid UTXO entry: {}", line))
+}
+
+// None for "-", otherwise parsed with `parse`.
+fn parse_optional<T, F>(line: &str, column: &str, parse: F) -> io::Result<Option<T>>
+        where F: FnOnce(&str) -> Option<T> {
+    match column {
+        "-" => Ok(None),
+        column => parse(column).map(Some).ok_or_else(|| invalid_line(line)),
+    }
+}
+
+fn parse_line(line: &str, is_v2: bool) -> io::Result<UtxoEntry> {
+    let parts = line.split_whitespace().collect::<Vec<_>>();
+    let (tx_id_hex, vout, amount, height, rest) = match parts.as_slice() {
+        [tx_id_hex, vout, amount, height, rest @ ..] => (tx_id_hex, vout, amount, height, rest),
+        _ => return Err(invalid_line(line)),
+    };
+    let mut utxo = UtxoEntry {
+        tx_id_hex: tx_id_hex.to_string(),
+        vout: vout.parse().map_err(|_| invalid_line(line))?,
+        amount: amount.parse().map_err(|_| invalid_line(line))?,
+        height: parse_optional(line, height, |height| height.parse().ok())?,
+        ..UtxoEntry::default()
+    };
+    match (is_v2, rest) {
+        (false, []) => {},
+        (true, [script, is_coinbase, slp_token, token]) => {
+            utxo.script = parse_optional(line, script, |script| Vec::<u8>::from_hex(script).ok())?;
+            utxo.is_coinbase = match *is_coinbase {
+                "0" => false,
+                "1" => true,
+                _ => return Err(invalid_line(line)),
+            };
+            utxo.slp_token = parse_optional(line, slp_token, |slp_token| {
+                let mut parts = slp_token.splitn(2, ':');
+                let token_id = tx_hex_to_hash(parts.next()?)?;
+                Some((token_id, parts.next()?.parse().ok()?))
+            })?;
+            utxo.token = parse_optional(line, token, |token| {
+                match split_token_prefix(&Vec::<u8>::from_hex(token).ok()?) {
+                    Ok((Some(token), [])) => Some(token),
+                    _ => None,
+                }
+            })?;
+        },
+        _ => return Err(invalid_line(line)),
+    }
+    Ok(utxo)
+}
+
+fn format_line(utxo: &UtxoEntry) -> String {
+    let optional = |column: Option<String>| column.unwrap_or_else(|| "-".to_string());
+    format!("{} {} {} {} {} {} {} {}",
+            utxo.tx_id_hex,
+            utxo.vout,
+            utxo.amount,
+            optional(utxo.height.map(|height| height.to_string())),
+            optional(utxo.script.as_ref().map(|script| script.to_hex())),
+            utxo.is_coinbase as u8,
+            optional(utxo.slp_token.map(|(token_id, amount)| format!("{}:{}", tx_hash_to_hex(&token_id), amount))),
+            optional(utxo.token.as_ref().map(|token| token.prefix_to_vec().to_hex())))
+}
+
+impl FileUtxoStore {
+    // Loads the store from `path`; a missing file is an empty store.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut memory = MemoryUtxoStore::new();
+        match fs::File::open(&path) {
+            Ok(file) => {
+                let mut lines = io::BufReader::new(file).lines().peekable();
+                let is_v2 = match lines.peek() {
+                    Some(Ok(line)) => line.trim() == FILE_FORMAT_V2,
+                    _ => false,
+                };
+                for line in lines.skip(is_v2 as usize) {
+                    let line = line?;
+                    if !line.trim().is_empty() {
+                        memory.utxos.push(parse_line(&line, is_v2)?);
+                    }
+                }
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {},
+            Err(err) => return Err(err),
+        }
+        Ok(FileUtxoStore { path, memory })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl UtxoStore for FileUtxoStore {
+    type Error = io::Error;
+
+    fn insert(&mut self, utxo: UtxoEntry) -> io::Result<()> {
+        let Ok(()) = self.memory.insert(utxo);
+        Ok(())
+    }
+
+    fn spend(&mut self, outpoint: &TxOutpoint) -> io::Result<Option<UtxoEntry>> {
+        let Ok(utxo) = self.memory.spend(outpoint);
+        Ok(utxo)
+    }
+
+    fn list(&self) -> io::Result<Vec<UtxoEntry>> {
+        Ok(self.memory.utxos.clone())
+    }
+
+    fn persist(&mut self) -> io::Result<()> {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        {
+            let mut file = io::BufWriter::new(fs::File::create(&tmp_path)?);
+            writeln!(file, "{}", FILE_FORMAT_V2)?;
+            for utxo in &self.memory.utxos {
+                writeln!(file, "{}", format_line(utxo))?;
+            }
+            file.into_inner()?.sync_all()?;
+        }
+        fs::rename(&tmp_path, &self.path)
+    }
+}
+
+impl Wallet {
+    // UTXOs of the store which aren't frozen.
+    pub fn store_utxos<S: UtxoStore>(&self, store: &S) -> Result<Vec<UtxoEntry>, S::Error> {
+        Ok(self.spendable_utxos(&store.list()?))
+    }
+
+    // Spends the tx's inputs from the store and adds its outputs paying to the wallet.
+    pub fn apply_tx<S: UtxoStore>(&self, store: &mut S, tx: &Tx, height: Option<u32>)
+            -> Result<(), S::Error> {
+        for input in tx.inputs() {
+            store.spend(&input.outpoint)?;
+        }
+        let own_script = P2PKHOutput { value: 0, address: self.address().clone() }.script();
+        let tx_hash = tx.hash();
+        let tx_id_hex = tx_hash_to_hex(&tx_hash);
+        // unvalidated, see SLPValidator
+        let slp_message = tx.outputs().first().and_then(|output| SLPMessage::parse(&output.script).ok());
+        for (vout, output) in tx.outputs().iter().enumerate() {
+            if output.script.to_vec() == own_script.to_vec() {
+                store.insert(UtxoEntry {
+                    tx_id_hex: tx_id_hex.clone(),
+                    vout: vout as u32,
+                    amount: output.value,
+                    height,
+                    script: None,
+                    is_coinbase: tx.is_coinbase(),
+                    slp_token: slp_message.as_ref()
+                        .and_then(|message| message.output_token(&tx_hash, vout as u32)),
+                    token: output.token.clone(),
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Changes to the store made by one tracked tx, to revert it if its block is disconnected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TxUndo {
+    pub tx_hash: [u8; 32],
+    pub height: Option<u32>,
+    // entries removed by the tx: spent UTXOs and previous entries of re-applied outputs
+    pub removed: Vec<UtxoEntry>,
+    pub added: Vec<TxOutpoint>,
+}
+
+// Keeps a UtxoStore up to date from a stream of block and mempool txs, for the outputs
+// paying to any of the tracked scripts. Undo data of applied txs is kept until pruned, so
+// reorgs can be reverted without an indexer.
+#[derive(Clone, Debug)]
+pub struct UtxoTracker<S: UtxoStore> {
+    store: S,
+    scripts: Vec<Vec<u8>>,
+    undo: Vec<TxUndo>,
+}
+
+impl<S: UtxoStore> UtxoTracker<S> {
+    pub fn new(store: S) -> Self {
+        UtxoTracker { store, scripts: Vec::new(), undo: Vec::new() }
+    }
+
+    pub fn with_script(mut self, script: &Script) -> Self {
+        self.add_script(script);
+        self
+    }
+
+    pub fn add_script(&mut self, script: &Script) {
+        let script = script.to_vec();
+        if !self.scripts.contains(&script) {
+            self.scripts.push(script);
+        }
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn into_store(self) -> S {
+        self.store
+    }
+
+    // Undo data of the applied txs, oldest first.
+    pub fn undo_data(&self) -> &[TxUndo] {
+        &self.undo
+    }
+
+    // Spends the tx's inputs and adds its outputs paying to a tracked script. A mempool tx
+    // applied again once confirmed updates the height of its outputs.
+    pub fn apply_tx(&mut self, tx: &Tx, height: Option<u32>) -> Result<(), S::Error> {
+        let tx_hash = tx.hash();
+        let mut removed = Vec::new();
+        let mut added = Vec::new();
+        for input in tx.inputs() {
+            removed.extend(self.store.spend(&input.outpoint)?);
+        }
+        let tx_id_hex = tx_hash_to_hex(&tx_hash);
+        // unvalidated, see SLPValidator
+        let slp_message = tx.outputs().first().and_then(|output| SLPMessage::parse(&output.script).ok());
+        for (vout, output) in tx.outputs().iter().enumerate() {
+            if !self.scripts.contains(&output.script.to_vec()) {
+                continue;
+            }
+            let outpoint = TxOutpoint { tx_hash, vout: vout as u32 };
+            removed.extend(self.store.spend(&outpoint)?);
+            self.store.insert(UtxoEntry {
+                tx_id_hex: tx_id_hex.clone(),
+                vout: vout as u32,
+                amount: output.value,
+                height,
+                script: Some(output.script.to_vec()),
+                is_coinbase: tx.is_coinbase(),
+                slp_token: slp_message.as_ref()
+                    .and_then(|message| message.output_token(&tx_hash, vout as u32)),
+                token: output.token.clone(),
+            })?;
+            added.push(outpoint);
+        }
+        self.undo.push(TxUndo { tx_hash, height, removed, added });
+        Ok(())
+    }
+
+    // Reverts the most recently applied tx, returning its hash.
+    pub fn undo_last(&mut self) -> Result<Option<[u8; 32]>, S::Error> {
+        let undo = match self.undo.pop() {
+            Some(undo) => undo,
+            None => return Ok(None),
+        };
+        for outpoint in &undo.added {
+            self.store.spend(outpoint)?;
+        }
+        for utxo in undo.removed {
+            self.store.insert(utxo)?;
+        }
+        Ok(Some(undo.tx_hash))
+    }
+
+    // Disconnects the blocks from `height` on: reverts all txs applied since the first tx
+    // confirmed at `height` or above, including mempool txs applied later, and returns their
+    // hashes, latest first, e.g. to re-apply the ones still in the mempool.
+    pub fn disconnect_from(&mut self, height: u32) -> Result<Vec<[u8; 32]>, S::Error> {
+        let first = self.undo.iter()
+            .position(|undo| undo.height.map(|tx_height| tx_height >= height).unwrap_or(false));
+        let mut reverted = Vec::new();
+        if let Some(first) = first {
+            while self.undo.len() > first {
+                reverted.extend(self.undo_last()?);
+            }
+        }
+        Ok(reverted)
+    }
+
+    // Drops undo data of txs confirmed below `height`, which can't be reorged anymore. Stops
+    // at the first unconfirmed tx, as later txs might depend on it.
+    pub fn prune_undo(&mut self, height: u32) {
+        let num_final = self.undo.iter()
+            .take_while(|undo| undo.height.map(|tx_height| tx_height < height).unwrap_or(false))
+            .count();
+        self.undo.drain(..num_final);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::{Address, AddressType};
+    use crate::tx::{TxInput, TxOutput};
+
+    #[test]
+    fn test_utxo_tracker() {
+        let address = Address::from_bytes(AddressType::P2PKH, [0x11; 20]);
+        let script = P2PKHOutput { value: 0, address }.script();
+        let mut tracker = UtxoTracker::new(MemoryUtxoStore::new()).with_script(&script);
+        let spend = |spent: &[TxOutpoint], values: &[u64]| Tx::new(
+            2,
+            spent.iter().map(|outpoint| TxInput::new(outpoint.clone(), Script::empty(), 0xffff_ffff)).collect(),
+            values.iter().map(|&value| TxOutput::new(value, script.clone()))
+                .chain(Some(TxOutput::new(1000, Script::empty())))
+                .collect(),
+            0,
+        );
+        let funding = spend(&[TxOutpoint { tx_hash: [0x22; 32], vout: 0 }], &[10_000, 20_000]);
+        let outpoint = |tx: &Tx, vout| TxOutpoint { tx_hash: tx.hash(), vout };
+        let payment = spend(&[outpoint(&funding, 0)], &[9_000]);
+        let amounts = |tracker: &UtxoTracker<MemoryUtxoStore>| {
+            let Ok(utxos) = tracker.store().list();
+            utxos.iter().map(|utxo| (utxo.amount, utxo.height)).collect::<Vec<_>>()
+        };
+
+        tracker.apply_tx(&funding, Some(100)).unwrap();
+        tracker.apply_tx(&payment, None).unwrap();
+        assert_eq!(amounts(&tracker), vec![(20_000, Some(100)), (9_000, None)]);
+        tracker.apply_tx(&payment, Some(101)).unwrap();
+        assert_eq!(amounts(&tracker), vec![(20_000, Some(100)), (9_000, Some(101))]);
+
+        // block 101 is reorged, the payment goes back to the mempool
+        assert_eq!(tracker.disconnect_from(101).unwrap(), vec![payment.hash()]);
+        assert_eq!(amounts(&tracker), vec![(20_000, Some(100)), (9_000, None)]);
+        assert_eq!(tracker.undo_last().unwrap(), Some(payment.hash()));
+        assert_eq!(amounts(&tracker), vec![(20_000, Some(100)), (10_000, Some(100))]);
+
+        tracker.apply_tx(&payment, Some(101)).unwrap();
+        tracker.prune_undo(101);
+        assert_eq!(tracker.undo_data().len(), 1);
+        assert_eq!(tracker.disconnect_from(100).unwrap(), vec![payment.hash()]);
+    }
+
+    #[test]
+    fn test_file_utxo_store() {
+        let path = std::env::temp_dir().join(format!("cashcontracts-utxos-{}", std::process::id()));
+        fs::write(&path, format!("{} 1 546 -\n", tx_hash_to_hex(&[0x11; 32]))).unwrap();
+        let mut store = FileUtxoStore::open(&path).unwrap();
+        assert_eq!(store.list().unwrap()[0].amount, 546);
+        let utxos = vec![
+            UtxoEntry {
+                tx_id_hex: tx_hash_to_hex(&[0x22; 32]),
+                vout: 0,
+                amount: 5_000_000_000,
+                height: Some(100),
+                script: Some(vec![0x51]),
+                is_coinbase: true,
+                ..UtxoEntry::default()
+            },
+            UtxoEntry {
+                tx_id_hex: tx_hash_to_hex(&[0x33; 32]),
+                vout: 2,
+                amount: 546,
+                slp_token: Some(([0x44; 32], 100)),
+                token: Some(crate::cash_tokens::TokenData::fungible([0x55; 32], 7)),
+                ..UtxoEntry::default()
+            },
+        ];
+        for utxo in &utxos {
+            store.insert(utxo.clone()).unwrap();
+        }
+        store.spend(&TxOutpoint { tx_hash: [0x11; 32], vout: 1 }).unwrap();
+        store.persist().unwrap();
+        let reloaded = FileUtxoStore::open(&path).unwrap().list().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(reloaded, utxos);
+    }
+}
@@ -1,65 +1,695 @@
 use crate::address::{Address, AddressError};
 use crate::unsigned_tx::{UnsignedTx, UnsignedInput, Output};
-use crate::tx::{TxOutpoint, tx_hex_to_hash};
-use crate::outputs::P2PKHOutput;
+use crate::tx::{Tx, TxOutpoint, tx_hex_to_hash};
+use crate::crypto::{Crypto, PublicKey};
+#[cfg(feature = "secp256k1")]
+use crate::crypto::secp256k1::CryptoSecp256k1;
+use crate::outputs::{P2PKHOutput, OpReturnOutput, SLPSend, SLP_TOKEN_TYPE_FUNGIBLE};
+use crate::op_return::OpReturnError;
+use crate::backend::BackendError;
+use crate::slp::SLPLeftoverError;
+use crate::sig_encoding::SigEncodingError;
+use crate::cash_tokens::TokenData;
+use crate::time_lock::TimeLockError;
+
+use std::collections::HashSet;
 
 
 pub struct Wallet {
     address: Address,
     fee_per_kb: u64,
+    dust_limit: u64,
+    frozen: HashSet<TxOutpoint>,
+    dust_filter: u64,
+    whitelisted: HashSet<TxOutpoint>,
+    chain_height: Option<u32>,
+    anti_fee_sniping: bool,
 }
 
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct UtxoEntry {
     pub tx_id_hex: String,
     pub vout: u32,
     pub amount: u64,
+    // Block height the UTXO was confirmed in; None if unconfirmed.
+    pub height: Option<u32>,
+    // Serialized locking script; None if it pays to the wallet's own P2PKH address.
+    pub script: Option<Vec<u8>>,
+    pub is_coinbase: bool,
+    // SLP token id and amount, if known to carry SLP tokens.
+    pub slp_token: Option<([u8; 32], u64)>,
+    pub token: Option<TokenData>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WalletError {
+    InsufficientFunds { missing: u64 },
+    DustOutput { idx: usize },
+    NoUtxos,
+    AddressError(AddressError),
+    InsufficientTokens { missing: u64 },
+    SLP(SLPLeftoverError),
+    WrongSecretKey,
+    OpReturn(OpReturnError),
+    Backend(BackendError),
+    InvalidSignature { idx: usize, error: SigEncodingError },
+    MissingSignature { idx: usize },
+    TimeLock(TimeLockError),
+}
+
+// UTXO carrying `token_amount` SLP tokens with id `token_id`.
+#[derive(Clone, Debug)]
+pub struct TokenUtxoEntry {
+    pub utxo: UtxoEntry,
+    pub token_id: [u8; 32],
+    pub token_amount: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConsolidationReport {
+    pub num_inputs: usize,
+    pub num_outputs: usize,
+    pub fee_paid: u64,
+    // Fee that spending the merged UTXOs individually would cost at the same fee rate.
+    pub future_fee_saved: u64,
+}
+
+// Per-tx options of send_to_address and send_to_addresses.
+#[derive(Clone, Debug, Default)]
+pub struct SendOptions {
+    // Overrides the wallet's fee rate for this tx only.
+    pub fee_per_kb: Option<u64>,
+    // Placed at output 0, before the payments.
+    pub op_return: Option<OpReturnOutput>,
+}
+
+pub struct Consolidation {
+    pub txs: Vec<UnsignedTx>,
+    pub report: ConsolidationReport,
 }
 
 pub const DUST_AMOUNT: u64 = 546;
+pub const DEFAULT_FEE_PER_KB: u64 = 1000;
+// Version of txs built by the wallets, so inputs can use relative lock times.
+pub const WALLET_TX_VERSION: i32 = 2;
+// outpoint + script len + sig push + max sig + pubkey push + pubkey + sequence
+pub const P2PKH_INPUT_SIZE: usize = 36 + 1 + 1 + 73 + 1 + 33 + 4;
+// Confirmations before coinbase outputs can be spent.
+pub const COINBASE_MATURITY: u32 = 100;
+
+impl UtxoEntry {
+    pub fn outpoint(&self) -> Option<TxOutpoint> {
+        Some(TxOutpoint {
+            tx_hash: tx_hex_to_hash(&self.tx_id_hex)?,
+            vout: self.vout,
+        })
+    }
+
+    // Whether the UTXO carries SLP tokens or CashTokens, which BCH sends would burn.
+    pub fn has_tokens(&self) -> bool {
+        self.slp_token.is_some() || self.token.is_some()
+    }
+
+    // Coinbase outputs are spendable once they have COINBASE_MATURITY confirmations at
+    // `chain_height`; without a chain height, confirmed coinbase outputs are assumed mature.
+    pub fn is_mature(&self, chain_height: Option<u32>) -> bool {
+        if !self.is_coinbase {
+            return true;
+        }
+        match (self.height, chain_height) {
+            (None, _) => false,
+            (Some(height), Some(chain_height)) =>
+                chain_height.saturating_sub(height) + 1 >= COINBASE_MATURITY,
+            (Some(_), None) => true,
+        }
+    }
+}
+
+impl WalletError {
+    // Satoshis missing to fund the tx; 0 if the error has a different cause.
+    pub fn missing_amount(&self) -> u64 {
+        match *self {
+            WalletError::InsufficientFunds { missing } => missing,
+            _ => 0,
+        }
+    }
+}
+
+impl From<AddressError> for WalletError {
+    fn from(err: AddressError) -> Self {
+        WalletError::AddressError(err)
+    }
+}
+
+impl From<SLPLeftoverError> for WalletError {
+    fn from(err: SLPLeftoverError) -> Self {
+        match err {
+            SLPLeftoverError::InsufficientFunds(missing) => WalletError::InsufficientFunds { missing },
+            err => WalletError::SLP(err),
+        }
+    }
+}
+
+impl SendOptions {
+    pub fn with_fee_rate(mut self, fee_per_kb: u64) -> Self {
+        self.fee_per_kb = Some(fee_per_kb);
+        self
+    }
+
+    pub fn with_op_return(mut self, op_return: OpReturnOutput) -> Self {
+        self.op_return = Some(op_return);
+        self
+    }
+}
+
+impl ConsolidationReport {
+    pub fn is_beneficial(&self) -> bool {
+        self.future_fee_saved > self.fee_paid
+    }
+}
 
 impl Wallet {
-    pub fn from_cash_addr(cash_addr: String) -> Result<Self, AddressError> {
+    pub fn from_cash_addr(cash_addr: String) -> Result<Self, WalletError> {
         let addr = Address::from_cash_addr(cash_addr)?;
         Ok(Wallet {
             address: addr,
-            fee_per_kb: 1000,
+            fee_per_kb: DEFAULT_FEE_PER_KB,
+            dust_limit: DUST_AMOUNT,
+            frozen: HashSet::new(),
+            dust_filter: 0,
+            whitelisted: HashSet::new(),
+            chain_height: None,
+            anti_fee_sniping: false,
         })
     }
 
+    pub fn with_fee_rate(mut self, fee_per_kb: u64) -> Self {
+        self.fee_per_kb = fee_per_kb;
+        self
+    }
+
+    pub fn with_dust_limit(mut self, dust_limit: u64) -> Self {
+        self.dust_limit = dust_limit;
+        self
+    }
+
+    // UTXOs below `threshold` are ignored by coin selection unless whitelisted, so unsolicited
+    // dust can't link the wallet's coins. A threshold of 0 disables the filter.
+    pub fn with_dust_filter(mut self, threshold: u64) -> Self {
+        self.dust_filter = threshold;
+        self
+    }
+
+    // Chain height for coinbase maturity and anti-fee-sniping.
+    pub fn with_chain_height(mut self, chain_height: u32) -> Self {
+        self.chain_height = Some(chain_height);
+        self
+    }
+
+    // Locks txs to the chain height, if known, see UnsignedTx::set_anti_fee_sniping.
+    pub fn with_anti_fee_sniping(mut self) -> Self {
+        self.anti_fee_sniping = true;
+        self
+    }
+
+    pub fn set_fee_rate(&mut self, fee_per_kb: u64) {
+        self.fee_per_kb = fee_per_kb;
+    }
+
+    pub fn set_dust_limit(&mut self, dust_limit: u64) {
+        self.dust_limit = dust_limit;
+    }
+
+    pub fn set_dust_filter(&mut self, threshold: u64) {
+        self.dust_filter = threshold;
+    }
+
+    pub fn set_chain_height(&mut self, chain_height: Option<u32>) {
+        self.chain_height = chain_height;
+    }
+
+    pub fn set_anti_fee_sniping(&mut self, anti_fee_sniping: bool) {
+        self.anti_fee_sniping = anti_fee_sniping;
+    }
+
+    pub fn chain_height(&self) -> Option<u32> {
+        self.chain_height
+    }
+
+    pub fn dust_filter(&self) -> u64 {
+        self.dust_filter
+    }
+
+    pub fn fee_per_kb(&self) -> u64 {
+        self.fee_per_kb
+    }
+
     pub fn address(&self) -> &Address {
         &self.address
     }
 
-    pub fn init_tx(&self, utxos: &[UtxoEntry]) -> UnsignedTx {
-        let mut tx_build = UnsignedTx::new_simple();
-        for utxo in utxos {
-            tx_build.add_input(UnsignedInput {
-                output: Box::new(P2PKHOutput {
-                    address: self.address.clone(),
-                    value: utxo.amount,
-                }),
-                outpoint: TxOutpoint {
-                    tx_hash: tx_hex_to_hash(&utxo.tx_id_hex).unwrap(),
-                    vout: utxo.vout,
-                },
-                sequence: 0xffff_ffff,
-            });
+    // Frozen UTXOs, e.g. SLP dust or outputs of pending txs, are never spent by the wallet.
+    pub fn freeze(&mut self, outpoint: TxOutpoint) {
+        self.frozen.insert(outpoint);
+    }
+
+    pub fn unfreeze(&mut self, outpoint: &TxOutpoint) -> bool {
+        self.frozen.remove(outpoint)
+    }
+
+    pub fn is_frozen(&self, utxo: &UtxoEntry) -> bool {
+        utxo.outpoint().map(|outpoint| self.frozen.contains(&outpoint)).unwrap_or(false)
+    }
+
+    // Whitelisted UTXOs are spent even if they are below the dust filter threshold, as are
+    // UTXOs carrying tokens, which always sit on dust carriers.
+    pub fn whitelist(&mut self, outpoint: TxOutpoint) {
+        self.whitelisted.insert(outpoint);
+    }
+
+    pub fn unwhitelist(&mut self, outpoint: &TxOutpoint) -> bool {
+        self.whitelisted.remove(outpoint)
+    }
+
+    pub fn is_dust_filtered(&self, utxo: &UtxoEntry) -> bool {
+        utxo.amount < self.dust_filter && !utxo.has_tokens() &&
+            !utxo.outpoint().map(|outpoint| self.whitelisted.contains(&outpoint)).unwrap_or(false)
+    }
+
+    // Whether the UTXO pays to the wallet's P2PKH address, as assumed if its script is unknown.
+    pub fn is_own_utxo(&self, utxo: &UtxoEntry) -> bool {
+        match &utxo.script {
+            Some(script) => script == &P2PKHOutput { value: 0, address: self.address.clone() }.script().to_vec(),
+            None => true,
+        }
+    }
+
+    // UTXOs the wallet can spend as BCH: paying to its address, mature, without tokens,
+    // and neither frozen nor filtered as dust.
+    pub fn spendable_utxos(&self, utxos: &[UtxoEntry]) -> Vec<UtxoEntry> {
+        utxos.iter()
+            .filter(|utxo| self.is_own_utxo(utxo) && utxo.is_mature(self.chain_height) && !utxo.has_tokens())
+            .filter(|utxo| !self.is_frozen(utxo) && !self.is_dust_filtered(utxo))
+            .cloned()
+            .collect()
+    }
+
+    // Adds all spendable UTXOs as inputs.
+    pub fn init_tx(&self, utxos: &[UtxoEntry]) -> Result<UnsignedTx, WalletError> {
+        self.tx_spending(&self.spendable_utxos(utxos))
+    }
+
+    // Tx spending exactly `utxos`, without any filtering.
+    fn tx_spending(&self, utxos: &[UtxoEntry]) -> Result<UnsignedTx, WalletError> {
+        let mut tx_build = UnsignedTx::new_with_version(WALLET_TX_VERSION);
+        add_p2pkh_inputs(&mut tx_build, utxos, &self.address);
+        if let (true, Some(chain_height)) = (self.anti_fee_sniping, self.chain_height) {
+            tx_build.set_anti_fee_sniping(chain_height).map_err(WalletError::TimeLock)?;
         }
-        tx_build
+        Ok(tx_build)
+    }
+
+    pub fn send_to_address(&self,
+                           address: Address,
+                           amount: u64,
+                           utxos: &[UtxoEntry],
+                           options: SendOptions) -> Result<UnsignedTx, WalletError> {
+        self.send_to_addresses(&[(address, amount)], utxos, options)
+    }
+
+    // Pays all recipients in a single transaction, in order, followed by the change output.
+    pub fn send_to_addresses(&self,
+                             recipients: &[(Address, u64)],
+                             utxos: &[UtxoEntry],
+                             options: SendOptions) -> Result<UnsignedTx, WalletError> {
+        if let Some(idx) = recipients.iter().position(|(_, amount)| *amount < self.dust_amount()) {
+            return Err(WalletError::DustOutput { idx });
+        }
+        let mut tx_build = self.init_tx(utxos)?;
+        if tx_build.inputs().is_empty() {
+            return Err(WalletError::NoUtxos);
+        }
+        add_op_return(&mut tx_build, options.op_return)?;
+        for (address, amount) in recipients {
+            tx_build.add_output(P2PKHOutput {
+                address: address.clone(),
+                value: *amount,
+            }.to_output());
+        }
+        tx_build.add_leftover_output(self.address.clone(),
+                                     options.fee_per_kb.unwrap_or(self.fee_per_kb),
+                                     self.dust_amount())?;
+        Ok(tx_build)
     }
 
-    pub fn send_to_address(&self, address: Address, amount: u64, utxos: &[UtxoEntry])
-            -> Result<UnsignedTx, u64> {
-        let mut tx_build = self.init_tx(utxos);
+    // Spends all UTXOs to `address` in a single output, paying the fee from it (no change).
+    pub fn sweep_to(&self, address: Address, utxos: &[UtxoEntry]) -> Result<UnsignedTx, WalletError> {
+        let mut tx_build = self.init_tx(utxos)?;
+        if tx_build.inputs().is_empty() {
+            return Err(WalletError::NoUtxos);
+        }
+        let output_idx = tx_build.add_leftover_output(address, self.fee_per_kb, self.dust_amount())?;
+        if output_idx.is_none() {
+            return Err(WalletError::DustOutput { idx: 0 });
+        }
+        Ok(tx_build)
+    }
+
+    // Merges UTXOs back to the wallet address, using at most `max_inputs` inputs per tx.
+    // Groups of a single UTXO are left alone, as there is nothing to merge.
+    pub fn consolidate(&self, utxos: &[UtxoEntry], max_inputs: usize)
+            -> Result<Consolidation, WalletError> {
+        let mut txs = Vec::new();
+        let mut report = ConsolidationReport {
+            num_inputs: 0,
+            num_outputs: 0,
+            fee_paid: 0,
+            future_fee_saved: 0,
+        };
+        for chunk in self.spendable_utxos(utxos).chunks(max_inputs.max(2)) {
+            if chunk.len() < 2 {
+                continue;
+            }
+            let tx_build = self.sweep_to(self.address.clone(), chunk)?;
+            let input_amount = chunk.iter().map(|utxo| utxo.amount).sum::<u64>();
+            let output_amount = tx_build.outputs().iter().map(|output| output.value).sum::<u64>();
+            report.num_inputs += chunk.len();
+            report.num_outputs += 1;
+            report.fee_paid += input_amount - output_amount;
+            report.future_fee_saved +=
+                ((chunk.len() - 1) * P2PKH_INPUT_SIZE) as u64 * self.fee_per_kb / 1000;
+            txs.push(tx_build);
+        }
+        Ok(Consolidation { txs, report })
+    }
+
+    // Sends fungible SLP tokens to `recipient`, selecting matching token UTXOs in order and paying
+    // the dust outputs and fee from `bch_utxos`. Token and BCH change go to the wallet.
+    pub fn send_token(&self,
+                      token_id: [u8; 32],
+                      amount: u64,
+                      recipient: Address,
+                      token_utxos: &[TokenUtxoEntry],
+                      bch_utxos: &[UtxoEntry]) -> Result<UnsignedTx, WalletError> {
+        let mut selected = Vec::new();
+        let mut selected_amount = 0;
+        let matching_utxos = token_utxos.iter()
+            .filter(|token_utxo| token_utxo.token_id == token_id && !self.is_frozen(&token_utxo.utxo));
+        for token_utxo in matching_utxos {
+            if selected_amount >= amount {
+                break;
+            }
+            selected.push(token_utxo.utxo.clone());
+            selected_amount += token_utxo.token_amount;
+        }
+        if selected_amount < amount {
+            return Err(WalletError::InsufficientTokens { missing: amount - selected_amount });
+        }
+        // token UTXOs are spent as selected, coin selection would drop them for carrying tokens
+        selected.extend(self.spendable_utxos(bch_utxos));
+        let mut tx_build = self.tx_spending(&selected)?;
+        let send = SLPSend {
+            token_type: SLP_TOKEN_TYPE_FUNGIBLE,
+            token_id,
+            output_quantities: vec![amount],
+        };
+        let op_return = send.into_output().map_err(|err| WalletError::SLP(SLPLeftoverError::SLP(err)))?;
+        tx_build.add_output(op_return.to_output());
         tx_build.add_output(P2PKHOutput {
-            address,
-            value: amount,
+            address: recipient,
+            value: self.dust_amount(),
         }.to_output());
-        tx_build.add_leftover_output(self.address.clone(), self.fee_per_kb, self.dust_amount())?;
+        if selected_amount > amount {
+            tx_build.add_slp_token_change(self.address.clone(),
+                                          selected_amount - amount,
+                                          self.dust_amount())?;
+        }
+        tx_build.add_slp_leftover_output(self.address.clone(), self.fee_per_kb, self.dust_amount())?;
         Ok(tx_build)
     }
 
+    // Signs all inputs with `secret_key`, which has to belong to the wallet address.
+    pub fn sign<C: Crypto>(&self, crypto: &C, tx_build: &UnsignedTx, secret_key: &C::SecretKey)
+            -> Result<Tx, WalletError> {
+        let pub_key = crypto.secret_to_pub_key(secret_key).serialize();
+        if C::hash160(&pub_key) != *self.address.bytes() {
+            return Err(WalletError::WrongSecretKey);
+        }
+        tx_build.sign_with(crypto, secret_key)
+    }
+
+    // Like sign, but uses a fresh secp256k1 context.
+    #[cfg(feature = "secp256k1")]
+    pub fn sign_secp256k1(&self, tx_build: &UnsignedTx, secret_key: &secp256k1::SecretKey)
+            -> Result<Tx, WalletError> {
+        self.sign(&CryptoSecp256k1::new(), tx_build, secret_key)
+    }
+
     pub fn dust_amount(&self) -> u64 {
-        DUST_AMOUNT
+        self.dust_limit
+    }
+}
+
+fn add_op_return(tx_build: &mut UnsignedTx, op_return: Option<OpReturnOutput>)
+        -> Result<(), WalletError> {
+    if let Some(op_return) = op_return {
+        op_return.validate().map_err(WalletError::OpReturn)?;
+        tx_build.insert_output(0, op_return.to_output());
+    }
+    Ok(())
+}
+
+pub(crate) fn add_p2pkh_inputs(tx_build: &mut UnsignedTx, utxos: &[UtxoEntry], address: &Address) {
+    for utxo in utxos {
+        tx_build.add_input(UnsignedInput::new(
+            TxOutpoint {
+                tx_hash: tx_hex_to_hash(&utxo.tx_id_hex).unwrap(),
+                vout: utxo.vout,
+            },
+            Box::new(P2PKHOutput {
+                address: address.clone(),
+                value: utxo.amount,
+            }),
+            0xffff_ffff,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::AddressType;
+    use crate::tx::tx_hash_to_hex;
+    use crate::covenant::serialize_outputs;
+    use crate::time_lock::{LOCK_TIME_THRESHOLD, SEQUENCE_LOCK_TIME_ENABLED};
+
+    fn utxo(tx_hash: [u8; 32], amount: u64) -> UtxoEntry {
+        UtxoEntry { tx_id_hex: tx_hash_to_hex(&tx_hash), amount, ..UtxoEntry::default() }
+    }
+
+    fn wallet() -> Wallet {
+        let address = Address::from_bytes(AddressType::P2PKH, [0x11; 20]);
+        Wallet::from_cash_addr(address.cash_addr().to_string()).unwrap()
+    }
+
+    fn fee(tx_build: &UnsignedTx, fee_per_kb: u64) -> u64 {
+        tx_build.estimate_size() as u64 * fee_per_kb / 1000
+    }
+
+    #[test]
+    fn test_send_token() {
+        let address = Address::from_bytes(AddressType::P2PKH, [0x11; 20]);
+        let wallet = Wallet::from_cash_addr(address.cash_addr().to_string()).unwrap();
+        let utxo = |tx_hash: [u8; 32], amount, slp_token| UtxoEntry {
+            tx_id_hex: tx_hash_to_hex(&tx_hash),
+            amount,
+            slp_token,
+            ..UtxoEntry::default()
+        };
+        let token_utxo = TokenUtxoEntry {
+            utxo: utxo([0x22; 32], DUST_AMOUNT, Some(([0x33; 32], 100))),
+            token_id: [0x33; 32],
+            token_amount: 100,
+        };
+        let recipient = Address::from_bytes(AddressType::P2PKH, [0x44; 20]);
+        let bch_utxos = vec![utxo([0x55; 32], 10_000, None), token_utxo.utxo.clone()];
+        let tx_build = wallet.send_token([0x33; 32], 60, recipient, std::slice::from_ref(&token_utxo), &bch_utxos).unwrap();
+        let spent = tx_build.inputs().iter().map(|input| input.outpoint.tx_hash).collect::<Vec<_>>();
+        assert_eq!(spent, vec![[0x22; 32], [0x55; 32]]);
+        assert_eq!(tx_build.outputs().len(), 4);
+
+        let wallet = wallet.with_dust_filter(1000);
+        assert!(!wallet.is_dust_filtered(&token_utxo.utxo));
+        assert!(wallet.is_dust_filtered(&utxo([0x66; 32], DUST_AMOUNT, None)));
+    }
+
+    #[test]
+    fn test_anti_fee_sniping() {
+        let address = Address::from_bytes(AddressType::P2PKH, [0x11; 20]);
+        let utxos = vec![UtxoEntry { tx_id_hex: tx_hash_to_hex(&[0x22; 32]), amount: 10_000, ..UtxoEntry::default() }];
+        let wallet = Wallet::from_cash_addr(address.cash_addr().to_string()).unwrap()
+            .with_chain_height(800_000);
+        assert_eq!(wallet.init_tx(&utxos).unwrap().lock_time(), 0);
+        let mut wallet = wallet.with_anti_fee_sniping();
+        let tx_build = wallet.sweep_to(address, &utxos).unwrap();
+        assert_eq!(tx_build.lock_time(), 800_000);
+        assert_eq!(tx_build.input_sequence(0), SEQUENCE_LOCK_TIME_ENABLED);
+        wallet.set_chain_height(Some(LOCK_TIME_THRESHOLD));
+        assert_eq!(wallet.init_tx(&utxos).err(),
+                   Some(WalletError::TimeLock(TimeLockError::InvalidChainHeight(LOCK_TIME_THRESHOLD))));
+    }
+
+    #[test]
+    fn test_send_to_addresses() {
+        let wallet = wallet();
+        let utxos = vec![utxo([0x22; 32], 10_000), utxo([0x33; 32], 20_000)];
+        let recipient1 = Address::from_bytes(AddressType::P2PKH, [0x44; 20]);
+        let recipient2 = Address::from_bytes(AddressType::P2PKH, [0x55; 20]);
+        let recipients = vec![(recipient1.clone(), 1_000), (recipient2.clone(), 2_000)];
+        let tx_build = wallet.send_to_addresses(&recipients, &utxos, SendOptions::default()).unwrap();
+        assert_eq!(tx_build.inputs().len(), 2);
+        let outputs = tx_build.outputs();
+        assert_eq!(outputs.len(), 3);
+        assert_eq!(outputs[0].script.to_vec(), P2PKHOutput { value: 0, address: recipient1.clone() }.script().to_vec());
+        assert_eq!(outputs[1].script.to_vec(), P2PKHOutput { value: 0, address: recipient2.clone() }.script().to_vec());
+        assert_eq!((outputs[0].value, outputs[1].value), (1_000, 2_000));
+        assert_eq!(outputs[2].script.to_vec(), P2PKHOutput { value: 0, address: wallet.address().clone() }.script().to_vec());
+        assert_eq!(outputs[2].value, 27_000 - fee(&tx_build, DEFAULT_FEE_PER_KB));
+
+        // the fee rate can be overridden per tx, and single payments build the same tx
+        let options = SendOptions::default().with_fee_rate(5_000);
+        let tx_build = wallet.send_to_addresses(&recipients, &utxos, options.clone()).unwrap();
+        assert_eq!(tx_build.outputs()[2].value, 27_000 - fee(&tx_build, 5_000));
+        let single = wallet.send_to_address(recipient1.clone(), 1_000, &utxos, options.clone()).unwrap();
+        let multi = wallet.send_to_addresses(&recipients[..1], &utxos, options).unwrap();
+        assert_eq!(serialize_outputs(single.outputs()), serialize_outputs(multi.outputs()));
+
+        let with_dust = vec![(recipient1.clone(), 1_000), (recipient2, DUST_AMOUNT - 1)];
+        assert_eq!(wallet.send_to_addresses(&with_dust, &utxos, SendOptions::default()).err(),
+                   Some(WalletError::DustOutput { idx: 1 }));
+        assert_eq!(wallet.send_to_addresses(&recipients, &[], SendOptions::default()).err(),
+                   Some(WalletError::NoUtxos));
+        let too_much = vec![(recipient1, 30_000)];
+        assert!(matches!(wallet.send_to_addresses(&too_much, &utxos, SendOptions::default()),
+                         Err(WalletError::InsufficientFunds { .. })));
+    }
+
+    #[test]
+    fn test_sweep_to() {
+        let wallet = wallet();
+        let destination = Address::from_bytes(AddressType::P2PKH, [0x44; 20]);
+        let utxos = vec![utxo([0x22; 32], 10_000), utxo([0x33; 32], 20_000)];
+        let tx_build = wallet.sweep_to(destination.clone(), &utxos).unwrap();
+        assert_eq!(tx_build.inputs().len(), 2);
+        assert_eq!(tx_build.outputs().len(), 1);
+        let output = &tx_build.outputs()[0];
+        assert_eq!(output.script.to_vec(), P2PKHOutput { value: 0, address: destination.clone() }.script().to_vec());
+        assert_eq!(output.value, 30_000 - fee(&tx_build, DEFAULT_FEE_PER_KB));
+
+        // UTXOs left after paying the fee have to cover the dust limit
+        let dust_utxos = vec![utxo([0x22; 32], DUST_AMOUNT + 100)];
+        assert_eq!(wallet.sweep_to(destination.clone(), &dust_utxos).err(), Some(WalletError::DustOutput { idx: 0 }));
+        assert_eq!(wallet.sweep_to(destination, &[]).err(), Some(WalletError::NoUtxos));
+    }
+
+    #[test]
+    fn test_consolidate() {
+        let wallet = wallet();
+        let utxos = (1..=5).map(|i| utxo([i; 32], 10_000)).collect::<Vec<_>>();
+        // the fifth UTXO is left alone, as it has nothing to merge with
+        let consolidation = wallet.consolidate(&utxos, 2).unwrap();
+        assert_eq!(consolidation.txs.len(), 2);
+        let mut fee_paid = 0;
+        for tx_build in &consolidation.txs {
+            assert_eq!((tx_build.inputs().len(), tx_build.outputs().len()), (2, 1));
+            assert_eq!(tx_build.outputs()[0].script.to_vec(),
+                       P2PKHOutput { value: 0, address: wallet.address().clone() }.script().to_vec());
+            fee_paid += 20_000 - tx_build.outputs()[0].value;
+        }
+        assert_eq!(consolidation.report, ConsolidationReport {
+            num_inputs: 4,
+            num_outputs: 2,
+            fee_paid,
+            future_fee_saved: 2 * P2PKH_INPUT_SIZE as u64,
+        });
+
+        let consolidation = wallet.consolidate(&utxos, 100).unwrap();
+        assert_eq!(consolidation.txs.len(), 1);
+        assert_eq!(consolidation.txs[0].inputs().len(), 5);
+        assert_eq!(consolidation.report.future_fee_saved, 4 * P2PKH_INPUT_SIZE as u64);
+        assert_eq!(consolidation.report.fee_paid, fee(&consolidation.txs[0], DEFAULT_FEE_PER_KB));
+
+        // max_inputs below 2 still merges pairs, a single UTXO gives no tx
+        assert_eq!(wallet.consolidate(&utxos, 0).unwrap().txs.len(), 2);
+        assert!(wallet.consolidate(&utxos[..1], 10).unwrap().txs.is_empty());
+
+        // future savings scale with the fee rate
+        let consolidation = wallet.with_fee_rate(10_000).consolidate(&utxos, 100).unwrap();
+        assert_eq!(consolidation.report.future_fee_saved, 40 * P2PKH_INPUT_SIZE as u64);
+    }
+
+    #[test]
+    fn test_freeze() {
+        let mut wallet = wallet();
+        let recipient = Address::from_bytes(AddressType::P2PKH, [0x44; 20]);
+        let utxos = vec![utxo([0x22; 32], 10_000), utxo([0x33; 32], 20_000)];
+        let frozen = utxos[1].outpoint().unwrap();
+        wallet.freeze(frozen.clone());
+        assert!(!wallet.is_frozen(&utxos[0]));
+        assert!(wallet.is_frozen(&utxos[1]));
+        assert_eq!(wallet.spendable_utxos(&utxos), vec![utxos[0].clone()]);
+
+        // frozen UTXOs are skipped by every send, even if the rest can't cover the amount
+        let tx_build = wallet.send_to_address(recipient.clone(), 5_000, &utxos, SendOptions::default()).unwrap();
+        assert_eq!(tx_build.inputs().len(), 1);
+        assert_eq!(tx_build.inputs()[0].outpoint.tx_hash, [0x22; 32]);
+        assert!(matches!(wallet.send_to_address(recipient.clone(), 15_000, &utxos, SendOptions::default()),
+                         Err(WalletError::InsufficientFunds { .. })));
+        assert_eq!(wallet.sweep_to(recipient.clone(), &utxos[1..]).err(), Some(WalletError::NoUtxos));
+        let token_utxo = TokenUtxoEntry {
+            utxo: UtxoEntry { slp_token: Some(([0x55; 32], 100)), ..utxo([0x33; 32], DUST_AMOUNT) },
+            token_id: [0x55; 32],
+            token_amount: 100,
+        };
+        assert_eq!(wallet.send_token([0x55; 32], 10, recipient.clone(), &[token_utxo], &utxos).err(),
+                   Some(WalletError::InsufficientTokens { missing: 10 }));
+
+        assert!(wallet.unfreeze(&frozen));
+        assert!(!wallet.unfreeze(&frozen));
+        assert_eq!(wallet.spendable_utxos(&utxos).len(), 2);
+        assert!(wallet.send_to_address(recipient, 15_000, &utxos, SendOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn test_send_with_op_return() {
+        use crate::op_return::{OpReturnBuilder, OpReturnError};
+        use crate::script::PushEncoding;
+        let wallet = wallet();
+        let recipient = Address::from_bytes(AddressType::P2PKH, [0x44; 20]);
+        let utxos = vec![utxo([0x22; 32], 10_000)];
+        let mut builder = OpReturnBuilder::with_lokad_id(b"TEST").unwrap();
+        builder.push(b"hello".to_vec());
+        let op_return = builder.build().unwrap();
+        let options = SendOptions::default().with_op_return(op_return.clone());
+        let tx_build = wallet.send_to_address(recipient.clone(), 5_000, &utxos, options).unwrap();
+        let outputs = tx_build.outputs();
+        assert_eq!(outputs.len(), 3);
+        assert_eq!(outputs[0].value, 0);
+        assert_eq!(outputs[0].script.to_vec(), op_return.to_vec().unwrap());
+        assert_eq!(outputs[1].value, 5_000);
+        assert_eq!(outputs[2].value, 5_000 - fee(&tx_build, DEFAULT_FEE_PER_KB));
+
+        // the OP_RETURN is validated before it's attached
+        let mut op_return = OpReturnOutput::new(vec![vec![0x11; 10]], false)
+            .with_encodings(vec![PushEncoding::Direct])
+            .unwrap();
+        op_return.pushes[0] = vec![0x11; 100];
+        let options = SendOptions::default().with_op_return(op_return);
+        assert_eq!(wallet.send_to_address(recipient, 5_000, &utxos, options).err(),
+                   Some(WalletError::OpReturn(OpReturnError::InvalidPushEncoding {
+                       idx: 0,
+                       len: 100,
+                       encoding: PushEncoding::Direct,
+                   })));
     }
 }
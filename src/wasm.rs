@@ -0,0 +1,260 @@
+use crate::address::{Address, AddressType};
+use crate::backend::address_script;
+use crate::outputs::{P2PKHOutput, SLPSend, SLPGenesis};
+use crate::script::Script;
+use crate::tx::{TxOutpoint, TxOutput, tx_hex_to_hash};
+use crate::unsigned_tx::{Output, UnsignedTx, UnsignedInput};
+use crate::hex_traits::{FromHex, ToHex};
+
+use wasm_bindgen::prelude::*;
+
+
+// wasm_bindgen wrappers for web wallets, exported to JS without the Wasm prefix. Errors are
+// thrown as strings of the Debug representation of the Rust error; tx ids and token ids are
+// hex in the usual reversed byte order.
+
+fn js_error<E: std::fmt::Debug>(err: E) -> JsValue {
+    JsValue::from_str(&format!("{:?}", err))
+}
+
+fn hash_from_hex(hex: &str) -> Result<[u8; 32], JsValue> {
+    tx_hex_to_hash(hex).ok_or_else(|| js_error("Invalid hash"))
+}
+
+#[wasm_bindgen(js_name = Address)]
+pub struct WasmAddress {
+    address: Address,
+}
+
+#[wasm_bindgen(js_name = Script)]
+pub struct WasmScript {
+    script: Script,
+}
+
+#[wasm_bindgen(js_name = UnsignedTx)]
+pub struct WasmUnsignedTx {
+    tx: UnsignedTx,
+}
+
+#[wasm_bindgen(js_class = Address)]
+impl WasmAddress {
+    #[wasm_bindgen(js_name = fromCashAddr)]
+    pub fn from_cash_addr(cash_addr: String) -> Result<WasmAddress, JsValue> {
+        Ok(WasmAddress { address: Address::from_cash_addr(cash_addr).map_err(js_error)? })
+    }
+
+    #[wasm_bindgen(js_name = fromHash)]
+    pub fn from_hash(prefix: &str, is_p2sh: bool, hash: &[u8]) -> Result<WasmAddress, JsValue> {
+        let addr_type = if is_p2sh { AddressType::P2SH } else { AddressType::P2PKH };
+        let address = Address::from_slice_prefix(prefix, addr_type, hash)
+            .ok_or_else(|| js_error("Invalid hash length"))?;
+        Ok(WasmAddress { address })
+    }
+
+    #[wasm_bindgen(js_name = cashAddr)]
+    pub fn cash_addr(&self) -> String {
+        self.address.cash_addr().to_string()
+    }
+
+    pub fn hash(&self) -> Vec<u8> {
+        self.address.bytes().to_vec()
+    }
+
+    #[wasm_bindgen(js_name = isP2sh)]
+    pub fn is_p2sh(&self) -> bool {
+        self.address.addr_type() == AddressType::P2SH
+    }
+
+    pub fn prefix(&self) -> String {
+        self.address.prefix().to_string()
+    }
+
+    #[wasm_bindgen(js_name = withPrefix)]
+    pub fn with_prefix(&self, prefix: String) -> WasmAddress {
+        WasmAddress { address: self.address.with_prefix(prefix) }
+    }
+}
+
+#[wasm_bindgen(js_class = Script)]
+impl WasmScript {
+    #[wasm_bindgen(js_name = fromHex)]
+    pub fn from_hex(hex: &str) -> Result<WasmScript, JsValue> {
+        Ok(WasmScript { script: Script::from_hex(hex).map_err(js_error)? })
+    }
+
+    // Locking script paying to `address`.
+    #[wasm_bindgen(js_name = forAddress)]
+    pub fn for_address(address: &WasmAddress) -> WasmScript {
+        WasmScript { script: address_script(&address.address) }
+    }
+
+    #[wasm_bindgen(js_name = toHex)]
+    pub fn to_hex(&self) -> String {
+        self.script.to_hex()
+    }
+
+    // One op per line, as printed by Display.
+    #[wasm_bindgen(js_name = toString)]
+    pub fn to_string_js(&self) -> String {
+        self.script.to_string()
+    }
+}
+
+impl Default for WasmUnsignedTx {
+    fn default() -> Self {
+        WasmUnsignedTx { tx: UnsignedTx::new_simple() }
+    }
+}
+
+#[wasm_bindgen(js_class = UnsignedTx)]
+impl WasmUnsignedTx {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> WasmUnsignedTx {
+        WasmUnsignedTx::default()
+    }
+
+    #[wasm_bindgen(js_name = setVersion)]
+    pub fn set_version(&mut self, version: i32) {
+        self.tx.set_version(version);
+    }
+
+    #[wasm_bindgen(js_name = setLockTime)]
+    pub fn set_lock_time(&mut self, lock_time: u32) {
+        self.tx.set_lock_time(lock_time);
+    }
+
+    #[wasm_bindgen(js_name = addP2pkhInput)]
+    pub fn add_p2pkh_input(&mut self,
+                           tx_id: &str,
+                           vout: u32,
+                           value: u64,
+                           address: &WasmAddress,
+                           sequence: u32) -> Result<usize, JsValue> {
+        Ok(self.tx.add_input(UnsignedInput::new(
+            TxOutpoint { tx_hash: hash_from_hex(tx_id)?, vout },
+            Box::new(P2PKHOutput { value, address: address.address.clone() }),
+            sequence,
+        )))
+    }
+
+    #[wasm_bindgen(js_name = addOutput)]
+    pub fn add_output(&mut self, value: u64, script: &WasmScript) -> usize {
+        self.tx.add_output(TxOutput::new(value, script.script.clone()))
+    }
+
+    #[wasm_bindgen(js_name = addAddressOutput)]
+    pub fn add_address_output(&mut self, value: u64, address: &WasmAddress) -> usize {
+        self.tx.add_output(TxOutput::new(value, address_script(&address.address)))
+    }
+
+    #[wasm_bindgen(js_name = addLeftoverOutput)]
+    pub fn add_leftover_output(&mut self, address: &WasmAddress, fee_per_kb: u64, dust_limit: u64)
+            -> Result<Option<usize>, JsValue> {
+        self.tx.add_leftover_output(address.address.clone(), fee_per_kb, dust_limit).map_err(js_error)
+    }
+
+    // Inserts the SLP SEND message as output 0.
+    #[wasm_bindgen(js_name = addSlpSend)]
+    pub fn add_slp_send(&mut self, token_type: u8, token_id: &str, output_quantities: Vec<u64>)
+            -> Result<(), JsValue> {
+        let send = SLPSend { token_type, token_id: hash_from_hex(token_id)?, output_quantities };
+        let output = send.into_output().map_err(js_error)?;
+        self.tx.insert_output(0, output.to_output());
+        Ok(())
+    }
+
+    // Inserts the SLP GENESIS message as output 0; `token_document_hash` is hex.
+    #[wasm_bindgen(js_name = addSlpGenesis)]
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_slp_genesis(&mut self,
+                           token_type: u8,
+                           token_ticker: &str,
+                           token_name: &str,
+                           token_document_url: &str,
+                           token_document_hash: &str,
+                           decimals: u8,
+                           mint_baton_vout: Option<u8>,
+                           initial_token_mint_quantity: u64) -> Result<(), JsValue> {
+        let genesis = SLPGenesis {
+            token_type,
+            token_ticker: token_ticker.as_bytes().to_vec(),
+            token_name: token_name.as_bytes().to_vec(),
+            token_document_url: token_document_url.as_bytes().to_vec(),
+            token_document_hash: Vec::<u8>::from_hex(token_document_hash).map_err(js_error)?,
+            decimals,
+            mint_baton_vout,
+            initial_token_mint_quantity,
+        };
+        let output = genesis.into_output().map_err(js_error)?;
+        self.tx.insert_output(0, output.to_output());
+        Ok(())
+    }
+
+    #[wasm_bindgen(js_name = addSlpTokenChange)]
+    pub fn add_slp_token_change(&mut self, address: &WasmAddress, token_amount: u64, dust_amount: u64)
+            -> Result<usize, JsValue> {
+        self.tx.add_slp_token_change(address.address.clone(), token_amount, dust_amount)
+            .map_err(js_error)
+    }
+
+    #[wasm_bindgen(js_name = addSlpLeftoverOutput)]
+    pub fn add_slp_leftover_output(&mut self, address: &WasmAddress, fee_per_kb: u64, dust_limit: u64)
+            -> Result<Option<usize>, JsValue> {
+        self.tx.add_slp_leftover_output(address.address.clone(), fee_per_kb, dust_limit)
+            .map_err(js_error)
+    }
+
+    #[wasm_bindgen(js_name = estimateSize)]
+    pub fn estimate_size(&self) -> usize {
+        self.tx.estimate_size()
+    }
+
+    // Hex digests to sign, one per input.
+    pub fn sighashes(&self, sighash_type: u32) -> Vec<String> {
+        self.tx.sighashes(sighash_type).iter()
+            .map(|sighash| sighash.to_hex())
+            .collect()
+    }
+
+    // Hex of the signed tx, given hex DER signatures and pub keys, one per input.
+    pub fn sign(&self, signatures: Vec<String>, pub_keys: Vec<String>) -> Result<String, JsValue> {
+        let decode = |items: Vec<String>| items.iter()
+            .map(|item| Vec::<u8>::from_hex(item))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(js_error);
+        let tx = self.tx.sign(decode(signatures)?, decode(pub_keys)?).map_err(js_error)?;
+        Ok(tx.to_hex())
+    }
+
+    #[cfg(feature = "secp256k1")]
+    #[wasm_bindgen(js_name = signWith)]
+    pub fn sign_with(&self, secret_key: &[u8]) -> Result<String, JsValue> {
+        use crate::crypto::secp256k1::CryptoSecp256k1;
+        let secret_key = secp256k1::SecretKey::from_slice(secret_key).map_err(js_error)?;
+        let tx = self.tx.sign_with(&CryptoSecp256k1::new(), &secret_key).map_err(js_error)?;
+        Ok(tx.to_hex())
+    }
+}
+
+#[cfg(all(test, feature = "secp256k1"))]
+mod tests {
+    use super::*;
+    use crate::tx::Tx;
+
+    #[test]
+    fn test_wasm_unsigned_tx() {
+        let address = WasmAddress::from_cash_addr(
+            "bitcoincash:qr6m7j9njldwwzlg9v7v53unlr4jkmx6eylep8ekg2".to_string()
+        ).unwrap();
+        assert_eq!(address.hash().to_hex(), "f5bf48b397dae70be82b3cca4793f8eb2b6cdac9");
+        let mut tx = WasmUnsignedTx::new();
+        tx.add_p2pkh_input(&[0x11; 32].to_hex(), 0, 10_000, &address, 0xffff_ffff).unwrap();
+        tx.add_slp_send(1, &[0x22; 32].to_hex(), vec![100]).unwrap();
+        tx.add_address_output(546, &address);
+        assert_eq!(tx.add_leftover_output(&address, 1000, 546), Ok(Some(2)));
+        assert_eq!(tx.sighashes(0x41).len(), 1);
+        let signed = Tx::from_hex(&tx.sign_with(&[1; 32]).unwrap()).unwrap();
+        assert_eq!(signed.outputs().len(), 3);
+        assert_eq!(signed.outputs()[1].script.to_hex(), WasmScript::for_address(&address).to_hex());
+    }
+}